@@ -1,30 +1,56 @@
+use std::io::{self, Write};
 use std::time::Duration;
 
 use evdev::InputEvent;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
 
+use crate::ahk::PasteMethod;
 use crate::event::{KeyEvent, RelativeEvent};
 
 // Input to ActionDispatcher. This should only contain things that are easily testable.
-#[derive(Debug)]
+// --print-actions writes each as a line of JSON, e.g. {"key_event":{"key":"KEY_A","value":"press"}},
+// to a file of its own, as stdout also gets messages such as "mode: default".
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Action {
     // InputEvent (EventType::KEY) sent to evdev
     KeyEvent(KeyEvent),
+    // InputEvents (EventType::KEY) sent to evdev with a single SYN, so they happen at once
+    KeyEventBatch(Vec<KeyEvent>),
     // InputEvent (EventType::RELATIVE, NOT mouse movement events) sent to evdev
     RelativeEvent(RelativeEvent),
     // InputEvent (EventType::RELATIVE, ONLY mouse movement events) a collection of mouse movement sent to evdev
     MouseMovementEventCollection(Vec<RelativeEvent>),
     // InputEvent of any event types. It's discouraged to use this for testing because
     // we don't have full control over timeval and it's not pattern-matching friendly.
+    #[serde(serialize_with = "serialize_input_event")]
     InputEvent(InputEvent),
+    // InputEvent (EventType::LED or SOUND) written to the physical devices that support it
+    #[serde(serialize_with = "serialize_input_event")]
+    FeedbackEvent(InputEvent),
     // Run a command
     Command(Vec<String>),
+    // Run a command on the primary selection and type back its output, unless it takes longer
+    // than `timeout`
+    PipeSelection {
+        command: Vec<String>,
+        #[serde(serialize_with = "serialize_millis")]
+        timeout: Duration,
+    },
+    // Trigger a paste in the focused application
+    Paste(PasteMethod),
+    // Keys sent at AHK SendLevel `level`, which EventHandler runs through the hotkeys again
+    SendLevel { level: u8, actions: Vec<Action> },
+    // Sent from the extra output device `device_id` instead of the main one
+    Routed { device_id: String, actions: Vec<Action> },
     // keypress_delay_ms
+    #[serde(serialize_with = "serialize_millis")]
     Delay(Duration),
     // Text expansion via clipboard for hotstrings
     TextExpansion {
         trigger_len: usize,
         replacement: String,
-        add_space: bool,
     },
     //     HotstringExpansion {
     //     trigger_len: usize,
@@ -32,3 +58,83 @@ pub enum Action {
     //     add_space: bool,
     // },
 }
+
+fn serialize_input_event<S: Serializer>(event: &InputEvent, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("InputEvent", 3)?;
+    state.serialize_field("event_type", &event.event_type().0)?;
+    state.serialize_field("code", &event.code())?;
+    state.serialize_field("value", &event.value())?;
+    state.end()
+}
+
+// In milliseconds, fractions included
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+// Each action as a line of JSON, for --print-actions
+pub fn write_actions(out: &mut impl Write, actions: &[Action]) -> io::Result<()> {
+    for action in actions {
+        writeln!(out, "{}", serde_json::to_string(action)?)?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_actions, Action};
+    use crate::event::{Event, KeyEvent, KeyValue, RelativeEvent};
+    use crate::tests::{get_event_handler, parse_config};
+    use evdev::{EventType, InputEvent, KeyCode as Key};
+    use indoc::indoc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_serialize_actions() {
+        let actions = [
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::RelativeEvent(RelativeEvent::new_with(8, -1)),
+            Action::InputEvent(InputEvent::new(EventType::LED.0, 1, 1)),
+            Action::Delay(Duration::from_micros(1500)),
+            Action::Command(vec!["true".to_string()]),
+            Action::TextExpansion {
+                trigger_len: 4,
+                replacement: "by the way".to_string(),
+            },
+        ];
+        let lines: Vec<_> = actions.iter().map(|action| serde_json::to_string(action).unwrap()).collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"key_event":{"key":"KEY_A","value":"press"}}"#,
+                r#"{"relative_event":{"code":8,"value":-1}}"#,
+                r#"{"input_event":{"event_type":17,"code":1,"value":1}}"#,
+                r#"{"delay":1.5}"#,
+                r#"{"command":["true"]}"#,
+                r#"{"text_expansion":{"trigger_len":4,"replacement":"by the way"}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_actions_one_json_line_each() {
+        let config = parse_config(indoc! {"
+            keymap:
+              - remap:
+                  Esc: { set_mode: vim }
+                  C-a: [home, { launch: [\"true\"] }]
+            "});
+        let mut event_handler = get_event_handler(&config, None);
+        let mut out = Vec::new();
+        for key in [Key::KEY_LEFTCTRL, Key::KEY_A, Key::KEY_ESC] {
+            let actions = event_handler.on_events(&vec![Event::key_press(key)], &config).unwrap();
+            write_actions(&mut out, &actions).unwrap();
+        }
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(r#"{"command":["true"]}"#), "{out}");
+        for line in out.lines() {
+            assert!(serde_json::from_str::<serde_json::Value>(line).is_ok(), "not JSON: {line}");
+        }
+    }
+}
@@ -1,46 +1,121 @@
-use evdev::{uinput::VirtualDevice, EventType, InputEvent, KeyCode as Key};
+use evdev::{uinput::VirtualDevice, Device, EventType, InputEvent, KeyCode as Key, LedCode, SoundCode};
 use fork::{fork, setsid, Fork};
 use log::debug;
 use log::error;
+use log::warn;
+use std::collections::HashMap;
 use nix::sys::signal;
 use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet};
 use std::process::{exit, Command, Stdio};
 use crate::action::Action;
 use crate::event::{KeyEvent, KeyValue, RelativeEvent};
 use crate::ahk::interpreter::AhkInterpreter;  
+use crate::ahk::{MissingClipboardTool, PasteMethod, WaylandTextInjector};
+
+// Where dispatched input events go: the uinput device, or a recorder in tests
+pub trait EventSink {
+    fn emit(&mut self, events: &[InputEvent]) -> std::io::Result<()>;
+}
+
+impl EventSink for VirtualDevice {
+    fn emit(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+        VirtualDevice::emit(self, events)
+    }
+}
 
 pub struct ActionDispatcher<'a> {
-    device: VirtualDevice,
+    device: Box<dyn EventSink>,
+    // Extra output devices by id, for route_to
+    routes: HashMap<String, Box<dyn EventSink>>,
     sigaction_set: bool,
+    // Devices with LEDs or a speaker, opened on the first FeedbackEvent
+    feedback_devices: Option<Vec<Device>>,
+    // Cleared once wl-clipboard turns out to be missing, so text expansion stops quietly
+    clipboard_available: bool,
+    injector: WaylandTextInjector,
+    // --debug-commands: wait for each command and log its output and exit status
+    debug_commands: bool,
     _interpreter: &'a mut AhkInterpreter<'a>,
 }
 
 impl<'a> ActionDispatcher<'a> {
-    pub fn new(device: VirtualDevice, interpreter: &'a mut AhkInterpreter<'a>) -> Self {
+    pub fn new(device: impl EventSink + 'static, interpreter: &'a mut AhkInterpreter<'a>) -> Self {
         ActionDispatcher {
-            device,
+            device: Box::new(device),
+            routes: HashMap::new(),
             sigaction_set: false,
+            feedback_devices: None,
+            clipboard_available: true,
+            injector: WaylandTextInjector::default(),
+            debug_commands: false,
             _interpreter: interpreter,
         }
     }
 
+    pub fn add_route(&mut self, device_id: &str, device: impl EventSink + 'static) {
+        self.routes.insert(device_id.to_string(), Box::new(device));
+    }
+
+    pub fn set_debug_commands(&mut self, debug_commands: bool) {
+        self.debug_commands = debug_commands;
+    }
+
+    // Switches the clipboard tools, e.g. after a config reload changed them
+    pub fn set_injector(&mut self, injector: WaylandTextInjector) {
+        self.injector = injector;
+        self.clipboard_available = true;
+    }
+
     pub fn on_action(&mut self, action: Action) -> anyhow::Result<()> {
         match action {
             Action::KeyEvent(key_event) => self.on_key_event(key_event)?,
+            Action::KeyEventBatch(key_events) => self.on_key_events(key_events)?,
             Action::RelativeEvent(relative_event) => self.on_relative_event(relative_event)?,
             Action::MouseMovementEventCollection(mouse_movement_events) => {
                 self.send_mousemovement_event_batch(mouse_movement_events)?;
             }
             Action::InputEvent(event) => self.send_event(event)?,
+            Action::FeedbackEvent(event) => self.send_feedback_event(event)?,
             Action::Command(command) => self.run_command(command),
+            Action::PipeSelection { command, timeout } => {
+                if !self.clipboard_available {
+                    return Ok(());
+                }
+                let output = crate::ahk::pipe_selection(&self.injector, &command, timeout);
+                if let Some(output) = self.check_clipboard(output)?.flatten() {
+                    if !output.is_empty() {
+                        self.paste_text(&output)?;
+                    }
+                }
+            }
+            Action::Paste(method) => self.paste(method)?,
+            // EventHandler normally unpacks these; anything left is sent as is
+            Action::SendLevel { actions, .. } => {
+                for action in actions {
+                    self.on_action(action)?;
+                }
+            }
+            Action::Routed { device_id, actions } => {
+                let Some(mut route) = self.routes.remove(&device_id) else {
+                    warn!("route_to: no output device {device_id:?}, sending from the main one");
+                    for action in actions {
+                        self.on_action(action)?;
+                    }
+                    return Ok(());
+                };
+                std::mem::swap(&mut self.device, &mut route);
+                let result = actions.into_iter().try_for_each(|action| self.on_action(action));
+                std::mem::swap(&mut self.device, &mut route);
+                self.routes.insert(device_id, route);
+                result?;
+            }
             Action::Delay(_) => {}   
 
-            Action::TextExpansion { trigger_len, replacement, add_space } => {
-                let final_text = if add_space {
-                    format!("{}\u{00A0}", replacement)
-                } else {
-                    replacement.clone()
-                };
+            Action::TextExpansion { trigger_len, replacement } => {
+                // Copy first, so the trigger stays put if there's no clipboard to paste from
+                if !self.copy_to_clipboard(&replacement)? {
+                    return Ok(());
+                }
 
                 // Delete trigger
                 for _ in 0..trigger_len {
@@ -48,15 +123,46 @@ impl<'a> ActionDispatcher<'a> {
                     self.on_key_event(KeyEvent::new(Key::KEY_BACKSPACE, KeyValue::Release))?;
                 }
 
-                // Copy replacement to clipboard
-                crate::ahk::WaylandTextInjector::copy_to_clipboard(&final_text)?;
+                // Shift+Insert works in terminals too, unlike Ctrl+V
+                self.paste(PasteMethod::ShiftInsert)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn paste_text(&mut self, text: &str) -> anyhow::Result<()> {
+        if self.copy_to_clipboard(text)? {
+            // Shift+Insert works in terminals too, unlike Ctrl+V
+            self.paste(PasteMethod::ShiftInsert)?;
+        }
+        Ok(())
+    }
+
+    // Returns false without copying when wl-clipboard is missing
+    fn copy_to_clipboard(&mut self, text: &str) -> anyhow::Result<bool> {
+        if !self.clipboard_available {
+            return Ok(false);
+        }
+        let result = self.injector.copy_to_clipboard(text);
+        Ok(self.check_clipboard(result)?.is_some())
+    }
 
-                // Paste using Shift+Insert instead of Ctrl+V
-                self.on_key_event(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press))?;
-                self.on_key_event(KeyEvent::new(Key::KEY_INSERT, KeyValue::Press))?;
-                self.on_key_event(KeyEvent::new(Key::KEY_INSERT, KeyValue::Release))?;
-                self.on_key_event(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Release))?;
+    // Turns a missing wl-clipboard into `None`, reported once, and disables clipboard use from then on
+    fn check_clipboard<T>(&mut self, result: anyhow::Result<T>) -> anyhow::Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.is::<MissingClipboardTool>() => {
+                error!("{e}; text expansion and pipe_selection are disabled");
+                self.clipboard_available = false;
+                Ok(None)
             }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn paste(&mut self, method: PasteMethod) -> anyhow::Result<()> {
+        for event in method.key_events() {
+            self.on_key_event(event)?;
         }
         Ok(())
     }
@@ -67,6 +173,12 @@ impl<'a> ActionDispatcher<'a> {
         self.device.emit(&[ev])
     }
 
+    fn on_key_events(&mut self, events: Vec<KeyEvent>) -> std::io::Result<()> {
+        let batch: Vec<InputEvent> =
+            events.iter().map(|event| InputEvent::new(EventType::KEY.0, event.code(), event.value())).collect();
+        self.device.emit(&batch)
+    }
+
     fn on_relative_event(&mut self, event: RelativeEvent) -> std::io::Result<()> {
         let ev = InputEvent::new(EventType::RELATIVE.0, event.code, event.value);
         self.device.emit(&[ev])
@@ -87,6 +199,39 @@ impl<'a> ActionDispatcher<'a> {
         self.device.emit(&[event])
     }
 
+    // The uinput device can't take LED or sound events, so write them to the real devices,
+    // which accept writes even while grabbed
+    fn send_feedback_event(&mut self, event: InputEvent) -> std::io::Result<()> {
+        let devices = self.feedback_devices.get_or_insert_with(|| {
+            evdev::enumerate()
+                .map(|(_, device)| device)
+                .filter(|device| device.supported_leds().is_some() || device.supported_sounds().is_some())
+                .collect()
+        });
+        let mut failed = false;
+        for device in devices.iter_mut() {
+            let supported = match event.event_type() {
+                EventType::LED => device
+                    .supported_leds()
+                    .is_some_and(|leds| leds.contains(LedCode(event.code()))),
+                _ => device
+                    .supported_sounds()
+                    .is_some_and(|sounds| sounds.contains(SoundCode(event.code()))),
+            };
+            if supported {
+                if let Err(e) = device.send_events(&[event]) {
+                    warn!("Failed to send {:?} to {:?}: {}", event, device.name(), e);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            // Likely unplugged; look the devices up again next time
+            self.feedback_devices = None;
+        }
+        Ok(())
+    }
+
     fn run_command(&mut self, command: Vec<String>) {
         if !self.sigaction_set {
             let sig_action = SigAction::new(SigHandler::SigDfl, SaFlags::SA_NOCLDWAIT, SigSet::empty());
@@ -102,6 +247,19 @@ impl<'a> ActionDispatcher<'a> {
                 match fork() {
                     Ok(Fork::Child) => {
                         setsid().expect("Failed to setsid.");
+                        // Still detached, but this process stays around until the command is done
+                        if self.debug_commands {
+                            // SA_NOCLDWAIT is inherited, and would keep this from waiting for the command
+                            let sig_action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+                            unsafe {
+                                sigaction(signal::SIGCHLD, &sig_action).expect("Failed to reset SIGCHLD handler");
+                            }
+                            match command_output_log(&command) {
+                                Ok(lines) => lines.iter().for_each(|line| debug!("{line}")),
+                                Err(e) => error!("Error running command: {e:?}"),
+                            }
+                            exit(0);
+                        }
                         match Command::new(&command[0])
                             .args(&command[1..])
                             .stdin(Stdio::null())
@@ -130,4 +288,143 @@ impl<'a> ActionDispatcher<'a> {
             Err(e) => error!("Error spawning process: {e:?}"),
         }
     }
-}
\ No newline at end of file
+}
+
+// Runs `command` to the end, returning what --debug-commands logs: each line of its output, then
+// how it exited
+fn command_output_log(command: &[String]) -> std::io::Result<Vec<String>> {
+    let output = Command::new(&command[0]).args(&command[1..]).stdin(Stdio::null()).output()?;
+    let mut lines = vec![];
+    for (stream, bytes) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        let text = String::from_utf8_lossy(bytes);
+        lines.extend(text.lines().map(|line| format!("{command:?} {stream}: {line}")));
+    }
+    lines.push(format!("{command:?} exited with {}", output.status));
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command_output_log, ActionDispatcher, EventSink};
+    use crate::action::Action;
+    use crate::ahk::interpreter::AhkInterpreter;
+    use crate::ahk::{PasteMethod, WaylandTextInjector};
+    use crate::client::WMClient;
+    use crate::event::{KeyEvent, KeyValue};
+    use crate::tests::{NoWindowClient, TempConfigDir};
+    use evdev::{EventType, InputEvent, KeyCode as Key};
+    use std::cell::RefCell;
+    use std::os::unix::fs::PermissionsExt;
+    use std::rc::Rc;
+
+    // Keeps every emitted event, shared with the test as the dispatcher owns its sink
+    #[derive(Clone, Default)]
+    struct Recorder(Rc<RefCell<Vec<InputEvent>>>);
+
+    impl EventSink for Recorder {
+        fn emit(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+            self.0.borrow_mut().extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    impl Recorder {
+        // The key events as (key, value)
+        fn keys(&self) -> Vec<(Key, i32)> {
+            self.0
+                .borrow()
+                .iter()
+                .filter(|event| event.event_type() == EventType::KEY)
+                .map(|event| (Key::new(event.code()), event.value()))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_dispatch_ctrl_v_paste() {
+        let recorder = Recorder::default();
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        let mut dispatcher = ActionDispatcher::new(recorder.clone(), &mut interpreter);
+
+        dispatcher.on_action(Action::Paste(PasteMethod::CtrlV)).unwrap();
+
+        let expected = [(Key::KEY_LEFTCTRL, 1), (Key::KEY_V, 1), (Key::KEY_V, 0), (Key::KEY_LEFTCTRL, 0)];
+        assert_eq!(recorder.keys(), expected);
+    }
+
+    #[test]
+    fn test_dispatch_routed_keys() {
+        let (main, tablet) = (Recorder::default(), Recorder::default());
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        let mut dispatcher = ActionDispatcher::new(main.clone(), &mut interpreter);
+        dispatcher.add_route("tablet", tablet.clone());
+        let routed = |device_id: &str| Action::Routed {
+            device_id: device_id.to_string(),
+            actions: vec![
+                Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            ],
+        };
+
+        dispatcher.on_action(routed("tablet")).unwrap();
+        assert_eq!(tablet.keys(), [(Key::KEY_A, 1), (Key::KEY_A, 0)]);
+        assert_eq!(main.keys(), []);
+
+        // The main device is back for everything else, and takes keys for unknown devices
+        dispatcher.on_action(Action::Paste(PasteMethod::CtrlV)).unwrap();
+        dispatcher.on_action(routed("pedal")).unwrap();
+        assert_eq!(tablet.keys().len(), 2);
+        assert_eq!(main.keys().len(), 6);
+    }
+
+    #[test]
+    fn test_dispatch_text_expansion() {
+        let dir = TempConfigDir::new("dispatch", &[("copy", "#!/bin/sh\ncat > /dev/null\n")]);
+        let copy_cmd = dir.join("copy");
+        std::fs::set_permissions(&copy_cmd, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let recorder = Recorder::default();
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        let mut dispatcher = ActionDispatcher::new(recorder.clone(), &mut interpreter);
+        let expansion = || Action::TextExpansion {
+            trigger_len: 2,
+            replacement: "by the way".to_string(),
+        };
+
+        dispatcher.set_injector(WaylandTextInjector::new(copy_cmd.to_str().unwrap(), "wl-paste"));
+        dispatcher.on_action(expansion()).unwrap();
+        // Without a clipboard tool the trigger stays
+        dispatcher.set_injector(WaylandTextInjector::new(dir.join("missing").to_str().unwrap(), "wl-paste"));
+        dispatcher.on_action(expansion()).unwrap();
+
+        let expected = [
+            (Key::KEY_BACKSPACE, 1),
+            (Key::KEY_BACKSPACE, 0),
+            (Key::KEY_BACKSPACE, 1),
+            (Key::KEY_BACKSPACE, 0),
+            (Key::KEY_LEFTSHIFT, 1),
+            (Key::KEY_INSERT, 1),
+            (Key::KEY_INSERT, 0),
+            (Key::KEY_LEFTSHIFT, 0),
+        ];
+        assert_eq!(recorder.keys(), expected);
+    }
+
+    #[test]
+    fn test_command_output_log() {
+        let command = ["sh", "-c", "echo out; echo err >&2; exit 3"].map(String::from);
+        let command_name = format!("{command:?}");
+
+        let lines = command_output_log(&command).unwrap();
+
+        let expected = [
+            format!("{command_name} stdout: out"),
+            format!("{command_name} stderr: err"),
+            format!("{command_name} exited with exit status: 3"),
+        ];
+        assert_eq!(lines, expected);
+    }
+}
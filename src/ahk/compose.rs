@@ -0,0 +1,58 @@
+// Compose-key sequences (as in the default X11/libxkbcommon Compose table) for
+// characters that have no key on a US layout. Each component is typed with
+// keys::char_to_key_shift, so it must itself be plain ASCII.
+pub fn compose_sequence(ch: char) -> Option<&'static str> {
+    let sequence = match ch {
+        'á' => "'a",
+        'é' => "'e",
+        'í' => "'i",
+        'ó' => "'o",
+        'ú' => "'u",
+        'Á' => "'A",
+        'É' => "'E",
+        'Í' => "'I",
+        'Ó' => "'O",
+        'Ú' => "'U",
+        'à' => "`a",
+        'è' => "`e",
+        'ì' => "`i",
+        'ò' => "`o",
+        'ù' => "`u",
+        'À' => "`A",
+        'È' => "`E",
+        'Ì' => "`I",
+        'Ò' => "`O",
+        'Ù' => "`U",
+        'â' => "^a",
+        'ê' => "^e",
+        'î' => "^i",
+        'ô' => "^o",
+        'û' => "^u",
+        'ä' => "\"a",
+        'ë' => "\"e",
+        'ï' => "\"i",
+        'ö' => "\"o",
+        'ü' => "\"u",
+        'Ä' => "\"A",
+        'Ö' => "\"O",
+        'Ü' => "\"U",
+        'ñ' => "~n",
+        'Ñ' => "~N",
+        'ç' => ",c",
+        'Ç' => ",C",
+        'ß' => "ss",
+        '€' => "=e",
+        '£' => "-L",
+        '°' => "oo",
+        '©' => "oc",
+        '®' => "or",
+        '«' => "<<",
+        '»' => ">>",
+        '¿' => "??",
+        '¡' => "!!",
+        '—' => "---",
+        '–' => "--.",
+        _ => return None,
+    };
+    Some(sequence)
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use super::parser::unescape_ahk_string;
+
+// Just enough of AHK's expressions for Send arguments: `"text"`, `var`, `a . b`, `a = b` (`==`,
+// `!=`, `!==`, `<>`) and `cond ? a : b`. Values are strings, as AHK converts numbers where needed.
+#[derive(Debug)]
+enum Expr {
+    Value(String),
+    Variable(String),
+    Concat(Vec<Expr>),
+    Compare(Comparison, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Equal,
+    CaseEqual,
+    NotEqual,
+    CaseNotEqual,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Str(String),
+    Word(String),
+    Op(&'static str),
+}
+
+// Variable names are lowercase in `variables`, as AHK's are case-insensitive. An unset variable
+// is an error, like in AHK v2.
+pub fn evaluate(expr: &str, variables: &HashMap<String, String>) -> Result<String, String> {
+    eval(&parse(expr)?, variables)
+}
+
+// The variables `expr` uses, or why it isn't an expression this can evaluate
+pub fn variables(expr: &str) -> Result<Vec<String>, String> {
+    let mut names = vec![];
+    collect_variables(&parse(expr)?, &mut names);
+    Ok(names)
+}
+
+// What Send types when its argument isn't an expression, e.g. v1's `Send {Enter}`
+pub fn literal(arg: &str) -> String {
+    unescape_ahk_string(arg.trim_matches(|c| c == '"' || c == '\''))
+}
+
+fn collect_variables(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Value(_) => {}
+        Expr::Variable(name) => names.push(name.clone()),
+        Expr::Concat(parts) => parts.iter().for_each(|part| collect_variables(part, names)),
+        Expr::Compare(_, left, right) => {
+            collect_variables(left, names);
+            collect_variables(right, names);
+        }
+        Expr::Ternary(condition, then, otherwise) => {
+            collect_variables(condition, names);
+            collect_variables(then, names);
+            collect_variables(otherwise, names);
+        }
+    }
+}
+
+// AHK's truthiness: empty strings and zero are false
+fn is_truthy(value: &str) -> bool {
+    !value.is_empty() && value.trim().parse::<f64>().ok().is_none_or(|n| n != 0.0)
+}
+
+fn parse(expr: &str) -> Result<Expr, String> {
+    let tokens = tokenize(expr)?;
+    let mut pos = 0;
+    let parsed = parse_ternary(&tokens, &mut pos)?;
+    if pos < tokens.len() {
+        return Err(format!("Unexpected {:?} in {expr}", tokens[pos]));
+    }
+    Ok(parsed)
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut raw = String::new();
+                loop {
+                    match chars.next() {
+                        Some('`') => {
+                            raw.push('`');
+                            raw.extend(chars.next());
+                        }
+                        Some(ch) if ch == c => break,
+                        Some(ch) => raw.push(ch),
+                        None => return Err(format!("Unterminated string in {expr}")),
+                    }
+                }
+                tokens.push(Token::Str(unescape_ahk_string(&raw)));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    // A dot continues a number like 1.5, and is concatenation anywhere else
+                    let decimal_point = c == '.'
+                        && word.chars().all(|c| c.is_ascii_digit())
+                        && chars.clone().nth(1).is_some_and(|c| c.is_ascii_digit());
+                    if !(c.is_alphanumeric() || c == '_' || decimal_point) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+            _ => {
+                let rest: String = chars.clone().take(3).collect();
+                let op = ["!==", "==", "!=", "<>", "=", "?", ":", "(", ")", "."]
+                    .into_iter()
+                    .find(|op| rest.starts_with(op))
+                    .ok_or_else(|| format!("Unsupported {c:?} in {expr}"))?;
+                for _ in 0..op.len() {
+                    chars.next();
+                }
+                tokens.push(Token::Op(op));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_ternary(tokens: &[Token], pos: &mut usize) -> Result<Expr, String> {
+    let condition = parse_comparison(tokens, pos)?;
+    if tokens.get(*pos) != Some(&Token::Op("?")) {
+        return Ok(condition);
+    }
+    *pos += 1;
+    let then = parse_ternary(tokens, pos)?;
+    if tokens.get(*pos) != Some(&Token::Op(":")) {
+        return Err("Expected : in a ternary".to_string());
+    }
+    *pos += 1;
+    let otherwise = parse_ternary(tokens, pos)?;
+    Ok(Expr::Ternary(Box::new(condition), Box::new(then), Box::new(otherwise)))
+}
+
+fn parse_comparison(tokens: &[Token], pos: &mut usize) -> Result<Expr, String> {
+    let left = parse_concat(tokens, pos)?;
+    let comparison = match tokens.get(*pos) {
+        Some(Token::Op("=")) => Comparison::Equal,
+        Some(Token::Op("==")) => Comparison::CaseEqual,
+        Some(Token::Op("!=" | "<>")) => Comparison::NotEqual,
+        Some(Token::Op("!==")) => Comparison::CaseNotEqual,
+        _ => return Ok(left),
+    };
+    *pos += 1;
+    let right = parse_concat(tokens, pos)?;
+    Ok(Expr::Compare(comparison, Box::new(left), Box::new(right)))
+}
+
+// Binds tighter than comparisons, as in AHK
+fn parse_concat(tokens: &[Token], pos: &mut usize) -> Result<Expr, String> {
+    let mut parts = vec![parse_primary(tokens, pos)?];
+    while tokens.get(*pos) == Some(&Token::Op(".")) {
+        *pos += 1;
+        parts.push(parse_primary(tokens, pos)?);
+    }
+    Ok(if parts.len() == 1 { parts.remove(0) } else { Expr::Concat(parts) })
+}
+
+fn parse_primary(tokens: &[Token], pos: &mut usize) -> Result<Expr, String> {
+    let token = tokens.get(*pos).ok_or("Expected a value")?;
+    *pos += 1;
+    match token {
+        Token::Str(s) => Ok(Expr::Value(s.clone())),
+        Token::Word(word) if word.eq_ignore_ascii_case("true") => Ok(Expr::Value("1".to_string())),
+        Token::Word(word) if word.eq_ignore_ascii_case("false") => Ok(Expr::Value("0".to_string())),
+        Token::Word(word) if word.parse::<f64>().is_ok() => Ok(Expr::Value(word.clone())),
+        Token::Word(word) => Ok(Expr::Variable(word.to_lowercase())),
+        Token::Op("(") => {
+            let inner = parse_ternary(tokens, pos)?;
+            if tokens.get(*pos) != Some(&Token::Op(")")) {
+                return Err("Expected )".to_string());
+            }
+            *pos += 1;
+            Ok(inner)
+        }
+        Token::Op(op) => Err(format!("Unexpected {op}")),
+    }
+}
+
+fn eval(expr: &Expr, variables: &HashMap<String, String>) -> Result<String, String> {
+    match expr {
+        Expr::Value(value) => Ok(value.clone()),
+        Expr::Variable(name) => variables.get(name).cloned().ok_or_else(|| format!("{name} isn't set")),
+        Expr::Concat(parts) => parts.iter().map(|part| eval(part, variables)).collect(),
+        Expr::Compare(comparison, left, right) => {
+            let (left, right) = (eval(left, variables)?, eval(right, variables)?);
+            // Numbers compare as numbers, so 1 = 1.0
+            let numbers = left.trim().parse::<f64>().ok().zip(right.trim().parse::<f64>().ok());
+            let equal = match (numbers, comparison) {
+                (Some((left, right)), _) => left == right,
+                (None, Comparison::Equal | Comparison::NotEqual) => left.to_lowercase() == right.to_lowercase(),
+                (None, Comparison::CaseEqual | Comparison::CaseNotEqual) => left == right,
+            };
+            let negated = matches!(comparison, Comparison::NotEqual | Comparison::CaseNotEqual);
+            Ok(if equal != negated { "1" } else { "0" }.to_string())
+        }
+        // Only the chosen branch is evaluated, so the other may use unset variables
+        Expr::Ternary(condition, then, otherwise) => {
+            if is_truthy(&eval(condition, variables)?) {
+                eval(then, variables)
+            } else {
+                eval(otherwise, variables)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::evaluate;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_evaluate() {
+        let variables = HashMap::from([("mode".to_string(), "Dark".to_string()), ("n".to_string(), "1".to_string())]);
+        let eval = |expr| evaluate(expr, &variables);
+
+        assert_eq!(eval(r#""a`tb""#).unwrap(), "a\tb");
+        assert_eq!(eval("Mode").unwrap(), "Dark");
+        assert_eq!(eval(r#"mode = "dark" ? "night" : "day""#).unwrap(), "night");
+        assert_eq!(eval(r#"mode == "dark" ? "night" : "day""#).unwrap(), "day");
+        assert_eq!(eval(r#"n != 1.0 ? unset : 'one'"#).unwrap(), "one");
+        assert_eq!(eval(r#"(n = 2) ? "a" : n = 1 ? "b" : "c""#).unwrap(), "b");
+        assert!(eval("unset").is_err());
+        assert_eq!(eval(r#""a" . mode . 1.5"#).unwrap(), "aDark1.5");
+        assert_eq!(eval(r#"mode . "" = "dark" ? "night" : "day""#).unwrap(), "night");
+        assert!(eval(r#""a" . "#).is_err());
+        assert!(eval("{Enter}").is_err());
+    }
+}
@@ -1,17 +1,91 @@
 use crate::action::Action;
-use crate::ahk::types::{AhkAction, WindowCriteria};
+use crate::ahk::expression;
+use crate::ahk::send_parser::split_blind;
+use crate::ahk::types::{AhkAction, LockKey, LockState, TitleMatchMode, WinGetInfo, WindowCriteria};
 use crate::client::WMClient;
-use crate::event::{KeyEvent, KeyValue};
+use crate::event::{KeyEvent, KeyValue, PRESS, RELEASE};
+use crate::event_handler::MODIFIER_KEYS;
+use crate::keys::char_to_key_shift;
 use evdev::KeyCode as Key;
+use log::warn;
+use regex::Regex;
 use std::error::Error;
-use std::time::Duration;
-use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+
+// How often a deferred WinWait/WinWaitActive/KeyWait re-checks the windows or keys
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A WinWait/WinWaitActive/KeyWait that hasn't resolved yet, together with the actions queued behind it.
+#[derive(Debug)]
+pub struct PendingWait {
+    kind: WaitKind,
+    deadline: Instant,
+    // The script gave no timeout, so the deadline is the max_wait cap
+    capped: bool,
+    next_check: Instant,
+    rest: Vec<AhkAction>,
+    // Modifiers the Sends before the wait left down, to release once the rest has run
+    sent_modifiers: Vec<Key>,
+}
+
+#[derive(Debug)]
+enum WaitKind {
+    Active(WindowCriteria),
+    Exists(WindowCriteria),
+    // Until the key is down (`pressed`) or up
+    Key { key: Key, pressed: bool },
+}
+
+impl PendingWait {
+    fn new(kind: WaitKind, timeout_ms: Option<u64>, max_wait: Duration, now: Instant) -> Self {
+        PendingWait {
+            kind,
+            deadline: now + timeout_ms.map_or(max_wait, Duration::from_millis),
+            capped: timeout_ms.is_none(),
+            next_check: now + WAIT_POLL_INTERVAL,
+            rest: vec![],
+            sent_modifiers: vec![],
+        }
+    }
+
+    // When AhkInterpreter::resume should look at this wait again
+    pub fn next_check(&self) -> Instant {
+        self.next_check
+    }
+}
 
 pub struct AhkInterpreter<'a> {
     wm_client: &'a mut WMClient,
     application_cache: Option<String>,
     title_cache: Option<String>,
     active_virtual_modifiers: HashSet<Key>,
+    keypress_delay: Duration,
+    // Upper bound of the random extra delay between typed characters
+    type_jitter: Duration,
+    rng: fastrand::Rng,
+    // Typed before compose sequences; None pastes non-ASCII text instead
+    compose_key: Option<Key>,
+    // Runs a `kdotool search` command, returning its stdout; None if it couldn't run
+    window_search: fn(&[String]) -> Option<String>,
+    // Whether a lock key's LED is lit; None if that can't be told
+    led_state: fn(LockKey) -> Option<bool>,
+    // How long a WinWait/WinWaitActive without a timeout waits before giving up
+    max_wait: Duration,
+    clock: fn() -> Instant,
+    // SendLevel of this hotkey's later Sends; 0 keeps them from triggering hotkeys
+    send_level: u8,
+    // GroupAdd's window groups, keyed by lowercased name
+    window_groups: HashMap<String, Vec<WindowCriteria>>,
+    // Physical keys down when the interpreter was made, for KeyWait
+    held_keys: HashSet<Key>,
+    title_match_mode: TitleMatchMode,
+    // The titles in AHK actions compiled for SetTitleMatchMode RegEx, keyed by the title
+    title_regexes: HashMap<String, Regex>,
+    // The script's top-level variables and what WinGet stores, keyed by lowercased name
+    variables: HashMap<String, String>,
+    // Modifiers a Send pressed, like `{Ctrl down}`, that no later Send has released yet
+    sent_modifiers: Vec<Key>,
 }
 
 impl<'a> AhkInterpreter<'a> {
@@ -21,9 +95,94 @@ impl<'a> AhkInterpreter<'a> {
             application_cache: None,
             title_cache: None,
             active_virtual_modifiers: HashSet::new(),
+            keypress_delay: Duration::ZERO,
+            type_jitter: Duration::ZERO,
+            rng: fastrand::Rng::new(),
+            compose_key: None,
+            window_search: run_window_search,
+            led_state: read_led_state,
+            max_wait: Duration::from_secs(30),
+            clock: Instant::now,
+            send_level: 0,
+            window_groups: HashMap::new(),
+            held_keys: HashSet::new(),
+            title_match_mode: TitleMatchMode::default(),
+            title_regexes: HashMap::new(),
+            variables: HashMap::new(),
+            sent_modifiers: vec![],
         }
     }
 
+    pub fn set_keypress_delay(&mut self, keypress_delay: Duration) {
+        self.keypress_delay = keypress_delay;
+    }
+
+    pub fn set_type_jitter(&mut self, type_jitter: Duration, rng: fastrand::Rng) {
+        self.type_jitter = type_jitter;
+        self.rng = rng;
+    }
+
+    pub fn set_compose_key(&mut self, compose_key: Option<Key>) {
+        self.compose_key = compose_key;
+    }
+
+    pub fn set_window_search(&mut self, window_search: fn(&[String]) -> Option<String>) {
+        self.window_search = window_search;
+    }
+
+    pub fn set_led_state(&mut self, led_state: fn(LockKey) -> Option<bool>) {
+        self.led_state = led_state;
+    }
+
+    pub fn set_max_wait(&mut self, max_wait: Duration) {
+        self.max_wait = max_wait;
+    }
+
+    pub fn set_clock(&mut self, clock: fn() -> Instant) {
+        self.clock = clock;
+    }
+
+    pub fn set_window_groups(&mut self, window_groups: HashMap<String, Vec<WindowCriteria>>) {
+        self.window_groups = window_groups;
+    }
+
+    pub fn set_variables(&mut self, variables: HashMap<String, String>) {
+        self.variables = variables;
+    }
+
+    pub fn set_title_match_mode(&mut self, title_match_mode: TitleMatchMode) {
+        self.title_match_mode = title_match_mode;
+    }
+
+    pub fn set_title_regexes(&mut self, title_regexes: HashMap<String, Regex>) {
+        self.title_regexes = title_regexes;
+    }
+
+    pub fn set_held_keys(&mut self, held_keys: impl IntoIterator<Item = Key>) {
+        self.held_keys = held_keys.into_iter().collect();
+    }
+
+    // AHK's Ctrl, Alt, Shift and Win parse as the left key, but either side counts as held
+    fn key_held(&self, key: Key) -> bool {
+        let other_side = match key {
+            Key::KEY_LEFTCTRL => Key::KEY_RIGHTCTRL,
+            Key::KEY_LEFTALT => Key::KEY_RIGHTALT,
+            Key::KEY_LEFTSHIFT => Key::KEY_RIGHTSHIFT,
+            Key::KEY_LEFTMETA => Key::KEY_RIGHTMETA,
+            _ => key,
+        };
+        self.held_keys.contains(&key) || self.held_keys.contains(&other_side)
+    }
+
+    // keypress_delay plus a uniform random jitter, or None when both are zero
+    fn typing_delay(&mut self) -> Option<Duration> {
+        if self.type_jitter.is_zero() {
+            return Some(self.keypress_delay).filter(|delay| !delay.is_zero());
+        }
+        let jitter_ms = self.rng.u64(0..=self.type_jitter.as_millis() as u64);
+        Some(self.keypress_delay + Duration::from_millis(jitter_ms))
+    }
+
     pub fn set_virtual_modifiers(&mut self, modifiers: &[Key]) {
         self.active_virtual_modifiers = modifiers.iter().copied().collect();
         eprintln!("DEBUG: Set active virtual modifiers: {:?}", self.active_virtual_modifiers);
@@ -31,11 +190,107 @@ impl<'a> AhkInterpreter<'a> {
 
     pub fn execute(&mut self, action: &AhkAction) -> Result<Vec<Action>, Box<dyn Error>> {
         let mut actions = Vec::new();
-        self.execute_into(action, &mut actions)?;
+        self.execute_into(action, &mut actions, false)?;
+        self.release_sent_modifiers(&mut actions);
         Ok(actions)
     }
 
-    fn execute_into(&mut self, action: &AhkAction, actions: &mut Vec<Action>) -> Result<(), Box<dyn Error>> {
+    // Like execute, but instead of blocking on a WinWait/WinWaitActive/KeyWait it stops there and
+    // returns the wait with everything after it, for the caller to resume() later.
+    pub fn execute_deferred(
+        &mut self,
+        action: &AhkAction,
+    ) -> Result<(Vec<Action>, Option<PendingWait>), Box<dyn Error>> {
+        let mut actions = Vec::new();
+        let pending = self.execute_into(action, &mut actions, true)?;
+        let pending = self.finish(pending, &mut actions);
+        Ok((actions, pending))
+    }
+
+    // Checks a pending wait once. Until it resolves or times out, it comes back unchanged apart
+    // from its next check; after that the queued actions run (and may stop at another wait).
+    pub fn resume(&mut self, mut pending: PendingWait) -> Result<(Vec<Action>, Option<PendingWait>), Box<dyn Error>> {
+        let resolved = match &pending.kind {
+            WaitKind::Active(criteria) => self.window_matches(criteria).unwrap_or(false),
+            WaitKind::Exists(criteria) => self.window_exists(criteria),
+            WaitKind::Key { key, pressed } => self.key_held(*key) == *pressed,
+        };
+        let now = (self.clock)();
+        if !resolved && now < pending.deadline {
+            pending.next_check = now + WAIT_POLL_INTERVAL;
+            return Ok((vec![], Some(pending)));
+        }
+        if !resolved && pending.capped {
+            warn!("Wait for {:?} gave up after max_wait_ms ({:?})", pending.kind, self.max_wait);
+        } else if !resolved {
+            eprintln!("DEBUG: Wait for {:?} timed out", pending.kind);
+        }
+
+        self.sent_modifiers = std::mem::take(&mut pending.sent_modifiers);
+        let mut actions = Vec::new();
+        let pending = self.execute_sequence(&pending.rest, &mut actions, true)?;
+        let pending = self.finish(pending, &mut actions);
+        Ok((actions, pending))
+    }
+
+    // A pending wait takes the modifiers still down along; without one the actions are done, so
+    // they're released rather than left stuck. A `{Blind}` Send's modifiers aren't tracked.
+    fn finish(&mut self, pending: Option<PendingWait>, actions: &mut Vec<Action>) -> Option<PendingWait> {
+        match pending {
+            Some(mut pending) => {
+                pending.sent_modifiers = std::mem::take(&mut self.sent_modifiers);
+                Some(pending)
+            }
+            None => {
+                self.release_sent_modifiers(actions);
+                None
+            }
+        }
+    }
+
+    fn release_sent_modifiers(&mut self, actions: &mut Vec<Action>) {
+        for key in std::mem::take(&mut self.sent_modifiers) {
+            eprintln!("DEBUG: Releasing {:?} a Send left down", key);
+            actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)));
+        }
+    }
+
+    fn track_sent_modifiers(&mut self, sent: &[Action], blind: bool) {
+        for action in sent {
+            let Action::KeyEvent(event) = action else { continue };
+            if !MODIFIER_KEYS.contains(&event.key) || self.active_virtual_modifiers.contains(&event.key) {
+                continue;
+            }
+            match event.value() {
+                PRESS if !blind && !self.sent_modifiers.contains(&event.key) => self.sent_modifiers.push(event.key),
+                RELEASE => self.sent_modifiers.retain(|key| *key != event.key),
+                _ => {}
+            }
+        }
+    }
+
+    // Runs `sequence` in order; a deferred wait inside it takes the remaining actions along
+    fn execute_sequence(
+        &mut self,
+        sequence: &[AhkAction],
+        actions: &mut Vec<Action>,
+        defer_waits: bool,
+    ) -> Result<Option<PendingWait>, Box<dyn Error>> {
+        for (i, action) in sequence.iter().enumerate() {
+            if let Some(mut pending) = self.execute_into(action, actions, defer_waits)? {
+                pending.rest.extend_from_slice(&sequence[i + 1..]);
+                return Ok(Some(pending));
+            }
+        }
+        Ok(None)
+    }
+
+    fn execute_into(
+        &mut self,
+        action: &AhkAction,
+        actions: &mut Vec<Action>,
+        defer_waits: bool,
+    ) -> Result<Option<PendingWait>, Box<dyn Error>> {
         match action {
             AhkAction::Run(parts) => {
                 let mut cmd = Vec::new();
@@ -53,18 +308,37 @@ impl<'a> AhkInterpreter<'a> {
             AhkAction::Send(keys) => {
                 eprintln!("DEBUG INTERPRETER: Converting Send('{}') with virtual modifiers: {:?}", 
                     keys, self.active_virtual_modifiers);
-                
-                for modifier in &self.active_virtual_modifiers {
-                    eprintln!("DEBUG: Releasing virtual modifier: {:?}", modifier);
-                    actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Release)));
-                }
-                
+
+                let (blind, keys) = split_blind(keys);
+                let mut sent = Vec::new();
                 let send_actions = self.convert_send_to_actions(keys);
-                actions.extend(send_actions);
-                
-                for modifier in &self.active_virtual_modifiers {
-                    eprintln!("DEBUG: Re-pressing virtual modifier: {:?}", modifier);
-                    actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Press)));
+                self.track_sent_modifiers(&send_actions, blind);
+                if blind {
+                    // Type with whatever is held, without pressing or releasing the held keys
+                    sent.extend(send_actions.into_iter().filter(|action| match action {
+                        Action::KeyEvent(event) => !self.active_virtual_modifiers.contains(&event.key),
+                        _ => true,
+                    }));
+                } else {
+                    for modifier in &self.active_virtual_modifiers {
+                        eprintln!("DEBUG: Releasing virtual modifier: {:?}", modifier);
+                        sent.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Release)));
+                    }
+
+                    sent.extend(send_actions);
+
+                    for modifier in &self.active_virtual_modifiers {
+                        eprintln!("DEBUG: Re-pressing virtual modifier: {:?}", modifier);
+                        sent.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Press)));
+                    }
+                }
+                if self.send_level > 0 {
+                    actions.push(Action::SendLevel {
+                        level: self.send_level,
+                        actions: sent,
+                    });
+                } else {
+                    actions.extend(sent);
                 }
             }
 
@@ -87,6 +361,18 @@ impl<'a> AhkInterpreter<'a> {
                 actions.push(Action::Delay(Duration::from_millis(*ms)));
             }
 
+            AhkAction::SetKeyDelay(ms) => {
+                self.keypress_delay = Duration::from_millis(*ms);
+            }
+
+            AhkAction::SendLevel(level) => {
+                self.send_level = *level;
+            }
+
+            AhkAction::SetTitleMatchMode(mode) => {
+                self.title_match_mode = *mode;
+            }
+
             AhkAction::Shell(script) => {
                 actions.push(Action::Command(vec![
                     "/bin/sh".to_string(),
@@ -96,18 +382,54 @@ impl<'a> AhkInterpreter<'a> {
             }
 
             AhkAction::Block(block_actions) => {
-                for block_action in block_actions {
-                    self.execute_into(block_action, actions)?;
-                }
+                return self.execute_sequence(block_actions, actions, defer_waits);
+            }
+
+            AhkAction::SendExpression(expr) => {
+                let keys = expression::evaluate(expr, &self.variables).unwrap_or_else(|e| {
+                    warn!("Sending {expr} as text: {e}");
+                    expression::literal(expr)
+                });
+                return self.execute_into(&AhkAction::Send(keys), actions, defer_waits);
+            }
+
+            AhkAction::WinGet { target_var, what, criteria } => {
+                let value = self.win_get(*what, criteria.as_ref()).unwrap_or_else(|| {
+                    warn!("No window for WinGet {what:?} of {criteria:?}, setting {target_var} to \"\"");
+                    String::new()
+                });
+                self.variables.insert(target_var.to_lowercase(), value);
             }
 
             AhkAction::WinActivate(criteria) => {
-                let cmd = self.build_kdotool_command("windowactivate", criteria);
+                let Some(criteria) = self.resolve_group(criteria) else {
+                    return Ok(None);
+                };
+                let cmd = self.build_kdotool_command("windowactivate", &criteria);
                 actions.push(Action::Command(cmd));
             }
 
             AhkAction::WinClose(criteria) => {
-                let cmd = self.build_kdotool_command("windowclose", criteria);
+                let Some(criteria) = self.resolve_group(criteria) else {
+                    return Ok(None);
+                };
+                let cmd = self.build_kdotool_command("windowclose", &criteria);
+                actions.push(Action::Command(cmd));
+            }
+
+            AhkAction::WinMaximize(criteria) => {
+                let Some(criteria) = self.resolve_group(criteria) else {
+                    return Ok(None);
+                };
+                let cmd = kdotool_maximize(self.build_kdotool_command("windowstate", &criteria));
+                actions.push(Action::Command(cmd));
+            }
+
+            AhkAction::WinMinimize(criteria) => {
+                let Some(criteria) = self.resolve_group(criteria) else {
+                    return Ok(None);
+                };
+                let cmd = self.build_kdotool_command("windowminimize", &criteria);
                 actions.push(Action::Command(cmd));
             }
 
@@ -119,57 +441,172 @@ impl<'a> AhkInterpreter<'a> {
                 
                 if is_active {
                     eprintln!("DEBUG INTERPRETER: Executing then_actions ({} actions)", then_actions.len());
-                    for then_action in then_actions {
-                        self.execute_into(then_action, actions)?;
-                    }
+                    return self.execute_sequence(then_actions, actions, defer_waits);
                 } else if let Some(else_actions) = else_actions {
                     eprintln!("DEBUG INTERPRETER: Executing else_actions ({} actions)", else_actions.len());
-                    for else_action in else_actions {
-                        self.execute_into(else_action, actions)?;
+                    return self.execute_sequence(else_actions, actions, defer_waits);
+                }
+            }
+
+            AhkAction::SetLockState { lock, state } => {
+                let target = match state {
+                    LockState::On | LockState::AlwaysOn => Some(true),
+                    LockState::Off | LockState::AlwaysOff => Some(false),
+                    LockState::Toggle => None,
+                };
+                // Only a toggle is possible through the virtual keyboard, so On/Off need the LED
+                let toggle = match (target, (self.led_state)(*lock)) {
+                    (None, _) => true,
+                    (Some(on), Some(lit)) => on != lit,
+                    (Some(_), None) => {
+                        warn!("Can't read the {:?} LED, leaving it as is", lock);
+                        false
                     }
+                };
+                if toggle {
+                    actions.push(Action::KeyEvent(KeyEvent::new(lock.key(), KeyValue::Press)));
+                    actions.push(Action::KeyEvent(KeyEvent::new(lock.key(), KeyValue::Release)));
                 }
             }
 
+            AhkAction::WinWaitActive { criteria, timeout_ms } if defer_waits => {
+                let kind = WaitKind::Active(criteria.clone());
+                return Ok(Some(PendingWait::new(kind, *timeout_ms, self.max_wait, (self.clock)())));
+            }
+
+            AhkAction::WinWait { criteria, timeout_ms } if defer_waits => {
+                let kind = WaitKind::Exists(criteria.clone());
+                return Ok(Some(PendingWait::new(kind, *timeout_ms, self.max_wait, (self.clock)())));
+            }
+
+            AhkAction::KeyWait { key, wait_for_release, timeout_ms } if defer_waits => {
+                let kind = WaitKind::Key { key: *key, pressed: !wait_for_release };
+                return Ok(Some(PendingWait::new(kind, *timeout_ms, self.max_wait, (self.clock)())));
+            }
+
+            // The held keys only change between events, so blocking here would never see them change
+            AhkAction::KeyWait { key, .. } => {
+                warn!("KeyWait {:?} only waits when deferred, skipping it", key);
+            }
+
             AhkAction::WinWaitActive { criteria, timeout_ms } => {
-                let poll_interval_ms = 50;
-                
-                if let Some(timeout) = timeout_ms {
-                    let max_attempts = timeout / poll_interval_ms;
-                    eprintln!("DEBUG: WinWaitActive - waiting for window (timeout: {}ms)", timeout);
-                    
-                    for attempt in 0..max_attempts {
-                        if self.check_window_active(criteria).unwrap_or(false) {
-                            eprintln!("DEBUG: WinWaitActive - window became active after {} ms", attempt * poll_interval_ms);
-                            return Ok(());
-                        }
-                        std::thread::sleep(std::time::Duration::from_millis(poll_interval_ms));
-                    }
-                    
-                    eprintln!("DEBUG: WinWaitActive - timed out after {} ms", timeout);
-                } else {
-                    eprintln!("DEBUG: WinWaitActive - waiting for window (no timeout)");
-                    let mut elapsed = 0u64;
-                    
-                    loop {
-                        if self.check_window_active(criteria).unwrap_or(false) {
-                            eprintln!("DEBUG: WinWaitActive - window became active after {} ms", elapsed);
-                            break;
-                        }
-                        std::thread::sleep(std::time::Duration::from_millis(poll_interval_ms));
-                        elapsed += poll_interval_ms;
-                    }
+                self.wait_for_window("WinWaitActive", criteria, *timeout_ms, |this, criteria| {
+                    this.check_window_active(criteria).unwrap_or(false)
+                });
+            }
+
+            AhkAction::WinWait { criteria, timeout_ms } => {
+                self.wait_for_window("WinWait", criteria, *timeout_ms, Self::window_exists);
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Polls `check` until it holds or `timeout_ms` runs out (None waits forever)
+    fn wait_for_window(
+        &mut self,
+        name: &str,
+        criteria: &WindowCriteria,
+        timeout_ms: Option<u64>,
+        check: fn(&mut Self, &WindowCriteria) -> bool,
+    ) {
+        let poll_interval_ms = 50;
+        
+        if let Some(timeout) = timeout_ms {
+            let max_attempts = timeout / poll_interval_ms;
+            eprintln!("DEBUG: {} - waiting for window (timeout: {}ms)", name, timeout);
+            
+            for attempt in 0..max_attempts {
+                if check(self, criteria) {
+                    eprintln!("DEBUG: {} - window found after {} ms", name, attempt * poll_interval_ms);
+                    return;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(poll_interval_ms));
+            }
+            
+            eprintln!("DEBUG: {} - timed out after {} ms", name, timeout);
+        } else {
+            eprintln!("DEBUG: {} - waiting for window (no timeout)", name);
+            let mut elapsed = 0u64;
+            let max_wait_ms = self.max_wait.as_millis() as u64;
+            
+            loop {
+                if check(self, criteria) {
+                    eprintln!("DEBUG: {} - window found after {} ms", name, elapsed);
+                    break;
+                }
+                if elapsed >= max_wait_ms {
+                    warn!("{} - gave up after max_wait_ms ({} ms)", name, max_wait_ms);
+                    break;
                 }
+                std::thread::sleep(std::time::Duration::from_millis(poll_interval_ms));
+                elapsed += poll_interval_ms;
             }
         }
+    }
 
-        Ok(())
+    // The active window's info, unless `criteria` picks another window, which kdotool looks up
+    fn win_get(&mut self, what: WinGetInfo, criteria: Option<&WindowCriteria>) -> Option<String> {
+        let criteria = match criteria {
+            Some(criteria) if !self.window_matches(criteria).unwrap_or(false) => self.resolve_group(criteria)?,
+            _ => {
+                return match what {
+                    WinGetInfo::Title => self.wm_client.current_window(),
+                    WinGetInfo::Class => self.wm_client.current_application(),
+                    WinGetInfo::Pid => self.wm_client.current_pid().map(|pid| pid.to_string()),
+                };
+            }
+        };
+        let mut cmd = self.kdotool_search(&criteria);
+        cmd.push(match what {
+            WinGetInfo::Title => "getwindowname".to_string(),
+            WinGetInfo::Class => "getwindowclassname".to_string(),
+            WinGetInfo::Pid => "getwindowpid".to_string(),
+        });
+        let output = (self.window_search)(&cmd)?;
+        output.lines().next().filter(|line| !line.is_empty()).map(str::to_string)
+    }
+
+    // Without a search result (e.g. no kdotool), an active window at least exists
+    fn window_exists(&mut self, criteria: &WindowCriteria) -> bool {
+        if let WindowCriteria::Group(name) = criteria {
+            return self.group_members(name).iter().any(|member| self.window_exists(member));
+        }
+        let cmd = self.kdotool_search(criteria);
+        match (self.window_search)(&cmd) {
+            Some(window_ids) => !window_ids.trim().is_empty(),
+            None => self.window_matches(criteria).unwrap_or(false),
+        }
+    }
+
+    // A group's members, which GroupAdd keeps from being groups themselves
+    fn group_members(&self, name: &str) -> Vec<WindowCriteria> {
+        self.window_groups.get(&name.to_lowercase()).cloned().unwrap_or_else(|| {
+            warn!("ahk_group {name} has no GroupAdd");
+            vec![]
+        })
+    }
+
+    // Window commands on a group act on its first member with a window, or on nothing
+    fn resolve_group(&mut self, criteria: &WindowCriteria) -> Option<WindowCriteria> {
+        match criteria {
+            WindowCriteria::Group(name) => {
+                self.group_members(name).into_iter().find(|member| self.window_exists(member))
+            }
+            criteria => Some(criteria.clone()),
+        }
     }
 
     fn check_window_active(&mut self, criteria: &WindowCriteria) -> Result<bool, Box<dyn Error>> {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        self.window_matches(criteria)
+    }
+
+    // The active window check itself, without check_window_active's settle delay
+    fn window_matches(&mut self, criteria: &WindowCriteria) -> Result<bool, Box<dyn Error>> {
         self.application_cache = None;
         self.title_cache = None;
-        
-        std::thread::sleep(std::time::Duration::from_millis(50));
 
         match criteria {
             WindowCriteria::Exe(exe) => {
@@ -246,142 +683,214 @@ WindowCriteria::Title(title) => {
     }
     
     let window_title = window_title.unwrap_or_default();
-    eprintln!("DEBUG: Checking if '{}' == '{}'", window_title, title);
-    Ok(window_title == *title)
+    eprintln!("DEBUG: Checking if '{}' matches '{}' ({:?})", window_title, title, self.title_match_mode);
+    Ok(self.title_match_mode.matches(title, self.title_regexes.get(title), &window_title))
+}
+
+WindowCriteria::Pid(pid) => {
+    let window_pid = self.wm_client.current_pid();
+    
+    #[cfg(feature = "kde")]
+    let window_pid = window_pid.or_else(|| {
+        std::process::Command::new("kdotool")
+            .arg("getactivewindow")
+            .arg("getwindowpid")
+            .output()
+            .ok()
+            .and_then(|out| {
+                if out.status.success() {
+                    String::from_utf8_lossy(&out.stdout).trim().parse().ok()
+                } else {
+                    None
+                }
+            })
+    });
+    
+    eprintln!("DEBUG: Checking if {:?} == {}", window_pid, pid);
+    Ok(window_pid == Some(*pid))
+}
+
+WindowCriteria::Group(name) => {
+    for member in self.group_members(name) {
+        if self.window_matches(&member)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
 }
         }
     }
 
-    fn convert_send_to_actions(&self, send_str: &str) -> Vec<Action> {
-        use crate::ahk::send_parser::{parse_send_string, SendToken};
-        use crate::event::{KeyEvent, KeyValue};
+    fn convert_send_to_actions(&mut self, send_str: &str) -> Vec<Action> {
+        use crate::ahk::send_parser::{parse_send_string, MouseEvent, SendToken};
+        use crate::event::{KeyEvent, KeyValue, RelativeEvent};
+        use evdev::RelativeAxisCode;
         
         let tokens = parse_send_string(send_str);
         let mut actions = Vec::new();
+        let mut typed = false;
         
         for token in tokens {
             match token {
-                SendToken::Text(text) => {
-                    for ch in text.chars() {
-                        if let Some((key, needs_shift)) = self.char_to_key_with_shift(ch) {
-                            if needs_shift {
-                                actions.push(Action::KeyEvent(KeyEvent::new(
-                                    Key::KEY_LEFTSHIFT, 
-                                    KeyValue::Press
-                                )));
+                SendToken::Text(text) => self.push_text(&text, &mut actions, &mut typed),
+                SendToken::Unicode(c) => self.push_text(&c.to_string(), &mut actions, &mut typed),
+                SendToken::Delay(delay_ms) => actions.push(Action::Delay(Duration::from_millis(delay_ms))),
+                SendToken::Key { key, modifiers } => {
+                    self.push_typing_delay(&mut actions, &mut typed);
+                    for modifier in &modifiers {
+                        actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Press)));
+                    }
+                    actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)));
+                    actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)));
+                    for modifier in modifiers.iter().rev() {
+                        actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Release)));
+                    }
+                }
+                SendToken::Mouse { events, modifiers } => {
+                    self.push_typing_delay(&mut actions, &mut typed);
+                    for modifier in &modifiers {
+                        actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Press)));
+                    }
+                    for event in events {
+                        match event {
+                            MouseEvent::Press(button) => {
+                                actions.push(Action::KeyEvent(KeyEvent::new(button, KeyValue::Press)))
+                            }
+                            MouseEvent::Release(button) => {
+                                actions.push(Action::KeyEvent(KeyEvent::new(button, KeyValue::Release)))
                             }
-                            
-                            actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)));
-                            actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)));
-                            
-                            if needs_shift {
-                                actions.push(Action::KeyEvent(KeyEvent::new(
-                                    Key::KEY_LEFTSHIFT, 
-                                    KeyValue::Release
-                                )));
+                            MouseEvent::Wheel { axis, value } => {
+                                actions.push(Action::RelativeEvent(RelativeEvent::new_with(axis.0, value)))
+                            }
+                            MouseEvent::Move { x, y } => {
+                                for (axis, value) in [(RelativeAxisCode::REL_X, x), (RelativeAxisCode::REL_Y, y)] {
+                                    if value != 0 {
+                                        actions.push(Action::RelativeEvent(RelativeEvent::new_with(axis.0, value)));
+                                    }
+                                }
                             }
                         }
                     }
+                    for modifier in modifiers.iter().rev() {
+                        actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Release)));
+                    }
                 }
-                SendToken::Key { key, modifiers } => {
+                SendToken::KeyHold { key, modifiers, duration_ms } => {
+                    self.push_typing_delay(&mut actions, &mut typed);
                     for modifier in &modifiers {
                         actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Press)));
                     }
                     actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)));
+                    actions.push(Action::Delay(Duration::from_millis(duration_ms)));
                     actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)));
                     for modifier in modifiers.iter().rev() {
                         actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Release)));
                     }
                 }
+                SendToken::KeyState { key, modifiers, pressed } => {
+                    self.push_typing_delay(&mut actions, &mut typed);
+                    for modifier in &modifiers {
+                        actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Press)));
+                    }
+                    let value = if pressed { KeyValue::Press } else { KeyValue::Release };
+                    actions.push(Action::KeyEvent(KeyEvent::new(key, value)));
+                    for modifier in modifiers.iter().rev() {
+                        actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Release)));
+                    }
+                }
+                SendToken::KeyRepeat { key, modifiers, count } => {
+                    self.push_typing_delay(&mut actions, &mut typed);
+                    for modifier in &modifiers {
+                        actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Press)));
+                    }
+                    for i in 0..count {
+                        if i > 0 {
+                            actions.push(Action::Delay(self.keypress_delay));
+                        }
+                        actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)));
+                        actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)));
+                    }
+                    for modifier in modifiers.iter().rev() {
+                        actions.push(Action::KeyEvent(KeyEvent::new(*modifier, KeyValue::Release)));
+                    }
+                }
             }
         }
         
         actions
     }
 
-    fn char_to_key_with_shift(&self, ch: char) -> Option<(Key, bool)> {
-        match ch {
-            'a'..='z' => {
-                let key = match ch {
-                    'a' => Key::KEY_A, 'b' => Key::KEY_B, 'c' => Key::KEY_C,
-                    'd' => Key::KEY_D, 'e' => Key::KEY_E, 'f' => Key::KEY_F,
-                    'g' => Key::KEY_G, 'h' => Key::KEY_H, 'i' => Key::KEY_I,
-                    'j' => Key::KEY_J, 'k' => Key::KEY_K, 'l' => Key::KEY_L,
-                    'm' => Key::KEY_M, 'n' => Key::KEY_N, 'o' => Key::KEY_O,
-                    'p' => Key::KEY_P, 'q' => Key::KEY_Q, 'r' => Key::KEY_R,
-                    's' => Key::KEY_S, 't' => Key::KEY_T, 'u' => Key::KEY_U,
-                    'v' => Key::KEY_V, 'w' => Key::KEY_W, 'x' => Key::KEY_X,
-                    'y' => Key::KEY_Y, 'z' => Key::KEY_Z,
-                    _ => return None,
-                };
-                Some((key, false))
-            }
-            'A'..='Z' => {
-                let key = match ch {
-                    'A' => Key::KEY_A, 'B' => Key::KEY_B, 'C' => Key::KEY_C,
-                    'D' => Key::KEY_D, 'E' => Key::KEY_E, 'F' => Key::KEY_F,
-                    'G' => Key::KEY_G, 'H' => Key::KEY_H, 'I' => Key::KEY_I,
-                    'J' => Key::KEY_J, 'K' => Key::KEY_K, 'L' => Key::KEY_L,
-                    'M' => Key::KEY_M, 'N' => Key::KEY_N, 'O' => Key::KEY_O,
-                    'P' => Key::KEY_P, 'Q' => Key::KEY_Q, 'R' => Key::KEY_R,
-                    'S' => Key::KEY_S, 'T' => Key::KEY_T, 'U' => Key::KEY_U,
-                    'V' => Key::KEY_V, 'W' => Key::KEY_W, 'X' => Key::KEY_X,
-                    'Y' => Key::KEY_Y, 'Z' => Key::KEY_Z,
-                    _ => return None,
-                };
-                Some((key, true))
-            }
-            '0' => Some((Key::KEY_0, false)),
-            '1' => Some((Key::KEY_1, false)),
-            '2' => Some((Key::KEY_2, false)),
-            '3' => Some((Key::KEY_3, false)),
-            '4' => Some((Key::KEY_4, false)),
-            '5' => Some((Key::KEY_5, false)),
-            '6' => Some((Key::KEY_6, false)),
-            '7' => Some((Key::KEY_7, false)),
-            '8' => Some((Key::KEY_8, false)),
-            '9' => Some((Key::KEY_9, false)),
-            '!' => Some((Key::KEY_1, true)),
-            '@' => Some((Key::KEY_2, true)),
-            '#' => Some((Key::KEY_3, true)),
-            '$' => Some((Key::KEY_4, true)),
-            '%' => Some((Key::KEY_5, true)),
-            '^' => Some((Key::KEY_6, true)),
-            '&' => Some((Key::KEY_7, true)),
-            '*' => Some((Key::KEY_8, true)),
-            '(' => Some((Key::KEY_9, true)),
-            ')' => Some((Key::KEY_0, true)),
-            ' ' => Some((Key::KEY_SPACE, false)),
-            '.' => Some((Key::KEY_DOT, false)),
-            ',' => Some((Key::KEY_COMMA, false)),
-            ';' => Some((Key::KEY_SEMICOLON, false)),
-            '/' => Some((Key::KEY_SLASH, false)),
-            '\'' => Some((Key::KEY_APOSTROPHE, false)),
-            '-' => Some((Key::KEY_MINUS, false)),
-            '=' => Some((Key::KEY_EQUAL, false)),
-            '[' => Some((Key::KEY_LEFTBRACE, false)),
-            ']' => Some((Key::KEY_RIGHTBRACE, false)),
-            '\\' => Some((Key::KEY_BACKSLASH, false)),
-            '`' => Some((Key::KEY_GRAVE, false)),
-            ':' => Some((Key::KEY_SEMICOLON, true)),
-            '?' => Some((Key::KEY_SLASH, true)),
-            '"' => Some((Key::KEY_APOSTROPHE, true)),
-            '_' => Some((Key::KEY_MINUS, true)),
-            '+' => Some((Key::KEY_EQUAL, true)),
-            '{' => Some((Key::KEY_LEFTBRACE, true)),
-            '}' => Some((Key::KEY_RIGHTBRACE, true)),
-            '|' => Some((Key::KEY_BACKSLASH, true)),
-            '~' => Some((Key::KEY_GRAVE, true)),
-            '<' => Some((Key::KEY_COMMA, true)),
-            '>' => Some((Key::KEY_DOT, true)),
-            '\n' => Some((Key::KEY_ENTER, false)),
-            '\t' => Some((Key::KEY_TAB, false)),
-            _ => None,
+    // Text typed as it is, e.g. a hotstring's expansion, without Send's {Key} syntax
+    pub fn type_text(&mut self, text: &str) -> Vec<Action> {
+        let mut actions = vec![];
+        self.push_text(text, &mut actions, &mut false);
+        actions
+    }
+
+    // Each character with its key if it has one, otherwise composed or pasted
+    fn push_text(&mut self, text: &str, actions: &mut Vec<Action>, typed: &mut bool) {
+        // Characters with neither a key nor a compose sequence, pasted together
+        let mut unmapped = String::new();
+        // Any line ending is one Enter
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        for ch in text.chars() {
+            let keys = match char_to_key_shift(ch) {
+                Some(key) => vec![key],
+                None => match self.compose_keys(ch) {
+                    Some(keys) => keys,
+                    None => {
+                        unmapped.push(ch);
+                        continue;
+                    }
+                },
+            };
+            if !unmapped.is_empty() {
+                actions.push(Self::paste_action(std::mem::take(&mut unmapped)));
+            }
+            self.push_typing_delay(actions, typed);
+            for (key, needs_shift) in keys {
+                if needs_shift {
+                    actions.push(Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)));
+                }
+                actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)));
+                actions.push(Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)));
+                if needs_shift {
+                    actions.push(Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Release)));
+                }
+            }
+        }
+        if !unmapped.is_empty() {
+            actions.push(Self::paste_action(unmapped));
         }
     }
 
-    #[cfg(feature = "kde")]
-    fn build_kdotool_command(&self, action: &str, criteria: &WindowCriteria) -> Vec<String> {
+    // Delay between keystrokes of a Send; nothing before the first one
+    fn push_typing_delay(&mut self, actions: &mut Vec<Action>, typed: &mut bool) {
+        if *typed {
+            if let Some(delay) = self.typing_delay() {
+                actions.push(Action::Delay(delay));
+            }
+        }
+        *typed = true;
+    }
+
+    // The compose key followed by the sequence's keystrokes, if compose is enabled
+    fn compose_keys(&self, ch: char) -> Option<Vec<(Key, bool)>> {
+        let compose_key = self.compose_key?;
+        let sequence = crate::ahk::compose::compose_sequence(ch)?;
+        std::iter::once(Some((compose_key, false)))
+            .chain(sequence.chars().map(char_to_key_shift))
+            .collect()
+    }
+
+    fn paste_action(text: String) -> Action {
+        Action::TextExpansion {
+            trigger_len: 0,
+            replacement: text,
+        }
+    }
+
+    fn kdotool_search(&self, criteria: &WindowCriteria) -> Vec<String> {
         let mut cmd = vec!["kdotool".to_string(), "search".to_string()];
         
         match criteria {
@@ -397,8 +906,22 @@ WindowCriteria::Title(title) => {
                 cmd.push("--classname".to_string());
                 cmd.push(exe.clone());
             }
+            WindowCriteria::Pid(pid) => {
+                cmd.push("--pid".to_string());
+                cmd.push(pid.to_string());
+            }
+            WindowCriteria::Group(name) => {
+                warn!("ahk_group {name} can't be searched for directly; no window matches");
+                return vec!["false".to_string()];
+            }
         }
         
+        cmd
+    }
+
+    #[cfg(feature = "kde")]
+    fn build_kdotool_command(&self, action: &str, criteria: &WindowCriteria) -> Vec<String> {
+        let mut cmd = self.kdotool_search(criteria);
         cmd.push(action.to_string());
         cmd
     }
@@ -408,3 +931,585 @@ WindowCriteria::Title(title) => {
         vec![]
     }
 }
+
+// Lit if any keyboard's LED is, e.g. /sys/class/leds/input3::capslock; None without such LEDs
+fn read_led_state(lock: LockKey) -> Option<bool> {
+    let suffix = format!("::{}", lock.led_name());
+    let mut state = None;
+    for entry in std::fs::read_dir("/sys/class/leds").ok()?.flatten() {
+        if !entry.file_name().to_string_lossy().ends_with(&suffix) {
+            continue;
+        }
+        if let Ok(brightness) = std::fs::read_to_string(entry.path().join("brightness")) {
+            state = Some(state.unwrap_or(false) || brightness.trim() != "0");
+        }
+    }
+    state
+}
+
+// kdotool has no windowmaximize, so a windowstate command sets both maximized states instead
+pub fn kdotool_maximize(mut cmd: Vec<String>) -> Vec<String> {
+    if !cmd.is_empty() {
+        cmd.extend(["--add", "MAXIMIZED_VERT", "--add", "MAXIMIZED_HORZ"].map(String::from));
+    }
+    cmd
+}
+
+fn run_window_search(cmd: &[String]) -> Option<String> {
+    let output = std::process::Command::new(&cmd[0]).args(&cmd[1..]).output().ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AhkInterpreter;
+    use crate::action::Action;
+    use crate::ahk::types::{AhkAction, LockKey, LockState, WinGetInfo, WindowCriteria};
+    use crate::client::{Client, WMClient};
+    use crate::event::{KeyEvent, KeyValue};
+    use crate::tests::{advance_fake_clock, fake_clock, NoWindowClient};
+    use evdev::KeyCode as Key;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    struct PidClient(u32);
+
+    impl Client for PidClient {
+        fn supported(&mut self) -> bool {
+            true
+        }
+        fn current_window(&mut self) -> Option<String> {
+            None
+        }
+        fn current_application(&mut self) -> Option<String> {
+            None
+        }
+        fn current_pid(&mut self) -> Option<u32> {
+            Some(self.0)
+        }
+    }
+
+    struct AppClient(&'static str);
+
+    impl Client for AppClient {
+        fn supported(&mut self) -> bool {
+            true
+        }
+        fn current_window(&mut self) -> Option<String> {
+            None
+        }
+        fn current_application(&mut self) -> Option<String> {
+            Some(self.0.to_string())
+        }
+    }
+
+    #[test]
+    fn test_if_win_active_matches_group_member() {
+        let action = AhkAction::IfWinActive {
+            criteria: WindowCriteria::Group("Browsers".to_string()),
+            then_actions: vec![AhkAction::Send("a".to_string())],
+            else_actions: Some(vec![AhkAction::Send("b".to_string())]),
+        };
+        let run = |application| {
+            let mut wm_client = WMClient::new("app", Box::new(AppClient(application)));
+            let mut interpreter = AhkInterpreter::new(&mut wm_client);
+            let members = vec![WindowCriteria::Exe("firefox".to_string()), WindowCriteria::Exe("chromium".to_string())];
+            interpreter.set_window_groups(HashMap::from([("browsers".to_string(), members)]));
+            format!("{:?}", interpreter.execute(&action).unwrap())
+        };
+
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        let then = format!("{:?}", vec![key(Key::KEY_A, KeyValue::Press), key(Key::KEY_A, KeyValue::Release)]);
+        assert_eq!(run("firefox"), then);
+        assert_eq!(run("chromium"), then);
+        assert_ne!(run("kate"), then);
+    }
+
+    #[test]
+    fn test_if_win_active_matches_pid() {
+        let mut wm_client = WMClient::new("pid", Box::new(PidClient(4242)));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        let action = |pid| AhkAction::IfWinActive {
+            criteria: WindowCriteria::Pid(pid),
+            then_actions: vec![AhkAction::Send("a".to_string())],
+            else_actions: Some(vec![AhkAction::Send("b".to_string())]),
+        };
+
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        assert_eq!(
+            format!("{:?}", interpreter.execute(&action(4242)).unwrap()),
+            format!("{:?}", vec![key(Key::KEY_A, KeyValue::Press), key(Key::KEY_A, KeyValue::Release)])
+        );
+        assert_eq!(
+            format!("{:?}", interpreter.execute(&action(1)).unwrap()),
+            format!("{:?}", vec![key(Key::KEY_B, KeyValue::Press), key(Key::KEY_B, KeyValue::Release)])
+        );
+    }
+
+    struct TitleClient(&'static str);
+
+    impl Client for TitleClient {
+        fn supported(&mut self) -> bool {
+            true
+        }
+        fn current_window(&mut self) -> Option<String> {
+            Some(self.0.to_string())
+        }
+        fn current_application(&mut self) -> Option<String> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_win_get_title_into_variable() {
+        let mut wm_client = WMClient::new("title", Box::new(TitleClient("hi")));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_window_search(|cmd| {
+            assert_eq!(cmd, ["kdotool", "search", "--classname", "foo", "getwindowname"]);
+            Some("ok\n".to_string())
+        });
+        let win_get = |criteria| AhkAction::WinGet {
+            target_var: "Title".to_string(),
+            what: WinGetInfo::Title,
+            criteria,
+        };
+        let block = AhkAction::Block(vec![
+            win_get(None),
+            AhkAction::SendExpression("title".to_string()),
+            win_get(Some(WindowCriteria::Exe("foo".to_string()))),
+            AhkAction::SendExpression(r#"title = "ok" ? "k" : "x""#.to_string()),
+        ]);
+
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        assert_eq!(
+            format!("{:?}", interpreter.execute(&block).unwrap()),
+            format!(
+                "{:?}",
+                vec![
+                    key(Key::KEY_H, KeyValue::Press),
+                    key(Key::KEY_H, KeyValue::Release),
+                    key(Key::KEY_I, KeyValue::Press),
+                    key(Key::KEY_I, KeyValue::Release),
+                    key(Key::KEY_K, KeyValue::Press),
+                    key(Key::KEY_K, KeyValue::Release),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_win_wait_uses_window_search() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_window_search(|cmd| {
+            assert_eq!(cmd, ["kdotool", "search", "--classname", "foo"]);
+            Some("12345\n".to_string())
+        });
+
+        let start = Instant::now();
+        let action = AhkAction::WinWait {
+            criteria: WindowCriteria::Exe("foo".to_string()),
+            timeout_ms: Some(5000),
+        };
+        assert!(interpreter.execute(&action).unwrap().is_empty());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_deferred_win_wait_queues_rest_of_block() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_window_search(|_| Some(String::new()));
+
+        let block = AhkAction::Block(vec![
+            AhkAction::Send("a".to_string()),
+            AhkAction::WinWait {
+                criteria: WindowCriteria::Exe("foo".to_string()),
+                timeout_ms: Some(0),
+            },
+            AhkAction::Send("b".to_string()),
+        ]);
+        let (actions, pending) = interpreter.execute_deferred(&block).unwrap();
+
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        assert_eq!(
+            format!("{actions:?}"),
+            format!("{:?}", vec![key(Key::KEY_A, KeyValue::Press), key(Key::KEY_A, KeyValue::Release)])
+        );
+        let (actions, pending) = interpreter.resume(pending.unwrap()).unwrap();
+        assert!(pending.is_none());
+        assert_eq!(
+            format!("{actions:?}"),
+            format!("{:?}", vec![key(Key::KEY_B, KeyValue::Press), key(Key::KEY_B, KeyValue::Release)])
+        );
+    }
+
+    #[test]
+    fn test_run_max_maximizes_window_once_it_appears() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_window_search(|_| Some("1\n".to_string()));
+
+        let config = crate::ahk::parser::AhkParser::new().parse_file("^j::Run \"firefox\",, Max\n").unwrap();
+        let (actions, pending) = interpreter.execute_deferred(&config.hotkeys[0].action).unwrap();
+        assert_eq!(
+            format!("{actions:?}"),
+            format!("{:?}", vec![Action::Command(vec!["/bin/sh".into(), "-c".into(), "firefox".into()])])
+        );
+
+        let (actions, pending) = interpreter.resume(pending.unwrap()).unwrap();
+        assert!(pending.is_none());
+        let kdotool = |args: &[&str]| -> Vec<String> {
+            if !cfg!(feature = "kde") {
+                return vec![];
+            }
+            ["kdotool", "search", "--classname", "firefox"].iter().chain(args).map(|s| s.to_string()).collect()
+        };
+        assert_eq!(
+            format!("{actions:?}"),
+            format!(
+                "{:?}",
+                vec![
+                    Action::Command(kdotool(&["windowactivate"])),
+                    Action::Command(kdotool(&["windowstate", "--add", "MAXIMIZED_VERT", "--add", "MAXIMIZED_HORZ"])),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_infinite_win_wait_active_gives_up_at_max_wait() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_clock(fake_clock);
+        interpreter.set_max_wait(Duration::from_secs(30));
+
+        let block = AhkAction::Block(vec![
+            AhkAction::WinWaitActive {
+                criteria: WindowCriteria::Exe("foo".to_string()),
+                timeout_ms: None,
+            },
+            AhkAction::Send("b".to_string()),
+        ]);
+        let (_, pending) = interpreter.execute_deferred(&block).unwrap();
+
+        advance_fake_clock(29_999);
+        let (actions, pending) = interpreter.resume(pending.unwrap()).unwrap();
+        assert!(actions.is_empty());
+
+        advance_fake_clock(1);
+        let (actions, pending) = interpreter.resume(pending.unwrap()).unwrap();
+        assert!(pending.is_none());
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        assert_eq!(
+            format!("{actions:?}"),
+            format!("{:?}", vec![key(Key::KEY_B, KeyValue::Press), key(Key::KEY_B, KeyValue::Release)])
+        );
+    }
+
+    #[test]
+    fn test_key_wait_resolves_when_key_is_released() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_held_keys([Key::KEY_RIGHTCTRL]);
+
+        let block = AhkAction::Block(vec![
+            AhkAction::KeyWait {
+                key: Key::KEY_LEFTCTRL,
+                wait_for_release: true,
+                timeout_ms: None,
+            },
+            AhkAction::Send("b".to_string()),
+        ]);
+        let (actions, pending) = interpreter.execute_deferred(&block).unwrap();
+        assert!(actions.is_empty());
+        let (actions, pending) = interpreter.resume(pending.unwrap()).unwrap();
+        assert!(actions.is_empty());
+
+        interpreter.set_held_keys([]);
+        let (actions, pending) = interpreter.resume(pending.unwrap()).unwrap();
+        assert!(pending.is_none());
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        assert_eq!(
+            format!("{actions:?}"),
+            format!("{:?}", vec![key(Key::KEY_B, KeyValue::Press), key(Key::KEY_B, KeyValue::Release)])
+        );
+    }
+
+    #[test]
+    fn test_win_wait_times_out_without_window() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_window_search(|_| Some(String::new()));
+
+        let start = Instant::now();
+        let action = AhkAction::WinWait {
+            criteria: WindowCriteria::Exe("foo".to_string()),
+            timeout_ms: Some(100),
+        };
+        interpreter.execute(&action).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_set_lock_state() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_led_state(|lock| {
+            assert_eq!(lock, LockKey::CapsLock);
+            Some(true)
+        });
+        let set_caps_lock = |state| AhkAction::SetLockState {
+            lock: LockKey::CapsLock,
+            state,
+        };
+
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        let toggle = vec![
+            key(Key::KEY_CAPSLOCK, KeyValue::Press),
+            key(Key::KEY_CAPSLOCK, KeyValue::Release),
+        ];
+        let actions = interpreter.execute(&set_caps_lock(LockState::Toggle)).unwrap();
+        assert_eq!(format!("{toggle:?}"), format!("{actions:?}"));
+        // Already lit
+        assert!(interpreter.execute(&set_caps_lock(LockState::On)).unwrap().is_empty());
+        let actions = interpreter.execute(&set_caps_lock(LockState::AlwaysOff)).unwrap();
+        assert_eq!(format!("{toggle:?}"), format!("{actions:?}"));
+    }
+
+    #[test]
+    fn test_send_preserves_case() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+
+        let actions = interpreter.execute(&AhkAction::Send("Hi".to_string())).unwrap();
+        let expected = vec![
+            key(Key::KEY_LEFTSHIFT, KeyValue::Press),
+            key(Key::KEY_H, KeyValue::Press),
+            key(Key::KEY_H, KeyValue::Release),
+            key(Key::KEY_LEFTSHIFT, KeyValue::Release),
+            key(Key::KEY_I, KeyValue::Press),
+            key(Key::KEY_I, KeyValue::Release),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+
+        // With a modifier the letter becomes a key combo, which must keep the capital too
+        let actions = interpreter.execute(&AhkAction::Send("^H".to_string())).unwrap();
+        let expected = vec![
+            key(Key::KEY_LEFTCTRL, KeyValue::Press),
+            key(Key::KEY_LEFTSHIFT, KeyValue::Press),
+            key(Key::KEY_H, KeyValue::Press),
+            key(Key::KEY_H, KeyValue::Release),
+            key(Key::KEY_LEFTSHIFT, KeyValue::Release),
+            key(Key::KEY_LEFTCTRL, KeyValue::Release),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+    }
+
+    #[test]
+    fn test_send_blind() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_virtual_modifiers(&[Key::KEY_LEFTCTRL]);
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+
+        // A normal Send lets go of the held Ctrl around the keys
+        let actions = interpreter.execute(&AhkAction::Send("a".to_string())).unwrap();
+        let expected = vec![
+            key(Key::KEY_LEFTCTRL, KeyValue::Release),
+            key(Key::KEY_A, KeyValue::Press),
+            key(Key::KEY_A, KeyValue::Release),
+            key(Key::KEY_LEFTCTRL, KeyValue::Press),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+
+        // {Blind} keeps it held, and doesn't press or release it for ^ either
+        let actions = interpreter.execute(&AhkAction::Send("{Blind}a^b".to_string())).unwrap();
+        let expected = vec![
+            key(Key::KEY_A, KeyValue::Press),
+            key(Key::KEY_A, KeyValue::Release),
+            key(Key::KEY_B, KeyValue::Press),
+            key(Key::KEY_B, KeyValue::Release),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+    }
+
+    #[test]
+    fn test_send_releases_modifier_left_down() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+
+        // Shift is let go by the second Send, Ctrl only once the hotkey's actions are done
+        let block = AhkAction::Block(vec![
+            AhkAction::Send("{Ctrl down}{Shift down}".to_string()),
+            AhkAction::Send("a{Shift up}".to_string()),
+        ]);
+        let actions = interpreter.execute(&block).unwrap();
+        let expected = vec![
+            key(Key::KEY_LEFTCTRL, KeyValue::Press),
+            key(Key::KEY_LEFTSHIFT, KeyValue::Press),
+            key(Key::KEY_A, KeyValue::Press),
+            key(Key::KEY_A, KeyValue::Release),
+            key(Key::KEY_LEFTSHIFT, KeyValue::Release),
+            key(Key::KEY_LEFTCTRL, KeyValue::Release),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+
+        // {Blind} asks for it to stay down
+        let actions = interpreter.execute(&AhkAction::Send("{Blind}{Ctrl down}".to_string())).unwrap();
+        let expected = vec![key(Key::KEY_LEFTCTRL, KeyValue::Press)];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+    }
+
+    #[test]
+    fn test_send_delay() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+
+        let actions = interpreter.execute(&AhkAction::Send("a{Delay 100}b".to_string())).unwrap();
+
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        let expected = vec![
+            key(Key::KEY_A, KeyValue::Press),
+            key(Key::KEY_A, KeyValue::Release),
+            Action::Delay(Duration::from_millis(100)),
+            key(Key::KEY_B, KeyValue::Press),
+            key(Key::KEY_B, KeyValue::Release),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+    }
+
+    #[test]
+    fn test_send_lone_modifier() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+
+        let actions = interpreter.execute(&AhkAction::Send("{LWin}".to_string())).unwrap();
+
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        let expected = vec![
+            key(Key::KEY_LEFTMETA, KeyValue::Press),
+            key(Key::KEY_LEFTMETA, KeyValue::Release),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+    }
+
+    #[test]
+    fn test_set_key_delay_before_send() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+
+        let block = AhkAction::Block(vec![
+            AhkAction::Send("ab".to_string()),
+            AhkAction::SetKeyDelay(30),
+            AhkAction::Send("a{Tab}".to_string()),
+        ]);
+        let actions = interpreter.execute(&block).unwrap();
+
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        let expected = vec![
+            key(Key::KEY_A, KeyValue::Press),
+            key(Key::KEY_A, KeyValue::Release),
+            key(Key::KEY_B, KeyValue::Press),
+            key(Key::KEY_B, KeyValue::Release),
+            key(Key::KEY_A, KeyValue::Press),
+            key(Key::KEY_A, KeyValue::Release),
+            Action::Delay(Duration::from_millis(30)),
+            key(Key::KEY_TAB, KeyValue::Press),
+            key(Key::KEY_TAB, KeyValue::Release),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+    }
+
+    #[test]
+    fn test_send_compose_sequence() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_compose_key(Some(Key::KEY_RIGHTALT));
+
+        let actions = interpreter.execute(&AhkAction::Send("é✓".to_string())).unwrap();
+
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        let expected = vec![
+            key(Key::KEY_RIGHTALT, KeyValue::Press),
+            key(Key::KEY_RIGHTALT, KeyValue::Release),
+            key(Key::KEY_APOSTROPHE, KeyValue::Press),
+            key(Key::KEY_APOSTROPHE, KeyValue::Release),
+            key(Key::KEY_E, KeyValue::Press),
+            key(Key::KEY_E, KeyValue::Release),
+            Action::TextExpansion {
+                trigger_len: 0,
+                replacement: "✓".to_string(),
+            },
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+    }
+
+    #[test]
+    fn test_send_type_jitter_bounds() {
+        let send = |seed| {
+            let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+            let mut interpreter = AhkInterpreter::new(&mut wm_client);
+            interpreter.set_keypress_delay(Duration::from_millis(5));
+            interpreter.set_type_jitter(Duration::from_millis(10), fastrand::Rng::with_seed(seed));
+            let actions = interpreter.execute(&AhkAction::Send("hello world".to_string())).unwrap();
+            actions
+                .into_iter()
+                .filter_map(|action| match action {
+                    Action::Delay(delay) => Some(delay),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let delays = send(7);
+        assert_eq!(delays.len(), "hello world".len() - 1);
+        assert!(delays
+            .iter()
+            .all(|delay| (Duration::from_millis(5)..=Duration::from_millis(15)).contains(delay)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+        assert_eq!(delays, send(7));
+    }
+
+    #[test]
+    fn test_send_key_repeat_holds_modifiers_once() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+        interpreter.set_keypress_delay(Duration::from_millis(5));
+
+        let actions = interpreter.execute(&AhkAction::Send("^{Tab 3}".to_string())).unwrap();
+
+        let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+        let delay = || Action::Delay(Duration::from_millis(5));
+        let expected = vec![
+            key(Key::KEY_LEFTCTRL, KeyValue::Press),
+            key(Key::KEY_TAB, KeyValue::Press),
+            key(Key::KEY_TAB, KeyValue::Release),
+            delay(),
+            key(Key::KEY_TAB, KeyValue::Press),
+            key(Key::KEY_TAB, KeyValue::Release),
+            delay(),
+            key(Key::KEY_TAB, KeyValue::Press),
+            key(Key::KEY_TAB, KeyValue::Release),
+            key(Key::KEY_LEFTCTRL, KeyValue::Release),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+    }
+
+    #[test]
+    fn test_send_key_hold() {
+        let mut wm_client = WMClient::new("none", Box::new(NoWindowClient));
+        let mut interpreter = AhkInterpreter::new(&mut wm_client);
+
+        let actions = interpreter.execute(&AhkAction::Send("{w 200ms}".to_string())).unwrap();
+
+        let expected = vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_W, KeyValue::Press)),
+            Action::Delay(Duration::from_millis(200)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_W, KeyValue::Release)),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod compose;
+pub mod expression;
 pub mod parser;
 pub mod send_parser;
 pub mod transpiler;
@@ -5,7 +7,7 @@ pub mod types;
 pub mod wayland_inject;
 pub mod interpreter;
 
-pub use parser::{parse_ahk_file, string_to_key};
+pub use parser::{normalize_source, parse_ahk_file, parse_exec_predicate, parse_win_active};
 pub use send_parser::*;
 pub use transpiler::*;
 pub use types::*;
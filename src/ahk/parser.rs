@@ -1,13 +1,40 @@
+use crate::ahk::expression;
 use crate::ahk::types::*;
+use crate::keys::{char_to_key_shift, name_to_key};
+use crate::single_instance::SingleInstance;
 use evdev::KeyCode;
+use lazy_static::lazy_static;
 use regex::Regex;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use thiserror::Error;
+
+lazy_static! {
+    static ref HOTIF: Regex = Regex::new(r#"#HotIf\s+(.+)"#).unwrap();
+    static ref ASSIGNMENT: Regex = Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*)\s*:=\s*(.+)$").unwrap();
+    static ref HOTSTRING: Regex = Regex::new(r"^(:([*?CcOoPpSsIiKkEeXxTtBbZz0-9]*):)?([^:]+)::(.*)$").unwrap();
+}
 
 pub struct AhkParser {
     // hotif_contexts: Vec<String>,
+    // Run a single-line hotkey's unknown action as a shell command instead of failing
+    lenient: bool,
+    // Top-level `name := value` assignments above the hotkey being parsed, for Send
+    // expressions; keyed by lowercased name
+    variables: HashMap<String, String>,
+    // Variables a WinGet in the hotkey being parsed sets, which Sends can only use once it runs
+    locals: RefCell<HashSet<String>>,
+}
+
+#[derive(Debug, Error)]
+#[error("line {line}: {msg}")]
+pub struct AhkParseError {
+    pub line: usize,
+    pub msg: String,
 }
 
-fn unescape_ahk_string(s: &str) -> String {
+pub(super) fn unescape_ahk_string(s: &str) -> String {
     let mut result = String::new();
     let mut chars = s.chars().peekable();
 
@@ -38,13 +65,52 @@ fn unescape_ahk_string(s: &str) -> String {
     result
 }
 
+// A hotstring's options, starting from #Hotstring's defaults
+#[derive(Debug, Clone, Copy, Default)]
+struct HotstringOptions {
+    immediate: bool,
+    case_sensitive: bool,
+    omit_char: bool,
+    execute: bool,
+}
+
+impl HotstringOptions {
+    // Options such as `*`, `C` or `O`, case-insensitive. A number after one is its value: `0`
+    // turns it off again, as does C1 (case-insensitive, like no C). Unsupported options are skipped.
+    fn apply(mut self, options: &str) -> Self {
+        let mut chars = options.chars().filter(|c| !c.is_whitespace()).peekable();
+        while let Some(option) = chars.next() {
+            let mut value = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                value.push(digit);
+            }
+            let on = value != "0";
+            match option.to_ascii_lowercase() {
+                '*' => self.immediate = on,
+                'c' => self.case_sensitive = on && value != "1",
+                'o' => self.omit_char = on,
+                'x' => self.execute = on,
+                _ => {}
+            }
+        }
+        self
+    }
+}
+
 impl AhkParser {
     pub fn new() -> Self {
         Self {
             // hotif_contexts: Vec::new(),
+            lenient: false,
+            variables: HashMap::new(),
+            locals: RefCell::new(HashSet::new()),
         }
     }
 
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
     // fn parse_window_criteria(&self, content: &str) -> Result<WindowCriteria, String> { // original before attempt at on.website
     //     let content = content.trim().trim_matches(|c| c == '"' || c == '\'');
         
@@ -58,29 +124,14 @@ impl AhkParser {
     //     }
     // }
 
-        fn parse_window_criteria(&self, s: &str) -> Result<WindowCriteria, String> {
+    // Accepts a #HotIf-style WinActive(...) or a bare "WinTitle" argument as given to WinActivate etc.
+    fn parse_window_criteria(&self, s: &str) -> Result<WindowCriteria, String> {
         let s = s.trim();
-        if s.starts_with("WinActive(") && s.ends_with(")") {
-            let inner = &s[10..s.len()-1].trim_matches('"');
-            if let Some(exe) = inner.strip_prefix("ahk_exe ") {
-                Ok(WindowCriteria::Exe(exe.trim().to_string()))
-            } else if let Some(class) = inner.strip_prefix("ahk_class ") {
-                Ok(WindowCriteria::Class(class.trim().to_string()))
-            } else {
-                Ok(WindowCriteria::Title(inner.to_string()))
-            }
-        } else if s.starts_with("!WinActive(") && s.ends_with(")") {
-            let inner = &s[11..s.len()-1].trim_matches('"');
-            // For negated, we can wrap in negated IfWinActive later if needed
-            if let Some(exe) = inner.strip_prefix("ahk_exe ") {
-                Ok(WindowCriteria::Exe(exe.trim().to_string())) // Handle negation in interpreter
-            } else if let Some(class) = inner.strip_prefix("ahk_class ") {
-                Ok(WindowCriteria::Class(class.trim().to_string()))
-            } else {
-                Ok(WindowCriteria::Title(inner.to_string()))
-            }
+        if s.trim_start_matches('!').trim_start().starts_with("WinActive(") {
+            // Negation is handled by the caller
+            parse_win_active(s).map(|(criteria, _negated)| criteria)
         } else {
-            Err(format!("Invalid hotkey context: {}", s))
+            parse_win_title(s.trim_matches('"'))
         }
     }
 
@@ -119,14 +170,29 @@ impl AhkParser {
 //     }
 // }
 
-    pub fn parse_file(&mut self, content: &str) -> Result<AhkConfig, String> {
+    pub fn parse_file(&mut self, content: &str) -> Result<AhkConfig, AhkParseError> {
+        let content = strip_block_comments(&normalize_source(content));
+        let mut line = 0;
+        self.parse_lines(&content, &mut line).map_err(|msg| AhkParseError { line, msg })
+    }
+
+    // `line` follows the (1-based) line the statement being parsed starts on
+    fn parse_lines(&mut self, content: &str, line_number: &mut usize) -> Result<AhkConfig, String> {
         let mut hotkeys = Vec::new();
         let mut hotstrings = Vec::new();
         let mut current_context = None;
+        let mut input_level = 0;
+        let mut single_instance = None;
+        let mut title_match_mode = None;
+        let mut hotstring_defaults = HotstringOptions::default();
+        let mut window_groups: HashMap<String, Vec<WindowCriteria>> = HashMap::new();
+        // Until the first hotkey or hotstring, or a `return`
+        let mut auto_execute = true;
 
         let mut lines = content.lines().enumerate().peekable();
 
-        while let Some((_line_num, line)) = lines.next() {
+        while let Some((line_num, line)) = lines.next() {
+            *line_number = line_num + 1;
             let line = line.trim();
 
             if line.is_empty() || line.starts_with(';') {
@@ -134,7 +200,7 @@ impl AhkParser {
             }
 
             if line.starts_with("#HotIf") {
-                current_context = self.parse_hotif(line)?;
+                current_context = self.parse_hotif(strip_inline_comment(line))?;
                 continue;
             }
 
@@ -143,8 +209,82 @@ impl AhkParser {
                 continue;
             }
 
+            // #InputLevel N applies to the hotkeys after it
+            if let Some(level) = line.strip_prefix("#InputLevel") {
+                let level = strip_inline_comment(level).trim();
+                input_level = level.parse().map_err(|_| format!("Invalid #InputLevel: {level}"))?;
+                continue;
+            }
+
+            if let Some(mode) = line.strip_prefix("#SingleInstance") {
+                single_instance = Some(match strip_inline_comment(mode).trim().to_lowercase().as_str() {
+                    "" | "prompt" | "ignore" => SingleInstance::Ignore,
+                    "force" => SingleInstance::Force,
+                    "off" => SingleInstance::Off,
+                    mode => return Err(format!("Invalid #SingleInstance: {mode}")),
+                });
+                continue;
+            }
+
+            // #Hotstring C: default options for the hotstrings after it
+            if let Some(options) = line.strip_prefix("#Hotstring") {
+                let options = strip_inline_comment(options).trim();
+                let first = options.split_whitespace().next().unwrap_or_default().to_lowercase();
+                if ["endchars", "nomouse", "mousereset"].contains(&first.as_str()) {
+                    eprintln!("WARNING: #Hotstring {first} isn't supported, ignoring it");
+                } else {
+                    hotstring_defaults = hotstring_defaults.apply(options);
+                }
+                continue;
+            }
+
+            // Like #InputLevel, applies to the hotkeys after it
+            if let Some(mode) = line.strip_prefix("SetTitleMatchMode") {
+                if let Some(mode) = parse_title_match_mode(strip_inline_comment(mode))? {
+                    title_match_mode = Some(mode);
+                }
+                continue;
+            }
+
+            if line.starts_with("GroupAdd") {
+                let (name, criteria) = parse_group_add(strip_inline_comment(line))?;
+                window_groups.entry(name.to_lowercase()).or_default().push(criteria);
+                continue;
+            }
+
+            if let Some(caps) = ASSIGNMENT.captures(strip_inline_comment(line)) {
+                // Scripts assign plenty this can't evaluate, which only matters once a Send uses it
+                match expression::evaluate(caps[2].trim(), &self.variables) {
+                    Ok(value) => {
+                        self.variables.insert(caps[1].to_lowercase(), value);
+                    }
+                    Err(e) => eprintln!("WARNING: Ignoring the assignment to {}: {e}", &caps[1]),
+                }
+                continue;
+            }
+
+            if auto_execute {
+                let statement = strip_inline_comment(line);
+                if statement.eq_ignore_ascii_case("return") {
+                    auto_execute = false;
+                    continue;
+                }
+                if !is_hotkey_or_hotstring(statement) {
+                    // Settings that only matter to AHK itself, e.g. `#Requires AutoHotkey v2.0`
+                    let command = statement.split([' ', ',', '(']).next().unwrap_or_default().to_lowercase();
+                    if !AUTO_EXECUTE_NO_OPS.contains(&command.as_str()) {
+                        eprintln!("WARNING: Ignoring line {} of the auto-execute section: {statement}", *line_number);
+                    }
+                    continue;
+                }
+                auto_execute = false;
+            }
+
+            // A WinGet's variable belongs to the hotkey or hotstring that runs it
+            self.locals.get_mut().clear();
+
             if line.starts_with(':') {
-                if let Some(hotstring) = self.parse_hotstring(line, current_context.clone())? {
+                if let Some(hotstring) = self.parse_hotstring(line, current_context.clone(), hotstring_defaults)? {
                     hotstrings.push(hotstring);
                     continue;
                 } else {
@@ -166,12 +306,17 @@ impl AhkParser {
                         line
                     };
                     
-                    if let Some(hotkey) = self.parse_multiline_hotkey(hotkey_def, &mut lines, current_context.clone())? {
+                    let hotkey = self.parse_multiline_hotkey(hotkey_def, &mut lines, current_context.clone())?;
+                    if let Some(mut hotkey) = hotkey {
+                        hotkey.input_level = input_level;
+                        hotkey.title_match_mode = title_match_mode;
                         hotkeys.push(hotkey);
                     }
                 } else {
                     // Single-line hotkey
-                    if let Some(hotkey) = self.parse_hotkey(line, current_context.clone())? {
+                    if let Some(mut hotkey) = self.parse_hotkey(line, current_context.clone())? {
+                        hotkey.input_level = input_level;
+                        hotkey.title_match_mode = title_match_mode;
                         hotkeys.push(hotkey);
                     } else {
                         return Err(format!("Failed to parse hotkey line: {}", line));
@@ -180,7 +325,13 @@ impl AhkParser {
             }
         }
 
-        Ok(AhkConfig { hotkeys, hotstrings })
+        Ok(AhkConfig {
+            hotkeys,
+            hotstrings,
+            single_instance,
+            window_groups,
+            variables: self.variables.clone(),
+        })
     }
 
   
@@ -329,29 +480,31 @@ fn parse_block_actions<'a>(
 }
 
     fn parse_hotif(&mut self, line: &str) -> Result<Option<String>, String> {
-        let re = Regex::new(r#"#HotIf\s+(.+)"#).unwrap();
-        if let Some(caps) = re.captures(line) {
+        if let Some(caps) = HOTIF.captures(line) {
             Ok(Some(caps[1].to_string()))
         } else {
             Ok(None)
         }
     }
 
-    fn parse_hotstring(&self, line: &str, context: Option<String>) -> Result<Option<AhkHotstring>, String> {
-        let re = Regex::new(r"^(:([*?CcOoPpSsIiKkEeXxTtBbZz0-9]*):)?([^:]+)::(.*)$").unwrap();
-
-        if let Some(caps) = re.captures(line) {
-            let options = caps.get(2).map(|m| m.as_str()).unwrap_or("");
+    fn parse_hotstring(
+        &self,
+        line: &str,
+        context: Option<String>,
+        defaults: HotstringOptions,
+    ) -> Result<Option<AhkHotstring>, String> {
+        if let Some(caps) = HOTSTRING.captures(line) {
+            let options = defaults.apply(caps.get(2).map(|m| m.as_str()).unwrap_or(""));
             let trigger = caps[3].to_string();
             let replacement = caps[4].to_string();
 
             Ok(Some(AhkHotstring {
                 trigger,
                 replacement,
-                immediate: options.contains('*'),
-                case_sensitive: options.contains('C'),
-                omit_char: options.contains('O') || options.contains('o'),
-                execute: options.contains('X') || options.contains('x'),
+                immediate: options.immediate,
+                case_sensitive: options.case_sensitive,
+                omit_char: options.omit_char,
+                execute: options.execute,
                 context,
             }))
         } else {
@@ -395,14 +548,16 @@ fn parse_block_actions<'a>(
         let hotkey_def = parts[0].trim();
         let action_str = parts[1].trim();
 
-        let action_str = if let Some(comment_pos) = action_str.find(" ;") {
-            &action_str[..comment_pos]
-        } else {
-            action_str
-        };
+        let action_str = strip_inline_comment(action_str);
 
         let (modifiers, key, is_wildcard) = self.parse_key_combo(hotkey_def)?;
-        let action = self.parse_action(action_str)?;
+        let action = match self.parse_action(action_str) {
+            Err(e) if self.lenient => {
+                eprintln!("WARNING: {e}, running it as a shell command");
+                AhkAction::Shell(action_str.to_string())
+            }
+            action => action?,
+        };
 
     //     let final_action = if let Some(ref ctx) = context { // good 1.12.2026
     //         let criteria = self.parse_window_criteria(ctx)?;
@@ -422,7 +577,8 @@ fn parse_block_actions<'a>(
     //         context: None,
     //         is_wildcard,
     //     }))
-    let final_action = if let Some(ref ctx) = context {
+    // Exec() contexts are checked by the event handler, not the interpreter
+    let final_action = if let Some(ctx) = context.as_ref().filter(|ctx| parse_exec_predicate(ctx).is_none()) {
     let criteria = self.parse_window_criteria(ctx)?;
 
     // Build AHK-style Send string that reproduces the original physical hotkey
@@ -551,8 +707,10 @@ Ok(Some(AhkHotkey {
     modifiers,
     key,
     action: final_action,
-    context: None,          // We already consumed/used the context
+    context: context.filter(|ctx| parse_exec_predicate(ctx).is_some()),
     is_wildcard,
+    input_level: 0,
+    title_match_mode: None,
 }))
     
     
@@ -573,11 +731,14 @@ Ok(Some(AhkHotkey {
                 is_wildcard = true;
                 rest = &rest[1..];
             } else if rest.starts_with('$') {
+                // AHK's "force the hook": our own output comes from the uinput device, which is
+                // never grabbed, so a hotkey's Send can't trigger hotkeys anyway
                 rest = &rest[1..];
             }
         }
 
-        loop {
+        // The last character is the key, so `!::` is the `!` key rather than Alt alone
+        while rest.len() > 1 {
             if rest.starts_with('^') {
                 modifiers.push(KeyCode::KEY_LEFTCTRL);
                 rest = &rest[1..];
@@ -598,15 +759,26 @@ Ok(Some(AhkHotkey {
         if rest.contains(" & ") {
             let parts: Vec<&str> = rest.split(" & ").collect();
             if parts.len() == 2 {
-                if let Some(mod_key) = string_to_key(parts[0].trim()) {
+                if let Some(mod_key) = name_to_key(parts[0].trim()) {
                     modifiers.push(mod_key);
                 }
-                let main_key = string_to_key(parts[1].trim()).ok_or_else(|| format!("Unknown key: {}", parts[1]))?;
+                let main_key = name_to_key(parts[1].trim()).ok_or_else(|| format!("Unknown key: {}", parts[1]))?;
                 return Ok((modifiers, main_key, is_wildcard));
             }
         }
 
-        let key = string_to_key(rest.trim()).ok_or_else(|| format!("Unknown key: {}", rest))?;
+        let name = rest.trim();
+        // A symbol typed with Shift is its key with Shift, so `!::` and `+1::` are the same hotkey
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            if let Some((key, true)) = char_to_key_shift(c).filter(|_| !c.is_alphabetic()) {
+                if !modifiers.contains(&KeyCode::KEY_LEFTSHIFT) {
+                    modifiers.push(KeyCode::KEY_LEFTSHIFT);
+                }
+                return Ok((modifiers, key, is_wildcard));
+            }
+        }
+        let key = name_to_key(name).ok_or_else(|| format!("Unknown key: {}", rest))?;
 
         Ok((modifiers, key, is_wildcard))
     }
@@ -809,11 +981,59 @@ if let Ok(_action) = self.parse_action(peek_trimmed) {
         action,
         context,
         is_wildcard,
+        input_level: 0,
+        title_match_mode: None,
     }))
 }
 
+    // v2's Send(...) argument: an expression such as `"text"`, `"a" . b` or `dark ? "a" : "b"`
+    fn send_action(&self, arg: &str) -> AhkAction {
+        match expression::evaluate(arg, &self.variables) {
+            Ok(keys) => AhkAction::Send(keys),
+            Err(e) => {
+                let locals = self.locals.borrow();
+                if expression::variables(arg).is_ok_and(|names| names.iter().any(|name| locals.contains(name))) {
+                    return AhkAction::SendExpression(arg.to_string());
+                }
+                eprintln!("WARNING: Sending {arg} as text: {e}");
+                AhkAction::Send(expression::literal(arg))
+            }
+        }
+    }
+
+    // `title := WinGetTitle("A")` or v1's `WinGetTitle, title, A`
+    fn parse_win_get(&self, s: &str) -> Result<Option<AhkAction>, String> {
+        const FUNCTIONS: [(&str, WinGetInfo); 3] =
+            [("WinGetTitle", WinGetInfo::Title), ("WinGetClass", WinGetInfo::Class), ("WinGetPID", WinGetInfo::Pid)];
+        let call = ASSIGNMENT.captures(s).and_then(|caps| {
+            FUNCTIONS.iter().find_map(|&(function, what)| {
+                let args = caps[2].trim().strip_prefix(function)?.strip_prefix('(')?.strip_suffix(')')?;
+                Some((caps[1].to_string(), what, args.to_string()))
+            })
+        });
+        let call = call.or_else(|| {
+            FUNCTIONS.iter().find_map(|&(function, what)| {
+                let (target_var, args) = s.strip_prefix(function)?.trim_start().strip_prefix(',')?.split_once(',')?;
+                Some((target_var.trim().to_string(), what, args.to_string()))
+            })
+        });
+        let Some((target_var, what, args)) = call else {
+            return Ok(None);
+        };
+        let criteria = match args.trim().trim_matches('"') {
+            "" | "A" => None,
+            _ => Some(self.parse_window_criteria(&args)?),
+        };
+        self.locals.borrow_mut().insert(target_var.to_lowercase());
+        Ok(Some(AhkAction::WinGet { target_var, what, criteria }))
+    }
+
     fn parse_action(&self, action_str: &str) -> Result<AhkAction, String> {
-        let s = action_str.trim();
+        let s = strip_inline_comment(action_str).trim();
+
+        if let Some(action) = self.parse_win_get(s)? {
+            return Ok(action);
+        }
 
         // Handle WinActivate
         if let Some(rest) = s.strip_prefix("WinActivate(") {
@@ -837,6 +1057,16 @@ if let Ok(_action) = self.parse_action(peek_trimmed) {
             }
         }
 
+        // Handle WinWait (window exists, active or not): WinWait("criteria", timeout_ms)
+        if let Some(rest) = s.strip_prefix("WinWait(") {
+            if let Some(content) = rest.strip_suffix(')') {
+                let parts: Vec<&str> = content.split(',').map(|s| s.trim()).collect();
+                let criteria = self.parse_window_criteria(parts[0])?;
+                let timeout_ms = parts.get(1).and_then(|timeout| timeout.parse::<u64>().ok());
+                return Ok(AhkAction::WinWait { criteria, timeout_ms });
+            }
+        }
+
         // Handle WinClose
         if let Some(rest) = s.strip_prefix("WinClose(") {
             if let Some(content) = rest.strip_suffix(')') {
@@ -845,42 +1075,93 @@ if let Ok(_action) = self.parse_action(peek_trimmed) {
             }
         }
 
+        // Handle WinMaximize/WinMinimize
+        if let Some(rest) = s.strip_prefix("WinMaximize(") {
+            if let Some(content) = rest.strip_suffix(')') {
+                let criteria = self.parse_window_criteria(content)?;
+                return Ok(AhkAction::WinMaximize(criteria));
+            }
+        }
+        if let Some(rest) = s.strip_prefix("WinMinimize(") {
+            if let Some(content) = rest.strip_suffix(')') {
+                let criteria = self.parse_window_criteria(content)?;
+                return Ok(AhkAction::WinMinimize(criteria));
+            }
+        }
+
         // Handle Run with space: Run "command" or Run 'command'
         if let Some(rest) = s.strip_prefix("Run ") {
-            let cmd = rest.trim().trim_matches(|c| c == '"' || c == '\'');
-            let cmd = unescape_ahk_string(cmd);
-            let parts: Vec<String> = cmd.split_whitespace().map(String::from).collect();
-            return Ok(AhkAction::Run(parts));
+            return Ok(parse_run(rest));
         }
 
         // Handle Run with parentheses: Run("command")
         if let Some(rest) = s.strip_prefix("Run(") {
-            if let Some(cmd) = rest.strip_suffix(')') {
-                let cmd = cmd.trim().trim_matches(|c| c == '"' || c == '\'');
-                let cmd = unescape_ahk_string(cmd);
-                let parts: Vec<String> = cmd.split_whitespace().map(String::from).collect();
-                return Ok(AhkAction::Run(parts));
+            if let Some(args) = rest.strip_suffix(')') {
+                return Ok(parse_run(args));
             }
         }
 
         for prefix in ["SendInput(", "SendEvent(", "Send("] {
             if let Some(rest) = s.strip_prefix(prefix) {
                 if let Some(content) = rest.strip_suffix(')') {
-                    let keys = content.trim().trim_matches(|c| c == '"' || c == '\'');
-                    let keys = unescape_ahk_string(keys);
-                    return Ok(AhkAction::Send(keys));
+                    return Ok(self.send_action(content.trim()));
                 }
             }
         }
 
+        // v1's Send takes plain text, so `Send a` types an a
         for prefix in ["SendInput ", "SendEvent ", "Send "] {
             if let Some(rest) = s.strip_prefix(prefix) {
-                let keys = rest.trim_matches(|c| c == '"' || c == '\'');
-                let keys = unescape_ahk_string(keys);
-                return Ok(AhkAction::Send(keys));
+                return Ok(AhkAction::Send(expression::literal(rest)));
+            }
+        }
+
+        // SetKeyDelay(N), SetKeyDelay, N or SetKeyDelay N; an optional press duration is ignored
+        if let Some(rest) = s.strip_prefix("SetKeyDelay") {
+            let args = rest.trim().trim_start_matches(',');
+            let args = args.trim().strip_prefix('(').and_then(|a| a.strip_suffix(')')).unwrap_or(args);
+            if let Ok(ms) = args.split(',').next().unwrap_or_default().trim().parse::<u64>() {
+                return Ok(AhkAction::SetKeyDelay(ms));
             }
         }
 
+        // SendLevel(N), SendLevel, N or SendLevel N
+        if let Some(rest) = s.strip_prefix("SendLevel") {
+            let arg = rest.trim().trim_start_matches(',').trim();
+            let arg = arg.strip_prefix('(').and_then(|a| a.strip_suffix(')')).unwrap_or(arg);
+            let level = arg.trim().parse::<u8>().map_err(|_| format!("Invalid SendLevel: {arg}"))?;
+            return Ok(AhkAction::SendLevel(level));
+        }
+
+        if let Some(rest) = s.strip_prefix("SetTitleMatchMode") {
+            return Ok(parse_title_match_mode(rest)?.map_or(AhkAction::Block(vec![]), AhkAction::SetTitleMatchMode));
+        }
+
+        // SetCapsLockState, On / SetNumLockState("Off") / SetScrollLockState Toggle
+        for (command, lock) in [
+            ("SetCapsLockState", LockKey::CapsLock),
+            ("SetNumLockState", LockKey::NumLock),
+            ("SetScrollLockState", LockKey::ScrollLock),
+        ] {
+            if let Some(rest) = s.strip_prefix(command) {
+                let arg = rest.trim().trim_start_matches(',').trim();
+                let arg = arg.strip_prefix('(').and_then(|a| a.strip_suffix(')')).unwrap_or(arg);
+                let state = match arg.trim().trim_matches('"').to_lowercase().as_str() {
+                    "on" | "1" => LockState::On,
+                    "off" | "0" => LockState::Off,
+                    "alwayson" => LockState::AlwaysOn,
+                    "alwaysoff" => LockState::AlwaysOff,
+                    "toggle" | "-1" => LockState::Toggle,
+                    _ => return Err(format!("Invalid {command} state: {arg}")),
+                };
+                return Ok(AhkAction::SetLockState { lock, state });
+            }
+        }
+
+        if s.starts_with("KeyWait") {
+            return parse_key_wait(s);
+        }
+
         if let Some(rest) = s.strip_prefix("Sleep ") {
             if let Ok(ms) = rest.trim().parse::<u64>() {
                 return Ok(AhkAction::Sleep(ms));
@@ -888,111 +1169,682 @@ if let Ok(_action) = self.parse_action(peek_trimmed) {
         }
 
         if s.starts_with("Media_") || s.starts_with("Volume_") {
-            if let Some(key) = string_to_key(s) {
+            if let Some(key) = name_to_key(s) {
                 return Ok(AhkAction::Remap(vec![key]));
             }
         }
 
-        if let Some(key) = string_to_key(s) {
+        if let Some(key) = name_to_key(s) {
             return Ok(AhkAction::Remap(vec![key]));
         }
 
+        // `a::bc` remaps to a sequence of single keys, unless the word is a command this doesn't
+        // support, like `Reload`, or one with its argument run on, like `Sleep100`
+        let command = s.trim_end_matches(|c: char| c.is_ascii_digit()).to_lowercase();
+        if s.chars().all(|c| c.is_ascii_alphanumeric()) && !AHK_COMMANDS.contains(&command.as_str()) {
+            let keys: Option<Vec<KeyCode>> = s.chars().map(|c| name_to_key(&c.to_string())).collect();
+            if let Some(keys) = keys {
+                return Ok(AhkAction::Remap(keys));
+            }
+        }
+
         Err(format!("Unknown action: {s}"))
     }
 }
 
-pub fn string_to_key(s: &str) -> Option<KeyCode> {
-    match s.to_lowercase().as_str() {
-        "a" => Some(KeyCode::KEY_A),
-        "b" => Some(KeyCode::KEY_B),
-        "c" => Some(KeyCode::KEY_C),
-        "d" => Some(KeyCode::KEY_D),
-        "e" => Some(KeyCode::KEY_E),
-        "f" => Some(KeyCode::KEY_F),
-        "g" => Some(KeyCode::KEY_G),
-        "h" => Some(KeyCode::KEY_H),
-        "i" => Some(KeyCode::KEY_I),
-        "j" => Some(KeyCode::KEY_J),
-        "k" => Some(KeyCode::KEY_K),
-        "l" => Some(KeyCode::KEY_L),
-        "m" => Some(KeyCode::KEY_M),
-        "n" => Some(KeyCode::KEY_N),
-        "o" => Some(KeyCode::KEY_O),
-        "p" => Some(KeyCode::KEY_P),
-        "q" => Some(KeyCode::KEY_Q),
-        "r" => Some(KeyCode::KEY_R),
-        "s" => Some(KeyCode::KEY_S),
-        "t" => Some(KeyCode::KEY_T),
-        "u" => Some(KeyCode::KEY_U),
-        "v" => Some(KeyCode::KEY_V),
-        "w" => Some(KeyCode::KEY_W),
-        "x" => Some(KeyCode::KEY_X),
-        "y" => Some(KeyCode::KEY_Y),
-        "z" => Some(KeyCode::KEY_Z),
-        "0" => Some(KeyCode::KEY_0),
-        "1" => Some(KeyCode::KEY_1),
-        "2" => Some(KeyCode::KEY_2),
-        "3" => Some(KeyCode::KEY_3),
-        "4" => Some(KeyCode::KEY_4),
-        "5" => Some(KeyCode::KEY_5),
-        "6" => Some(KeyCode::KEY_6),
-        "7" => Some(KeyCode::KEY_7),
-        "8" => Some(KeyCode::KEY_8),
-        "9" => Some(KeyCode::KEY_9),
-        "space" => Some(KeyCode::KEY_SPACE),
-        "enter" | "return" => Some(KeyCode::KEY_ENTER),
-        "tab" => Some(KeyCode::KEY_TAB),
-        "backspace" => Some(KeyCode::KEY_BACKSPACE),
-        "delete" | "del" => Some(KeyCode::KEY_DELETE),
-        "escape" | "esc" => Some(KeyCode::KEY_ESC),
-        "capslock" => Some(KeyCode::KEY_CAPSLOCK),
-        "up" => Some(KeyCode::KEY_UP),
-        "down" => Some(KeyCode::KEY_DOWN),
-        "left" => Some(KeyCode::KEY_LEFT),
-        "right" => Some(KeyCode::KEY_RIGHT),
-        "home" => Some(KeyCode::KEY_HOME),
-        "end" => Some(KeyCode::KEY_END),
-        "pageup" | "pgup" => Some(KeyCode::KEY_PAGEUP),
-        "pagedown" | "pgdn" => Some(KeyCode::KEY_PAGEDOWN),
-        "insert" => Some(KeyCode::KEY_INSERT),
-        "f1" => Some(KeyCode::KEY_F1),
-        "f2" => Some(KeyCode::KEY_F2),
-        "f3" => Some(KeyCode::KEY_F3),
-        "f4" => Some(KeyCode::KEY_F4),
-        "f5" => Some(KeyCode::KEY_F5),
-        "f6" => Some(KeyCode::KEY_F6),
-        "f7" => Some(KeyCode::KEY_F7),
-        "f8" => Some(KeyCode::KEY_F8),
-        "f9" => Some(KeyCode::KEY_F9),
-        "f10" => Some(KeyCode::KEY_F10),
-        "f11" => Some(KeyCode::KEY_F11),
-        "f12" => Some(KeyCode::KEY_F12),
-        "f13" => Some(KeyCode::KEY_F13),
-        "f14" => Some(KeyCode::KEY_F14),
-        "f15" => Some(KeyCode::KEY_F15),
-        "f16" => Some(KeyCode::KEY_F16),
-        "f17" => Some(KeyCode::KEY_F17),
-        "f18" => Some(KeyCode::KEY_F18),
-        "f19" => Some(KeyCode::KEY_F19),
-        "f20" => Some(KeyCode::KEY_F20),
-        "f21" => Some(KeyCode::KEY_F21),
-        "f22" => Some(KeyCode::KEY_F22),
-        "f23" => Some(KeyCode::KEY_F23),
-        "f24" => Some(KeyCode::KEY_F24),
-        "media_play_pause" => Some(KeyCode::KEY_PLAYPAUSE),
-        "media_next" => Some(KeyCode::KEY_NEXTSONG),
-        "media_prev" => Some(KeyCode::KEY_PREVIOUSSONG),
-        "media_stop" => Some(KeyCode::KEY_STOPCD),
-        "volume_up" => Some(KeyCode::KEY_VOLUMEUP),
-        "volume_down" => Some(KeyCode::KEY_VOLUMEDOWN),
-        "volume_mute" => Some(KeyCode::KEY_MUTE),
+// `"command"`, `"command",, Max` or `"command", "dir", "Min"`. Wayland has no way to launch
+// a window in a given state, so Max/Min wait for the program's window (by its name, as there's
+// no PID to go by) and then activate and maximize or minimize it.
+fn parse_run(args: &str) -> AhkAction {
+    let (target, options) = split_run_args(args.trim());
+    let cmd = unescape_ahk_string(target);
+    let parts: Vec<String> = cmd.split_whitespace().map(String::from).collect();
+    let program = parts.first().filter(|program| !program.contains("://"));
+    let maximize = match options.map(str::to_lowercase).as_deref() {
+        Some("max") => Some(true),
+        Some("min") => Some(false),
+        Some("hide") => {
+            eprintln!("WARNING: Run option Hide isn't supported on Wayland, running {cmd} normally");
+            None
+        }
         _ => None,
+    };
+    let (Some(program), Some(maximize)) = (program, maximize) else {
+        return AhkAction::Run(parts);
+    };
+    let name = program.rsplit('/').next().unwrap_or(program);
+    let criteria = WindowCriteria::Exe(name.to_string());
+    AhkAction::Block(vec![
+        AhkAction::Run(parts.clone()),
+        AhkAction::WinWait {
+            criteria: criteria.clone(),
+            timeout_ms: None,
+        },
+        AhkAction::WinActivate(criteria.clone()),
+        if maximize {
+            AhkAction::WinMaximize(criteria)
+        } else {
+            AhkAction::WinMinimize(criteria)
+        },
+    ])
+}
+
+// The target, unquoted, and the Options argument if there is one. Commas inside the quoted
+// target belong to it.
+fn split_run_args(args: &str) -> (&str, Option<&str>) {
+    let quote = match args.chars().next() {
+        Some(c @ ('"' | '\'')) => c,
+        _ => return (args, None),
+    };
+    let mut escaped = false;
+    let end = args[1..].char_indices().find(|&(_, c)| {
+        let closing = c == quote && !escaped;
+        escaped = c == '`' && !escaped;
+        closing
+    });
+    let Some((end, _)) = end else {
+        return (args.trim_matches(quote), None);
+    };
+    let target = &args[1..end + 1];
+    // The arguments after Target are WorkingDir and Options
+    let options = args[end + 2..]
+        .split(',')
+        .nth(2)
+        .map(|options| options.trim().trim_matches(|c| c == '"' || c == '\''))
+        .filter(|options| !options.is_empty());
+    (target, options)
+}
+
+// AHK command names, lowercased, that are never a sequence of keys in a remap
+const AHK_COMMANDS: [&str; 45] = [
+    "run", "for", "try", "if", "else", "exit", "edit", "goto", "loop", "send", "sleep", "click", "pause", "break",
+    "throw", "catch", "while", "until", "reload", "return", "global", "local", "static", "gosub", "exitapp",
+    "suspend", "msgbox", "sendraw", "sendtext", "sendinput", "sendevent", "sendplay", "setkeydelay", "winactivate",
+    "winclose", "winminimize", "winmaximize", "winwait", "winwaitactive", "keywait", "tooltip", "traytip",
+    "inputbox", "listvars", "listhotkeys",
+];
+
+// What the auto-execute section may hold that has no effect here
+const AUTO_EXECUTE_NO_OPS: [&str; 8] = [
+    "#requires",
+    "#warn",
+    "#noenv",
+    "#persistent",
+    "persistent",
+    "sendmode",
+    "setworkingdir",
+    "installkeybdhook",
+];
+
+// `^j::`, `CapsLock & j::` or `:*:btw::`, as opposed to a statement with `::` in a
+// string, like `MsgBox "a::b"`
+fn is_hotkey_or_hotstring(line: &str) -> bool {
+    if line.starts_with(':') {
+        return true;
     }
+    let Some(end) = line.find("::") else {
+        return false;
+    };
+    let keys = line[..end].replace(" & ", "&");
+    !keys.is_empty() && !keys.contains(char::is_whitespace)
+}
+
+/// Drops `/* ... */` block comments. As in AHK, `/*` only opens a comment at the
+/// start of a line; the comment ends at the first `*/` after it.
+fn strip_block_comments(content: &str) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut in_comment = false;
+
+    for line in content.lines() {
+        let mut rest = line;
+        if !in_comment && rest.trim_start().starts_with("/*") {
+            in_comment = true;
+            rest = &rest.trim_start()[2..];
+        }
+        if in_comment {
+            match rest.find("*/") {
+                Some(end) => {
+                    in_comment = false;
+                    rest = &rest[end + 2..];
+                }
+                None => {
+                    result.push('\n');
+                    continue;
+                }
+            }
+        }
+        result.push_str(rest);
+        result.push('\n');
+    }
+
+    result
+}
+
+/// Cuts a trailing `;` comment. AHK only treats `;` as a comment when it follows
+/// whitespace, and never inside a quoted string.
+fn strip_inline_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut prev = ' ';
+    let mut chars = line.char_indices();
+
+    while let Some((i, ch)) = chars.next() {
+        match quote {
+            Some(q) if ch == q => quote = None,
+            Some(_) if ch == '`' => {
+                chars.next();
+            }
+            Some(_) => {}
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == ';' && prev.is_whitespace() => return line[..i].trim_end(),
+            None => {}
+        }
+        prev = ch;
+    }
+
+    line
+}
+
+/// Parses a `WinActive("...")` or `!WinActive("...")` context into its criteria and
+/// whether it is negated. Only a leading `ahk_exe`/`ahk_class`/`ahk_pid` selects the
+/// criteria type; anything else is a window title.
+pub fn parse_win_active(context: &str) -> Result<(WindowCriteria, bool), String> {
+    let s = context.trim();
+    let (negated, s) = match s.strip_prefix('!') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, s),
+    };
+    let inner = s
+        .strip_prefix("WinActive(")
+        .and_then(|rest| rest.strip_suffix(')'))
+        .ok_or_else(|| format!("Invalid hotkey context: {}", context))?
+        .trim_matches('"');
+    Ok((parse_win_title(inner)?, negated))
+}
+
+// SetTitleMatchMode's argument: `2`, `, 2` or `("RegEx")`. None for Fast and Slow, which
+// only affect hidden text.
+fn parse_title_match_mode(args: &str) -> Result<Option<TitleMatchMode>, String> {
+    let arg = args.trim().trim_start_matches(',').trim();
+    let arg = arg.strip_prefix('(').and_then(|a| a.strip_suffix(')')).unwrap_or(arg);
+    let mode = match arg.trim().trim_matches('"').to_lowercase().as_str() {
+        "1" => TitleMatchMode::Prefix,
+        "2" => TitleMatchMode::Substring,
+        "3" => TitleMatchMode::Exact,
+        "regex" => TitleMatchMode::Regex,
+        "fast" | "slow" => return Ok(None),
+        _ => return Err(format!("Invalid SetTitleMatchMode: {arg}")),
+    };
+    Ok(Some(mode))
+}
+
+// The unquoted WinTitle argument, e.g. `ahk_exe firefox` or a plain title
+fn parse_win_title(inner: &str) -> Result<WindowCriteria, String> {
+    let criteria = if let Some(exe) = inner.strip_prefix("ahk_exe ") {
+        WindowCriteria::Exe(exe.trim().to_string())
+    } else if let Some(pid) = inner.strip_prefix("ahk_pid ") {
+        parse_pid(pid)?
+    } else if let Some(class) = inner.strip_prefix("ahk_class ") {
+        WindowCriteria::Class(class.trim().to_string())
+    } else if let Some(group) = inner.strip_prefix("ahk_group ") {
+        WindowCriteria::Group(group.trim().to_string())
+    } else {
+        WindowCriteria::Title(inner.to_string())
+    };
+    Ok(criteria)
+}
+
+// `GroupAdd("browsers", "ahk_exe firefox")`, or v1's `GroupAdd, browsers, ahk_exe firefox`
+fn parse_group_add(line: &str) -> Result<(String, WindowCriteria), String> {
+    let rest = line["GroupAdd".len()..].trim();
+    let args = match rest.strip_prefix('(') {
+        Some(args) => args.strip_suffix(')'),
+        None => rest.strip_prefix(','),
+    }
+    .ok_or_else(|| format!("Invalid GroupAdd: {line}"))?;
+    let mut args = args.split(',').map(|arg| arg.trim().trim_matches('"').trim());
+    let (Some(name), Some(win_title)) = (args.next(), args.next()) else {
+        return Err(format!("GroupAdd needs a group name and a WinTitle: {line}"));
+    };
+    if name.is_empty() {
+        return Err(format!("GroupAdd needs a group name and a WinTitle: {line}"));
+    }
+    if args.next().is_some() {
+        eprintln!("WARNING: GroupAdd only supports the group name and WinTitle: {line}");
+    }
+    match parse_win_title(win_title)? {
+        WindowCriteria::Group(_) => Err(format!("GroupAdd can't add a group to a group: {line}")),
+        criteria => Ok((name.to_string(), criteria)),
+    }
+}
+
+// KeyWait("Ctrl", "D T3") / KeyWait, Ctrl, D T3. L (the logical state) is the only state there is.
+fn parse_key_wait(line: &str) -> Result<AhkAction, String> {
+    let rest = line["KeyWait".len()..].trim();
+    let args = match rest.strip_prefix('(') {
+        Some(args) => args.strip_suffix(')'),
+        None => Some(rest.strip_prefix(',').unwrap_or(rest)),
+    }
+    .ok_or_else(|| format!("Invalid KeyWait: {line}"))?;
+    let mut args = args.split(',').map(|arg| arg.trim().trim_matches('"').trim());
+    let name = args.next().unwrap_or_default();
+    let key = name_to_key(name).ok_or_else(|| format!("Unknown key in KeyWait: {name}"))?;
+
+    let mut wait_for_release = true;
+    let mut timeout_ms = None;
+    let options = args.next().unwrap_or_default().to_lowercase();
+    let mut chars = options.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            'd' => wait_for_release = false,
+            'l' => {}
+            't' => {
+                let mut seconds = String::new();
+                while let Some(digit) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                    seconds.push(digit);
+                }
+                let seconds = seconds.parse::<f64>().map_err(|_| format!("Invalid KeyWait timeout: {line}"))?;
+                timeout_ms = Some((seconds * 1000.0) as u64);
+            }
+            c if c.is_whitespace() => {}
+            _ => return Err(format!("Invalid KeyWait option '{c}': {line}")),
+        }
+    }
+    Ok(AhkAction::KeyWait { key, wait_for_release, timeout_ms })
+}
+
+fn parse_pid(pid: &str) -> Result<WindowCriteria, String> {
+    pid.trim()
+        .parse::<u32>()
+        .map(WindowCriteria::Pid)
+        .map_err(|_| format!("Invalid ahk_pid: {}", pid))
+}
+
+/// Parses a `#HotIf Exec("command")` context into the command to run, via `sh -c`.
+pub fn parse_exec_predicate(context: &str) -> Option<Vec<String>> {
+    let inner = context.trim().strip_prefix("Exec(")?.strip_suffix(')')?.trim();
+    let command = inner.trim_matches(|c| c == '"' || c == '\'');
+    Some(vec!["/bin/sh".to_string(), "-c".to_string(), unescape_ahk_string(command)])
+}
+
+/// Strips a leading UTF-8 BOM and carriage returns left behind by Windows editors.
+pub fn normalize_source(content: &str) -> String {
+    content.strip_prefix('\u{feff}').unwrap_or(content).replace('\r', "")
 }
 
 pub fn parse_ahk_file(path: &Path) -> Result<AhkConfig, String> {
     let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read file: {}", e))?;
 
     let mut parser = AhkParser::new();
-    parser.parse_file(&content)
-}
\ No newline at end of file
+    parser.parse_file(&content).map_err(|e| e.to_string())
+}
+#[cfg(test)]
+mod tests {
+    use super::AhkParser;
+    use crate::ahk::types::{AhkAction, LockKey, LockState, TitleMatchMode, WinGetInfo, WindowCriteria};
+    use crate::single_instance::SingleInstance;
+    use evdev::KeyCode;
+
+    #[test]
+    fn test_parse_block_comment() {
+        let content = "/*\n^j::Send(\"hi\")\n*/\n^k::Send(\"ok\")\n/* single line */\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+
+        assert_eq!(config.hotkeys.len(), 1);
+        assert_eq!(config.hotkeys[0].key, KeyCode::KEY_K);
+    }
+
+    #[test]
+    fn test_parse_auto_execute_section() {
+        let content = "#Requires AutoHotkey v2.0\n\
+                       SendMode \"Input\"\n\
+                       greeting := \"hi\"\n\
+                       MsgBox \"Ready::\"\n\
+                       return\n\
+                       CapsLock & j::Send(greeting)\n\
+                       ^k::Send(\"b\")\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+        let keys: Vec<_> = config.hotkeys.iter().map(|hotkey| hotkey.key).collect();
+
+        assert_eq!(keys, [KeyCode::KEY_J, KeyCode::KEY_K]);
+        assert_eq!(config.variables["greeting"], "hi");
+    }
+
+    #[test]
+    fn test_parse_shifted_symbol_hotkeys() {
+        let content = "+1::Send(\"a\")\n!::Send(\"b\")\n^?::Send(\"c\")\n+::Send(\"d\")\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+        let combos: Vec<_> = config.hotkeys.iter().map(|hotkey| (hotkey.modifiers.clone(), hotkey.key)).collect();
+
+        assert_eq!(
+            combos,
+            [
+                (vec![KeyCode::KEY_LEFTSHIFT], KeyCode::KEY_1),
+                (vec![KeyCode::KEY_LEFTSHIFT], KeyCode::KEY_1),
+                (vec![KeyCode::KEY_LEFTCTRL, KeyCode::KEY_LEFTSHIFT], KeyCode::KEY_SLASH),
+                (vec![KeyCode::KEY_LEFTSHIFT], KeyCode::KEY_EQUAL),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_send_ternary() {
+        let content = "dark := true\n\
+                       theme := \"Light\" ; set by the installer\n\
+                       ^j::Send(dark ? \"night\" : \"day\")\n\
+                       ^k::Send(theme = \"dark\" ? \"night\" : \"day\")\n\
+                       ^l::Send(\"a\" . theme)\n\
+                       ^m::Send {Enter}\n\
+                       ^n::Send theme\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+        let sent: Vec<_> = config
+            .hotkeys
+            .iter()
+            .map(|hotkey| match &hotkey.action {
+                AhkAction::Send(keys) => keys.as_str(),
+                action => panic!("Expected Send, got {action:?}"),
+            })
+            .collect();
+
+        assert_eq!(sent, ["night", "day", "aLight", "{Enter}", "theme"]);
+    }
+
+    #[test]
+    fn test_parse_win_get() {
+        let content = "^j::\n\
+                       {\n\
+                       title := WinGetTitle(\"A\")\n\
+                       WinGetClass, class, ahk_exe firefox\n\
+                       Send(title)\n\
+                       Send(class)\n\
+                       }\n\
+                       ^k::Send(title)\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+        let AhkAction::Block(actions) = &config.hotkeys[0].action else {
+            panic!("Expected a block, got {:?}", config.hotkeys[0].action);
+        };
+        assert_eq!(
+            format!("{actions:?}"),
+            format!("{:?}", [
+                AhkAction::WinGet { target_var: "title".to_string(), what: WinGetInfo::Title, criteria: None },
+                AhkAction::WinGet {
+                    target_var: "class".to_string(),
+                    what: WinGetInfo::Class,
+                    criteria: Some(WindowCriteria::Exe("firefox".to_string())),
+                },
+                AhkAction::SendExpression("title".to_string()),
+                AhkAction::SendExpression("class".to_string()),
+            ])
+        );
+        // ^j's title isn't set for ^k
+        assert!(matches!(config.hotkeys[1].action, AhkAction::Send(_)));
+    }
+
+    #[test]
+    fn test_parse_hotstring_defaults() {
+        let content = "::btw::by the way\n\
+                       #Hotstring C *\n\
+                       ::Btw::By the way\n\
+                       :*0:ty::thank you\n\
+                       #Hotstring c0\n\
+                       :O:omw::on my way\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+        let options: Vec<_> = config
+            .hotstrings
+            .iter()
+            .map(|hotstring| (hotstring.case_sensitive, hotstring.immediate, hotstring.omit_char))
+            .collect();
+
+        assert_eq!(options, [(false, false, false), (true, true, false), (true, false, false), (false, true, true)]);
+    }
+
+    #[test]
+    fn test_parse_inline_comment_after_run() {
+        let content = "^j::Run(\"notify-send hello ;world\") ; launch notifier\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+
+        match &config.hotkeys[0].action {
+            AhkAction::Run(parts) => assert_eq!(parts.join(" "), "notify-send hello ;world"),
+            action => panic!("unexpected action: {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_parse_run_options() {
+        let content = "^j::Run \"firefox --new-window\",, Max\n\
+                       ^k::Run(\"/usr/bin/kate\", , \"Min\")\n\
+                       ^l::Run \"foot\",, Hide\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+
+        let firefox = WindowCriteria::Exe("firefox".to_string());
+        let expected = AhkAction::Block(vec![
+            AhkAction::Run(vec!["firefox".to_string(), "--new-window".to_string()]),
+            AhkAction::WinWait {
+                criteria: firefox.clone(),
+                timeout_ms: None,
+            },
+            AhkAction::WinActivate(firefox.clone()),
+            AhkAction::WinMaximize(firefox),
+        ]);
+        assert_eq!(format!("{:?}", config.hotkeys[0].action), format!("{:?}", expected));
+        match &config.hotkeys[1].action {
+            AhkAction::Block(actions) => assert!(matches!(
+                &actions[3],
+                AhkAction::WinMinimize(WindowCriteria::Exe(exe)) if exe == "kate"
+            )),
+            action => panic!("unexpected action: {:?}", action),
+        }
+        match &config.hotkeys[2].action {
+            AhkAction::Run(parts) => assert_eq!(parts, &["foot"]),
+            action => panic!("unexpected action: {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_parse_single_instance() {
+        let config = AhkParser::new().parse_file("#SingleInstance Force\n^j::Send(\"hi\")\n").unwrap();
+        assert_eq!(config.single_instance, Some(SingleInstance::Force));
+        let config = AhkParser::new().parse_file("#SingleInstance ; AHK's Prompt\n").unwrap();
+        assert_eq!(config.single_instance, Some(SingleInstance::Ignore));
+        assert!(AhkParser::new().parse_file("#SingleInstance Twice\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_set_title_match_mode() {
+        let content = "^a::Send(\"a\")\n\
+                       SetTitleMatchMode 1\n\
+                       ^b::Send(\"b\")\n\
+                       SetTitleMatchMode(\"RegEx\")\n\
+                       ^c::Send(\"c\")\n\
+                       SetTitleMatchMode, 3\n\
+                       SetTitleMatchMode Slow\n\
+                       ^d::\n\
+                       {\n\
+                           SetTitleMatchMode 2\n\
+                           WinActivate(\"Notes\")\n\
+                       }\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+        let modes: Vec<_> = config.hotkeys.iter().map(|hotkey| hotkey.title_match_mode).collect();
+        assert_eq!(
+            modes,
+            [None, Some(TitleMatchMode::Prefix), Some(TitleMatchMode::Regex), Some(TitleMatchMode::Exact)]
+        );
+        match &config.hotkeys[3].action {
+            AhkAction::Block(actions) => {
+                assert!(matches!(actions[0], AhkAction::SetTitleMatchMode(TitleMatchMode::Substring)))
+            }
+            action => panic!("expected a block, got {action:?}"),
+        }
+        assert!(AhkParser::new().parse_file("SetTitleMatchMode 4\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_group_add() {
+        let content = "GroupAdd(\"Browsers\", \"ahk_exe firefox\")\n\
+                       GroupAdd, browsers, ahk_exe chromium ; v1\n\
+                       ^w::WinClose(\"ahk_group Browsers\")\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+
+        assert_eq!(
+            format!("{:?}", config.window_groups["browsers"]),
+            format!("{:?}", [WindowCriteria::Exe("firefox".into()), WindowCriteria::Exe("chromium".into())])
+        );
+        assert!(matches!(
+            &config.hotkeys[0].action,
+            AhkAction::WinClose(WindowCriteria::Group(name)) if name == "Browsers"
+        ));
+        assert!(AhkParser::new().parse_file("GroupAdd(\"all\", \"ahk_group Browsers\")\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_hotif_exec() {
+        let content = "#HotIf Exec(\"pgrep -x obs\")\n^j::Send(\"hi\")\n#HotIf\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+
+        let context = config.hotkeys[0].context.as_deref().unwrap();
+        assert_eq!(
+            super::parse_exec_predicate(context),
+            Some(vec!["/bin/sh".to_string(), "-c".to_string(), "pgrep -x obs".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_parse_win_wait() {
+        match AhkParser::new().parse_action("WinWait(\"ahk_exe foo\", 500)").unwrap() {
+            AhkAction::WinWait {
+                criteria: WindowCriteria::Exe(exe),
+                timeout_ms,
+            } => {
+                assert_eq!(exe, "foo");
+                assert_eq!(timeout_ms, Some(500));
+            }
+            action => panic!("unexpected action: {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_parse_set_key_delay() {
+        for line in ["SetKeyDelay(50)", "SetKeyDelay, 50", "SetKeyDelay 50, 10"] {
+            match AhkParser::new().parse_action(line).unwrap() {
+                AhkAction::SetKeyDelay(ms) => assert_eq!(ms, 50),
+                action => panic!("unexpected action for {line}: {:?}", action),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_set_lock_state() {
+        let cases = [
+            ("SetCapsLockState, On", LockKey::CapsLock, LockState::On),
+            ("SetCapsLockState(\"AlwaysOff\")", LockKey::CapsLock, LockState::AlwaysOff),
+            ("SetNumLockState Toggle", LockKey::NumLock, LockState::Toggle),
+            ("SetScrollLockState, 0", LockKey::ScrollLock, LockState::Off),
+        ];
+        for (line, expected_lock, expected_state) in cases {
+            match AhkParser::new().parse_action(line).unwrap() {
+                AhkAction::SetLockState { lock, state } => assert_eq!((lock, state), (expected_lock, expected_state)),
+                action => panic!("unexpected action for {line}: {:?}", action),
+            }
+        }
+        assert!(AhkParser::new().parse_action("SetCapsLockState, Sometimes").is_err());
+    }
+
+    #[test]
+    fn test_parse_key_wait() {
+        let cases = [
+            ("KeyWait(\"Ctrl\")", KeyCode::KEY_LEFTCTRL, true, None),
+            ("KeyWait \"a\", \"D T0.5\"", KeyCode::KEY_A, false, Some(500)),
+            ("KeyWait, LButton, DT3", KeyCode::BTN_LEFT, false, Some(3000)),
+        ];
+        for (line, expected_key, expected_release, expected_timeout) in cases {
+            match AhkParser::new().parse_action(line).unwrap() {
+                AhkAction::KeyWait { key, wait_for_release, timeout_ms } => {
+                    assert_eq!((key, wait_for_release, timeout_ms), (expected_key, expected_release, expected_timeout))
+                }
+                action => panic!("unexpected action for {line}: {:?}", action),
+            }
+        }
+        assert!(AhkParser::new().parse_action("KeyWait(\"Ctrl\", \"X\")").is_err());
+    }
+
+    #[test]
+    fn test_parse_hook_prefix() {
+        let config = AhkParser::new().parse_file("$^j::Send(\"^j\")\n").unwrap();
+
+        assert_eq!(config.hotkeys[0].key, KeyCode::KEY_J);
+        assert_eq!(config.hotkeys[0].modifiers, vec![KeyCode::KEY_LEFTCTRL]);
+        assert!(!config.hotkeys[0].is_wildcard);
+    }
+
+    #[test]
+    fn test_parse_input_level() {
+        let content = "^j::Send(\"a\")\n#InputLevel 1\na::b\n#InputLevel 0\n\
+            ^k::{\n    SendLevel(1)\n    Send(\"a\")\n}\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+
+        let levels: Vec<u8> = config.hotkeys.iter().map(|hotkey| hotkey.input_level).collect();
+        assert_eq!(levels, vec![0, 1, 0]);
+        match &config.hotkeys[2].action {
+            AhkAction::Block(actions) => assert!(matches!(actions[0], AhkAction::SendLevel(1))),
+            action => panic!("unexpected action: {:?}", action),
+        }
+        assert!(AhkParser::new().parse_action("SendLevel, high").is_err());
+    }
+
+    #[test]
+    fn test_parse_remap() {
+        let config = AhkParser::new().parse_file("a::b\nx::bc\na::bc\nb::abcd\n").unwrap();
+
+        match &config.hotkeys[0].action {
+            AhkAction::Remap(keys) => assert_eq!(keys, &vec![KeyCode::KEY_B]),
+            action => panic!("unexpected action: {:?}", action),
+        }
+        match &config.hotkeys[1].action {
+            AhkAction::Remap(keys) => assert_eq!(keys, &vec![KeyCode::KEY_B, KeyCode::KEY_C]),
+            action => panic!("unexpected action: {:?}", action),
+        }
+        match &config.hotkeys[2].action {
+            AhkAction::Remap(keys) => assert_eq!(keys, &vec![KeyCode::KEY_B, KeyCode::KEY_C]),
+            action => panic!("unexpected action: {:?}", action),
+        }
+        match &config.hotkeys[3].action {
+            AhkAction::Remap(keys) => {
+                assert_eq!(keys, &vec![KeyCode::KEY_A, KeyCode::KEY_B, KeyCode::KEY_C, KeyCode::KEY_D])
+            }
+            action => panic!("unexpected action: {:?}", action),
+        }
+
+        // Command words aren't typed out
+        for command in ["Reload", "ExitApp", "Suspend", "Sleep100", "Exit", "run"] {
+            let action = AhkParser::new().parse_action(command);
+            assert!(!matches!(action, Ok(AhkAction::Remap(_))), "{command}: {action:?}");
+        }
+        assert!(AhkParser::new().parse_file("^r::Reload\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_ahk_pid_criteria() {
+        let content = "#HotIf WinActive(\"ahk_pid 4242\")\n^j::Send(\"hi\")\n#HotIf\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+
+        match &config.hotkeys[0].action {
+            AhkAction::IfWinActive { criteria: WindowCriteria::Pid(pid), .. } => assert_eq!(*pid, 4242),
+            action => panic!("unexpected action: {:?}", action),
+        }
+        assert!(AhkParser::new().parse_file("#HotIf WinActive(\"ahk_pid abc\")\n^j::Send(\"hi\")\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_bom_and_crlf() {
+        let content = "\u{feff}#HotIf WinActive(\"ahk_exe firefox\")\r\n^j::Send(\"hi\")\r\n#HotIf\r\n";
+        let config = AhkParser::new().parse_file(content).unwrap();
+
+        assert_eq!(config.hotkeys.len(), 1);
+        let hotkey = &config.hotkeys[0];
+        assert_eq!(hotkey.key, KeyCode::KEY_J);
+        assert_eq!(hotkey.modifiers, vec![KeyCode::KEY_LEFTCTRL]);
+        match &hotkey.action {
+            AhkAction::IfWinActive { criteria: WindowCriteria::Exe(exe), .. } => assert_eq!(exe, "firefox"),
+            action => panic!("unexpected action: {:?}", action),
+        }
+    }
+}
@@ -1,33 +1,93 @@
-use evdev::KeyCode;
+use crate::keys::{char_to_key_shift, name_to_key};
+use evdev::{KeyCode, RelativeAxisCode};
+use log::warn;
 
 #[derive(Debug, Clone)]
 pub enum SendToken {
     Key { key: KeyCode, modifiers: Vec<KeyCode> },
+    // {Key N}: the same key N times, modifiers held around the whole group
+    KeyRepeat { key: KeyCode, modifiers: Vec<KeyCode>, count: usize },
+    // {Key Nms}: the key held down for N milliseconds, e.g. for games that ignore quick taps
+    KeyHold { key: KeyCode, modifiers: Vec<KeyCode>, duration_ms: u64 },
+    // {Key down} or {Key up}: only the press or the release, e.g. to hold Ctrl across later keys
+    KeyState { key: KeyCode, modifiers: Vec<KeyCode>, pressed: bool },
+    // {Click ...}, {LButton}, {WheelDown N} and the like, modifiers held around all of them
+    Mouse { events: Vec<MouseEvent>, modifiers: Vec<KeyCode> },
     Text(String),
+    // {U+XXXX} or {Asc N}, a character by its code point; modifiers don't apply
+    Unicode(char),
+    // {Delay N}: a pause of N milliseconds before the next key, e.g. for a laggy app
+    Delay(u64),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MouseEvent {
+    Press(KeyCode),
+    Release(KeyCode),
+    // One wheel notch; REL_WHEEL is positive upwards, REL_HWHEEL to the right
+    Wheel { axis: RelativeAxisCode, value: i32 },
+    // Wayland doesn't let clients place the pointer, so {Click X Y} moves by X, Y instead
+    Move { x: i32, y: i32 },
+}
+
+// Bounds on what one Send string can produce, so a typo like {a 100000000} doesn't hang typing
+const MAX_TOKENS: usize = 10_000;
+const MAX_REPEAT: usize = 1_000;
+
+// `^`, `!`, `+` and `#` are modifiers of the next key, except where they're literal characters:
+// in braces (`{^}`, which works for any character), after a backtick (`` `^ ``), doubled (`^^`
+// is one `^`, with any modifiers before the pair), or at the end of the string. A leading `{Text}`
+// makes the whole rest literal, like SendText.
 pub fn parse_send_string(input: &str) -> Vec<SendToken> {
+    if let Some(literal) = strip_text_mode(input) {
+        return match literal {
+            "" => vec![],
+            literal => vec![SendToken::Text(literal.to_string())],
+        };
+    }
+
     let mut tokens = Vec::new();
     let mut chars = input.chars().peekable();
     let mut current_mods = Vec::new();
     let mut text_buffer = String::new();
 
     while let Some(&ch) = chars.peek() {
+        if tokens.len() >= MAX_TOKENS {
+            warn!("Send: only sending the first {MAX_TOKENS} keys of a longer string");
+            text_buffer.clear();
+            break;
+        }
         match ch {
             '^' | '!' | '+' | '#' => {
+                chars.next();
+                if chars.peek().is_none_or(|&next| next == ch) {
+                    chars.next();
+                    push_char(ch, &mut tokens, &mut current_mods, &mut text_buffer);
+                    continue;
+                }
+
                 // Flush text buffer before processing modifier
                 if !text_buffer.is_empty() {
                     tokens.push(SendToken::Text(text_buffer.clone()));
                     text_buffer.clear();
                 }
 
+                let modifier = match ch {
+                    '^' => KeyCode::KEY_LEFTCTRL,
+                    '!' => KeyCode::KEY_LEFTALT,
+                    '+' => KeyCode::KEY_LEFTSHIFT,
+                    _ => KeyCode::KEY_LEFTMETA,
+                };
+                // `+!+!a` holds each modifier once
+                if !current_mods.contains(&modifier) {
+                    current_mods.push(modifier);
+                }
+            }
+            '`' => {
                 chars.next();
-                match ch {
-                    '^' => current_mods.push(KeyCode::KEY_LEFTCTRL),
-                    '!' => current_mods.push(KeyCode::KEY_LEFTALT),
-                    '+' => current_mods.push(KeyCode::KEY_LEFTSHIFT),
-                    '#' => current_mods.push(KeyCode::KEY_LEFTMETA),
-                    _ => {}
+                match chars.next_if(|next| "^!+#{}".contains(*next)) {
+                    Some(escaped) => push_char(escaped, &mut tokens, &mut current_mods, &mut text_buffer),
+                    None => push_char('`', &mut tokens, &mut current_mods, &mut text_buffer),
                 }
             }
             '{' => {
@@ -39,43 +99,73 @@ pub fn parse_send_string(input: &str) -> Vec<SendToken> {
 
                 chars.next();
                 let mut key_name = String::new();
-                while let Some(&c) = chars.peek() {
+                // `{}}` is the brace itself
+                if let Some(c) = chars.next_if_eq(&'}') {
+                    key_name.push(c);
+                }
+                let mut closed = false;
+                for c in chars.by_ref() {
                     if c == '}' {
-                        chars.next();
+                        closed = true;
                         break;
                     }
                     key_name.push(c);
-                    chars.next();
                 }
 
-                if let Some(key) = parse_special_key(&key_name) {
+                if !closed {
+                    // An unterminated `{` names no key, so it's typed as it is
+                    for c in std::iter::once('{').chain(key_name.chars()) {
+                        push_char(c, &mut tokens, &mut current_mods, &mut text_buffer);
+                    }
+                } else if let Some(code_point) = parse_code_point(&key_name) {
+                    match code_point {
+                        Some(c) => tokens.push(SendToken::Unicode(c)),
+                        None => warn!("Send: {{{key_name}}} isn't a valid character, skipping it"),
+                    }
+                    current_mods.clear();
+                } else if let Some(delay_ms) = parse_delay(&key_name) {
+                    tokens.push(SendToken::Delay(delay_ms));
+                } else if let Some(events) = parse_mouse(&key_name) {
+                    tokens.push(SendToken::Mouse {
+                        events,
+                        modifiers: current_mods.clone(),
+                    });
+                    current_mods.clear();
+                } else if let Some((key, pressed)) = parse_key_state(&key_name) {
+                    tokens.push(SendToken::KeyState {
+                        key,
+                        modifiers: current_mods.clone(),
+                        pressed,
+                    });
+                    current_mods.clear();
+                } else if let Some((key, duration_ms)) = parse_key_hold(&key_name) {
+                    tokens.push(SendToken::KeyHold {
+                        key,
+                        modifiers: current_mods.clone(),
+                        duration_ms,
+                    });
+                    current_mods.clear();
+                } else if let Some((key, count)) = parse_key_repeat(&key_name) {
+                    tokens.push(SendToken::KeyRepeat {
+                        key,
+                        modifiers: current_mods.clone(),
+                        count,
+                    });
+                    current_mods.clear();
+                } else if let Some(key) = name_to_key(&key_name) {
                     tokens.push(SendToken::Key {
                         key,
                         modifiers: current_mods.clone(),
                     });
                     current_mods.clear();
+                } else if let (Some(c), None) = (key_name.chars().next(), key_name.chars().nth(1)) {
+                    // A character that needs Shift, like `{^}`
+                    push_char(c, &mut tokens, &mut current_mods, &mut text_buffer);
                 }
             }
             _ => {
                 let c = chars.next().unwrap();
-                // If we have modifiers, treat as a key
-                if !current_mods.is_empty() {
-                    if !text_buffer.is_empty() {
-                        tokens.push(SendToken::Text(text_buffer.clone()));
-                        text_buffer.clear();
-                    }
-
-                    if let Some(key) = char_to_key(c) {
-                        tokens.push(SendToken::Key {
-                            key,
-                            modifiers: current_mods.clone(),
-                        });
-                        current_mods.clear();
-                    }
-                } else {
-                    // No modifiers - accumulate as text
-                    text_buffer.push(c);
-                }
+                push_char(c, &mut tokens, &mut current_mods, &mut text_buffer);
             }
         }
     }
@@ -88,110 +178,313 @@ pub fn parse_send_string(input: &str) -> Vec<SendToken> {
     tokens
 }
 
-fn parse_special_key(name: &str) -> Option<KeyCode> {
-    match name.to_lowercase().as_str() {
-        "enter" | "return" => Some(KeyCode::KEY_ENTER),
-        "tab" => Some(KeyCode::KEY_TAB),
-        "space" => Some(KeyCode::KEY_SPACE),
-        "backspace" | "bs" => Some(KeyCode::KEY_BACKSPACE),
-        "delete" | "del" => Some(KeyCode::KEY_DELETE),
-        "escape" | "esc" => Some(KeyCode::KEY_ESC),
-        "up" => Some(KeyCode::KEY_UP),
-        "down" => Some(KeyCode::KEY_DOWN),
-        "left" => Some(KeyCode::KEY_LEFT),
-        "right" => Some(KeyCode::KEY_RIGHT),
-        "home" => Some(KeyCode::KEY_HOME),
-        "end" => Some(KeyCode::KEY_END),
-        "pgup" | "pageup" => Some(KeyCode::KEY_PAGEUP),
-        "pgdn" | "pagedown" => Some(KeyCode::KEY_PAGEDOWN),
-        "media_play_pause" => Some(KeyCode::KEY_PLAYPAUSE),
-        "media_next" => Some(KeyCode::KEY_NEXTSONG),
-        "media_prev" | "media_previous" => Some(KeyCode::KEY_PREVIOUSSONG),
-        "media_stop" => Some(KeyCode::KEY_STOPCD),
-        "volume_up" => Some(KeyCode::KEY_VOLUMEUP),
-        "volume_down" => Some(KeyCode::KEY_VOLUMEDOWN),
-        "volume_mute" | "mute" => Some(KeyCode::KEY_MUTE),
-        "a" => Some(KeyCode::KEY_A),
-        "b" => Some(KeyCode::KEY_B),
-        "c" => Some(KeyCode::KEY_C),
-        "d" => Some(KeyCode::KEY_D),
-        "e" => Some(KeyCode::KEY_E),
-        "f" => Some(KeyCode::KEY_F),
-        "g" => Some(KeyCode::KEY_G),
-        "h" => Some(KeyCode::KEY_H),
-        "i" => Some(KeyCode::KEY_I),
-        "j" => Some(KeyCode::KEY_J),
-        "k" => Some(KeyCode::KEY_K),
-        "l" => Some(KeyCode::KEY_L),
-        "m" => Some(KeyCode::KEY_M),
-        "n" => Some(KeyCode::KEY_N),
-        "o" => Some(KeyCode::KEY_O),
-        "p" => Some(KeyCode::KEY_P),
-        "q" => Some(KeyCode::KEY_Q),
-        "r" => Some(KeyCode::KEY_R),
-        "s" => Some(KeyCode::KEY_S),
-        "t" => Some(KeyCode::KEY_T),
-        "u" => Some(KeyCode::KEY_U),
-        "v" => Some(KeyCode::KEY_V),
-        "w" => Some(KeyCode::KEY_W),
-        "x" => Some(KeyCode::KEY_X),
-        "y" => Some(KeyCode::KEY_Y),
-        "z" => Some(KeyCode::KEY_Z),
-        "f1" => Some(KeyCode::KEY_F1),
-        "f2" => Some(KeyCode::KEY_F2),
-        "f3" => Some(KeyCode::KEY_F3),
-        "f4" => Some(KeyCode::KEY_F4),
-        "f5" => Some(KeyCode::KEY_F5),
-        "f6" => Some(KeyCode::KEY_F6),
-        "f7" => Some(KeyCode::KEY_F7),
-        "f8" => Some(KeyCode::KEY_F8),
-        "f9" => Some(KeyCode::KEY_F9),
-        "f10" => Some(KeyCode::KEY_F10),
-        "f11" => Some(KeyCode::KEY_F11),
-        "f12" => Some(KeyCode::KEY_F12),
-        _ => None,
+// A character as typed: text, or with pending modifiers a key with them
+fn push_char(c: char, tokens: &mut Vec<SendToken>, current_mods: &mut Vec<KeyCode>, text_buffer: &mut String) {
+    // If we have modifiers, treat as a key
+    if !current_mods.is_empty() {
+        if !text_buffer.is_empty() {
+            tokens.push(SendToken::Text(text_buffer.clone()));
+            text_buffer.clear();
+        }
+
+        if let Some((key, shift)) = char_to_key_shift(c) {
+            let mut modifiers = std::mem::take(current_mods);
+            // Keep the case the way Text does: capitals (and shifted symbols) get Shift
+            if shift && !modifiers.contains(&KeyCode::KEY_LEFTSHIFT) {
+                modifiers.push(KeyCode::KEY_LEFTSHIFT);
+            }
+            tokens.push(SendToken::Key { key, modifiers });
+        }
+    } else {
+        // No modifiers - accumulate as text
+        text_buffer.push(c);
     }
 }
 
-fn char_to_key(c: char) -> Option<KeyCode> {
-    match c.to_ascii_lowercase() {
-        'a' => Some(KeyCode::KEY_A),
-        'b' => Some(KeyCode::KEY_B),
-        'c' => Some(KeyCode::KEY_C),
-        'd' => Some(KeyCode::KEY_D),
-        'e' => Some(KeyCode::KEY_E),
-        'f' => Some(KeyCode::KEY_F),
-        'g' => Some(KeyCode::KEY_G),
-        'h' => Some(KeyCode::KEY_H),
-        'i' => Some(KeyCode::KEY_I),
-        'j' => Some(KeyCode::KEY_J),
-        'k' => Some(KeyCode::KEY_K),
-        'l' => Some(KeyCode::KEY_L),
-        'm' => Some(KeyCode::KEY_M),
-        'n' => Some(KeyCode::KEY_N),
-        'o' => Some(KeyCode::KEY_O),
-        'p' => Some(KeyCode::KEY_P),
-        'q' => Some(KeyCode::KEY_Q),
-        'r' => Some(KeyCode::KEY_R),
-        's' => Some(KeyCode::KEY_S),
-        't' => Some(KeyCode::KEY_T),
-        'u' => Some(KeyCode::KEY_U),
-        'v' => Some(KeyCode::KEY_V),
-        'w' => Some(KeyCode::KEY_W),
-        'x' => Some(KeyCode::KEY_X),
-        'y' => Some(KeyCode::KEY_Y),
-        'z' => Some(KeyCode::KEY_Z),
-        '0' => Some(KeyCode::KEY_0),
-        '1' => Some(KeyCode::KEY_1),
-        '2' => Some(KeyCode::KEY_2),
-        '3' => Some(KeyCode::KEY_3),
-        '4' => Some(KeyCode::KEY_4),
-        '5' => Some(KeyCode::KEY_5),
-        '6' => Some(KeyCode::KEY_6),
-        '7' => Some(KeyCode::KEY_7),
-        '8' => Some(KeyCode::KEY_8),
-        '9' => Some(KeyCode::KEY_9),
-        ' ' => Some(KeyCode::KEY_SPACE),
+// `{Blind}` at the start of a Send string: leave the modifiers the user is holding alone
+pub fn split_blind(input: &str) -> (bool, &str) {
+    match input.get(..7) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("{Blind}") => (true, &input[7..]),
+        _ => (false, input),
+    }
+}
+
+// `{Text}` at the start of a Send string: the rest is typed as it is, braces and `^!+#` included
+fn strip_text_mode(input: &str) -> Option<&str> {
+    match input.get(..6) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("{Text}") => Some(&input[6..]),
         _ => None,
     }
 }
+
+// `U+00E9` (hex) or `Asc 233` (decimal, taken as a Unicode code point rather than a code page's);
+// Some(None) when the number isn't a character
+fn parse_code_point(name: &str) -> Option<Option<char>> {
+    let name = name.trim();
+    let (digits, radix) = match name.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("U+") => (&name[2..], 16),
+        _ => {
+            let (command, number) = name.split_once(char::is_whitespace)?;
+            if !command.eq_ignore_ascii_case("Asc") {
+                return None;
+            }
+            (number.trim(), 10)
+        }
+    };
+    Some(u32::from_str_radix(digits, radix).ok().and_then(char::from_u32))
+}
+
+fn parse_delay(name: &str) -> Option<u64> {
+    let (command, delay_ms) = name.trim().split_once(char::is_whitespace)?;
+    if !command.eq_ignore_ascii_case("Delay") {
+        return None;
+    }
+    delay_ms.trim().parse().ok()
+}
+
+fn parse_key_state(name: &str) -> Option<(KeyCode, bool)> {
+    let (key_name, state) = name.trim().rsplit_once(char::is_whitespace)?;
+    let pressed = match state.to_lowercase().as_str() {
+        "down" => true,
+        "up" => false,
+        _ => return None,
+    };
+    let key = name_to_key(key_name.trim())?;
+    Some((key, pressed))
+}
+
+fn parse_key_hold(name: &str) -> Option<(KeyCode, u64)> {
+    let (key_name, duration) = name.trim().rsplit_once(char::is_whitespace)?;
+    let duration_ms = duration.to_lowercase().strip_suffix("ms")?.parse::<u64>().ok()?;
+    let key = name_to_key(key_name.trim())?;
+    Some((key, duration_ms))
+}
+
+fn parse_key_repeat(name: &str) -> Option<(KeyCode, usize)> {
+    let (key_name, count) = name.trim().rsplit_once(char::is_whitespace)?;
+    let count = count.parse::<usize>().ok()?;
+    let key = name_to_key(key_name.trim())?;
+    Some((key, capped_repeat(count)))
+}
+
+fn capped_repeat(count: usize) -> usize {
+    if count > MAX_REPEAT {
+        warn!("Send: repeating {count} times is too many, repeating {MAX_REPEAT} times");
+    }
+    count.min(MAX_REPEAT)
+}
+
+enum MouseTarget {
+    Button(KeyCode),
+    Wheel(RelativeAxisCode, i32),
+}
+
+fn mouse_target(name: &str) -> Option<MouseTarget> {
+    let target = match name.to_lowercase().as_str() {
+        "left" | "l" | "lbutton" => MouseTarget::Button(KeyCode::BTN_LEFT),
+        "right" | "r" | "rbutton" => MouseTarget::Button(KeyCode::BTN_RIGHT),
+        "middle" | "m" | "mbutton" => MouseTarget::Button(KeyCode::BTN_MIDDLE),
+        "x1" | "xbutton1" => MouseTarget::Button(KeyCode::BTN_SIDE),
+        "x2" | "xbutton2" => MouseTarget::Button(KeyCode::BTN_EXTRA),
+        "wheelup" | "wu" => MouseTarget::Wheel(RelativeAxisCode::REL_WHEEL, 1),
+        "wheeldown" | "wd" => MouseTarget::Wheel(RelativeAxisCode::REL_WHEEL, -1),
+        "wheelleft" | "wl" => MouseTarget::Wheel(RelativeAxisCode::REL_HWHEEL, -1),
+        "wheelright" | "wr" => MouseTarget::Wheel(RelativeAxisCode::REL_HWHEEL, 1),
+        _ => return None,
+    };
+    Some(target)
+}
+
+// `{Click [X Y] [Button] [Count] [Down|Up]}`, or a button or wheel name with an optional count
+// or Down/Up, e.g. `{LButton down}` or `{WheelDown 3}`
+fn parse_mouse(name: &str) -> Option<Vec<MouseEvent>> {
+    let mut words = name.split(|c: char| c.is_whitespace() || c == ',').filter(|word| !word.is_empty());
+    let first = words.next()?;
+    let is_click = first.eq_ignore_ascii_case("click");
+    let mut target = if is_click {
+        MouseTarget::Button(KeyCode::BTN_LEFT)
+    } else {
+        let target = mouse_target(first)?;
+        // Short names like `L` or `WU` are only options of Click; `{L}` is the key
+        if first.len() <= 2 {
+            return None;
+        }
+        target
+    };
+
+    let mut numbers = vec![];
+    let mut down_up = None;
+    for word in words {
+        if let Ok(number) = word.parse::<i32>() {
+            numbers.push(number);
+        } else if word.eq_ignore_ascii_case("down") || word.eq_ignore_ascii_case("d") {
+            down_up = Some(true);
+        } else if word.eq_ignore_ascii_case("up") || word.eq_ignore_ascii_case("u") {
+            down_up = Some(false);
+        } else if word.eq_ignore_ascii_case("rel") || word.eq_ignore_ascii_case("relative") {
+            // Moves are relative either way
+        } else if is_click {
+            target = mouse_target(word)?;
+        } else {
+            return None;
+        }
+    }
+
+    // One number is the count, two are X Y, three are X Y and the count
+    let (position, count) = match (is_click, numbers.as_slice()) {
+        (_, []) => (None, 1),
+        (_, &[count]) => (None, count),
+        (true, &[x, y]) => (Some((x, y)), 1),
+        (true, &[x, y, count]) => (Some((x, y)), count),
+        _ => return None,
+    };
+    let count = capped_repeat(count.max(0) as usize);
+    let mut events: Vec<MouseEvent> = position.map(|(x, y)| MouseEvent::Move { x, y }).into_iter().collect();
+    match target {
+        MouseTarget::Button(button) => match down_up {
+            Some(true) => events.push(MouseEvent::Press(button)),
+            Some(false) => events.push(MouseEvent::Release(button)),
+            None => {
+                for _ in 0..count {
+                    events.extend([MouseEvent::Press(button), MouseEvent::Release(button)]);
+                }
+            }
+        },
+        MouseTarget::Wheel(axis, value) => {
+            events.extend((0..count).map(|_| MouseEvent::Wheel { axis, value }));
+        }
+    }
+    Some(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_send_string, MouseEvent, SendToken, MAX_REPEAT, MAX_TOKENS};
+    use evdev::{KeyCode, RelativeAxisCode};
+
+    fn mouse_events(input: &str) -> Vec<MouseEvent> {
+        match &parse_send_string(input)[..] {
+            [SendToken::Mouse { events, .. }] => events.clone(),
+            tokens => panic!("unexpected tokens for {input}: {tokens:?}"),
+        }
+    }
+
+    fn text(input: &str) -> String {
+        parse_send_string(input)
+            .into_iter()
+            .map(|token| match token {
+                SendToken::Text(text) => text,
+                token => panic!("unexpected token for {input}: {token:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_key_hold() {
+        let tokens = parse_send_string("+{Right 250ms}{a 5}");
+        assert_eq!(
+            format!("{tokens:?}"),
+            format!(
+                "{:?}",
+                [
+                    SendToken::KeyHold {
+                        key: KeyCode::KEY_RIGHT,
+                        modifiers: vec![KeyCode::KEY_LEFTSHIFT],
+                        duration_ms: 250,
+                    },
+                    SendToken::KeyRepeat {
+                        key: KeyCode::KEY_A,
+                        modifiers: vec![],
+                        count: 5,
+                    },
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_literal_modifier_symbols() {
+        for symbol in ['^', '!', '+', '#'] {
+            assert_eq!(text(&format!("{symbol}{symbol}a")), format!("{symbol}a"));
+            assert_eq!(text(&format!("a{{{symbol}}}")), format!("a{symbol}"));
+            assert_eq!(text(&format!("`{symbol}a")), format!("{symbol}a"));
+            assert_eq!(text(&format!("a{symbol}")), format!("a{symbol}"));
+        }
+        assert_eq!(text("{{}`}"), "{}");
+
+        // Modifiers before a doubled symbol apply to it
+        match &parse_send_string("!^^")[..] {
+            [SendToken::Key { key, modifiers }] => {
+                assert_eq!(*key, KeyCode::KEY_6);
+                assert_eq!(modifiers, &[KeyCode::KEY_LEFTALT, KeyCode::KEY_LEFTSHIFT]);
+            }
+            tokens => panic!("unexpected tokens for !^^: {tokens:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_text_mode() {
+        assert_eq!(text("{Text}^!a"), "^!a");
+        assert_eq!(text("{text}{Enter} #1 `+ {U+00E9} café"), "{Enter} #1 `+ {U+00E9} café");
+        assert!(parse_send_string("{Text}").is_empty());
+        // Only at the start
+        assert_eq!(text("a{Text}"), "a");
+    }
+
+    #[test]
+    fn test_parse_code_points() {
+        for input in ["{U+00E9}", "{u+e9}", "{Asc 233}"] {
+            match &parse_send_string(input)[..] {
+                [SendToken::Unicode(c)] => assert_eq!(*c, '\u{00e9}'),
+                tokens => panic!("unexpected tokens for {input}: {tokens:?}"),
+            }
+        }
+        // Surrogates aren't characters
+        assert_eq!(text("a{U+D800}b"), "ab");
+    }
+
+    #[test]
+    fn test_parse_mouse_tokens() {
+        let wheel_down = MouseEvent::Wheel {
+            axis: RelativeAxisCode::REL_WHEEL,
+            value: -1,
+        };
+        assert_eq!(mouse_events("{WheelDown 3}"), [wheel_down; 3]);
+
+        let left = [MouseEvent::Press(KeyCode::BTN_LEFT), MouseEvent::Release(KeyCode::BTN_LEFT)];
+        assert_eq!(mouse_events("{LButton}"), left);
+        assert_eq!(mouse_events("{Click 100 -20}"), [&[MouseEvent::Move { x: 100, y: -20 }], &left[..]].concat());
+        assert_eq!(mouse_events("{Click Right Down}"), [MouseEvent::Press(KeyCode::BTN_RIGHT)]);
+        assert_eq!(mouse_events("{Click 5 5 0}"), [MouseEvent::Move { x: 5, y: 5 }]);
+    }
+
+    #[test]
+    fn test_parse_bounds() {
+        // An unterminated `{` is text, modifiers applying to the brace
+        assert_eq!(text("a{Enter"), "a{Enter");
+        match &parse_send_string("^{x")[..] {
+            [SendToken::Key { key, modifiers }, SendToken::Text(rest)] => {
+                assert_eq!(*key, KeyCode::KEY_LEFTBRACE);
+                assert_eq!(modifiers, &[KeyCode::KEY_LEFTCTRL, KeyCode::KEY_LEFTSHIFT]);
+                assert_eq!(rest, "x");
+            }
+            tokens => panic!("unexpected tokens for ^{{x: {tokens:?}"),
+        }
+
+        match &parse_send_string("{a 99999999999}")[..] {
+            [SendToken::KeyRepeat { count, .. }] => assert_eq!(*count, MAX_REPEAT),
+            tokens => panic!("unexpected tokens for a giant repeat: {tokens:?}"),
+        }
+        assert_eq!(mouse_events("{WheelUp 2000000000}").len(), MAX_REPEAT);
+
+        match &parse_send_string(&format!("{}a", "+!^#".repeat(10_000)))[..] {
+            [SendToken::Key { key, modifiers }] => {
+                assert_eq!(*key, KeyCode::KEY_A);
+                assert_eq!(modifiers.len(), 4);
+            }
+            tokens => panic!("unexpected tokens for nested modifiers: {tokens:?}"),
+        }
+        assert_eq!(parse_send_string(&"^a{Enter}".repeat(MAX_TOKENS)).len(), MAX_TOKENS);
+        assert_eq!(text(&"{".repeat(100_000)).len(), 100_000);
+    }
+}
@@ -1,10 +1,20 @@
 
+use crate::single_instance::SingleInstance;
 use evdev::KeyCode;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::str::FromStr;
 
 #[derive(Debug, Clone)]
 pub struct AhkConfig {
     pub hotkeys: Vec<AhkHotkey>,
     pub hotstrings: Vec<AhkHotstring>,
+    pub single_instance: Option<SingleInstance>,
+    // GroupAdd's window groups, keyed by lowercased name
+    pub window_groups: HashMap<String, Vec<WindowCriteria>>,
+    // Top-level `name := value` assignments, keyed by lowercased name
+    pub variables: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone)]
@@ -14,6 +24,10 @@ pub struct AhkHotkey {
     pub action: AhkAction,
     pub context: Option<String>,
     pub is_wildcard: bool,
+    // #InputLevel: Sends at a higher SendLevel can trigger this hotkey
+    pub input_level: u8,
+    // The SetTitleMatchMode above this hotkey, if any
+    pub title_match_mode: Option<TitleMatchMode>,
 }
 
 #[derive(Debug, Clone)]
@@ -27,30 +41,162 @@ pub struct AhkHotstring {
     pub context: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+// Also the `{ ahk: ... }` keymap action, e.g. `{ ahk: { send: "{Down}" } }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum AhkAction {
     Run(Vec<String>),
     Send(String),
+    // Send whose argument uses a variable only set when the hotkey runs, such as WinGet's
+    SendExpression(String),
+    #[serde(serialize_with = "serialize_keys", deserialize_with = "deserialize_keys")]
     Remap(Vec<KeyCode>),
     Sleep(u64),
+    SetKeyDelay(u64),        // delay (ms) between keystrokes of later Sends in the same hotkey
+    SendLevel(u8),           // later Sends in the same hotkey can trigger hotkeys below this #InputLevel
+    SetTitleMatchMode(TitleMatchMode), // how later window titles in the same hotkey match
     Shell(String),           // NEW: raw shell script
     Block(Vec<AhkAction>),   // NEW: sequence of actions
     WinActivate(WindowCriteria),
     WinClose(WindowCriteria),
+    WinMaximize(WindowCriteria),
+    WinMinimize(WindowCriteria),
     IfWinActive {
         criteria: WindowCriteria,
         then_actions: Vec<AhkAction>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
         else_actions: Option<Vec<AhkAction>>,
     },
-    WinWaitActive { criteria: WindowCriteria, timeout_ms: Option<u64> },
+    WinWaitActive {
+        criteria: WindowCriteria,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
+    WinWait {
+        criteria: WindowCriteria,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
+    SetLockState { lock: LockKey, state: LockState },  // SetCapsLockState, On
+    // title := WinGetTitle("A"), for later SendExpressions in the same hotkey; None is the active window
+    WinGet {
+        target_var: String,
+        what: WinGetInfo,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        criteria: Option<WindowCriteria>,
+    },
+    KeyWait {
+        #[serde(serialize_with = "serialize_key", deserialize_with = "deserialize_key")]
+        key: KeyCode,
+        // Otherwise until it's pressed (the D option)
+        wait_for_release: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
 
 }
 
-#[derive(Debug, Clone)]
+// evdev names such as `KEY_A`
+fn serialize_keys<S: Serializer>(keys: &[KeyCode], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(keys.iter().map(|key| format!("{key:?}")))
+}
+
+fn deserialize_keys<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<KeyCode>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|name| KeyCode::from_str(name).map_err(|_| serde::de::Error::custom(format!("unknown key '{name}'"))))
+        .collect()
+}
+
+fn serialize_key<S: Serializer>(key: &KeyCode, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{key:?}"))
+}
+
+fn deserialize_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<KeyCode, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    KeyCode::from_str(&name).map_err(|_| serde::de::Error::custom(format!("unknown key '{name}'")))
+}
+
+// How a WinTitle's title matches the window's, as set by SetTitleMatchMode 1, 2, 3 or RegEx.
+// Case-sensitive, as in AHK.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleMatchMode {
+    Prefix,
+    // AHK v2's default
+    #[default]
+    Substring,
+    Exact,
+    Regex,
+}
+
+impl TitleMatchMode {
+    // `regex` is `pattern` compiled when the config loaded, None if it isn't a valid regex
+    pub fn matches(&self, pattern: &str, regex: Option<&Regex>, title: &str) -> bool {
+        match self {
+            TitleMatchMode::Prefix => title.starts_with(pattern),
+            TitleMatchMode::Substring => title.contains(pattern),
+            TitleMatchMode::Exact => title == pattern,
+            TitleMatchMode::Regex => regex.is_some_and(|regex| regex.is_match(title)),
+        }
+    }
+}
+
+// What WinGetTitle, WinGetClass and WinGetPID get
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WinGetInfo {
+    Title,
+    Class,
+    Pid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockKey {
+    CapsLock,
+    NumLock,
+    ScrollLock,
+}
+
+impl LockKey {
+    pub fn key(&self) -> KeyCode {
+        match self {
+            LockKey::CapsLock => KeyCode::KEY_CAPSLOCK,
+            LockKey::NumLock => KeyCode::KEY_NUMLOCK,
+            LockKey::ScrollLock => KeyCode::KEY_SCROLLLOCK,
+        }
+    }
+
+    // Suffix of the keyboard LEDs under /sys/class/leds, e.g. `input3::capslock`
+    pub fn led_name(&self) -> &'static str {
+        match self {
+            LockKey::CapsLock => "capslock",
+            LockKey::NumLock => "numlock",
+            LockKey::ScrollLock => "scrolllock",
+        }
+    }
+}
+
+// AlwaysOn/AlwaysOff set the state like On/Off; the lock key itself isn't disabled
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockState {
+    On,
+    Off,
+    AlwaysOn,
+    AlwaysOff,
+    Toggle,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum WindowCriteria {
     Title(String),      // WinActivate("Firefox")
     Class(String),      // WinActivate("ahk_class dolphin")
     Exe(String),        // WinActivate("ahk_exe google-chrome")
+    Pid(u32),           // WinActivate("ahk_pid 4242")
+    Group(String),      // WinActivate("ahk_group browsers")
 }
 
 #[derive(Debug, Clone)]
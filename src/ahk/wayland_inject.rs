@@ -1,31 +1,272 @@
+use crate::event::{KeyEvent, KeyValue};
 use anyhow::Result;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use evdev::KeyCode as Key;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
 use wait_timeout::ChildExt;
 
-pub struct WaylandTextInjector;
+/// How to trigger a paste in the focused application.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasteMethod {
+    CtrlV,
+    ShiftInsert,
+    // Pastes the primary selection rather than the clipboard
+    MiddleClick,
+}
+
+impl PasteMethod {
+    pub fn key_events(self) -> Vec<KeyEvent> {
+        let (modifier, key) = match self {
+            PasteMethod::CtrlV => (Some(Key::KEY_LEFTCTRL), Key::KEY_V),
+            PasteMethod::ShiftInsert => (Some(Key::KEY_LEFTSHIFT), Key::KEY_INSERT),
+            PasteMethod::MiddleClick => (None, Key::BTN_MIDDLE),
+        };
+        let mut events = Vec::new();
+        if let Some(modifier) = modifier {
+            events.push(KeyEvent::new(modifier, KeyValue::Press));
+        }
+        events.push(KeyEvent::new(key, KeyValue::Press));
+        events.push(KeyEvent::new(key, KeyValue::Release));
+        if let Some(modifier) = modifier {
+            events.push(KeyEvent::new(modifier, KeyValue::Release));
+        }
+        events
+    }
+}
+
+/// Source of the current selection; lets callers swap in a fake for tests.
+pub trait TextInjector {
+    fn get_primary(&self) -> Result<Option<String>>;
+}
+
+/// wl-copy or wl-paste could not be started because wl-clipboard is not installed.
+#[derive(Debug)]
+pub struct MissingClipboardTool(pub String);
+
+impl fmt::Display for MissingClipboardTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} not found; install wl-clipboard to use text expansion", self.0)
+    }
+}
+
+impl std::error::Error for MissingClipboardTool {}
+
+// Spawns a wl-clipboard tool, turning a missing binary into `MissingClipboardTool`
+fn spawn_clipboard_tool(command: &mut Command) -> Result<Child> {
+    command.spawn().map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            let program = command.get_program().to_string_lossy().to_string();
+            anyhow::Error::new(MissingClipboardTool(program))
+        } else {
+            e.into()
+        }
+    })
+}
+
+/// Clipboard access through wl-clipboard, or any tool taking the same arguments.
+pub struct WaylandTextInjector {
+    copy_cmd: String,
+    paste_cmd: String,
+}
 
 impl WaylandTextInjector {
-    pub fn copy_to_clipboard(text: &str) -> Result<()> {
-        let timeout = std::time::Duration::from_millis(500);
-        let mut child = Command::new("wl-copy")
+    pub fn new(copy_cmd: &str, paste_cmd: &str) -> Self {
+        WaylandTextInjector {
+            copy_cmd: copy_cmd.to_string(),
+            paste_cmd: paste_cmd.to_string(),
+        }
+    }
+
+    pub fn copy_to_clipboard(&self, text: &str) -> Result<()> {
+        copy_with(&self.copy_cmd, text)
+    }
+}
+
+impl Default for WaylandTextInjector {
+    fn default() -> Self {
+        Self::new("wl-copy", "wl-paste")
+    }
+}
+
+fn copy_with(program: &str, text: &str) -> Result<()> {
+    let timeout = std::time::Duration::from_millis(500);
+    let mut child = spawn_clipboard_tool(
+        Command::new(program)
             .arg("--type")
             .arg("text/plain")
-            .stdin(Stdio::piped())
-            .spawn()?;
+            .stdin(Stdio::piped()),
+    )?;
+
+    if let Some(stdin) = child.stdin.as_mut() {
+        stdin.write_all(text.as_bytes())?;
+    }
 
-        if let Some(stdin) = child.stdin.as_mut() {
-            stdin.write_all(text.as_bytes())?;
+    match child.wait_timeout(timeout)? {
+        Some(status) if status.success() => Ok(()),
+        Some(_) => Err(anyhow::anyhow!("{program} failed")),
+        None => {
+            child.kill()?;
+            Err(anyhow::anyhow!("{program} timed out"))
         }
+    }
+}
+
+impl TextInjector for WaylandTextInjector {
+    fn get_primary(&self) -> Result<Option<String>> {
+        let output = spawn_clipboard_tool(
+            Command::new(&self.paste_cmd)
+                .arg("--primary")
+                .arg("--no-newline")
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null()),
+        )?
+        .wait_with_output()?;
+
+        // wl-paste exits non-zero when nothing is selected
+        if output.status.success() {
+            Ok(Some(String::from_utf8_lossy(&output.stdout).to_string()))
+        } else {
+            Ok(None)
+        }
+    }
+}
 
-        match child.wait_timeout(timeout)? {
-            Some(status) if status.success() => Ok(()),
-            Some(_) => Err(anyhow::anyhow!("wl-copy failed")),
-            None => {
-                child.kill()?;
-                Err(anyhow::anyhow!("wl-copy timed out"))
-            }
+/// Runs `command` with the primary selection on stdin (and in place of any `$1`
+/// argument) and returns its stdout. Returns `None` when nothing is selected, or when
+/// the command runs longer than `timeout` and is killed.
+pub fn pipe_selection(injector: &dyn TextInjector, command: &[String], timeout: Duration) -> Result<Option<String>> {
+    let selection = match injector.get_primary()? {
+        Some(selection) if !selection.is_empty() => selection,
+        _ => {
+            warn!("pipe_selection: no primary selection, skipping {:?}", command);
+            return Ok(None);
         }
+    };
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("pipe_selection: empty command"))?;
+    let args: Vec<&str> = args
+        .iter()
+        .map(|arg| if arg == "$1" { selection.as_str() } else { arg.as_str() })
+        .collect();
+
+    let mut child = Command::new(program)
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(selection.as_bytes())?;
+    }
+    // Read on another thread so a command filling the pipe doesn't stall while it's waited for
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    if child.wait_timeout(timeout)?.is_none() {
+        warn!("pipe_selection: {:?} didn't finish within {:?}, killing it", command, timeout);
+        child.kill()?;
+        child.wait()?;
+        return Ok(None);
+    }
+    let output = reader.join().map_err(|_| anyhow::anyhow!("pipe_selection: reading the output panicked"))??;
+    Ok(Some(String::from_utf8_lossy(&output).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_with, pipe_selection, MissingClipboardTool, PasteMethod, TextInjector, WaylandTextInjector};
+    use crate::event::{KeyEvent, KeyValue};
+    use anyhow::Result;
+    use evdev::KeyCode as Key;
+    use std::time::{Duration, Instant};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    struct FakeInjector(Option<&'static str>);
+
+    impl TextInjector for FakeInjector {
+        fn get_primary(&self) -> Result<Option<String>> {
+            Ok(self.0.map(String::from))
+        }
+    }
+
+    #[test]
+    fn test_pipe_selection_to_cat() {
+        let output = pipe_selection(&FakeInjector(Some("hello world")), &["cat".to_string()], TIMEOUT).unwrap();
+        assert_eq!(output.as_deref(), Some("hello world"));
+    }
+
+    #[test]
+    fn test_pipe_selection_timeout() {
+        let command = ["sleep".to_string(), "10".to_string()];
+        let start = Instant::now();
+        let output = pipe_selection(&FakeInjector(Some("hello")), &command, Duration::from_millis(100)).unwrap();
+        assert_eq!(output, None);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_pipe_selection_without_selection() {
+        let output = pipe_selection(&FakeInjector(None), &["cat".to_string()], TIMEOUT).unwrap();
+        assert_eq!(output, None);
+    }
+
+    #[test]
+    fn test_copy_without_wl_clipboard() {
+        let error = copy_with("/nonexistent/wl-copy", "hello").unwrap_err();
+        assert!(error.is::<MissingClipboardTool>());
+        assert!(error.to_string().contains("install wl-clipboard"));
+    }
+
+    #[test]
+    fn test_custom_clipboard_commands() {
+        // echo prints the arguments meant for wl-paste, showing it ran in its place
+        let injector = WaylandTextInjector::new("/nonexistent/copy", "echo");
+        assert_eq!(injector.get_primary().unwrap().as_deref(), Some("--primary --no-newline\n"));
+        let error = injector.copy_to_clipboard("hello").unwrap_err();
+        assert!(error.to_string().starts_with("/nonexistent/copy not found"));
+    }
+
+    #[test]
+    fn test_paste_method_key_events() {
+        let events = |method: PasteMethod| format!("{:?}", method.key_events());
+        let expected = |keys: Vec<(Key, KeyValue)>| {
+            let keys: Vec<KeyEvent> = keys.into_iter().map(|(key, value)| KeyEvent::new(key, value)).collect();
+            format!("{keys:?}")
+        };
+
+        assert_eq!(
+            events(PasteMethod::CtrlV),
+            expected(vec![
+                (Key::KEY_LEFTCTRL, KeyValue::Press),
+                (Key::KEY_V, KeyValue::Press),
+                (Key::KEY_V, KeyValue::Release),
+                (Key::KEY_LEFTCTRL, KeyValue::Release),
+            ])
+        );
+        assert_eq!(
+            events(PasteMethod::ShiftInsert),
+            expected(vec![
+                (Key::KEY_LEFTSHIFT, KeyValue::Press),
+                (Key::KEY_INSERT, KeyValue::Press),
+                (Key::KEY_INSERT, KeyValue::Release),
+                (Key::KEY_LEFTSHIFT, KeyValue::Release),
+            ])
+        );
+        assert_eq!(
+            events(PasteMethod::MiddleClick),
+            expected(vec![(Key::BTN_MIDDLE, KeyValue::Press), (Key::BTN_MIDDLE, KeyValue::Release)])
+        );
     }
 }
 
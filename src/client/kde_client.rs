@@ -1,19 +1,33 @@
 use log::{warn, info};
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use dbus::blocking::Connection;
 use dbus::message::MatchRule;
 
+use crate::client::reconnect::{reconnect_loop, Backoff};
 use crate::client::Client;
 
+// Without the KWin script no window signal ever arrives, so after this long kdotool is polled instead
+const SIGNAL_WAIT: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
 pub struct KdeClient {
     active_window: Arc<Mutex<ActiveWindow>>,
+    connected: Arc<AtomicBool>,
+    // While kdotool is polled, the window stays current without D-Bus signals
+    polling: Arc<AtomicBool>,
+    // Logged once per disconnect rather than on every lookup
+    warned_stale: bool,
 }
 
 #[derive(Clone, Debug)]
 struct ActiveWindow {
     res_class: String,
+    // WM_CLASS instance, empty when the KWin script predates it or kdotool is polled
+    res_name: String,
     title: String,
 }
 
@@ -22,90 +36,160 @@ impl KdeClient {
         let active_window = Arc::new(Mutex::new(ActiveWindow {
             title: String::new(),
             res_class: String::new(),
+            res_name: String::new(),
         }));
+        let connected = Arc::new(AtomicBool::new(false));
+        let signaled = Arc::new(AtomicBool::new(false));
+        let polling = Arc::new(AtomicBool::new(false));
 
         let window_clone = Arc::clone(&active_window);
+        let connected_clone = Arc::clone(&connected);
+        let signaled_clone = Arc::clone(&signaled);
         
         thread::spawn(move || {
-            listen_for_window_changes(window_clone);
+            info!("KDE Client: Starting D-Bus listener");
+            reconnect_loop(
+                "KDE Client D-Bus",
+                &connected_clone,
+                Backoff::new(Duration::from_secs(1), Duration::from_secs(60)),
+                Connection::new_session,
+                |conn| listen_for_window_changes(conn, Arc::clone(&window_clone), Arc::clone(&signaled_clone)),
+                |delay| {
+                    thread::sleep(delay);
+                    ControlFlow::Continue(())
+                },
+            );
+        });
+
+        let window_clone = Arc::clone(&active_window);
+        let polling_clone = Arc::clone(&polling);
+        thread::spawn(move || {
+            thread::sleep(SIGNAL_WAIT);
+            if signaled.load(Ordering::SeqCst) {
+                return;
+            }
+            warn!("KDE Client: No window signal within {SIGNAL_WAIT:?}, is the KWin script installed?");
+            warn!("KDE Client: Polling kdotool for the active window instead");
+            polling_clone.store(true, Ordering::SeqCst);
+            poll_active_window(&window_clone, &signaled, query_kdotool, |interval| {
+                thread::sleep(interval);
+                ControlFlow::Continue(())
+            });
+            polling_clone.store(false, Ordering::SeqCst);
         });
 
         KdeClient {
             active_window,
+            connected,
+            polling,
+            warned_stale: false,
+        }
+    }
+
+    fn warn_if_stale(&mut self) {
+        if self.connected.load(Ordering::SeqCst) || self.polling.load(Ordering::SeqCst) {
+            self.warned_stale = false;
+        } else if !self.warned_stale {
+            self.warned_stale = true;
+            warn!("KDE Client: D-Bus is disconnected, window matching uses the last known window");
+        }
+    }
+}
+
+// Updates the window until a signal shows the KWin script is running after all
+fn poll_active_window(
+    window_state: &Mutex<ActiveWindow>,
+    signaled: &AtomicBool,
+    mut query: impl FnMut() -> Option<ActiveWindow>,
+    mut sleep: impl FnMut(Duration) -> ControlFlow<()>,
+) {
+    while !signaled.load(Ordering::SeqCst) {
+        if let Some(active_window) = query() {
+            if let Ok(mut window) = window_state.lock() {
+                *window = active_window;
+            }
+        }
+        if sleep(POLL_INTERVAL).is_break() {
+            return;
         }
     }
+    info!("KDE Client: KWin script is signaling, stopped polling kdotool");
+}
+
+fn query_kdotool() -> Option<ActiveWindow> {
+    let query = |getter: &str| {
+        let output = std::process::Command::new("kdotool")
+            .args(["getactivewindow", getter])
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    Some(ActiveWindow {
+        res_class: query("getwindowclassname")?,
+        res_name: String::new(),
+        title: query("getwindowname").unwrap_or_default(),
+    })
 }
 
-fn listen_for_window_changes(window_state: Arc<Mutex<ActiveWindow>>) {
-    info!("KDE Client: Starting D-Bus listener");
+// Runs until the connection fails
+fn listen_for_window_changes(
+    conn: Connection,
+    window_state: Arc<Mutex<ActiveWindow>>,
+    signaled: Arc<AtomicBool>,
+) -> Result<(), dbus::Error> {
+    // Create match rule for our signals
+    // Match all signals from the org.ahkwayland.ActiveWindow interface
+    let rule = MatchRule::new()
+        .with_type(dbus::message::MessageType::Signal)
+        .with_interface("org.ahkwayland.ActiveWindow")
+        .static_clone();
     
-    loop {
-        match Connection::new_session() {
-            Ok(conn) => {
-                info!("KDE Client: Connected to D-Bus session");
-                
-                // Clone Arc for the closure
-                let ws = Arc::clone(&window_state);
-                
-                // Create match rule for our signals
-                // Match all signals from the org.ahkwayland.ActiveWindow interface
-                let rule = MatchRule::new()
-                    .with_type(dbus::message::MessageType::Signal)
-                    .with_interface("org.ahkwayland.ActiveWindow")
-                    .static_clone();
-                
-                let match_token = match conn.add_match(rule, move |_: (), _conn, msg| {
-                    // Check if this is our signal
-                    if let (Some(interface), Some(member)) = (msg.interface(), msg.member()) {
-                        let interface_str = interface.to_string();
-                        let member_str = member.to_string();
-                        
-                        if interface_str == "org.ahkwayland.ActiveWindow" 
-                            && (member_str == "Changed" || member_str == "Initial") {
-                            // Try to read the two string arguments
-                            if let Ok((class, title)) = msg.read2::<String, String>() {
-                                info!("Window: class='{}', title='{}'", class, title);
-                                
-                                if let Ok(mut window) = ws.lock() {
-                                    window.res_class = class;
-                                    window.title = title.clone();
-                                    info!("Updated active window: caption: '{}', class: '{}'", 
-                                          window.title, window.res_class);
-                                }
-                            } else {
-                                warn!("Failed to parse D-Bus message arguments");
-                            }
-                        }
-                    }
-                    true
-                }) {
-                    Ok(token) => token,
-                    Err(e) => {
-                        warn!("Failed to add D-Bus match rule: {:?}", e);
-                        thread::sleep(Duration::from_secs(5));
-                        continue;
-                    }
-                };
-                
-                info!("KDE Client: Listening for window signals");
-                
-                // Process incoming messages
-                loop {
-                    if let Err(e) = conn.process(Duration::from_millis(1000)) {
-                        warn!("D-Bus connection error: {:?}", e);
-                        break;
+    let match_token = conn.add_match(rule, move |_: (), _conn, msg| {
+        // Check if this is our signal
+        if let (Some(interface), Some(member)) = (msg.interface(), msg.member()) {
+            let interface_str = interface.to_string();
+            let member_str = member.to_string();
+            
+            if interface_str == "org.ahkwayland.ActiveWindow" 
+                && (member_str == "Changed" || member_str == "Initial") {
+                // Older KWin scripts send only the class and title
+                let args = msg
+                    .read3::<String, String, String>()
+                    .or_else(|_| msg.read2::<String, String>().map(|(class, title)| (class, title, String::new())));
+                if let Ok((class, title, instance)) = args {
+                    signaled.store(true, Ordering::SeqCst);
+                    info!("Window: class='{}', instance='{}', title='{}'", class, instance, title);
+                    
+                    if let Ok(mut window) = window_state.lock() {
+                        window.res_class = class;
+                        window.res_name = instance;
+                        window.title = title.clone();
+                        info!("Updated active window: caption: '{}', class: '{}'", 
+                              window.title, window.res_class);
                     }
+                } else {
+                    warn!("Failed to parse D-Bus message arguments");
                 }
-                
-                // Clean up match on disconnect
-                let _ = conn.remove_match(match_token);
-            }
-            Err(e) => {
-                warn!("Failed to connect to D-Bus: {:?}", e);
-                thread::sleep(Duration::from_secs(5));
             }
         }
-    }
+        true
+    })?;
+    
+    info!("KDE Client: Listening for window signals");
+    
+    // Process incoming messages
+    let result = loop {
+        if let Err(e) = conn.process(Duration::from_millis(1000)) {
+            break Err(e);
+        }
+    };
+    
+    // Clean up match on disconnect
+    let _ = conn.remove_match(match_token);
+    result
 }
 
 impl Client for KdeClient {
@@ -114,6 +198,7 @@ impl Client for KdeClient {
     }
 
     fn current_window(&mut self) -> Option<String> {
+        self.warn_if_stale();
         let aw = self.active_window.lock().ok()?;
         let title = aw.title.clone();
         if !title.is_empty() {
@@ -124,6 +209,7 @@ impl Client for KdeClient {
     }
 
     fn current_application(&mut self) -> Option<String> {
+        self.warn_if_stale();
         let aw = self.active_window.lock().ok()?;
         let class = aw.res_class.clone();
         if !class.is_empty() {
@@ -132,4 +218,85 @@ impl Client for KdeClient {
             None
         }
     }
-}
\ No newline at end of file
+
+    fn current_instance(&mut self) -> Option<String> {
+        self.warn_if_stale();
+        let aw = self.active_window.lock().ok()?;
+        Some(aw.res_name.clone()).filter(|instance| !instance.is_empty())
+    }
+
+    fn is_connected(&mut self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{poll_active_window, ActiveWindow, KdeClient};
+    use crate::client::Client;
+    use std::ops::ControlFlow;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    fn firefox() -> Option<ActiveWindow> {
+        Some(ActiveWindow {
+            res_class: "firefox".to_string(),
+            res_name: String::new(),
+            title: "Mozilla Firefox".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_polling_populates_window_without_signals() {
+        let active_window = Arc::new(Mutex::new(ActiveWindow {
+            res_class: String::new(),
+            res_name: String::new(),
+            title: String::new(),
+        }));
+        let mut polls = 0;
+        poll_active_window(&active_window, &AtomicBool::new(false), firefox, |_| {
+            polls += 1;
+            if polls < 3 {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        });
+        assert_eq!(polls, 3);
+
+        // Polled windows aren't stale, even with D-Bus down
+        let mut client = KdeClient {
+            active_window,
+            connected: Arc::new(AtomicBool::new(false)),
+            polling: Arc::new(AtomicBool::new(true)),
+            warned_stale: false,
+        };
+        assert_eq!(client.current_application(), Some("firefox".to_string()));
+        assert_eq!(client.current_window(), Some("Mozilla Firefox".to_string()));
+        assert!(!client.warned_stale);
+    }
+
+    #[test]
+    fn test_polling_stops_once_signaled() {
+        let active_window = Mutex::new(ActiveWindow {
+            res_class: String::new(),
+            res_name: String::new(),
+            title: String::new(),
+        });
+        let signaled = AtomicBool::new(false);
+        let mut polls = 0;
+        poll_active_window(
+            &active_window,
+            &signaled,
+            || {
+                polls += 1;
+                firefox()
+            },
+            |_| {
+                signaled.store(true, Ordering::SeqCst);
+                ControlFlow::Continue(())
+            },
+        );
+        assert_eq!(polls, 1);
+    }
+}
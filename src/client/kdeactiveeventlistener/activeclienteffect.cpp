@@ -30,7 +30,7 @@ bool ActiveClientEffect::supported()
     return true;
 }
 
-void ActiveClientEffect::sendDBusSignal(const QString &signalName, const QString &windowClass, const QString &windowTitle)
+void ActiveClientEffect::sendDBusSignal(const QString &signalName, const QString &windowClass, const QString &windowTitle, const QString &windowInstance)
 {
     QDBusMessage msg = QDBusMessage::createSignal(
         QStringLiteral("/ActiveWindow"), 
@@ -38,7 +38,8 @@ void ActiveClientEffect::sendDBusSignal(const QString &signalName, const QString
         signalName
     );
     
-    msg << windowClass << windowTitle;
+    // The instance goes last so readers of the two-field signal keep working
+    msg << windowClass << windowTitle << windowInstance;
     QDBusConnection::sessionBus().send(msg);
 }
 
@@ -52,10 +53,11 @@ void ActiveClientEffect::onActiveClientChanged()
     auto *window = workspace()->activeWindow();
     if (window) {
         QString windowClass = window->resourceClass();
+        QString windowInstance = window->resourceName();
         QString windowTitle = window->caption();
         
         // Send immediate signal
-        sendDBusSignal(QStringLiteral("Changed"), windowClass, windowTitle);
+        sendDBusSignal(QStringLiteral("Changed"), windowClass, windowTitle, windowInstance);
         
         // If title looks incomplete, start polling
         if (hasProblematicTitle(windowTitle) || windowTitle == windowClass) {
@@ -94,11 +96,13 @@ void ActiveClientEffect::retryActiveWindowTitle(int attempt)
         if (!m_lastActiveWindow) return;
         
         QString windowClass = m_lastActiveWindow->resourceClass();
+        
+        QString windowInstance = m_lastActiveWindow->resourceName();
         QString windowTitle = m_lastActiveWindow->caption();
         
         // If we got a better title, send an update
         if (!hasProblematicTitle(windowTitle) && windowTitle != windowClass) {
-            sendDBusSignal(QStringLiteral("Changed"), windowClass, windowTitle);
+            sendDBusSignal(QStringLiteral("Changed"), windowClass, windowTitle, windowInstance);
             m_polledWindows.remove(m_lastActiveWindow);
             m_lastActiveWindow = nullptr;
         } else {
@@ -125,11 +129,12 @@ void ActiveClientEffect::pollWindowTitle()
     // If we have an active window being polled
     if (activeWindow && m_polledWindows.contains(activeWindow)) {
         QString windowClass = activeWindow->resourceClass();
+        QString windowInstance = activeWindow->resourceName();
         QString windowTitle = activeWindow->caption();
         
         // Check if title has improved
         if (!hasProblematicTitle(windowTitle) && windowTitle != windowClass) {
-            sendDBusSignal(QStringLiteral("Changed"), windowClass, windowTitle);
+            sendDBusSignal(QStringLiteral("Changed"), windowClass, windowTitle, windowInstance);
             m_polledWindows.remove(activeWindow);
         } else {
             // Continue polling every 500ms
@@ -142,12 +147,14 @@ void ActiveClientEffect::onWindowAdded(Window *window)
 {
     if (window) {
         QString windowClass = window->resourceClass();
+        QString windowInstance = window->resourceName();
         QString windowTitle = window->caption();
-        sendDBusSignal(QStringLiteral("Created"), windowClass, windowTitle);
+        sendDBusSignal(QStringLiteral("Created"), windowClass, windowTitle, windowInstance);
         
         // Listen for title changes on this window
         connect(window, &Window::captionChanged, this, [this, window]() {
             QString windowClass = window->resourceClass();
+            QString windowInstance = window->resourceName();
             QString windowTitle = window->caption();
             
             // Only send if this is the active window
@@ -156,7 +163,7 @@ void ActiveClientEffect::onWindowAdded(Window *window)
                 if (m_polledWindows.contains(window) && !hasProblematicTitle(windowTitle)) {
                     m_polledWindows.remove(window);
                 }
-                sendDBusSignal(QStringLiteral("Changed"), windowClass, windowTitle);
+                sendDBusSignal(QStringLiteral("Changed"), windowClass, windowTitle, windowInstance);
             }
         });
     }
@@ -172,8 +179,10 @@ void ActiveClientEffect::onWindowRemoved(Window *window)
         }
         
         QString windowClass = window->resourceClass();
+        
+        QString windowInstance = window->resourceName();
         QString windowTitle = window->caption();
-        sendDBusSignal(QStringLiteral("Destroyed"), windowClass, windowTitle);
+        sendDBusSignal(QStringLiteral("Destroyed"), windowClass, windowTitle, windowInstance);
         
         // Immediately check what became active (no delay)
         QTimer::singleShot(0, this, &ActiveClientEffect::onActiveClientChanged);
@@ -184,8 +193,9 @@ void ActiveClientEffect::emitInitialState()
     // Send the current active window on plugin startup
     if (auto *window = workspace()->activeWindow()) {
         QString windowClass = window->resourceClass();
+        QString windowInstance = window->resourceName();
         QString windowTitle = window->caption();
-        sendDBusSignal(QStringLiteral("Initial"), windowClass, windowTitle);
+        sendDBusSignal(QStringLiteral("Initial"), windowClass, windowTitle, windowInstance);
     }
 }
 
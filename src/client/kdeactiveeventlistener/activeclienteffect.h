@@ -27,7 +27,7 @@ public Q_SLOTS:
     void pollWindowTitle();
 
 private:
-    void sendDBusSignal(const QString &signalName, const QString &windowClass, const QString &windowTitle);
+    void sendDBusSignal(const QString &signalName, const QString &windowClass, const QString &windowTitle, const QString &windowInstance);
     bool hasProblematicTitle(const QString &title);
     Window *m_lastActiveWindow = nullptr;
     QSet<Window*> m_polledWindows;
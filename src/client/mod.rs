@@ -2,6 +2,20 @@ pub trait Client {
     fn supported(&mut self) -> bool;
     fn current_application(&mut self) -> Option<String>;
     fn current_window(&mut self) -> Option<String>;
+    // Instance half of WM_CLASS (the role, e.g. a Chrome app's id), for clients that can tell it
+    // apart from the class that current_application reports
+    fn current_instance(&mut self) -> Option<String> {
+        None
+    }
+    // PID of the focused window's process, for clients that can report it
+    fn current_pid(&mut self) -> Option<u32> {
+        None
+    }
+    // False while a client that listens for window changes has lost its connection, so the
+    // window it reports may be stale
+    fn is_connected(&mut self) -> bool {
+        true
+    }
 }
 
 pub struct WMClient {
@@ -23,12 +37,7 @@ impl WMClient {
         }
     }
     pub fn current_window(&mut self) -> Option<String> {
-        if self.supported.is_none() {
-            let supported = self.client.supported();
-            self.supported = Some(supported);
-            println!("application-client: {} (supported: {})", self.name, supported);
-        }
-        if !self.supported.unwrap() {
+        if !self.supported() {
             return None;
         }
 
@@ -43,12 +52,7 @@ impl WMClient {
     }
 
     pub fn current_application(&mut self) -> Option<String> {
-        if self.supported.is_none() {
-            let supported = self.client.supported();
-            self.supported = Some(supported);
-            println!("application-client: {} (supported: {})", self.name, supported);
-        }
-        if !self.supported.unwrap() {
+        if !self.supported() {
             return None;
         }
 
@@ -61,8 +65,39 @@ impl WMClient {
         }
         result
     }
+
+    pub fn current_instance(&mut self) -> Option<String> {
+        if !self.supported() {
+            return None;
+        }
+
+        self.client.current_instance()
+    }
+
+    pub fn current_pid(&mut self) -> Option<u32> {
+        if !self.supported() {
+            return None;
+        }
+
+        self.client.current_pid()
+    }
+
+    pub fn is_connected(&mut self) -> bool {
+        self.client.is_connected()
+    }
+
+    // Asks the client once, on first use
+    fn supported(&mut self) -> bool {
+        *self.supported.get_or_insert_with(|| {
+            let supported = self.client.supported();
+            println!("application-client: {} (supported: {})", self.name, supported);
+            supported
+        })
+    }
 }
 
+pub mod reconnect;
+
 #[cfg(feature = "gnome")]
 mod gnome_client;
 
@@ -0,0 +1,112 @@
+use log::{info, warn};
+use std::fmt::Debug;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Delays between reconnection attempts, doubling from `initial` up to `max`
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            next: initial,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+// Keeps a listener connected: `listen` runs until the connection is lost, then `connect` is retried
+// with backoff. `connected` tells the client whether its data is current. Stops when `sleep` breaks,
+// which only tests do.
+pub fn reconnect_loop<C, E: Debug>(
+    name: &str,
+    connected: &AtomicBool,
+    mut backoff: Backoff,
+    mut connect: impl FnMut() -> Result<C, E>,
+    mut listen: impl FnMut(C) -> Result<(), E>,
+    mut sleep: impl FnMut(Duration) -> ControlFlow<()>,
+) {
+    let mut failures = 0;
+    loop {
+        match connect() {
+            Ok(connection) => {
+                info!("{name}: connected");
+                connected.store(true, Ordering::SeqCst);
+                backoff.reset();
+                failures = 0;
+                let result = listen(connection);
+                connected.store(false, Ordering::SeqCst);
+                let delay = backoff.next_delay();
+                warn!("{name}: connection lost ({result:?}), reconnecting in {delay:?}");
+                if sleep(delay).is_break() {
+                    return;
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                let delay = backoff.next_delay();
+                warn!("{name}: failed to connect ({failures} attempts): {e:?}, retrying in {delay:?}");
+                if sleep(delay).is_break() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reconnect_loop, Backoff};
+    use std::ops::ControlFlow;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_reconnect_backoff_schedule() {
+        let connected = AtomicBool::new(false);
+        // Two failures, a connection that's lost, then failures until the cap
+        let mut attempts = vec![Err("down"), Err("down"), Ok(()), Err("down"), Err("down"), Err("down"), Err("down")];
+        attempts.reverse();
+        let mut connected_while_listening = vec![];
+        let mut delays = vec![];
+
+        reconnect_loop(
+            "test",
+            &connected,
+            Backoff::new(Duration::from_secs(1), Duration::from_secs(4)),
+            || attempts.pop().unwrap(),
+            |_| {
+                connected_while_listening.push(connected.load(Ordering::SeqCst));
+                Err("lost")
+            },
+            |delay| {
+                delays.push(delay.as_secs());
+                if delays.len() < 7 {
+                    ControlFlow::Continue(())
+                } else {
+                    ControlFlow::Break(())
+                }
+            },
+        );
+
+        assert_eq!(delays, [1, 2, 1, 2, 4, 4, 4]);
+        assert_eq!(connected_while_listening, [true]);
+        assert!(!connected.load(Ordering::SeqCst));
+    }
+}
@@ -2,7 +2,6 @@ use std::collections::HashMap;
 
 use anyhow::{Context, Result};
 use wayland_client::{
-    backend::ObjectId,
     event_created_child,
     globals::{registry_queue_init, GlobalListContents},
     protocol::wl_registry,
@@ -16,11 +15,50 @@ use wayland_protocols_wlr::foreign_toplevel::v1::client::{
 
 use crate::client::Client;
 
+// Toplevels are keyed by their protocol id so the state can be driven without a connection.
 #[derive(Default, Debug)]
 struct State {
-    active_window: Option<ObjectId>,
-    windows: HashMap<ObjectId, String>,
-    titles: HashMap<ObjectId, String>,
+    active_window: Option<u32>,
+    windows: HashMap<u32, String>,
+    titles: HashMap<u32, String>,
+}
+
+impl State {
+    fn on_toplevel(&mut self, id: u32) {
+        self.windows.insert(id, "<unknown>".into());
+        self.titles.insert(id, "<unknown>".into());
+    }
+
+    fn on_handle_event(&mut self, id: u32, event: HandleEvent) {
+        match event {
+            HandleEvent::AppId { app_id } => {
+                self.windows.insert(id, app_id);
+            }
+            HandleEvent::Title { title } => {
+                self.titles.insert(id, title);
+            }
+            HandleEvent::Closed => {
+                self.windows.remove(&id);
+                self.titles.remove(&id);
+                if self.active_window == Some(id) {
+                    self.active_window = None;
+                }
+            }
+            HandleEvent::State { state } => {
+                // The state is an array of native-endian u32 enum values
+                let activated = state
+                    .chunks_exact(4)
+                    .map(|value| u32::from_ne_bytes([value[0], value[1], value[2], value[3]]))
+                    .any(|value| value == HandleState::Activated as u32);
+                if activated {
+                    self.active_window = Some(id);
+                } else if self.active_window == Some(id) {
+                    self.active_window = None;
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 #[derive(Default)]
@@ -118,8 +156,7 @@ impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for State {
         _: &QueueHandle<State>,
     ) {
         if let ManagerEvent::Toplevel { toplevel } = event {
-            state.windows.insert(toplevel.id(), "<unknown>".into());
-            state.titles.insert(toplevel.id(), "<unknown>".into());
+            state.on_toplevel(toplevel.id().protocol_id());
         }
     }
 
@@ -137,24 +174,32 @@ impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for State {
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
-        match event {
-            HandleEvent::AppId { app_id } => {
-                state.windows.insert(handle.id(), app_id);
-            }
-            HandleEvent::Title { title } => {
-                state.titles.insert(handle.id(), title);
-            }
-            HandleEvent::Closed => {
-                state.windows.remove(&handle.id());
-                state.titles.remove(&handle.id());
-            }
-            HandleEvent::State { state: handle_state } => {
-                let activated = HandleState::Activated as u8;
-                if handle_state.contains(&activated) {
-                    state.active_window = Some(handle.id());
-                }
-            }
-            _ => {}
-        }
+        state.on_handle_event(handle.id().protocol_id(), event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HandleEvent, HandleState, State};
+
+    fn state_event(states: &[HandleState]) -> HandleEvent {
+        let state = states.iter().flat_map(|s| (*s as u32).to_ne_bytes()).collect();
+        HandleEvent::State { state }
+    }
+
+    #[test]
+    fn test_toplevel_state_update() {
+        let mut state = State::default();
+        state.on_toplevel(7);
+        state.on_handle_event(7, HandleEvent::AppId { app_id: "firefox".into() });
+        state.on_handle_event(7, HandleEvent::Title { title: "Mozilla Firefox".into() });
+        state.on_handle_event(7, state_event(&[HandleState::Maximized, HandleState::Activated]));
+
+        assert_eq!(state.active_window, Some(7));
+        assert_eq!(state.windows.get(&7).map(String::as_str), Some("firefox"));
+        assert_eq!(state.titles.get(&7).map(String::as_str), Some("Mozilla Firefox"));
+
+        state.on_handle_event(7, state_event(&[HandleState::Maximized]));
+        assert_eq!(state.active_window, None);
     }
 }
@@ -71,12 +71,24 @@ impl Client for X11Client {
     }
 
     fn current_application(&mut self) -> Option<String> {
+        let (instance, class) = self.focused_wm_class()?;
+        Some(format!("{instance}.{class}"))
+    }
+
+    // WM_CLASS's own instance field, as the instance itself may contain dots
+    fn current_instance(&mut self) -> Option<String> {
+        self.focused_wm_class().map(|(instance, _)| instance)
+    }
+}
+
+impl X11Client {
+    fn focused_wm_class(&mut self) -> Option<(String, String)> {
         self.connect();
         let mut window = get_focus_window(self)?;
         loop {
             if let Some(wm_class) = get_wm_class(self, window) {
                 // Workaround: https://github.com/JetBrains/jdk8u_jdk/blob/master/src/solaris/classes/sun/awt/X11/XFocusProxyWindow.java#L35
-                if &wm_class != "Focus-Proxy-Window.FocusProxy" {
+                if wm_class.0 != "Focus-Proxy-Window" || wm_class.1 != "FocusProxy" {
                     return Some(wm_class);
                 }
             }
@@ -103,7 +115,8 @@ fn get_parent_window(client: &mut X11Client, window: Window) -> Option<Window> {
         .ok()
 }
 
-fn get_wm_class(client: &mut X11Client, window: Window) -> Option<String> {
+// WM_CLASS as its instance and class fields
+fn get_wm_class(client: &mut X11Client, window: Window) -> Option<(String, String)> {
     let reply = get_cookie_reply_with_reconnect(client, |conn| {
         get_property(conn, false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 1024)
     })
@@ -119,7 +132,7 @@ fn get_wm_class(client: &mut X11Client, window: Window) -> Option<String> {
             if let Some(end) = name.iter().position(|byte| *byte == '\0' as u8).or(Some(name.len())) {
                 if end == name.len() - 1 || end == name.len() {
                     if let Ok(name) = String::from_utf8(name[..end].to_vec()) {
-                        return Some(format!("{prefix}.{name}"));
+                        return Some((prefix, name));
                     }
                 }
             }
@@ -129,7 +142,7 @@ fn get_wm_class(client: &mut X11Client, window: Window) -> Option<String> {
 }
 
 /// Get WM_CLASS by using _NET_ACTIVE_WINDOW
-fn current_application_fallback(client: &mut X11Client) -> Option<String> {
+fn current_application_fallback(client: &mut X11Client) -> Option<(String, String)> {
     let winid = get_focused_window_id(client.connection.as_ref()?, client.screen_num?).ok()?;
 
     get_wm_class(client, winid)
@@ -22,6 +22,9 @@ pub enum ApplicationMatcher {
     Name(String),
     // /regex/
     Regex(Regex),
+    // AHK titles under SetTitleMatchMode 1 and 2
+    Prefix(String),
+    Contains(String),
 }
 
 impl ApplicationMatcher {
@@ -36,6 +39,8 @@ impl ApplicationMatcher {
                 }
             }
             ApplicationMatcher::Regex(r) => r.is_match(app),
+            ApplicationMatcher::Prefix(s) => app.starts_with(s.as_str()),
+            ApplicationMatcher::Contains(s) => app.contains(s.as_str()),
         }
     }
 }
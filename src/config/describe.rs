@@ -0,0 +1,227 @@
+use crate::ahk::{AhkAction, WindowCriteria};
+use crate::config::application::{ApplicationMatcher, OnlyOrNot};
+use crate::config::key_press::{KeyPress, Modifier};
+use crate::config::keymap::Keymap;
+use crate::config::keymap_action::KeymapAction;
+use crate::hotstring::HotstringMatch;
+use evdev::KeyCode as Key;
+use std::fmt;
+
+// Human-readable summary of a loaded hotkey, e.g. for `--list`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotkeyDescription {
+    // AHK-style combo such as `^!t` or `CapsLock & j`
+    pub combo: String,
+    pub action: String,
+    pub context: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotstringDescription {
+    // AHK-style trigger such as `:*:btw`
+    pub trigger: String,
+    pub replacement: String,
+}
+
+impl fmt::Display for HotkeyDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}  {}", self.combo, self.action)?;
+        if let Some(context) = &self.context {
+            write!(f, "  [{context}]")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for HotstringDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}  {}", self.trigger, self.replacement)
+    }
+}
+
+pub fn describe_keymap(keymap: &Keymap) -> Vec<HotkeyDescription> {
+    let mut descriptions: Vec<HotkeyDescription> = keymap
+        .remap
+        .iter()
+        .map(|(key_press, actions)| {
+            let (action, action_context) = describe_actions(actions);
+            let context = describe_matcher(&keymap.application, "ahk_exe ")
+                .or_else(|| describe_matcher(&keymap.window, ""))
+                .or_else(|| keymap.predicate.as_ref().map(|command| describe_predicate(command)))
+                .or(action_context);
+            HotkeyDescription {
+                combo: combo_string(key_press),
+                action,
+                context,
+            }
+        })
+        .collect();
+    // remap is a HashMap, so sort for a stable listing
+    descriptions.sort_by(|a, b| a.combo.cmp(&b.combo));
+    descriptions
+}
+
+pub fn describe_hotstring(hotstring: &HotstringMatch) -> HotstringDescription {
+    let mut options = String::new();
+    if hotstring.immediate {
+        options.push('*');
+    }
+    if hotstring.case_sensitive {
+        options.push('C');
+    }
+    if hotstring.omit_char {
+        options.push('O');
+    }
+    if hotstring.execute {
+        options.push('X');
+    }
+    HotstringDescription {
+        trigger: format!(":{}:{}", options, hotstring.trigger),
+        replacement: hotstring.replacement.clone(),
+    }
+}
+
+pub fn combo_string(key_press: &KeyPress) -> String {
+    let mut combo = String::new();
+    for modifier in &key_press.modifiers {
+        if let Modifier::Key(key) = modifier {
+            combo.push_str(&key_name(key));
+            combo.push_str(" & ");
+        }
+    }
+    for (modifier, symbol) in [
+        (Modifier::Control, '^'),
+        (Modifier::Alt, '!'),
+        (Modifier::Shift, '+'),
+        (Modifier::Windows, '#'),
+    ] {
+        if key_press.modifiers.contains(&modifier) {
+            combo.push(symbol);
+        }
+    }
+    combo.push_str(&key_name(&key_press.key));
+    combo
+}
+
+// `KEY_T` -> `t`, `KEY_CAPSLOCK` -> `Capslock`
+fn key_name(key: &Key) -> String {
+    let name = format!("{key:?}");
+    let name = name.strip_prefix("KEY_").unwrap_or(&name);
+    if name.len() == 1 {
+        name.to_lowercase()
+    } else {
+        let mut chars = name.chars();
+        let first = chars.next().unwrap_or_default();
+        format!("{}{}", first, chars.as_str().to_lowercase())
+    }
+}
+
+fn describe_actions(actions: &[KeymapAction]) -> (String, Option<String>) {
+    match actions {
+        [] => ("Nothing".to_string(), None),
+        [action] => describe_action(action),
+        _ => ("Block".to_string(), None),
+    }
+}
+
+fn describe_action(action: &KeymapAction) -> (String, Option<String>) {
+    let kind = match action {
+        KeymapAction::KeyPressAndRelease(key_press) | KeymapAction::Atomic(key_press) => {
+            return (format!("Remap {}", combo_string(key_press)), None)
+        }
+        KeymapAction::KeyPress(_) | KeymapAction::KeyRepeat(_) | KeymapAction::KeyRelease(_) => "Key",
+        KeymapAction::Remap(_) => "Nested remap",
+        KeymapAction::Launch(_) => "Run",
+        KeymapAction::PipeSelection(_) => "PipeSelection",
+        KeymapAction::Paste(_) => "Paste",
+        KeymapAction::Scroll(_) => "Scroll",
+        KeymapAction::RawEvent(_) => "RawEvent",
+        KeymapAction::SetMode(_) => "SetMode",
+        KeymapAction::SetMark(_) | KeymapAction::WithMark(_) => "Mark",
+        KeymapAction::EscapeNextKey(_) => "EscapeNextKey",
+        KeymapAction::Sleep(_) => "Sleep",
+        KeymapAction::Sequence(_) => "Sequence",
+        KeymapAction::Conditional(_) => "Conditional",
+        KeymapAction::RouteTo(_) => "RouteTo",
+        KeymapAction::ToggleSuspend => "Suspend",
+        KeymapAction::SetWheelEnabled(_) | KeymapAction::ToggleWheel => "Wheel",
+        KeymapAction::SetExtraModifiers(_) => "SetExtraModifiers",
+        KeymapAction::AhkInterpreted(ahk_action) => return describe_ahk_action(ahk_action),
+    };
+    (kind.to_string(), None)
+}
+
+// Single-line #HotIf hotkeys are stored as an IfWinActive wrapper, so report its
+// criteria as the context and the wrapped action as the kind.
+fn describe_ahk_action(action: &AhkAction) -> (String, Option<String>) {
+    let kind = match action {
+        AhkAction::IfWinActive {
+            criteria, then_actions, ..
+        } => {
+            let kind = match then_actions.as_slice() {
+                [action] => describe_ahk_action(action).0,
+                _ => "Block".to_string(),
+            };
+            return (kind, Some(describe_criteria(criteria)));
+        }
+        AhkAction::Run(_) => "Run",
+        AhkAction::Send(_) | AhkAction::SendExpression(_) => "Send",
+        AhkAction::Remap(_) => "Remap",
+        AhkAction::Sleep(_) => "Sleep",
+        AhkAction::SetKeyDelay(_) => "SetKeyDelay",
+        AhkAction::SendLevel(_) => "SendLevel",
+        AhkAction::SetTitleMatchMode(_) => "SetTitleMatchMode",
+        AhkAction::Shell(_) => "Shell",
+        AhkAction::Block(_) => "Block",
+        AhkAction::WinActivate(_) => "WinActivate",
+        AhkAction::WinClose(_) => "WinClose",
+        AhkAction::WinMaximize(_) => "WinMaximize",
+        AhkAction::WinMinimize(_) => "WinMinimize",
+        AhkAction::WinWaitActive { .. } => "WinWaitActive",
+        AhkAction::WinWait { .. } => "WinWait",
+        AhkAction::SetLockState { .. } => "SetLockState",
+        AhkAction::KeyWait { .. } => "KeyWait",
+        AhkAction::WinGet { .. } => "WinGet",
+    };
+    (kind.to_string(), None)
+}
+
+fn describe_criteria(criteria: &WindowCriteria) -> String {
+    match criteria {
+        WindowCriteria::Title(title) => format!("WinActive(\"{title}\")"),
+        WindowCriteria::Class(class) => format!("WinActive(\"ahk_class {class}\")"),
+        WindowCriteria::Exe(exe) => format!("WinActive(\"ahk_exe {exe}\")"),
+        WindowCriteria::Pid(pid) => format!("WinActive(\"ahk_pid {pid}\")"),
+        WindowCriteria::Group(name) => format!("WinActive(\"ahk_group {name}\")"),
+    }
+}
+
+fn describe_matcher(matcher: &Option<OnlyOrNot>, prefix: &str) -> Option<String> {
+    let matcher = matcher.as_ref()?;
+    let (negate, matchers) = match (&matcher.only, &matcher.not) {
+        (Some(only), _) => ("", only),
+        (None, Some(not)) => ("!", not),
+        (None, None) => return None,
+    };
+    let parts: Vec<String> = matchers
+        .iter()
+        .map(|m| {
+            let name = match m {
+                ApplicationMatcher::Literal(s)
+                | ApplicationMatcher::Name(s)
+                | ApplicationMatcher::Prefix(s)
+                | ApplicationMatcher::Contains(s) => s.clone(),
+                ApplicationMatcher::Regex(r) => format!("/{}/", r.as_str()),
+            };
+            format!("{negate}WinActive(\"{prefix}{name}\")")
+        })
+        .collect();
+    Some(parts.join(" || "))
+}
+
+fn describe_predicate(command: &[String]) -> String {
+    match command {
+        [shell, flag, script] if shell == "/bin/sh" && flag == "-c" => format!("Exec(\"{script}\")"),
+        _ => format!("Exec(\"{}\")", command.join(" ")),
+    }
+}
@@ -1,7 +1,8 @@
 use crate::config::application::deserialize_string_or_vec;
 use serde::Deserialize;
+use std::path::PathBuf;
 
-// TODO: Use trait to allow only either `only` or `not`
+// A device has to match `only` and none of `not`
 #[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Device {
@@ -9,4 +10,31 @@ pub struct Device {
     pub only: Option<Vec<String>>,
     #[serde(default, deserialize_with = "deserialize_string_or_vec")]
     pub not: Option<Vec<String>>,
+    // The `match` of each `devices:` entry that loaded this one with an `only` of its own;
+    // a device must match one name of each
+    #[serde(skip)]
+    pub within: Vec<Vec<String>>,
+}
+
+// A `devices:` entry: another config file whose modmap and keymap only apply to the devices
+// that `match` names, with the same kinds of names as `only`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    #[serde(rename = "match", deserialize_with = "deserialize_string_or_vec")]
+    pub matcher: Option<Vec<String>>,
+    pub config: PathBuf,
+}
+
+impl DeviceConfig {
+    // The file's entries only apply to the devices `match` names, on top of their own `device`
+    pub fn device(&self, device: Option<Device>) -> Option<Device> {
+        let mut device = device.unwrap_or(Device { only: None, not: None, within: Vec::new() });
+        match (&device.only, &self.matcher) {
+            (None, _) => device.only = self.matcher.clone(),
+            (Some(_), Some(matcher)) => device.within.push(matcher.clone()),
+            (Some(_), None) => {}
+        }
+        Some(device)
+    }
 }
@@ -0,0 +1,18 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+// Why load_configs failed, so callers can tell a missing file from a broken one
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read {}: {source}", path.display())]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("Failed to parse {}: {source}", path.display())]
+    YamlParse { path: PathBuf, source: serde_yaml::Error },
+    #[error("Failed to parse {}: {source}", path.display())]
+    TomlParse { path: PathBuf, source: toml::de::Error },
+    #[error("AHK parse error in {} at line {line}: {msg}", path.display())]
+    AhkParse { path: PathBuf, line: usize, msg: String },
+    // A config that parsed, but can't work as written
+    #[error("{0}")]
+    Validation(String),
+}
@@ -0,0 +1,367 @@
+use crate::config::application::{ApplicationMatcher, OnlyOrNot};
+use crate::config::device::Device;
+use crate::config::key::key_name;
+use crate::config::key_press::{KeyPress, Modifier};
+use crate::config::keymap::{Keymap, LockCondition};
+use crate::config::keymap_action::{KeymapAction, SequenceStep};
+use crate::config::modmap::Modmap;
+use crate::config::modmap_action::{Keys, ModmapAction};
+use crate::config::remap::Remap;
+use crate::config::Config;
+use crate::hotstring::HotstringMatch;
+use evdev::EventType;
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, HashMap};
+
+// Writes the config in the schema the YAML loader reads, so loading the output gives the same
+// modmap, keymap, hotstrings, virtual modifiers, single_instance, window groups and variables.
+// Settings such as keypress_delay_ms aren't written; an AHK file never sets them.
+pub fn export_yaml(config: &Config) -> Result<String, serde_yaml::Error> {
+    let mut root = Mapping::new();
+    if let Some(single_instance) = config.single_instance {
+        root.insert("single_instance".into(), serde_yaml::to_value(single_instance)?);
+    }
+    if !config.window_groups.is_empty() {
+        // Sorted, so exporting the same config gives the same output
+        let window_groups: BTreeMap<_, _> = config.window_groups.iter().collect();
+        root.insert("window_groups".into(), serde_yaml::to_value(window_groups)?);
+    }
+    if !config.variables.is_empty() {
+        let variables: BTreeMap<_, _> = config.variables.iter().collect();
+        root.insert("variables".into(), serde_yaml::to_value(variables)?);
+    }
+    // Every AHK file adds CapsLock, so merged configs can list it more than once
+    let mut virtual_modifiers: Vec<Value> = vec![];
+    for key in &config.virtual_modifiers {
+        let name = Value::from(key_name(*key));
+        if !virtual_modifiers.contains(&name) {
+            virtual_modifiers.push(name);
+        }
+    }
+    if !virtual_modifiers.is_empty() {
+        root.insert("virtual_modifiers".into(), Value::Sequence(virtual_modifiers));
+    }
+    if !config.modmap.is_empty() {
+        root.insert("modmap".into(), config.modmap.iter().map(modmap_value).collect());
+    }
+    if !config.keymap.is_empty() {
+        root.insert("keymap".into(), config.keymap.iter().map(keymap_value).collect());
+    }
+    if !config.hotstrings.is_empty() {
+        let hotstrings = config.hotstrings.iter().map(hotstring_value).collect::<Result<_, _>>()?;
+        root.insert("hotstrings".into(), Value::Sequence(hotstrings));
+    }
+    serde_yaml::to_string(&root)
+}
+
+fn modmap_value(modmap: &Modmap) -> Value {
+    let mut remap: Vec<(String, Value)> = modmap
+        .remap
+        .iter()
+        .map(|(key, action)| (key_name(*key), modmap_action_value(action)))
+        .collect();
+    remap.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut value = Mapping::new();
+    if !modmap.name.is_empty() {
+        value.insert("name".into(), modmap.name.clone().into());
+    }
+    value.insert("remap".into(), Value::Mapping(remap.into_iter().map(|(k, v)| (k.into(), v)).collect()));
+    insert_conditions(
+        &mut value,
+        &modmap.application,
+        &modmap.window,
+        &modmap.instance,
+        &modmap.device,
+        &modmap.mode,
+    );
+    Value::Mapping(value)
+}
+
+fn modmap_action_value(action: &ModmapAction) -> Value {
+    match action {
+        ModmapAction::Keys(keys) => keys_value(keys),
+        ModmapAction::MultiPurposeKey(key) => {
+            let mut value = Mapping::new();
+            value.insert("held".into(), keys_value(&key.held));
+            value.insert("alone".into(), keys_value(&key.alone));
+            value.insert("alone_timeout_millis".into(), (key.alone_timeout.as_millis() as u64).into());
+            value.insert("free_hold".into(), key.free_hold.into());
+            Value::Mapping(value)
+        }
+        ModmapAction::PressReleaseKey(key) => {
+            let mut value = Mapping::new();
+            value.insert("skip_key_event".into(), key.skip_key_event.into());
+            value.insert("press".into(), actions_value(&key.press));
+            value.insert("repeat".into(), actions_value(&key.repeat));
+            value.insert("release".into(), actions_value(&key.release));
+            Value::Mapping(value)
+        }
+    }
+}
+
+fn keys_value(keys: &Keys) -> Value {
+    match keys {
+        Keys::Key(key) => key_name(*key).into(),
+        Keys::Keys(keys) => keys.iter().map(|key| Value::from(key_name(*key))).collect(),
+    }
+}
+
+fn keymap_value(keymap: &Keymap) -> Value {
+    let mut value = Mapping::new();
+    if !keymap.name.is_empty() {
+        value.insert("name".into(), keymap.name.clone().into());
+    }
+    value.insert("remap".into(), remap_value(&keymap.remap));
+    insert_conditions(
+        &mut value,
+        &keymap.application,
+        &keymap.window,
+        &keymap.instance,
+        &keymap.device,
+        &keymap.mode,
+    );
+    if keymap.exact_match {
+        value.insert("exact_match".into(), true.into());
+    }
+    if let Some(predicate) = &keymap.predicate {
+        value.insert("predicate".into(), strings_value(predicate));
+    }
+    if keymap.input_level > 0 {
+        value.insert("input_level".into(), keymap.input_level.into());
+    }
+    if keymap.repeat_while_held {
+        value.insert("repeat_while_held".into(), true.into());
+        value.insert("repeat_interval_ms".into(), keymap.repeat_interval_ms.into());
+    }
+    for (name, condition) in [("capslock", keymap.capslock), ("numlock", keymap.numlock)] {
+        if let Some(condition) = condition {
+            let state = if condition == LockCondition::On { "on" } else { "off" };
+            value.insert(name.into(), state.into());
+        }
+    }
+    Value::Mapping(value)
+}
+
+// remap is a HashMap, so sort for a stable output
+fn remap_value(remap: &HashMap<KeyPress, Vec<KeymapAction>>) -> Value {
+    let mut entries: Vec<(String, Value)> = remap
+        .iter()
+        .map(|(key_press, actions)| (key_press_string(key_press), actions_value(actions)))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Value::Mapping(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+}
+
+fn insert_conditions(
+    value: &mut Mapping,
+    application: &Option<OnlyOrNot>,
+    window: &Option<OnlyOrNot>,
+    instance: &Option<OnlyOrNot>,
+    device: &Option<Device>,
+    mode: &Option<Vec<String>>,
+) {
+    if let Some(application) = application {
+        value.insert("application".into(), matcher_value(application));
+    }
+    if let Some(window) = window {
+        value.insert("window".into(), matcher_value(window));
+    }
+    if let Some(instance) = instance {
+        value.insert("instance".into(), matcher_value(instance));
+    }
+    if let Some(device) = device {
+        let mut device_value = Mapping::new();
+        if let Some(only) = &device.only {
+            device_value.insert("only".into(), strings_value(only));
+        }
+        if let Some(not) = &device.not {
+            device_value.insert("not".into(), strings_value(not));
+        }
+        if !device.within.is_empty() {
+            // Only one `only` list can be written, and the devices: entry's `match` isn't it
+            eprintln!("WARNING: a device match narrowed by devices: to {:?} is written without it", device.within);
+        }
+        value.insert("device".into(), Value::Mapping(device_value));
+    }
+    if let Some(mode) = mode {
+        value.insert("mode".into(), strings_value(mode));
+    }
+}
+
+fn matcher_value(matcher: &OnlyOrNot) -> Value {
+    let regex_value = |regex: &str| Value::from(format!("/{}/", regex.replace('/', "\\/")));
+    let matchers_value = |matchers: &Vec<ApplicationMatcher>| -> Value {
+        matchers
+            .iter()
+            .map(|matcher| match matcher {
+                ApplicationMatcher::Literal(name) | ApplicationMatcher::Name(name) => Value::from(name.clone()),
+                ApplicationMatcher::Regex(regex) => regex_value(regex.as_str()),
+                // YAML only has regexes for these
+                ApplicationMatcher::Prefix(title) => regex_value(&format!("^{}", regex::escape(title))),
+                ApplicationMatcher::Contains(title) => regex_value(&regex::escape(title)),
+            })
+            .collect()
+    };
+    let mut value = Mapping::new();
+    if let Some(only) = &matcher.only {
+        value.insert("only".into(), matchers_value(only));
+    }
+    if let Some(not) = &matcher.not {
+        value.insert("not".into(), matchers_value(not));
+    }
+    Value::Mapping(value)
+}
+
+// A single action is written on its own, several as a list, none as null
+fn actions_value(actions: &[KeymapAction]) -> Value {
+    let mut values: Vec<Value> = actions.iter().filter_map(action_value).collect();
+    match values.len() {
+        0 => Value::Null,
+        1 => values.remove(0),
+        _ => Value::Sequence(values),
+    }
+}
+
+fn action_value(action: &KeymapAction) -> Option<Value> {
+    let value = match action {
+        KeymapAction::KeyPressAndRelease(key_press) => key_press_string(key_press).into(),
+        KeymapAction::KeyPress(key) => single("press", key_name(*key).into()),
+        KeymapAction::KeyRepeat(key) => single("repeat", key_name(*key).into()),
+        KeymapAction::KeyRelease(key) => single("release", key_name(*key).into()),
+        KeymapAction::Remap(remap) => nested_remap_value(remap),
+        KeymapAction::Launch(command) => single("launch", strings_value(command)),
+        KeymapAction::PipeSelection(action) => {
+            let mut value = Mapping::new();
+            value.insert("pipe_selection".into(), strings_value(&action.command));
+            if let Some(timeout_ms) = action.timeout_ms {
+                value.insert("timeout_ms".into(), timeout_ms.into());
+            }
+            Value::Mapping(value)
+        }
+        KeymapAction::Paste(method) => single("paste", serde_yaml::to_value(method).ok()?),
+        KeymapAction::Scroll(scroll) => {
+            let mut value = Mapping::new();
+            value.insert("direction".into(), serde_yaml::to_value(scroll.direction).ok()?);
+            value.insert("amount".into(), scroll.amount.into());
+            single("scroll", Value::Mapping(value))
+        }
+        KeymapAction::RawEvent(event) => {
+            let event_type = match event.event_type {
+                EventType::KEY => "key",
+                EventType::RELATIVE => "relative",
+                EventType::LED => "led",
+                _ => "sound",
+            };
+            let mut value = Mapping::new();
+            value.insert("event_type".into(), event_type.into());
+            value.insert("code".into(), event.code.into());
+            value.insert("value".into(), event.value.into());
+            single("raw_event", Value::Mapping(value))
+        }
+        KeymapAction::SetMode(mode) => single("set_mode", mode.clone().into()),
+        KeymapAction::SetMark(set) => single("set_mark", (*set).into()),
+        KeymapAction::WithMark(key_press) => single("with_mark", key_press_string(key_press).into()),
+        KeymapAction::Atomic(key_press) => single("atomic", key_press_string(key_press).into()),
+        KeymapAction::EscapeNextKey(escape) => single("escape_next_key", (*escape).into()),
+        KeymapAction::Sleep(millis) => single("sleep", (*millis).into()),
+        KeymapAction::Sequence(steps) => single("sequence", steps.iter().map(sequence_step_value).collect()),
+        KeymapAction::Conditional(conditional) => {
+            let mut value = Mapping::new();
+            if let Some(application) = &conditional.application {
+                value.insert("application".into(), matcher_value(application));
+            }
+            if let Some(window) = &conditional.window {
+                value.insert("window".into(), matcher_value(window));
+            }
+            value.insert("then".into(), actions_value(&conditional.then));
+            value.insert("otherwise".into(), actions_value(&conditional.otherwise));
+            single("conditional", Value::Mapping(value))
+        }
+        KeymapAction::RouteTo(route) => {
+            let mut value = Mapping::new();
+            value.insert("device_id".into(), route.device_id.clone().into());
+            value.insert("key_press".into(), key_press_string(&route.key_press).into());
+            single("route_to", Value::Mapping(value))
+        }
+        KeymapAction::ToggleSuspend => single("toggle_suspend", true.into()),
+        KeymapAction::SetWheelEnabled(enabled) => single("set_wheel_enabled", (*enabled).into()),
+        KeymapAction::ToggleWheel => single("toggle_wheel", true.into()),
+        KeymapAction::AhkInterpreted(ahk_action) => {
+            // Enum variants as `{ send: ... }` maps rather than YAML tags, which is what the loader reads
+            let value = serde_yaml::with::singleton_map_recursive::serialize(ahk_action, serde_yaml::value::Serializer);
+            single("ahk", value.ok()?)
+        }
+        // Only created while handling events
+        KeymapAction::SetExtraModifiers(_) => return None,
+    };
+    Some(value)
+}
+
+fn nested_remap_value(remap: &Remap) -> Value {
+    let mut value = Mapping::new();
+    value.insert("remap".into(), remap_value(&remap.remap));
+    if let Some(timeout) = remap.timeout {
+        value.insert("timeout_millis".into(), (timeout.as_millis() as u64).into());
+    }
+    if let Some(keys) = &remap.timeout_key {
+        value.insert("timeout_key".into(), keys.iter().map(|key| Value::from(key_name(*key))).collect());
+    }
+    Value::Mapping(value)
+}
+
+fn sequence_step_value(step: &SequenceStep) -> Value {
+    match step {
+        SequenceStep::KeyPressAndRelease(key_press) => key_press_string(key_press).into(),
+        SequenceStep::KeyPress(key) => single("press", key_name(*key).into()),
+        SequenceStep::KeyRelease(key) => single("release", key_name(*key).into()),
+        SequenceStep::Sleep(millis) => single("sleep", (*millis).into()),
+    }
+}
+
+fn hotstring_value(hotstring: &HotstringMatch) -> Result<Value, serde_yaml::Error> {
+    let mut value = Mapping::new();
+    value.insert("trigger".into(), hotstring.trigger.clone().into());
+    value.insert("replacement".into(), hotstring.replacement.clone().into());
+    for (option, set) in [
+        ("immediate", hotstring.immediate),
+        ("case_sensitive", hotstring.case_sensitive),
+        ("omit_char", hotstring.omit_char),
+        ("execute", hotstring.execute),
+    ] {
+        if set {
+            value.insert(option.into(), true.into());
+        }
+    }
+    if let Some(trailing_char) = hotstring.trailing_char {
+        value.insert("trailing_char".into(), serde_yaml::to_value(trailing_char)?);
+    }
+    Ok(Value::Mapping(value))
+}
+
+// `C-M-KEY_T`, `KEY_CAPSLOCK-KEY_J`
+fn key_press_string(key_press: &KeyPress) -> String {
+    let mut parts: Vec<String> = key_press
+        .modifiers
+        .iter()
+        .map(|modifier| match modifier {
+            Modifier::Shift => "Shift".to_string(),
+            Modifier::Control => "C".to_string(),
+            Modifier::Alt => "M".to_string(),
+            Modifier::Windows => "Super".to_string(),
+            Modifier::Key(key) => key_name(*key),
+        })
+        .collect();
+    parts.push(key_name(key_press.key));
+    parts.join("-")
+}
+
+fn strings_value(strings: &[String]) -> Value {
+    strings.iter().map(|s| Value::from(s.clone())).collect()
+}
+
+fn single(key: &str, value: Value) -> Value {
+    let mut mapping = Mapping::new();
+    mapping.insert(key.into(), value);
+    Value::Mapping(mapping)
+}
@@ -1,4 +1,5 @@
 use crate::event_handler::{DISGUISED_EVENT_OFFSETTER, KEY_MATCH_ANY};
+use crate::keys::parse_key_code;
 use evdev::KeyCode as Key;
 use serde::{Deserialize, Deserializer};
 use std::error::Error;
@@ -13,6 +14,10 @@ where
 }
 
 pub fn parse_key(input: &str) -> Result<Key, Box<dyn Error>> {
+    if let Some(key) = parse_key_code(input) {
+        return Ok(key?);
+    }
+
     // Everything is case-insensitive
     let name = input.to_uppercase();
 
@@ -138,6 +143,56 @@ pub fn parse_key(input: &str) -> Result<Key, Box<dyn Error>> {
     Err(format!("unknown key '{input}'").into())
 }
 
+// The aliases above for disguised relative events, by offset from DISGUISED_EVENT_OFFSETTER
+const DISGUISED_KEY_NAMES: [&str; 27] = [
+    "XRIGHTCURSOR",
+    "XLEFTCURSOR",
+    "XDOWNCURSOR",
+    "XUPCURSOR",
+    "XREL_Z_AXIS_1",
+    "XREL_Z_AXIS_2",
+    "XREL_RX_AXIS_1",
+    "XREL_RX_AXIS_2",
+    "XREL_RY_AXIS_1",
+    "XREL_RY_AXIS_2",
+    "XREL_RZ_AXIS_1",
+    "XREL_RZ_AXIS_2",
+    "XRIGHTSCROLL",
+    "XLEFTSCROLL",
+    "XREL_DIAL_1",
+    "XREL_DIAL_2",
+    "XUPSCROLL",
+    "XDOWNSCROLL",
+    "XREL_MISC_1",
+    "XREL_MISC_2",
+    "XREL_RESERVED_1",
+    "XREL_RESERVED_2",
+    "XHIRES_UPSCROLL",
+    "XHIRES_DOWNSCROLL",
+    "XHIRES_RIGHTSCROLL",
+    "XHIRES_LEFTSCROLL",
+    "ANY",
+];
+
+// A name parse_key reads back as `key`
+pub fn key_name(key: Key) -> String {
+    let offset = key.code().wrapping_sub(DISGUISED_EVENT_OFFSETTER);
+    match DISGUISED_KEY_NAMES.get(offset as usize) {
+        Some(name) => name.to_string(),
+        None => format!("{key:?}"),
+    }
+}
+
+#[test]
+fn test_key_name() {
+    for key in [Key::KEY_A, Key::KEY_CAPSLOCK, Key::BTN_RIGHT, KEY_MATCH_ANY] {
+        assert_eq!(parse_key(&key_name(key)).unwrap(), key);
+    }
+    for (offset, name) in DISGUISED_KEY_NAMES.iter().enumerate() {
+        assert_eq!(parse_key(name).unwrap(), Key(DISGUISED_EVENT_OFFSETTER + offset as u16));
+    }
+}
+
 #[test]
 fn test_parse_key() {
     // Can omit the 'KEY_' prefex
@@ -154,4 +209,9 @@ fn test_parse_key() {
 
     // Modifier without sidedness can't be a key.
     assert_eq!(parse_key("Shift").unwrap_err().to_string(), "unknown key 'Shift'");
+
+    // Any key by its evdev code
+    assert_eq!(parse_key("code:16").unwrap(), Key::KEY_Q);
+    assert_eq!(parse_key("code:0").unwrap_err().to_string(), "key code '0' isn't between 1 and 767");
+    assert_eq!(parse_key("code:304").unwrap_err().to_string(), "key code '304' isn't one the output device can send");
 }
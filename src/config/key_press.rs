@@ -30,6 +30,17 @@ impl<'de> Deserialize<'de> for KeyPress {
     }
 }
 
+// A single modifier, as in a key press: `Shift`, `C`, `Alt_R` and so on
+impl<'de> Deserialize<'de> for Modifier {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let modifier = String::deserialize(deserializer)?;
+        parse_modifier(&modifier).map_err(serde::de::Error::custom)
+    }
+}
+
 fn parse_key_press(input: &str) -> Result<KeyPress, Box<dyn error::Error>> {
     let keys: Vec<&str> = input.split('-').collect();
     if let Some((key, modifier_keys)) = keys.split_last() {
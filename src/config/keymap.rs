@@ -1,10 +1,13 @@
 use crate::config::application::deserialize_string_or_vec;
 use crate::config::application::OnlyOrNot;
+use crate::config::describe::combo_string;
 use crate::config::key_press::KeyPress;
 use crate::config::keymap_action::{Actions, KeymapAction};
 use evdev::KeyCode as Key;
 use serde::{Deserialize, Deserializer};
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 use super::device::Device;
 use super::key_press::Modifier;
@@ -20,11 +23,59 @@ pub struct Keymap {
     pub remap: HashMap<KeyPress, Vec<KeymapAction>>,
     pub application: Option<OnlyOrNot>,
     pub window: Option<OnlyOrNot>,
+    // WM_CLASS instance, for windows that share a class (e.g. Chrome apps)
+    pub instance: Option<OnlyOrNot>,
     pub device: Option<Device>,
     #[serde(default, deserialize_with = "deserialize_string_or_vec")]
     pub mode: Option<Vec<String>>,
     #[serde(default)]
     pub exact_match: bool,
+    // Command that must exit 0 for this keymap to apply
+    pub predicate: Option<Vec<String>>,
+    // AHK #InputLevel: Sends at a higher SendLevel can trigger this keymap
+    #[serde(default)]
+    pub input_level: u8,
+    // Dispatch the actions again every repeat_interval_ms while the key is held, instead of on
+    // the keyboard's own key repeat
+    #[serde(default)]
+    pub repeat_while_held: bool,
+    #[serde(default = "default_repeat_interval_ms")]
+    pub repeat_interval_ms: u64,
+    pub capslock: Option<LockCondition>,
+    pub numlock: Option<LockCondition>,
+    // The file this keymap was loaded from, for messages
+    #[serde(skip)]
+    pub source: String,
+}
+
+impl Keymap {
+    // e.g. `config.yml (Emacs)`
+    fn origin(&self) -> String {
+        match (self.source.is_empty(), self.name.is_empty()) {
+            (false, false) => format!("{} ({})", self.source, self.name),
+            (false, true) => self.source.clone(),
+            (true, false) => self.name.clone(),
+            (true, true) => "an unnamed keymap".to_string(),
+        }
+    }
+}
+
+// `numlock: on` applies the keymap only while NumLock's LED is lit, `off` only while it isn't
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockCondition {
+    On,
+    Off,
+}
+
+impl LockCondition {
+    pub fn matches(&self, lit: bool) -> bool {
+        lit == (*self == LockCondition::On)
+    }
+}
+
+pub fn default_repeat_interval_ms() -> u64 {
+    100
 }
 
 fn deserialize_remap<'de, D>(deserializer: D) -> Result<HashMap<KeyPress, Vec<KeymapAction>>, D::Error>
@@ -43,11 +94,35 @@ where
 pub struct KeymapEntry {
     pub actions: Vec<KeymapAction>,
     pub modifiers: Vec<Modifier>,
+    // Every key that satisfies one of `modifiers`, for O(1) lookups per keystroke
+    pub modifier_keys: HashSet<Key>,
     pub application: Option<OnlyOrNot>,
     pub title: Option<OnlyOrNot>,
+    pub instance: Option<OnlyOrNot>,
     pub device: Option<Device>,
     pub mode: Option<Vec<String>>,
     pub exact_match: bool,
+    pub predicate: Option<Vec<String>>,
+    pub input_level: u8,
+    // Set for repeat_while_held keymaps
+    pub repeat_interval: Option<Duration>,
+    pub capslock: Option<LockCondition>,
+    pub numlock: Option<LockCondition>,
+}
+
+impl KeymapEntry {
+    // No modifiers and no window/application/instance/device/mode/predicate/lock constraints
+    pub fn is_plain(&self) -> bool {
+        self.modifiers.is_empty()
+            && self.capslock.is_none()
+            && self.numlock.is_none()
+            && self.application.is_none()
+            && self.title.is_none()
+            && self.instance.is_none()
+            && self.device.is_none()
+            && self.mode.is_none()
+            && self.predicate.is_none()
+    }
 }
 
 // Convert an array of keymaps to a single hashmap whose key is a triggering key.
@@ -67,11 +142,20 @@ pub fn build_keymap_table(keymaps: &Vec<Keymap>) -> HashMap<Key, Vec<KeymapEntry
             entries.push(KeymapEntry {
                 actions: actions.to_vec(),
                 modifiers: key_press.modifiers.clone(),
+                modifier_keys: modifier_keys(&key_press.modifiers),
                 application: keymap.application.clone(),
                 title: keymap.window.clone(),
+                instance: keymap.instance.clone(),
                 device: keymap.device.clone(),
                 mode: keymap.mode.clone(),
                 exact_match: keymap.exact_match,
+                predicate: keymap.predicate.clone(),
+                input_level: keymap.input_level,
+                repeat_interval: keymap
+                    .repeat_while_held
+                    .then(|| Duration::from_millis(keymap.repeat_interval_ms)),
+                capslock: keymap.capslock,
+                numlock: keymap.numlock,
             });
             table.insert(key_press.key, entries);
         }
@@ -79,6 +163,56 @@ pub fn build_keymap_table(keymaps: &Vec<Keymap>) -> HashMap<Key, Vec<KeymapEntry
     table
 }
 
+// Combos bound again in the same context (modifiers, mode, application, window, device, lock
+// keys and so on) as an earlier keymap, which only the first binding ever gets. One message per conflict.
+pub fn conflicting_bindings(keymaps: &[Keymap]) -> Vec<String> {
+    let mut first_bound: HashMap<(String, String), String> = HashMap::new();
+    let mut conflicts = vec![];
+    for keymap in keymaps {
+        let context = format!(
+            "{:?}",
+            (
+                &keymap.application,
+                &keymap.window,
+                &keymap.instance,
+                &keymap.device,
+                &keymap.mode,
+                &keymap.predicate,
+                keymap.input_level,
+                keymap.capslock,
+                keymap.numlock,
+            )
+        );
+        // remap is a HashMap, so sort for stable messages
+        let mut combos: Vec<String> = keymap.remap.keys().map(combo_string).collect();
+        combos.sort();
+        for combo in combos {
+            match first_bound.entry((combo.clone(), context.clone())) {
+                Entry::Occupied(first) => {
+                    conflicts.push(format!("{combo} in {} is already bound in {}", keymap.origin(), first.get()))
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(keymap.origin());
+                }
+            }
+        }
+    }
+    conflicts
+}
+
+pub fn modifier_keys(modifiers: &[Modifier]) -> HashSet<Key> {
+    modifiers
+        .iter()
+        .flat_map(|modifier| match modifier {
+            Modifier::Shift => vec![Key::KEY_LEFTSHIFT, Key::KEY_RIGHTSHIFT],
+            Modifier::Control => vec![Key::KEY_LEFTCTRL, Key::KEY_RIGHTCTRL],
+            Modifier::Alt => vec![Key::KEY_LEFTALT, Key::KEY_RIGHTALT],
+            Modifier::Windows => vec![Key::KEY_LEFTMETA, Key::KEY_RIGHTMETA],
+            Modifier::Key(key) => vec![*key],
+        })
+        .collect()
+}
+
 // Subset of KeymapEntry for override_remap
 #[derive(Clone)]
 pub struct OverrideEntry {
@@ -1,11 +1,16 @@
+use crate::ahk::PasteMethod;
+use crate::config::application::OnlyOrNot;
 use crate::config::key_press::KeyPress;
 use std::collections::HashMap;
 
 use crate::config::remap::Remap;
+use crate::event::RelativeEvent;
 use evdev::KeyCode as Key;
+use evdev::{EventType, InputEvent, LedCode, RelativeAxisCode, SoundCode};
 use serde::de;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt::Debug;
+use std::str::FromStr;
 use std::time::Duration;
 
 use super::key::parse_key;
@@ -27,22 +32,45 @@ pub enum KeymapAction {
     Remap(Remap),
     #[serde(deserialize_with = "deserialize_launch")]
     Launch(Vec<String>),
+    #[serde(deserialize_with = "deserialize_pipe_selection")]
+    PipeSelection(PipeSelection),
+    #[serde(deserialize_with = "deserialize_paste")]
+    Paste(PasteMethod),
+    #[serde(deserialize_with = "deserialize_scroll")]
+    Scroll(Scroll),
+    #[serde(deserialize_with = "deserialize_raw_event")]
+    RawEvent(RawEvent),
     #[serde(deserialize_with = "deserialize_set_mode")]
     SetMode(String),
     #[serde(deserialize_with = "deserialize_set_mark")]
     SetMark(bool),
     #[serde(deserialize_with = "deserialize_with_mark")]
     WithMark(KeyPress),
+    #[serde(deserialize_with = "deserialize_atomic")]
+    Atomic(KeyPress),
     #[serde(deserialize_with = "deserialize_escape_next_key")]
     EscapeNextKey(bool),
     #[serde(deserialize_with = "deserialize_sleep")]
     Sleep(u64),
+    #[serde(deserialize_with = "deserialize_sequence")]
+    Sequence(Vec<SequenceStep>),
+    #[serde(deserialize_with = "deserialize_conditional")]
+    Conditional(Conditional),
+    #[serde(deserialize_with = "deserialize_route_to")]
+    RouteTo(RouteTo),
+    #[serde(deserialize_with = "deserialize_toggle_suspend")]
+    ToggleSuspend,
+    // Drops wheel events while false, until the next one; with enable_wheel: false there's no wheel to turn on
+    #[serde(deserialize_with = "deserialize_set_wheel_enabled")]
+    SetWheelEnabled(bool),
+    #[serde(deserialize_with = "deserialize_toggle_wheel")]
+    ToggleWheel,
+    #[serde(deserialize_with = "deserialize_ahk")]
+    AhkInterpreted(crate::ahk::types::AhkAction),
 
     // Internals
     #[serde(skip)]
     SetExtraModifiers(Vec<Key>),
-    #[serde(skip)]
-    AhkInterpreted(crate::ahk::types::AhkAction),
 }
 
 fn deserialize_key_press<'de, D>(deserializer: D) -> Result<Key, D::Error>
@@ -120,6 +148,70 @@ where
     Err(de::Error::custom("not a map with a single \"launch\" key"))
 }
 
+// `{ pipe_selection: [trans, -b] }`, optionally with a `timeout_ms` overriding command_timeout_ms
+#[derive(Clone, Debug)]
+pub struct PipeSelection {
+    pub command: Vec<String>,
+    pub timeout_ms: Option<u64>,
+}
+
+fn deserialize_pipe_selection<'de, D>(deserializer: D) -> Result<PipeSelection, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Action {
+        pipe_selection: Vec<String>,
+        timeout_ms: Option<u64>,
+    }
+    let action = Action::deserialize(deserializer)
+        .map_err(|_| de::Error::custom("not a map with a \"pipe_selection\" key and an optional \"timeout_ms\""))?;
+    Ok(PipeSelection {
+        command: action.pipe_selection,
+        timeout_ms: action.timeout_ms,
+    })
+}
+
+fn deserialize_paste<'de, D>(deserializer: D) -> Result<PasteMethod, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, PasteMethod>::deserialize(deserializer)?;
+    if let Some(method) = action.remove("paste") {
+        if action.is_empty() {
+            return Ok(method);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"paste\" key"))
+}
+
+fn deserialize_scroll<'de, D>(deserializer: D) -> Result<Scroll, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, Scroll>::deserialize(deserializer)?;
+    if let Some(scroll) = action.remove("scroll") {
+        if action.is_empty() {
+            return Ok(scroll);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"scroll\" key"))
+}
+
+fn deserialize_raw_event<'de, D>(deserializer: D) -> Result<RawEvent, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, RawEventConfig>::deserialize(deserializer)?;
+    if let Some(event) = action.remove("raw_event") {
+        if action.is_empty() {
+            return RawEvent::try_from(event).map_err(de::Error::custom);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"raw_event\" key"))
+}
+
 fn deserialize_set_mode<'de, D>(deserializer: D) -> Result<String, D::Error>
 where
     D: Deserializer<'de>,
@@ -159,6 +251,19 @@ where
     Err(de::Error::custom("not a map with a single \"with_mark\" key"))
 }
 
+fn deserialize_atomic<'de, D>(deserializer: D) -> Result<KeyPress, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, KeyPress>::deserialize(deserializer)?;
+    if let Some(key_press) = action.remove("atomic") {
+        if action.is_empty() {
+            return Ok(key_press);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"atomic\" key"))
+}
+
 fn deserialize_escape_next_key<'de, D>(deserializer: D) -> Result<bool, D::Error>
 where
     D: Deserializer<'de>,
@@ -185,6 +290,237 @@ where
     Err(de::Error::custom("not a map with a single \"sleep\" key"))
 }
 
+fn deserialize_sequence<'de, D>(deserializer: D) -> Result<Vec<SequenceStep>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, Vec<SequenceStep>>::deserialize(deserializer)?;
+    if let Some(steps) = action.remove("sequence") {
+        if action.is_empty() {
+            return Ok(steps);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"sequence\" key"))
+}
+
+fn deserialize_conditional<'de, D>(deserializer: D) -> Result<Conditional, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, Conditional>::deserialize(deserializer)?;
+    if let Some(conditional) = action.remove("conditional") {
+        if action.is_empty() {
+            if conditional.application.is_none() && conditional.window.is_none() {
+                return Err(de::Error::custom("conditional needs an application or window matcher"));
+            }
+            return Ok(conditional);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"conditional\" key"))
+}
+
+fn deserialize_route_to<'de, D>(deserializer: D) -> Result<RouteTo, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, RouteTo>::deserialize(deserializer)?;
+    if let Some(route) = action.remove("route_to") {
+        if action.is_empty() {
+            return Ok(route);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"route_to\" key"))
+}
+
+fn deserialize_toggle_suspend<'de, D>(deserializer: D) -> Result<(), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, bool>::deserialize(deserializer)?;
+    if let Some(true) = action.remove("toggle_suspend") {
+        if action.is_empty() {
+            return Ok(());
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"toggle_suspend: true\" key"))
+}
+
+fn deserialize_set_wheel_enabled<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, bool>::deserialize(deserializer)?;
+    if let Some(enabled) = action.remove("set_wheel_enabled") {
+        if action.is_empty() {
+            return Ok(enabled);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"set_wheel_enabled\" key"))
+}
+
+fn deserialize_toggle_wheel<'de, D>(deserializer: D) -> Result<(), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, bool>::deserialize(deserializer)?;
+    if let Some(true) = action.remove("toggle_wheel") {
+        if action.is_empty() {
+            return Ok(());
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"toggle_wheel: true\" key"))
+}
+
+fn deserialize_ahk<'de, D>(deserializer: D) -> Result<crate::ahk::types::AhkAction, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, crate::ahk::types::AhkAction>::deserialize(deserializer)?;
+    if let Some(ahk_action) = action.remove("ahk") {
+        if action.is_empty() {
+            return Ok(ahk_action);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"ahk\" key"))
+}
+
+// `{ sequence: [C-x, { sleep: 200 }, { press: a }, { release: a }] }`, run in order
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SequenceStep {
+    KeyPressAndRelease(KeyPress),
+    #[serde(deserialize_with = "deserialize_key_press")]
+    KeyPress(Key),
+    #[serde(deserialize_with = "deserialize_key_release")]
+    KeyRelease(Key),
+    #[serde(deserialize_with = "deserialize_sleep")]
+    Sleep(u64),
+}
+
+// `{ route_to: { device_id: tablet, key_press: C-a } }` sends the key press from the extra output
+// device with that id in `output_devices`, for tools that tell devices apart
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteTo {
+    pub device_id: String,
+    pub key_press: KeyPress,
+}
+
+// `{ conditional: { application: { only: firefox }, then: C-t, otherwise: C-n } }`, checked when
+// the key is pressed like IfWinActive. With both matchers, both must match.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Conditional {
+    pub application: Option<OnlyOrNot>,
+    pub window: Option<OnlyOrNot>,
+    #[serde(default, deserialize_with = "deserialize_actions")]
+    pub then: Vec<KeymapAction>,
+    #[serde(default, deserialize_with = "deserialize_actions")]
+    pub otherwise: Vec<KeymapAction>,
+}
+
+fn deserialize_actions<'de, D>(deserializer: D) -> Result<Vec<KeymapAction>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Actions::deserialize(deserializer)?.into_vec())
+}
+
+// `{ scroll: { direction: down, amount: 3 } }`, amount in wheel notches
+#[derive(Clone, Debug, Deserialize)]
+pub struct Scroll {
+    pub direction: ScrollDirection,
+    #[serde(default = "default_scroll_amount")]
+    pub amount: i32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrollDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+fn default_scroll_amount() -> i32 {
+    1
+}
+
+impl Scroll {
+    // Whole notches only: the output device has no high-resolution wheel axes to send the rest on
+    pub fn relative_event(&self) -> RelativeEvent {
+        let (axis, sign) = match self.direction {
+            ScrollDirection::Up => (RelativeAxisCode::REL_WHEEL, 1),
+            ScrollDirection::Down => (RelativeAxisCode::REL_WHEEL, -1),
+            ScrollDirection::Right => (RelativeAxisCode::REL_HWHEEL, 1),
+            ScrollDirection::Left => (RelativeAxisCode::REL_HWHEEL, -1),
+        };
+        RelativeEvent::new_with(axis.0, self.amount.saturating_mul(sign))
+    }
+}
+
+// `{ raw_event: { event_type: led, code: LED_CAPSL, value: 1 } }`, emitted as is
+#[derive(Clone, Debug)]
+pub struct RawEvent {
+    pub event_type: EventType,
+    pub code: u16,
+    pub value: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawEventConfig {
+    event_type: String,
+    code: RawEventCode,
+    value: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawEventCode {
+    Number(u16),
+    Name(String),
+}
+
+impl TryFrom<RawEventConfig> for RawEvent {
+    type Error = String;
+
+    fn try_from(config: RawEventConfig) -> Result<Self, Self::Error> {
+        // LED and sound events go to the physical devices; the others to the output device
+        let event_type = match config.event_type.to_lowercase().as_str() {
+            "key" => EventType::KEY,
+            "relative" => EventType::RELATIVE,
+            "led" => EventType::LED,
+            "sound" => EventType::SOUND,
+            other => return Err(format!("raw_event event_type must be key, relative, led or sound: {other}")),
+        };
+        let code = match config.code {
+            RawEventCode::Number(code) => code,
+            RawEventCode::Name(name) => {
+                let code = match event_type {
+                    EventType::KEY => parse_key(&name).map(|key| key.code()).ok(),
+                    EventType::RELATIVE => RelativeAxisCode::from_str(&name).map(|axis| axis.0).ok(),
+                    EventType::LED => LedCode::from_str(&name).map(|led| led.0).ok(),
+                    _ => SoundCode::from_str(&name).map(|sound| sound.0).ok(),
+                };
+                code.ok_or_else(|| format!("unknown raw_event code for {}: {name}", config.event_type))?
+            }
+        };
+        Ok(RawEvent {
+            event_type,
+            code,
+            value: config.value,
+        })
+    }
+}
+
+impl RawEvent {
+    pub fn input_event(&self) -> InputEvent {
+        InputEvent::new(self.event_type.0, self.code, self.value)
+    }
+}
+
 // Used only for deserializing Vec<Action>
 #[derive(Clone, Debug, Deserialize)]
 #[serde(untagged)]
@@ -207,10 +543,12 @@ impl Actions {
 
 #[cfg(test)]
 mod tests {
-    use super::KeymapAction;
+    use super::{KeymapAction, SequenceStep};
+    use crate::ahk::PasteMethod;
     use crate::config::key_press::KeyPress;
     use crate::config::key_press::Modifier;
     use crate::config::keymap_action::Actions;
+    use evdev::EventType;
     use evdev::KeyCode as Key;
 
     #[test]
@@ -230,6 +568,77 @@ mod tests {
         test_yaml_parsing_key_launch("{launch: [\"bla\"]}", vec!["bla".into()]);
     }
 
+    #[test]
+    fn test_pipe_selection_action() {
+        match serde_yaml::from_str("{pipe_selection: [trans, -b]}").unwrap() {
+            KeymapAction::PipeSelection(action) => {
+                assert_eq!(action.command, vec!["trans", "-b"]);
+                assert_eq!(action.timeout_ms, None);
+            }
+            _ => panic!("unexpected type"),
+        }
+        match serde_yaml::from_str("{pipe_selection: [trans], timeout_ms: 200}").unwrap() {
+            KeymapAction::PipeSelection(action) => assert_eq!(action.timeout_ms, Some(200)),
+            _ => panic!("unexpected type"),
+        }
+    }
+
+    #[test]
+    fn test_paste_action() {
+        match serde_yaml::from_str("{paste: ctrl_v}").unwrap() {
+            KeymapAction::Paste(method) => assert_eq!(method, PasteMethod::CtrlV),
+            _ => panic!("unexpected type"),
+        }
+    }
+
+    #[test]
+    fn test_raw_event_action() {
+        match serde_yaml::from_str("{raw_event: {event_type: led, code: LED_CAPSL, value: 1}}").unwrap() {
+            KeymapAction::RawEvent(event) => {
+                assert_eq!((event.event_type, event.code, event.value), (EventType::LED, 1, 1))
+            }
+            _ => panic!("unexpected type"),
+        }
+        match serde_yaml::from_str("{raw_event: {event_type: sound, code: 1, value: 1}}").unwrap() {
+            KeymapAction::RawEvent(event) => assert_eq!(event.event_type, EventType::SOUND),
+            _ => panic!("unexpected type"),
+        }
+        assert!(serde_yaml::from_str::<KeymapAction>("{raw_event: {event_type: absolute, code: 0, value: 1}}").is_err());
+        assert!(serde_yaml::from_str::<KeymapAction>("{raw_event: {event_type: led, code: NOPE, value: 1}}").is_err());
+    }
+
+    #[test]
+    fn test_sequence_action() {
+        match serde_yaml::from_str("{sequence: [C-x, {sleep: 200}, {press: a}, {release: a}]}").unwrap() {
+            KeymapAction::Sequence(steps) => assert_eq!(
+                format!("{steps:?}"),
+                format!(
+                    "{:?}",
+                    vec![
+                        SequenceStep::KeyPressAndRelease(KeyPress {
+                            key: Key::KEY_X,
+                            modifiers: vec![Modifier::Control],
+                        }),
+                        SequenceStep::Sleep(200),
+                        SequenceStep::KeyPress(Key::KEY_A),
+                        SequenceStep::KeyRelease(Key::KEY_A),
+                    ]
+                )
+            ),
+            _ => panic!("unexpected type"),
+        }
+        match toml::from_str::<std::collections::HashMap<String, KeymapAction>>(
+            "action = { sequence = [\"enter\", { sleep = 50 }] }",
+        )
+        .unwrap()
+        .remove("action")
+        {
+            Some(KeymapAction::Sequence(steps)) => assert_eq!(steps.len(), 2),
+            _ => panic!("unexpected type"),
+        }
+        assert!(serde_yaml::from_str::<KeymapAction>("{sequence: [{launch: [ls]}]}").is_err());
+    }
+
     #[test]
     fn test_null_action() {
         if let Actions::NoAction = serde_yaml::from_str("null").unwrap() {
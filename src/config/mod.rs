@@ -1,9 +1,13 @@
 pub mod application;
+pub mod describe;
 pub mod device;
+pub mod error;
+mod export;
 mod key;
 pub mod key_press;
 pub mod keymap;
 pub mod keymap_action;
+pub mod mode;
 mod modmap;
 pub mod modmap_action;
 pub mod remap;
@@ -11,21 +15,31 @@ pub mod remap;
 #[cfg(test)]
 mod tests;
 
-use crate::hotstring::{HotstringMatch, HotstringMatcher};
-use crate::ahk::WindowCriteria;
+use crate::hotstring::{HotstringMatch, HotstringMatcher, HotstringMode, TrailingChar};
+use crate::single_instance::SingleInstance;
+use crate::event_handler::MouseMotionOrder;
+use crate::device::GrabMode;
+use crate::ahk::{AhkConfig, TitleMatchMode, WindowCriteria};
 use crate::config::keymap_action::KeymapAction;
 use crate::config::key::parse_key;
+pub use error::ConfigError;
 extern crate serde_yaml;
 extern crate toml;
 
 use evdev::KeyCode as Key;
+use application::ApplicationMatcher;
 use keymap::Keymap;
+use device::DeviceConfig;
+use describe::{describe_hotstring, describe_keymap, HotkeyDescription, HotstringDescription};
+use mode::ModeInfo;
 use modmap::Modmap;
+use modmap_action::{default_alone_timeout, Keys, ModmapAction, MultiPurposeKey};
 use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use regex::Regex;
 use serde::{de::IgnoredAny, Deserialize, Deserializer};
 use std::{
     collections::HashMap,
-    error, fs,
+    fs,
     path::{Path, PathBuf},
     time::SystemTime,
 };
@@ -33,19 +47,46 @@ use std::{
 use self::{
     keymap::{build_keymap_table, KeymapEntry},
 };
-use crate::ahk::{parse_ahk_file, AhkAction};
+use crate::ahk::interpreter::kdotool_maximize;
+use crate::ahk::parser::AhkParser;
+use crate::ahk::{normalize_source, parse_exec_predicate, parse_win_active, AhkAction};
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default = "Vec::new")]
     pub modmap: Vec<Modmap>,
+    #[serde(default = "Vec::new")]
     pub keymap: Vec<Keymap>,
+    // Other config files to load after this one, relative to it
+    #[serde(default = "Vec::new")]
+    pub include: Vec<PathBuf>,
+    // Config files to load like `include`, but only for the devices each one matches
+    #[serde(default = "Vec::new")]
+    pub devices: Vec<DeviceConfig>,
     #[serde(default = "default_mode")]
     pub default_mode: String,
+    #[serde(default)]
+    pub modes: HashMap<String, ModeInfo>,
     #[serde(deserialize_with = "deserialize_virtual_modifiers", default = "Vec::new")]
     pub virtual_modifiers: Vec<Key>,
     #[serde(default)]
     pub keypress_delay_ms: u64,
+    #[serde(default)]
+    pub type_jitter_ms: u64,
+    #[serde(default = "default_max_wait_ms")]
+    pub max_wait_ms: u64,
+    #[serde(default)]
+    pub compose: bool,
+    #[serde(deserialize_with = "deserialize_key", default = "default_compose_key")]
+    pub compose_key: Key,
+    #[serde(default = "default_clipboard_copy_cmd")]
+    pub clipboard_copy_cmd: String,
+    #[serde(default = "default_clipboard_paste_cmd")]
+    pub clipboard_paste_cmd: String,
+    // How long pipe_selection's command may run before it's killed and nothing is typed back
+    #[serde(default = "default_command_timeout_ms")]
+    pub command_timeout_ms: u64,
     #[allow(dead_code)]
     #[serde(default)]
     pub shared: IgnoredAny,
@@ -55,8 +96,67 @@ pub struct Config {
     pub keymap_table: HashMap<Key, Vec<KeymapEntry>>,
     #[serde(default = "const_true")]
     pub enable_wheel: bool,
-    #[serde(skip)]
+    #[serde(default)]
+    pub mouse_motion_order: MouseMotionOrder,
+    #[serde(default)]
+    pub grab_mode: GrabMode,
+    // Drop a hotkey's presses while its previous ones' delays are still being sent
+    #[serde(default)]
+    pub hotkey_reentrancy_guard: bool,
+    // What with_mark adds while set_mark is on
+    #[serde(default = "default_mark_modifier")]
+    pub mark_modifier: key_press::Modifier,
+    // Shorthand for a modmap making CapsLock Escape on tap and Control on hold
+    #[serde(default)]
+    pub caps_escape_control: bool,
+    #[serde(default, deserialize_with = "deserialize_hotstrings")]
     pub hotstrings: Vec<HotstringMatch>,
+    // Typed after an expansion unless the hotstring sets its own
+    #[serde(default)]
+    pub hotstring_trailing_char: TrailingChar,
+    #[serde(default)]
+    pub hotstring_mode: HotstringMode,
+    // A pause this long between characters starts the hotstring buffer over; 0 never does
+    #[serde(default = "default_hotstring_idle_timeout_ms")]
+    pub hotstring_idle_timeout_ms: u64,
+    // Keys that feed the hotstring buffer but are still typed when they end a hotstring
+    #[serde(deserialize_with = "deserialize_virtual_modifiers", default = "Vec::new")]
+    pub passthrough_keys: Vec<Key>,
+    // Keys that clear the hotstring buffer, e.g. moving the cursor away from the word being typed.
+    // Other keys that don't type a character leave it alone.
+    #[serde(deserialize_with = "deserialize_virtual_modifiers", default = "default_hotstring_reset_keys")]
+    pub hotstring_reset_keys: Vec<Key>,
+    // AHK's #SingleInstance; None doesn't check for other instances
+    #[serde(default)]
+    pub single_instance: Option<SingleInstance>,
+    // AHK's GroupAdd, for `ahk_group` in AHK actions; keyed by lowercased name
+    #[serde(default)]
+    pub window_groups: HashMap<String, Vec<WindowCriteria>>,
+    // AHK's top-level variable assignments, for Sends that also use WinGet's; keyed by lowercased name
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    // AHK's SetTitleMatchMode, for titles in AHK actions
+    #[serde(default)]
+    pub title_match_mode: TitleMatchMode,
+    // The titles in AHK actions and window groups compiled once, for SetTitleMatchMode RegEx
+    #[serde(skip)]
+    pub title_regexes: HashMap<String, Regex>,
+    // Ids of extra output devices, created at startup, that route_to sends keys from
+    #[serde(default)]
+    pub output_devices: Vec<String>,
+    // The main output device's name and ids, unless --output-device-name, --vendor or --product set them
+    #[serde(default)]
+    pub virtual_device_name: Option<String>,
+    #[serde(default)]
+    pub virtual_device_vendor: Option<u16>,
+    #[serde(default)]
+    pub virtual_device_product: Option<u16>,
+    // Load the AHK files after this one even if a single-line hotkey has an unknown action
+    #[serde(default)]
+    pub ahk_lenient: bool,
+    // Fail to load when two keymaps bind the same combo in the same context, instead of warning
+    #[serde(default)]
+    pub strict: bool,
     #[serde(skip)]
     pub hotstring_matcher: Option<HotstringMatcher>,
 }
@@ -66,17 +166,66 @@ impl Config {
         Config {
             modmap: Vec::new(),
             keymap: Vec::new(),
+            include: Vec::new(),
+            devices: Vec::new(),
             default_mode: "default".to_string(),
+            modes: HashMap::new(),
             virtual_modifiers: Vec::new(),
             keypress_delay_ms: 0,
+            type_jitter_ms: 0,
+            max_wait_ms: default_max_wait_ms(),
+            compose: false,
+            compose_key: default_compose_key(),
+            clipboard_copy_cmd: default_clipboard_copy_cmd(),
+            clipboard_paste_cmd: default_clipboard_paste_cmd(),
+            command_timeout_ms: default_command_timeout_ms(),
             shared: IgnoredAny,
             modify_time: None,
             keymap_table: HashMap::new(),
             enable_wheel: true,
+            mouse_motion_order: MouseMotionOrder::default(),
+            grab_mode: GrabMode::default(),
+            hotkey_reentrancy_guard: false,
+            mark_modifier: default_mark_modifier(),
+            caps_escape_control: false,
             hotstrings: Vec::new(),
+            hotstring_trailing_char: TrailingChar::default(),
+            hotstring_mode: HotstringMode::default(),
+            hotstring_idle_timeout_ms: default_hotstring_idle_timeout_ms(),
+            passthrough_keys: Vec::new(),
+            hotstring_reset_keys: default_hotstring_reset_keys(),
+            single_instance: None,
+            window_groups: HashMap::new(),
+            variables: HashMap::new(),
+            title_match_mode: TitleMatchMode::default(),
+            title_regexes: HashMap::new(),
+            output_devices: Vec::new(),
+            virtual_device_name: None,
+            virtual_device_vendor: None,
+            virtual_device_product: None,
+            ahk_lenient: false,
+            strict: false,
             hotstring_matcher: None,
         }
     }
+
+    pub fn mode_metadata(&self, mode: &str) -> Option<&ModeInfo> {
+        self.modes.get(mode)
+    }
+
+    pub fn list_hotkeys(&self) -> Vec<HotkeyDescription> {
+        self.keymap.iter().flat_map(describe_keymap).collect()
+    }
+
+    pub fn list_hotstrings(&self) -> Vec<HotstringDescription> {
+        self.hotstrings.iter().map(describe_hotstring).collect()
+    }
+
+    // The modmap, keymap, hotstrings and virtual modifiers as a YAML config, e.g. to turn an
+    // AHK script into one that can be edited without AHK
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        export::export_yaml(self)
+    }
 }
 
 impl Default for Config {
@@ -107,21 +256,6 @@ fn get_file_ext(filename: &Path) -> ConfigFiletype {
     }
 }
 
-fn parse_ahk_context(context: &str) -> Option<application::OnlyOrNot> {
-    use regex::Regex;
-
-    let exe_re = Regex::new(r#"WinActive\("ahk_exe\s+([^"]+)"\)"#).ok()?;
-    if let Some(caps) = exe_re.captures(context) {
-        let exe = caps.get(1)?.as_str().to_string();
-        return Some(application::OnlyOrNot {
-            only: Some(vec![application::ApplicationMatcher::Literal(exe)]),
-            not: None,
-        });
-    }
-
-    None
-}
-
 pub fn config_watcher(watch: bool, files: &Vec<PathBuf>) -> anyhow::Result<Option<Inotify>> {
     if watch {
         let inotify = Inotify::init(InitFlags::IN_NONBLOCK)?;
@@ -153,16 +287,232 @@ where
         .collect()
 }
 
+fn deserialize_key<'de, D>(deserializer: D) -> Result<Key, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse_key(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+// `hotstrings: [{ trigger: btw, replacement: by the way }]`, the options as in AHK's `:*CO X:`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HotstringConfig {
+    trigger: String,
+    replacement: String,
+    #[serde(default)]
+    immediate: bool,
+    #[serde(default)]
+    case_sensitive: bool,
+    #[serde(default)]
+    omit_char: bool,
+    #[serde(default)]
+    execute: bool,
+    trailing_char: Option<TrailingChar>,
+}
+
+fn deserialize_hotstrings<'de, D>(deserializer: D) -> Result<Vec<HotstringMatch>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let hotstrings = Vec::<HotstringConfig>::deserialize(deserializer)?;
+    Ok(hotstrings
+        .into_iter()
+        .enumerate()
+        .map(|(id, h)| HotstringMatch {
+            trailing_char: h.trailing_char,
+            ..HotstringMatch::from_trigger(
+                id,
+                &h.trigger,
+                h.replacement,
+                h.immediate,
+                h.case_sensitive,
+                h.omit_char,
+                h.execute,
+            )
+        })
+        .collect())
+}
+
+fn default_max_wait_ms() -> u64 {
+    30_000
+}
+
+fn default_hotstring_idle_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_mark_modifier() -> key_press::Modifier {
+    key_press::Modifier::Shift
+}
+
+fn default_hotstring_reset_keys() -> Vec<Key> {
+    vec![
+        Key::KEY_UP,
+        Key::KEY_DOWN,
+        Key::KEY_LEFT,
+        Key::KEY_RIGHT,
+        Key::KEY_HOME,
+        Key::KEY_END,
+        Key::KEY_PAGEUP,
+        Key::KEY_PAGEDOWN,
+        Key::KEY_F1,
+        Key::KEY_F2,
+        Key::KEY_F3,
+        Key::KEY_F4,
+        Key::KEY_F5,
+        Key::KEY_F6,
+        Key::KEY_F7,
+        Key::KEY_F8,
+        Key::KEY_F9,
+        Key::KEY_F10,
+        Key::KEY_F11,
+        Key::KEY_F12,
+        Key::KEY_ESC,
+        Key::KEY_DELETE,
+        Key::KEY_INSERT,
+        Key::KEY_CAPSLOCK,
+    ]
+}
+
+fn default_compose_key() -> Key {
+    Key::KEY_RIGHTALT
+}
+
+fn default_clipboard_copy_cmd() -> String {
+    "wl-copy".to_string()
+}
+
+fn default_clipboard_paste_cmd() -> String {
+    "wl-paste".to_string()
+}
+
+fn default_command_timeout_ms() -> u64 {
+    5000
+}
+
 fn const_true() -> bool {
     true
 }
 
-pub fn load_configs(filenames: &[PathBuf]) -> Result<Config, Box<dyn error::Error>> {
-    let config_contents = fs::read_to_string(&filenames[0])?;
+pub fn load_configs(filenames: &[PathBuf]) -> Result<Config, ConfigError> {
+    load_configs_with(filenames, false)
+}
+
+// `ahk_lenient` (--ahk-lenient) runs a single-line AHK hotkey's unknown action as a shell
+// command, with a warning, instead of failing the load. An `ahk_lenient: true` file turns
+// it on for the files after it.
+pub fn load_configs_with(filenames: &[PathBuf], ahk_lenient: bool) -> Result<Config, ConfigError> {
+    let mut config = load_config_tree(&filenames[0], false, ahk_lenient, &mut vec![])?;
+    let ahk_lenient = ahk_lenient || config.ahk_lenient;
+
+    for filename in &filenames[1..] {
+        let c = load_config_tree(filename, true, ahk_lenient, &mut vec![])?;
+        merge_config(&mut config, c);
+    }
+
+    if config.caps_escape_control {
+        let modmap = caps_escape_control_modmap(&config);
+        config.modmap.push(modmap);
+    }
+    if !config.hotstrings.is_empty() {
+        config.hotstring_matcher = Some(HotstringMatcher::new(config.hotstrings.clone()));
+    }
+    // AHK group names are case-insensitive, and YAML ones are written by hand
+    for (name, members) in std::mem::take(&mut config.window_groups) {
+        config.window_groups.entry(name.to_lowercase()).or_default().extend(members);
+    }
+    // Like GroupAdd, a group only lists windows, so checking one never recurses
+    for (name, members) in &config.window_groups {
+        if let Some(WindowCriteria::Group(member)) = members.iter().find(|m| matches!(m, WindowCriteria::Group(_))) {
+            return Err(ConfigError::Validation(format!(
+                "window_groups: {name} lists ahk_group {member}, but a group's members can't be groups"
+            )));
+        }
+    }
+    config.variables = std::mem::take(&mut config.variables)
+        .into_iter()
+        .map(|(name, value)| (name.to_lowercase(), value))
+        .collect();
+    config.modify_time = filenames.last().and_then(|path| path.metadata().ok()?.modified().ok());
+    config.keymap_table = build_keymap_table(&config.keymap);
+
+    let conflicts = keymap::conflicting_bindings(&config.keymap);
+    if config.strict && !conflicts.is_empty() {
+        return Err(ConfigError::Validation(conflicts.join("\n")));
+    }
+    for conflict in conflicts {
+        eprintln!("WARNING: {conflict}; the first binding wins");
+    }
 
-    let mut config: Config = match get_file_ext(&filenames[0]) {
+    let unreachable = mode::unreachable_modes(&config);
+    if !unreachable.is_empty() {
+        let message = format!(
+            "Mode {} is in a `mode` list, but it isn't default_mode and no set_mode switches to it",
+            unreachable.join(", ")
+        );
+        if config.strict {
+            return Err(ConfigError::Validation(message));
+        }
+        eprintln!("WARNING: {message}");
+    }
+
+    Ok(config)
+}
+
+// Loads `filename` followed by the files it includes, depth-first. `stack` holds the
+// files whose includes are being loaded, so a file including itself is caught.
+fn load_config_tree(
+    filename: &Path,
+    additional: bool,
+    ahk_lenient: bool,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Config, ConfigError> {
+    let canonical = filename.canonicalize().map_err(|source| ConfigError::Io {
+        path: filename.to_path_buf(),
+        source,
+    })?;
+    if stack.contains(&canonical) {
+        let chain: Vec<String> = stack.iter().chain([&canonical]).map(|path| path.display().to_string()).collect();
+        return Err(ConfigError::Validation(format!("Include cycle: {}", chain.join(" -> "))));
+    }
+    stack.push(canonical);
+
+    let mut config = load_config_file(filename, additional, ahk_lenient)?;
+    let ahk_lenient = ahk_lenient || config.ahk_lenient;
+    let base = filename.parent().unwrap_or(Path::new("."));
+    for include in std::mem::take(&mut config.include) {
+        let c = load_config_tree(&base.join(include), true, ahk_lenient, stack)?;
+        merge_config(&mut config, c);
+    }
+    for device_config in std::mem::take(&mut config.devices) {
+        let mut c = load_config_tree(&base.join(&device_config.config), true, ahk_lenient, stack)?;
+        for modmap in &mut c.modmap {
+            modmap.device = device_config.device(modmap.device.take());
+        }
+        for keymap in &mut c.keymap {
+            keymap.device = device_config.device(keymap.device.take());
+        }
+        merge_config(&mut config, c);
+    }
+
+    stack.pop();
+    Ok(config)
+}
+
+fn load_config_file(filename: &Path, additional: bool, ahk_lenient: bool) -> Result<Config, ConfigError> {
+    let path = || filename.to_path_buf();
+    let config_contents = fs::read_to_string(filename).map_err(|source| ConfigError::Io { path: path(), source })?;
+    let config_contents = normalize_source(&config_contents);
+    let mut config: Config = match get_file_ext(filename) {
         ConfigFiletype::Ahk => {
-            let ahk_config = parse_ahk_file(&filenames[0]).map_err(|e| format!("AHK parse error: {}", e))?;
+            let mut parser = AhkParser::new();
+            parser.set_lenient(ahk_lenient);
+            let ahk_config = parser.parse_file(&config_contents).map_err(|e| ConfigError::AhkParse {
+                path: path(),
+                line: e.line,
+                msg: e.msg,
+            })?;
             let extracted_hotstrings = crate::ahk::transpiler::extract_hotstrings(&ahk_config);
 
             let mut config = Config::new();
@@ -170,11 +520,13 @@ pub fn load_configs(filenames: &[PathBuf]) -> Result<Config, Box<dyn error::Erro
 
             config.virtual_modifiers.push(Key::KEY_CAPSLOCK);
 
+            config.title_regexes = title_regexes(&ahk_config);
+
             let mut context_hotkeys = Vec::new();
             let mut global_hotkeys = Vec::new();
 
             for hotkey in ahk_config.hotkeys {
-                let keymap = convert_ahk_hotkey_to_keymap(hotkey);
+                let keymap = convert_ahk_hotkey_to_keymap(hotkey, &ahk_config.window_groups);
                 if keymap.window.is_some() || keymap.application.is_some() {
                     context_hotkeys.push(keymap);
                 } else {
@@ -185,68 +537,84 @@ pub fn load_configs(filenames: &[PathBuf]) -> Result<Config, Box<dyn error::Erro
             config.keymap.extend(global_hotkeys);
 
             config.hotstrings = extracted_hotstrings;
-            if !config.hotstrings.is_empty() {
-                config.hotstring_matcher = Some(HotstringMatcher::new(config.hotstrings.clone()));
-            }
+            config.single_instance = ahk_config.single_instance;
+            config.window_groups = ahk_config.window_groups;
+            config.variables = ahk_config.variables;
 
-            println!("Loaded {} AHK hotkeys", hotkey_count);
-            println!("Loaded {} AHK hotstrings", config.hotstrings.len());
+            let suffix = if additional { " (additional file)" } else { "" };
+            eprintln!("Loaded {} AHK hotkeys{}", hotkey_count, suffix);
+            eprintln!("Loaded {} AHK hotstrings{}", config.hotstrings.len(), suffix);
             config
         }
-        ConfigFiletype::Yaml => serde_yaml::from_str(&config_contents)?,
-        ConfigFiletype::Toml => toml::from_str(&config_contents)?,
+        ConfigFiletype::Yaml => serde_yaml::from_str(&config_contents)
+            .map_err(|source| ConfigError::YamlParse { path: path(), source })?,
+        ConfigFiletype::Toml => toml::from_str(&config_contents)
+            .map_err(|source| ConfigError::TomlParse { path: path(), source })?,
     };
-
-    for filename in &filenames[1..] {
-        let config_contents = fs::read_to_string(filename)?;
-        let c: Config = match get_file_ext(filename) {
-            ConfigFiletype::Ahk => {
-                let ahk_config = parse_ahk_file(filename).map_err(|e| format!("AHK parse error: {}", e))?;
-                let extracted_hotstrings = crate::ahk::transpiler::extract_hotstrings(&ahk_config);
-
-                let mut cfg = Config::new();
-                cfg.virtual_modifiers.push(Key::KEY_CAPSLOCK);
-
-                let hotkey_count = ahk_config.hotkeys.len();
-
-                let mut context_hotkeys = Vec::new();
-                let mut global_hotkeys = Vec::new();
-
-                for hotkey in ahk_config.hotkeys {
-                    let keymap = convert_ahk_hotkey_to_keymap(hotkey);
-                    if keymap.window.is_some() || keymap.application.is_some() {
-                        context_hotkeys.push(keymap);
-                    } else {
-                        global_hotkeys.push(keymap);
-                    }
-                }
-                cfg.keymap.extend(context_hotkeys);
-                cfg.keymap.extend(global_hotkeys);
-
-                cfg.hotstrings = extracted_hotstrings;
-                if !cfg.hotstrings.is_empty() {
-                    cfg.hotstring_matcher = Some(HotstringMatcher::new(cfg.hotstrings.clone()));
-                }
-
-                println!("Loaded {} AHK hotkeys (additional file)", hotkey_count);
-                println!("Loaded {} AHK hotstrings (additional file)", cfg.hotstrings.len());
-
-                cfg
-            }
-            ConfigFiletype::Yaml => serde_yaml::from_str(&config_contents)?,
-            ConfigFiletype::Toml => toml::from_str(&config_contents)?,
-        };
-
-        config.modmap.extend(c.modmap);
-        config.keymap.extend(c.keymap);
-        config.virtual_modifiers.extend(c.virtual_modifiers);
-        config.hotstrings.extend(c.hotstrings);
+    for keymap in &mut config.keymap {
+        keymap.source = filename.display().to_string();
     }
+    Ok(config)
+}
 
-    config.modify_time = filenames.last().and_then(|path| path.metadata().ok()?.modified().ok());
-    config.keymap_table = build_keymap_table(&config.keymap);
+// Settings other than these come from the first file only
+fn merge_config(config: &mut Config, c: Config) {
+    config.modmap.extend(c.modmap);
+    config.keymap.extend(c.keymap);
+    config.virtual_modifiers.extend(c.virtual_modifiers);
+    config.modes.extend(c.modes);
+    config.hotstrings.extend(c.hotstrings);
+    config.passthrough_keys.extend(c.passthrough_keys);
+    config.hotkey_reentrancy_guard |= c.hotkey_reentrancy_guard;
+    config.caps_escape_control |= c.caps_escape_control;
+    config.single_instance = config.single_instance.or(c.single_instance);
+    config.virtual_device_name = config.virtual_device_name.take().or(c.virtual_device_name);
+    config.virtual_device_vendor = config.virtual_device_vendor.or(c.virtual_device_vendor);
+    config.virtual_device_product = config.virtual_device_product.or(c.virtual_device_product);
+    for device_id in c.output_devices {
+        if !config.output_devices.contains(&device_id) {
+            config.output_devices.push(device_id);
+        }
+    }
+    for (name, members) in c.window_groups {
+        config.window_groups.entry(name).or_default().extend(members);
+    }
+    config.variables.extend(c.variables);
+    config.title_regexes.extend(c.title_regexes);
+}
 
-    Ok(config)
+// CapsLock is Escape when tapped and Control when held. It goes after the user's modmaps, so
+// an explicit CapsLock entry there wins. When hotkeys use CapsLock as a prefix (AHK's
+// `CapsLock & j`), holding it keeps acting as that virtual modifier instead of Control;
+// otherwise those hotkeys could never fire.
+fn caps_escape_control_modmap(config: &Config) -> Modmap {
+    let caps_prefix = config.virtual_modifiers.contains(&Key::KEY_CAPSLOCK)
+        && config
+            .keymap
+            .iter()
+            .flat_map(|keymap| keymap.remap.keys())
+            .any(|press| press.modifiers.contains(&key_press::Modifier::Key(Key::KEY_CAPSLOCK)));
+    let held = if caps_prefix {
+        eprintln!("caps_escape_control: CapsLock is a hotkey prefix, so holding it stays that prefix");
+        Key::KEY_CAPSLOCK
+    } else {
+        Key::KEY_LEFTCTRL
+    };
+    let action = ModmapAction::MultiPurposeKey(MultiPurposeKey {
+        held: Keys::Key(held),
+        alone: Keys::Key(Key::KEY_ESC),
+        alone_timeout: default_alone_timeout(),
+        free_hold: false,
+    });
+    Modmap {
+        name: "caps_escape_control".to_string(),
+        remap: HashMap::from([(Key::KEY_CAPSLOCK, action)]),
+        application: None,
+        window: None,
+        instance: None,
+        device: None,
+        mode: None,
+    }
 }
 
 fn convert_actions(action: AhkAction) -> Vec<KeymapAction> {
@@ -261,19 +629,29 @@ fn convert_actions(action: AhkAction) -> Vec<KeymapAction> {
 
 fn needs_interpreter(action: &AhkAction) -> bool {
     match action {
-        AhkAction::Send(_) => true,
+        AhkAction::Send(_) | AhkAction::SendExpression(_) => true,
+        // Only the interpreter keeps variables
+        AhkAction::WinGet { .. } => true,
         AhkAction::Remap(_) => true,
-        AhkAction::WinWaitActive { .. } => true,
+        AhkAction::SetKeyDelay(_) | AhkAction::SendLevel(_) => true,
+        AhkAction::SetLockState { .. } => true,
+        AhkAction::WinWaitActive { .. } | AhkAction::WinWait { .. } => true,
+        AhkAction::KeyWait { .. } => true,
         AhkAction::Block(actions) => actions.iter().any(needs_interpreter),
+        // Only the interpreter knows the groups
+        AhkAction::IfWinActive { criteria: WindowCriteria::Group(_), .. } => true,
         AhkAction::IfWinActive { then_actions, else_actions, .. } => {
             then_actions.iter().any(needs_interpreter) 
                 || else_actions.as_ref().map_or(false, |actions| actions.iter().any(needs_interpreter))
         }
+        AhkAction::WinActivate(criteria)
+        | AhkAction::WinClose(criteria)
+        | AhkAction::WinMaximize(criteria)
+        | AhkAction::WinMinimize(criteria) => matches!(criteria, WindowCriteria::Group(_)),
         AhkAction::Run(_) 
         | AhkAction::Shell(_) 
-        | AhkAction::Sleep(_) 
-        | AhkAction::WinActivate(_) 
-        | AhkAction::WinClose(_) => false,
+        | AhkAction::Sleep(_)
+        | AhkAction::SetTitleMatchMode(_) => false,
     }
 }
 
@@ -291,7 +669,7 @@ fn convert_actions_to_shell(action: AhkAction) -> Vec<KeymapAction> {
             }
             vec![KeymapAction::Launch(cmd)]
         }
-        AhkAction::Send(_keys) => {
+        AhkAction::Send(_) | AhkAction::SendExpression(_) => {
             eprintln!("WARNING: Send() in shell context - this won't work!");
             vec![]
         }
@@ -302,6 +680,10 @@ fn convert_actions_to_shell(action: AhkAction) -> Vec<KeymapAction> {
         AhkAction::Sleep(ms) => {
             vec![keymap_action::KeymapAction::Sleep(ms)]
         }
+        // Only affects Send, which the shell path can't run anyway
+        AhkAction::SetKeyDelay(_) | AhkAction::SendLevel(_) => vec![],
+        // kdotool matches titles its own way
+        AhkAction::SetTitleMatchMode(_) => vec![],
         AhkAction::Shell(script) => {
             vec![KeymapAction::Launch(vec![
                 "/bin/sh".to_string(),
@@ -319,13 +701,31 @@ fn convert_actions_to_shell(action: AhkAction) -> Vec<KeymapAction> {
         AhkAction::WinActivate(criteria) => {
             vec![KeymapAction::Launch(build_kdotool_command("windowactivate", &criteria))]
         }
-        AhkAction::WinWaitActive { .. } => {
-            eprintln!("WARNING: WinWaitActive in shell context - should use interpreter!");
+        AhkAction::WinWaitActive { .. } | AhkAction::WinWait { .. } => {
+            eprintln!("WARNING: WinWait/WinWaitActive in shell context - should use interpreter!");
+            vec![]
+        }
+        AhkAction::SetLockState { .. } => {
+            eprintln!("WARNING: SetLockState in shell context - should use interpreter!");
+            vec![]
+        }
+        AhkAction::KeyWait { .. } => {
+            eprintln!("WARNING: KeyWait in shell context - should use interpreter!");
+            vec![]
+        }
+        AhkAction::WinGet { .. } => {
+            eprintln!("WARNING: WinGet in shell context - should use interpreter!");
             vec![]
         }
         AhkAction::WinClose(criteria) => {
             vec![KeymapAction::Launch(build_kdotool_command("windowclose", &criteria))]
         }
+        AhkAction::WinMaximize(criteria) => {
+            vec![KeymapAction::Launch(kdotool_maximize(build_kdotool_command("windowstate", &criteria)))]
+        }
+        AhkAction::WinMinimize(criteria) => {
+            vec![KeymapAction::Launch(build_kdotool_command("windowminimize", &criteria))]
+        }
         AhkAction::IfWinActive { criteria, then_actions, else_actions } => {
             let condition_check = build_kdotool_shell(&criteria, "getactivewindow");
             let then_script = actions_to_shell_script(&then_actions);
@@ -406,6 +806,14 @@ fn build_kdotool_command(action: &str, criteria: &WindowCriteria) -> Vec<String>
             cmd.push("--classname".to_string());
             cmd.push(exe.clone());
         }
+        WindowCriteria::Pid(pid) => {
+            cmd.push("--pid".to_string());
+            cmd.push(pid.to_string());
+        }
+        WindowCriteria::Group(name) => {
+            eprintln!("WARNING: ahk_group {name} can't be searched for directly; no window matches");
+            return vec!["false".to_string()];
+        }
     }
     
     cmd.push(action.to_string());
@@ -439,12 +847,27 @@ fn build_kdotool_shell(criteria: &WindowCriteria, action: &str) -> String {
                     title.replace("'", "'\\''")
                 )
             }
+            WindowCriteria::Pid(pid) => {
+                format!(
+                    "test \"$(kdotool getactivewindow getwindowpid 2>/dev/null || echo '__NONE__')\" = '{}'",
+                    pid
+                )
+            }
+            WindowCriteria::Group(name) => {
+                eprintln!("WARNING: ahk_group {name} can't be checked directly; no window matches");
+                "false".to_string()
+            }
         }
     } else {
         let search_arg = match criteria {
             WindowCriteria::Title(title) => format!("--name '{}'", title.replace("'", "'\\''")),
             WindowCriteria::Class(class) => format!("--class '{}'", class.replace("'", "'\\''")),
             WindowCriteria::Exe(exe) => format!("--classname '{}'", exe.replace("'", "'\\''")),
+            WindowCriteria::Pid(pid) => format!("--pid {}", pid),
+            WindowCriteria::Group(name) => {
+                eprintln!("WARNING: ahk_group {name} can't be searched for directly; no window matches");
+                return "false".to_string();
+            }
         };
         
         format!("kdotool search {} {}", search_arg, action)
@@ -456,30 +879,133 @@ fn build_kdotool_shell(_criteria: &WindowCriteria, _action: &str) -> String {
     String::from("false")
 }
 
-fn convert_ahk_hotkey_to_keymap(hotkey: crate::ahk::AhkHotkey) -> Keymap {
+// match_window takes a Literal title as a case-insensitive substring, so an exact title is an anchored regex
+fn title_matcher(title: &str, mode: TitleMatchMode) -> ApplicationMatcher {
+    let regex = match mode {
+        TitleMatchMode::Prefix => return ApplicationMatcher::Prefix(title.to_string()),
+        TitleMatchMode::Substring => return ApplicationMatcher::Contains(title.to_string()),
+        TitleMatchMode::Exact => format!("^{}$", regex::escape(title)),
+        TitleMatchMode::Regex => title.to_string(),
+    };
+    match Regex::new(&regex) {
+        Ok(regex) => ApplicationMatcher::Regex(regex),
+        Err(e) => {
+            eprintln!("WARNING: Invalid title regex {title:?} ({e}), matching it as a substring");
+            ApplicationMatcher::Contains(title.to_string())
+        }
+    }
+}
+
+// A title that isn't a valid regex is left out, so it never matches under SetTitleMatchMode RegEx
+fn title_regexes(ahk_config: &AhkConfig) -> HashMap<String, Regex> {
+    let mut criteria = Vec::new();
+    for hotkey in &ahk_config.hotkeys {
+        push_window_criteria(&hotkey.action, &mut criteria);
+    }
+    criteria.extend(ahk_config.window_groups.values().flatten());
+    criteria
+        .into_iter()
+        .filter_map(|criteria| match criteria {
+            WindowCriteria::Title(title) => Some((title.clone(), Regex::new(title).ok()?)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn push_window_criteria<'a>(action: &'a AhkAction, criteria: &mut Vec<&'a WindowCriteria>) {
+    match action {
+        AhkAction::WinActivate(c)
+        | AhkAction::WinClose(c)
+        | AhkAction::WinMaximize(c)
+        | AhkAction::WinMinimize(c)
+        | AhkAction::WinWaitActive { criteria: c, .. }
+        | AhkAction::WinWait { criteria: c, .. }
+        | AhkAction::WinGet { criteria: Some(c), .. } => criteria.push(c),
+        AhkAction::IfWinActive {
+            criteria: c,
+            then_actions,
+            else_actions,
+        } => {
+            criteria.push(c);
+            for action in then_actions.iter().chain(else_actions.iter().flatten()) {
+                push_window_criteria(action, criteria);
+            }
+        }
+        AhkAction::Block(actions) => {
+            for action in actions {
+                push_window_criteria(action, criteria);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn convert_ahk_hotkey_to_keymap(
+    hotkey: crate::ahk::AhkHotkey,
+    window_groups: &HashMap<String, Vec<WindowCriteria>>,
+) -> Keymap {
     let mut keymap = Keymap {
         name: String::new(),
         remap: HashMap::new(),
         application: None,
         window: None,
+        instance: None,
         device: None,
         mode: None,
         exact_match: true,
+        predicate: None,
+        input_level: hotkey.input_level,
+        repeat_while_held: false,
+        repeat_interval_ms: keymap::default_repeat_interval_ms(),
+        capslock: None,
+        numlock: None,
+        source: String::new(),
     };
 
     if let Some(context) = &hotkey.context {
-        if context.contains("ahk_exe") {
-            keymap.application = parse_ahk_context(context);
-        } else {
-            use regex::Regex;
-            let title_re = Regex::new(r#"WinActive\("([^"]+)"\)"#).unwrap();
-            if let Some(caps) = title_re.captures(context) {
-                let window_title = caps[1].to_string();
-                keymap.window = Some(application::OnlyOrNot {
-                    only: Some(vec![application::ApplicationMatcher::Literal(window_title)]),
-                    not: None,
-                });
-                keymap.exact_match = true;
+        if let Some(command) = parse_exec_predicate(context) {
+            keymap.predicate = Some(command);
+        } else if let Ok((criteria, negated)) = parse_win_active(context) {
+            let matcher = |matcher: ApplicationMatcher| {
+                let matchers = Some(vec![matcher]);
+                if negated {
+                    application::OnlyOrNot { only: None, not: matchers }
+                } else {
+                    application::OnlyOrNot { only: matchers, not: None }
+                }
+            };
+            match &criteria {
+                WindowCriteria::Exe(exe) => {
+                    keymap.application = Some(matcher(ApplicationMatcher::Literal(exe.clone())))
+                }
+                WindowCriteria::Class(class) => {
+                    keymap.application = Some(matcher(ApplicationMatcher::Literal(class.clone())))
+                }
+                WindowCriteria::Title(title) => {
+                    keymap.window = Some(matcher(title_matcher(title, hotkey.title_match_mode.unwrap_or_default())))
+                }
+                // Keymaps only match on class and title, so check the PID through kdotool
+                WindowCriteria::Pid(_) => {
+                    let mut check = build_kdotool_shell(&criteria, "getactivewindow");
+                    if negated {
+                        check = format!("! {check}");
+                    }
+                    keymap.predicate = Some(vec!["/bin/sh".to_string(), "-c".to_string(), check]);
+                }
+                // Any member's window, which a keymap can't express when they mix titles and classes
+                WindowCriteria::Group(name) => {
+                    let members = window_groups.get(&name.to_lowercase()).map(Vec::as_slice).unwrap_or_default();
+                    if members.is_empty() {
+                        eprintln!("WARNING: #HotIf uses ahk_group {name}, which has no GroupAdd");
+                    }
+                    let checks: Vec<String> =
+                        members.iter().map(|member| build_kdotool_shell(member, "getactivewindow")).collect();
+                    let mut check = if checks.is_empty() { "false".to_string() } else { checks.join(" || ") };
+                    if negated {
+                        check = format!("! {{ {check}; }}");
+                    }
+                    keymap.predicate = Some(vec!["/bin/sh".to_string(), "-c".to_string(), check]);
+                }
             }
         }
     }
@@ -501,7 +1027,12 @@ fn convert_ahk_hotkey_to_keymap(hotkey: crate::ahk::AhkHotkey) -> Keymap {
         modifiers: modifiers.clone(),
     };
 
-    let actions = convert_actions(hotkey.action);
+    // The interpreter starts each hotkey in the default mode
+    let action = match hotkey.title_match_mode {
+        Some(mode) => AhkAction::Block(vec![AhkAction::SetTitleMatchMode(mode), hotkey.action]),
+        None => hotkey.action,
+    };
+    let actions = convert_actions(action);
 
     keymap.remap.insert(key_press, actions);
     keymap
@@ -0,0 +1,65 @@
+use crate::config::keymap_action::KeymapAction;
+use crate::config::modmap_action::ModmapAction;
+use crate::Config;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+// Values in `modes`, keyed by mode name. Only used by external tools that
+// want to show the current mode; the event handler ignores them.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModeInfo {
+    pub display_name: Option<String>,
+    pub color: Option<String>,
+}
+
+// Modes named in a modmap's or keymap's `mode` that are neither default_mode nor set by any
+// set_mode, so those entries could never apply. Sorted, for a stable error message.
+pub fn unreachable_modes(config: &Config) -> Vec<String> {
+    let mut reachable = HashSet::from([config.default_mode.as_str()]);
+    for keymap in &config.keymap {
+        for actions in keymap.remap.values() {
+            collect_set_modes(actions, &mut reachable);
+        }
+    }
+    for modmap in &config.modmap {
+        for action in modmap.remap.values() {
+            if let ModmapAction::PressReleaseKey(press_release) = action {
+                collect_set_modes(&press_release.press, &mut reachable);
+                collect_set_modes(&press_release.repeat, &mut reachable);
+                collect_set_modes(&press_release.release, &mut reachable);
+            }
+        }
+    }
+
+    let used = config.modmap.iter().filter_map(|modmap| modmap.mode.as_ref());
+    let used = used.chain(config.keymap.iter().filter_map(|keymap| keymap.mode.as_ref()));
+    let mut unreachable: Vec<String> = used
+        .flatten()
+        .filter(|mode| !reachable.contains(mode.as_str()))
+        .cloned()
+        .collect();
+    unreachable.sort();
+    unreachable.dedup();
+    unreachable
+}
+
+fn collect_set_modes<'a>(actions: &'a [KeymapAction], modes: &mut HashSet<&'a str>) {
+    for action in actions {
+        match action {
+            KeymapAction::SetMode(mode) => {
+                modes.insert(mode);
+            }
+            KeymapAction::Remap(remap) => {
+                for actions in remap.remap.values() {
+                    collect_set_modes(actions, modes);
+                }
+            }
+            KeymapAction::Conditional(conditional) => {
+                collect_set_modes(&conditional.then, modes);
+                collect_set_modes(&conditional.otherwise, modes);
+            }
+            _ => {}
+        }
+    }
+}
@@ -18,6 +18,8 @@ pub struct Modmap {
     pub remap: HashMap<Key, ModmapAction>,
     pub application: Option<OnlyOrNot>,
     pub window: Option<OnlyOrNot>,
+    // WM_CLASS instance, for windows that share a class (e.g. Chrome apps)
+    pub instance: Option<OnlyOrNot>,
     pub device: Option<Device>,
     #[serde(default, deserialize_with = "deserialize_string_or_vec")]
     pub mode: Option<Vec<String>>,
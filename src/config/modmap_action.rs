@@ -68,7 +68,7 @@ where
     Ok(actions.into_vec())
 }
 
-fn default_alone_timeout() -> Duration {
+pub(super) fn default_alone_timeout() -> Duration {
     Duration::from_millis(1000)
 }
 
@@ -1,9 +1,377 @@
+use crate::action::Action;
+use crate::ahk::parser::AhkParser;
+use crate::config::keymap::{build_keymap_table, conflicting_bindings};
+use crate::config::modmap_action::ModmapAction;
+use crate::config::{load_configs, load_configs_with, title_regexes, ConfigError};
+use crate::device::InputDeviceInfo;
+use crate::event::{Event, KeyEvent, KeyValue};
+use crate::tests::{get_event_handler, get_input_device_info, load_temp_config, TempConfigDir};
 use crate::Config;
+use evdev::KeyCode as Key;
 use indoc::indoc;
+use std::path::Path;
+use std::time::Duration;
 
 extern crate serde_yaml;
 extern crate toml;
 
+#[test]
+fn test_list_ahk_hotkeys() {
+    let ahk = indoc! {r#"
+        ^!t::Run("konsole")
+        #HotIf WinActive("ahk_exe firefox")
+        ^j::Send("{Down}")
+        #HotIf
+        ::btw::by the way
+        :*:@@::me@example.com
+        "#};
+    let config = load_temp_config("list", &[("config.ahk", ahk)]).unwrap();
+
+    let hotkeys: Vec<String> = config.list_hotkeys().iter().map(|h| h.to_string()).collect();
+    assert_eq!(
+        hotkeys,
+        vec![
+            "^!t  Run".to_string(),
+            "^j  Send  [WinActive(\"ahk_exe firefox\")]".to_string(),
+        ]
+    );
+    let hotstrings: Vec<String> = config.list_hotstrings().iter().map(|h| h.trigger.clone()).collect();
+    assert_eq!(hotstrings, vec!["::btw", ":*:@@"]);
+}
+
+#[test]
+fn test_ahk_context_title_containing_ahk_exe() {
+    let ahk = indoc! {r#"
+        #HotIf WinActive("Notes on ahk_exe matching")
+        ^j::
+        {
+            Send("{Down}")
+        }
+        #HotIf !WinActive("ahk_exe firefox")
+        ^k::
+        {
+            Send("{Up}")
+        }
+        #HotIf
+        "#};
+    let config = load_temp_config("title", &[("config.ahk", ahk)]).unwrap();
+
+    let contexts: Vec<Option<String>> = config.list_hotkeys().into_iter().map(|h| h.context).collect();
+    assert_eq!(
+        contexts,
+        vec![
+            Some("WinActive(\"Notes on ahk_exe matching\")".to_string()),
+            Some("!WinActive(\"ahk_exe firefox\")".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_set_title_match_mode() {
+    let title_matches = |mode: &str, title: &str| {
+        let ahk = format!("{mode}#HotIf WinActive(\"Notes\")\n^j::\n{{\n    Send(\"a\")\n}}\n");
+        let config = load_temp_config("title-mode", &[("config.ahk", &ahk)]).unwrap();
+        config.keymap[0].window.as_ref().unwrap().only.as_ref().unwrap()[0].matches(title)
+    };
+
+    // Substring matching is AHK v2's default
+    assert!(title_matches("", "My Notes - Editor"));
+    assert!(title_matches("SetTitleMatchMode 2\n", "My Notes - Editor"));
+    assert!(!title_matches("SetTitleMatchMode 1\n", "My Notes - Editor"));
+    assert!(title_matches("SetTitleMatchMode 1\n", "Notes - Editor"));
+    assert!(!title_matches("SetTitleMatchMode 3\n", "Notes - Editor"));
+    assert!(title_matches("SetTitleMatchMode 3\n", "Notes"));
+}
+
+#[test]
+fn test_title_regexes() {
+    let content = "^j::\n{\n    WinWaitActive(\"^Notes.*$\")\n    WinActivate(\"Mail (\")\n}\n";
+    let ahk_config = AhkParser::new().parse_file(content).unwrap();
+    let regexes = title_regexes(&ahk_config);
+
+    // Compiled once at load; a title that isn't a valid regex has none
+    assert!(regexes["^Notes.*$"].is_match("Notes - Editor"));
+    assert!(!regexes.contains_key("Mail ("));
+}
+
+#[test]
+fn test_set_title_match_mode_mid_file() {
+    let ahk = indoc! {r#"
+        #HotIf WinActive("Notes")
+        ^j::
+        {
+            Send("{Down}")
+        }
+        SetTitleMatchMode 3
+        ^k::
+        {
+            Send("{Up}")
+        }
+        #HotIf
+        "#};
+    let config = load_temp_config("title-mode-mid", &[("config.ahk", ahk)]).unwrap();
+
+    let title_matcher = |i: usize| &config.keymap[i].window.as_ref().unwrap().only.as_ref().unwrap()[0];
+    assert!(title_matcher(0).matches("My Notes - Editor"));
+    assert!(!title_matcher(1).matches("My Notes - Editor"));
+    assert!(title_matcher(1).matches("Notes"));
+}
+
+#[test]
+fn test_load_large_ahk_file() {
+    let mut source = String::new();
+    for i in 0..200 {
+        source.push_str(&format!("#HotIf WinActive(\"ahk_exe app{i}\")\n^F{}::Send(\"{{Down}}\")\n", i % 12 + 1));
+        source.push_str(&format!("#HotIf WinActive(\"Window {i}\")\n!F{}::Send(\"{{Up}}\")\n#HotIf\n", i % 12 + 1));
+        source.push_str(&format!(":*:tr{i}::replacement {i}\n"));
+    }
+    let config = load_temp_config("large", &[("config.ahk", &source)]).unwrap();
+
+    let contexts: Vec<String> = config.list_hotkeys().into_iter().filter_map(|h| h.context).collect();
+    assert_eq!(contexts.len(), 400);
+    assert!(contexts.contains(&"WinActive(\"ahk_exe app199\")".to_string()));
+    assert!(contexts.contains(&"WinActive(\"Window 199\")".to_string()));
+    assert_eq!(config.list_hotstrings().len(), 200);
+}
+
+#[test]
+fn test_yaml_include() {
+    let dir = TempConfigDir::new(
+        "include",
+        &[
+            ("config.yml", "include: [parts/child.yml]\nkeymap:\n  - remap:\n      C-a: home\n"),
+            ("parts/child.yml", "keymap:\n  - remap:\n      C-e: end\n"),
+        ],
+    );
+    let config = dir.load("config.yml");
+
+    // A cycle back to the parent is an error rather than endless recursion
+    dir.write("parts/child.yml", "include: [../config.yml]\n");
+    let cycle = dir.load("config.yml");
+
+    let combos: Vec<String> = config.unwrap().list_hotkeys().into_iter().map(|h| h.combo).collect();
+    assert_eq!(combos, vec!["^a", "^e"]);
+    assert!(cycle.unwrap_err().to_string().contains("Include cycle"));
+}
+
+#[test]
+fn test_virtual_device_config() {
+    let config = load_temp_config(
+        "virtual-device",
+        &[
+            (
+                "config.yml",
+                "include: [b.yml]\nvirtual_device_name: xremap laptop\nvirtual_device_vendor: 0x046d\n",
+            ),
+            ("b.yml", "virtual_device_name: other\nvirtual_device_product: 0xc52b\n"),
+        ],
+    )
+    .unwrap();
+
+    // The first file's settings win, and the included one fills in the rest
+    assert_eq!(Some("xremap laptop"), config.virtual_device_name.as_deref());
+    assert_eq!(Some(0x046d), config.virtual_device_vendor);
+    assert_eq!(Some(0xc52b), config.virtual_device_product);
+}
+
+#[test]
+fn test_devices_config() {
+    // An entry's own `device` narrows `match` further, so c is never remapped for Foo (event1)
+    // and e only for the one device both name
+    let foo_keymap = indoc! {"
+        keymap:
+          - remap: { a: b }
+          - remap: { c: d }
+            device: { not: event1 }
+          - remap: { e: f }
+            device: { only: [event0, Foo Keyboard] }
+    "};
+    let config = load_temp_config(
+        "devices",
+        &[
+            ("config.yml", "devices:\n  - { match: Foo Keyboard, config: foo.yml }\n"),
+            ("foo.yml", foo_keymap),
+        ],
+    )
+    .unwrap();
+
+    let foo = || InputDeviceInfo {
+        name: "Foo Keyboard",
+        path: Path::new("/dev/input/event1"),
+        vendor: 0x1234,
+        product: 0x5678,
+    };
+    let mut event_handler = get_event_handler(&config, None);
+    let mut press = |device, key| {
+        let events = vec![Event::KeyEvent(device, KeyEvent::new(key, KeyValue::Press))];
+        format!("{:?}", event_handler.on_events(&events, &config).unwrap())
+    };
+    let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+    let remapped = |to| {
+        let actions = [
+            key(to, KeyValue::Press),
+            key(to, KeyValue::Release),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ];
+        format!("{actions:?}")
+    };
+    let passed = |from| format!("{:?}", [key(from, KeyValue::Press)]);
+    assert_eq!(press(foo(), Key::KEY_A), remapped(Key::KEY_B));
+    assert_eq!(press(get_input_device_info(), Key::KEY_A), passed(Key::KEY_A));
+    assert_eq!(press(foo(), Key::KEY_C), passed(Key::KEY_C));
+    assert_eq!(press(get_input_device_info(), Key::KEY_C), passed(Key::KEY_C));
+    assert_eq!(press(foo(), Key::KEY_E), remapped(Key::KEY_F));
+    assert_eq!(press(get_input_device_info(), Key::KEY_E), passed(Key::KEY_E));
+}
+
+#[test]
+fn test_conflicting_bindings() {
+    // C-a in another mode is no conflict, C-e in the same context is
+    let b = "keymap:\n  - remap: { C-a: end }\n    mode: vim\n  - remap: { C-e: home }\n";
+    let dir = TempConfigDir::new("conflicts", &[("b.yml", b)]);
+    let load = |settings: &str| {
+        let keymap = "keymap:\n  - name: Emacs\n    remap: { C-a: home, C-e: end }\n";
+        dir.write("config.yml", &format!("{settings}include: [b.yml]\n{keymap}"));
+        dir.load("config.yml")
+    };
+    let lenient = load("default_mode: vim\n").unwrap();
+    let strict = load("default_mode: vim\nstrict: true\n");
+
+    let (b, config) = (dir.join("b.yml"), dir.join("config.yml"));
+    let expected = format!("^e in {} is already bound in {} (Emacs)", b.display(), config.display());
+    assert_eq!(strict.unwrap_err().to_string(), expected);
+    assert_eq!(conflicting_bindings(&lenient.keymap), [expected]);
+}
+
+#[test]
+fn test_unreachable_mode() {
+    let load = |yaml: &str| load_temp_config("modes", &[("config.yml", yaml)]).map_err(|e| e.to_string());
+    let modmap = "modmap:\n  - remap:\n      A: B\n    mode: [vim, visual]\n";
+    let unreachable = load(&format!("strict: true\n{modmap}"));
+    // Only a warning without strict
+    let lenient = load(modmap);
+    // Set from a modmap's press actions, or from a keymap nested remap
+    let reachable = load(&format!(
+        "{modmap}  - remap:\n      Esc:\n        press: {{ set_mode: vim }}\n        release: []\n\
+         keymap:\n  - remap:\n      C-v:\n        remap:\n          v: {{ set_mode: visual }}\n"
+    ));
+
+    assert_eq!(
+        unreachable.unwrap_err(),
+        "Mode vim, visual is in a `mode` list, but it isn't default_mode and no set_mode switches to it"
+    );
+    assert!(lenient.is_ok(), "{:?}", lenient.err());
+    assert!(reachable.is_ok(), "{:?}", reachable.err());
+}
+
+#[test]
+fn test_window_groups_of_groups() {
+    let yaml = "window_groups:\n  Browsers:\n    - !exe firefox\n    - !group browsers\n";
+    let error = load_temp_config("nested-groups", &[("config.yml", yaml)]).err().unwrap();
+
+    assert_eq!(
+        error.to_string(),
+        "window_groups: browsers lists ahk_group browsers, but a group's members can't be groups"
+    );
+}
+
+#[test]
+fn test_config_error_variants() {
+    let dir = TempConfigDir::new(
+        "errors",
+        &[
+            ("config.toml", "[[keymap]\nremap = {}\n"),
+            ("config.ahk", "^j::Send(\"a\")\n\n#SingleInstance Twice\n"),
+        ],
+    );
+    let toml = dir.load("config.toml").err().unwrap();
+    let ahk = dir.load("config.ahk").err().unwrap();
+    let missing = dir.load("missing.yml").err().unwrap();
+
+    assert!(matches!(toml, ConfigError::TomlParse { .. }), "{toml:?}");
+    assert!(matches!(ahk, ConfigError::AhkParse { line: 3, .. }), "{ahk:?}");
+    assert!(matches!(missing, ConfigError::Io { .. }), "{missing:?}");
+}
+
+#[test]
+fn test_ahk_lenient() {
+    let dir = TempConfigDir::new(
+        "lenient",
+        &[
+            ("hotkeys.ahk", "^j::MsgBox \"hi\"\n^k::Send(\"a\")\n"),
+            ("config.yml", "ahk_lenient: true\ninclude: [hotkeys.ahk]\n"),
+        ],
+    );
+
+    let strict = dir.load("hotkeys.ahk").err().unwrap();
+    let lenient = load_configs_with(&[dir.join("hotkeys.ahk")], true).unwrap();
+    let included = dir.load("config.yml").unwrap();
+
+    assert!(matches!(strict, ConfigError::AhkParse { line: 1, .. }), "{strict:?}");
+    let hotkeys: Vec<String> = lenient.list_hotkeys().into_iter().map(|hotkey| hotkey.action).collect();
+    assert_eq!(hotkeys, ["Run", "Send"]);
+    assert_eq!(included.keymap.len(), 2);
+}
+
+#[test]
+fn test_caps_escape_control() {
+    let dir = TempConfigDir::new(
+        "caps",
+        &[
+            ("config.yml", "caps_escape_control: true\n"),
+            ("layer.ahk", "CapsLock & j::Send(\"{Down}\")\n"),
+        ],
+    );
+    let plain = dir.load("config.yml").unwrap();
+    let with_layer = load_configs(&[dir.join("config.yml"), dir.join("layer.ahk")]).unwrap();
+
+    let caps_action = |config: &Config| {
+        let modmap = config.modmap.last().unwrap();
+        assert_eq!(modmap.name, "caps_escape_control");
+        match modmap.remap.get(&Key::KEY_CAPSLOCK) {
+            Some(ModmapAction::MultiPurposeKey(key)) => {
+                (key.held.clone().into_vec(), key.alone.clone().into_vec())
+            }
+            action => panic!("Expected a multi-purpose CapsLock, got {action:?}"),
+        }
+    };
+    assert_eq!(caps_action(&plain), (vec![Key::KEY_LEFTCTRL], vec![Key::KEY_ESC]));
+    // `CapsLock & j` needs CapsLock itself while held
+    assert_eq!(caps_action(&with_layer), (vec![Key::KEY_CAPSLOCK], vec![Key::KEY_ESC]));
+}
+
+#[test]
+fn test_export_ahk_as_yaml() {
+    let ahk = indoc! {r#"
+        ^!t::Run("konsole")
+        CapsLock & j::Send("{Down}")
+        #HotIf WinActive("ahk_exe firefox")
+        ^w::
+        {
+            Send("^{F4}")
+            Sleep 100
+        }
+        #HotIf
+        ::btw::by the way
+        :*:@@::me@example.com
+        "#};
+    let dir = TempConfigDir::new("export", &[("hotkeys.ahk", ahk)]);
+    let config = dir.load("hotkeys.ahk").unwrap();
+    let yaml = config.to_yaml().unwrap();
+    dir.write("config.yml", &yaml);
+    let reloaded = dir.load("config.yml").unwrap();
+
+    assert!(yaml.contains("ahk:\n        send: '{Down}'"), "{yaml}");
+    assert_eq!(reloaded.to_yaml().unwrap(), yaml);
+    let listing = |config: &Config| -> Vec<String> {
+        let hotkeys = config.list_hotkeys().into_iter().map(|h| h.to_string());
+        hotkeys.chain(config.list_hotstrings().into_iter().map(|h| h.to_string())).collect()
+    };
+    assert_eq!(listing(&reloaded), listing(&config));
+    assert!(reloaded.hotstring_matcher.is_some());
+}
+
 #[test]
 fn test_yaml_modmap_basic() {
     yaml_assert_parse(indoc! {"
@@ -67,6 +435,34 @@ fn test_yaml_modmap_multi_purpose_key() {
             alone_timeout_millis: 500
     "})
 }
+
+#[test]
+fn test_modmap_mode() {
+    let yaml: Config = serde_yaml::from_str(indoc! {"
+    modmap:
+      - remap:
+          A: B
+        mode: [vim, visual]
+      - remap:
+          C: D
+        mode: insert
+    "})
+    .unwrap();
+    let toml: Config = toml::from_str(indoc! {"
+    [[modmap]]
+    mode = [\"vim\", \"visual\"]
+    [modmap.remap]
+    A = \"B\"
+    "})
+    .unwrap();
+
+    let modes = |config: &Config| -> Vec<Option<Vec<String>>> {
+        config.modmap.iter().map(|modmap| modmap.mode.clone()).collect()
+    };
+    let vim = Some(vec!["vim".to_string(), "visual".to_string()]);
+    assert_eq!(modes(&yaml), vec![vim.clone(), Some(vec!["insert".to_string()])]);
+    assert_eq!(modes(&toml), vec![vim]);
+}
 #[test]
 fn test_yaml_modmap_multi_purpose_key_without_timeout() {
     yaml_assert_parse(indoc! {"
@@ -240,6 +636,22 @@ fn test_yaml_keymap_mark() {
     "})
 }
 
+#[test]
+fn test_yaml_keymap_exact_match() {
+    let config: Config = serde_yaml::from_str(indoc! {"
+    keymap:
+      - exact_match: true
+        remap:
+          M-f: C-right
+      - remap:
+          M-b: C-left
+    "})
+    .unwrap();
+    let table = build_keymap_table(&config.keymap);
+    assert!(table[&Key::KEY_F][0].exact_match);
+    assert!(!table[&Key::KEY_B][0].exact_match);
+}
+
 #[test]
 fn test_yaml_shared_data_anchor() {
     yaml_assert_parse(indoc! {"
@@ -538,6 +950,18 @@ fn test_toml_keymap_mark() {
     "})
 }
 
+#[test]
+fn test_toml_keymap_exact_match() {
+    let config: Config = toml::from_str(indoc! {"
+    [[keymap]]
+    exact_match = true
+    [keymap.remap]
+    M-f = \"C-right\"
+    "})
+    .unwrap();
+    assert!(config.keymap[0].exact_match);
+}
+
 #[test]
 fn test_toml_shared_data_anchor() {
     toml_assert_parse(indoc! {"
@@ -1,12 +1,14 @@
 extern crate evdev;
 extern crate nix;
 
+use crate::Config;
 use anyhow::bail;
 use derive_where::derive_where;
 use evdev::uinput::VirtualDevice;
-use evdev::{AttributeSet, BusType, Device, FetchEventsSynced, InputId, KeyCode as Key, RelativeAxisCode};
+use evdev::{AttributeSet, BusType, Device, FetchEventsSynced, InputId, KeyCode as Key, LedCode, RelativeAxisCode};
 use log::debug;
 use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::error::Error;
 #[cfg(feature = "udev")]
@@ -18,7 +20,7 @@ use std::os::unix::ffi::OsStrExt;
 use std::os::unix::prelude::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
-use std::{io, process};
+use std::{fmt, io, process};
 #[cfg(feature = "udev")]
 use udev::DeviceType;
 
@@ -47,18 +49,69 @@ static MOUSE_BTNS: [&str; 20] = [
 
 pub static mut DEVICE_NAME: Option<String> = None;
 
+// The output device's name, vendor and product id. Each comes from the command line, else from
+// the config's virtual_device_* settings, else the default; no name is InputDevice::current_name's.
+#[derive(Debug, PartialEq)]
+pub struct OutputDeviceIdentity {
+    pub name: Option<String>,
+    pub vendor: u16,
+    pub product: u16,
+}
+
+pub fn output_device_identity(
+    name: Option<String>,
+    vendor: Option<String>,
+    product: Option<String>,
+    config: &Config,
+) -> OutputDeviceIdentity {
+    OutputDeviceIdentity {
+        name: name.or_else(|| config.virtual_device_name.clone()),
+        vendor: device_id(vendor).or(config.virtual_device_vendor).unwrap_or(0x1234),
+        product: device_id(product).or(config.virtual_device_product).unwrap_or(0x5678),
+    }
+}
+
+// A --vendor or --product value, hex with or without 0x; None when unset or invalid
+fn device_id(flag: Option<String>) -> Option<u16> {
+    u16::from_str_radix(flag?.trim_start_matches("0x"), 16).ok()
+}
+
 // Credit: https://github.com/mooz/xkeysnail/blob/bf3c93b4fe6efd42893db4e6588e5ef1c4909cfb/xkeysnail/output.py#L10-L32
 pub fn output_device(
     bus_type: Option<BusType>,
     enable_wheel: bool,
     vendor: u16,
     product: u16,
+) -> Result<VirtualDevice, Box<dyn Error>> {
+    named_output_device(InputDevice::current_name(), bus_type, enable_wheel, vendor, product)
+}
+
+// The extra output device `device_id` from `output_devices`, for route_to
+pub fn routed_output_device(
+    device_id: &str,
+    bus_type: Option<BusType>,
+    enable_wheel: bool,
+    vendor: u16,
+    product: u16,
+) -> Result<VirtualDevice, Box<dyn Error>> {
+    named_output_device(&routed_device_name(device_id), bus_type, enable_wheel, vendor, product)
+}
+
+fn routed_device_name(device_id: &str) -> String {
+    format!("{} route={device_id}", InputDevice::current_name())
+}
+
+fn named_output_device(
+    name: &str,
+    bus_type: Option<BusType>,
+    enable_wheel: bool,
+    vendor: u16,
+    product: u16,
 ) -> Result<VirtualDevice, Box<dyn Error>> {
     let mut keys: AttributeSet<Key> = AttributeSet::new();
     for code in Key::KEY_RESERVED.code()..Key::BTN_TRIGGER_HAPPY40.code() {
         let key = Key::new(code);
-        let name = format!("{key:?}");
-        if name.starts_with("KEY_") || MOUSE_BTNS.contains(&&*name) {
+        if is_output_key(key) {
             keys.insert(key);
         }
     }
@@ -72,16 +125,39 @@ pub fn output_device(
     }
     relative_axes.insert(RelativeAxisCode::REL_MISC);
 
-    let device = VirtualDevice::builder()?
+    let device = VirtualDevice::builder()
+        .map_err(uinput_error)?
         // These are taken from https://docs.rs/evdev/0.12.0/src/evdev/uinput.rs.html#183-188
         .input_id(InputId::new(bus_type.unwrap_or(BusType::BUS_USB), vendor, product, 0x111))
-        .name(&InputDevice::current_name())
+        .name(name)
         .with_keys(&keys)?
         .with_relative_axes(&relative_axes)?
         .build()?;
     Ok(device)
 }
 
+// The keys the output device advertises, and so can send: the ones evdev names KEY_*, and mouse
+// buttons. Others, like gamepad buttons, would make it look like a different kind of device.
+pub fn is_output_key(key: Key) -> bool {
+    let name = format!("{key:?}");
+    key.code() < Key::BTN_TRIGGER_HAPPY40.code() && (name.starts_with("KEY_") || MOUSE_BTNS.contains(&&*name))
+}
+
+// Opening /dev/uinput fails before anything else when the module isn't loaded or this user
+// can't write to it, so say how to fix that rather than just "Permission denied"
+fn uinput_error(e: io::Error) -> io::Error {
+    let hint = match e.kind() {
+        io::ErrorKind::NotFound => "/dev/uinput doesn't exist; load the uinput module with `sudo modprobe uinput`",
+        io::ErrorKind::PermissionDenied => {
+            "this user can't write to /dev/uinput; add a udev rule such as \
+             `KERNEL==\"uinput\", GROUP=\"input\", TAG+=\"uaccess\"` and join the input group \
+             (`sudo gpasswd -a $USER input`), then log in again"
+        }
+        _ => return e,
+    };
+    io::Error::new(e.kind(), format!("{e}: {hint}"))
+}
+
 pub fn device_watcher(watch: bool) -> anyhow::Result<Option<Inotify>> {
     if watch {
         let inotify = Inotify::init(InitFlags::IN_NONBLOCK)?;
@@ -92,11 +168,24 @@ pub fn device_watcher(watch: bool) -> anyhow::Result<Option<Inotify>> {
     }
 }
 
+// How the selected devices are read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GrabMode {
+    // Grabbed, so apps only get what the remapping sends
+    #[default]
+    Exclusive,
+    // Read without grabbing, so apps get every event as typed and nothing is sent, e.g. to
+    // build hotstring buffers or run hotkeys' commands alongside another remapper
+    Monitor,
+}
+
 pub fn get_input_devices(
     device_opts: &[String],
     ignore_opts: &[String],
     mouse: bool,
     watch: bool,
+    grab_mode: GrabMode,
 ) -> anyhow::Result<HashMap<PathBuf, InputDevice>> {
     let mut devices: Vec<_> = InputDevice::devices()?.collect();
     devices.sort();
@@ -127,7 +216,7 @@ pub fn get_input_devices(
         // alternative is `Vec::retain_mut` whenever that gets stabilized
         .filter_map(|mut device| {
             // filter out any not matching devices and devices that error on grab
-            (device.is_input_device(device_opts, ignore_opts, mouse) && device.grab()).then_some(device)
+            (device.is_input_device(device_opts, ignore_opts, mouse) && device.grab_for(grab_mode)).then_some(device)
         })
         .collect();
 
@@ -146,6 +235,88 @@ pub fn get_input_devices(
     Ok(devices.into_iter().map(From::from).collect())
 }
 
+// A /dev/input device as `--list-devices` shows it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedDevice {
+    pub name: String,
+    pub path: PathBuf,
+    pub vendor: u16,
+    pub product: u16,
+    pub keyboard: bool,
+    pub mouse: bool,
+    // Grabbed with the given --device, --ignore and --mouse options
+    pub selected: bool,
+}
+
+impl ListedDevice {
+    pub fn to_info(&self) -> InputDeviceInfo<'_> {
+        InputDeviceInfo {
+            name: &self.name,
+            path: &self.path,
+            product: self.product,
+            vendor: self.vendor,
+        }
+    }
+}
+
+impl fmt::Display for ListedDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = if self.selected { '*' } else { ' ' };
+        write!(f, "{marker} {:18}: {} ({:04x}:{:04x})", self.path.display(), self.name, self.vendor, self.product)
+    }
+}
+
+// Every device that can be opened, sorted by path
+pub fn list(device_filter: &[String], ignore_filter: &[String], mouse: bool) -> io::Result<Vec<ListedDevice>> {
+    let devices = InputDevice::devices()?.map(|device| ListedDevice {
+        name: device.device_name().to_string(),
+        path: device.path.clone(),
+        vendor: device.vendor(),
+        product: device.product(),
+        keyboard: device.is_keyboard(),
+        mouse: device.is_mouse(),
+        selected: false,
+    });
+    Ok(mark_selected(devices, device_filter, ignore_filter, mouse))
+}
+
+fn mark_selected(
+    devices: impl IntoIterator<Item = ListedDevice>,
+    device_filter: &[String],
+    ignore_filter: &[String],
+    mouse: bool,
+) -> Vec<ListedDevice> {
+    let mut devices: Vec<ListedDevice> = devices
+        .into_iter()
+        .map(|mut device| {
+            device.selected =
+                is_selected(&device.to_info(), device.keyboard, device.mouse, device_filter, ignore_filter, mouse);
+            device
+        })
+        .collect();
+    devices.sort_by(|a, b| a.path.cmp(&b.path));
+    devices
+}
+
+fn is_selected(
+    info: &InputDeviceInfo,
+    keyboard: bool,
+    mouse_device: bool,
+    device_filter: &[String],
+    ignore_filter: &[String],
+    mouse: bool,
+) -> bool {
+    if info.is_own_output() {
+        return false;
+    }
+    let matches_any = |filter: &[String]| filter.iter().any(|f| info.matches(f));
+    (if device_filter.is_empty() {
+        keyboard || (mouse && mouse_device)
+    } else {
+        matches_any(device_filter)
+    }) && (ignore_filter.is_empty() || !matches_any(ignore_filter))
+}
+
 #[derive(Debug)]
 pub struct InputDeviceInfo<'a> {
     pub name: &'a str,
@@ -155,6 +326,11 @@ pub struct InputDeviceInfo<'a> {
 }
 
 impl<'a> InputDeviceInfo<'a> {
+    // One of our own output devices, i.e. the events are ones we injected
+    pub fn is_own_output(&self) -> bool {
+        self.name == InputDevice::current_name() || self.name.starts_with(&routed_device_name(""))
+    }
+
     pub fn matches(&self, filter: &str) -> bool {
         // Check exact matches for explicit selection
         if self.path.as_os_str() == filter || self.name == filter {
@@ -289,6 +465,10 @@ impl InputDevice {
         }
     }
 
+    pub fn grab_for(&mut self, grab_mode: GrabMode) -> bool {
+        grab_mode == GrabMode::Monitor || self.grab()
+    }
+
     pub fn ungrab(&mut self) {
         if let Err(error) = self.device.ungrab() {
             println!("Failed to ungrab device '{}' at '{}' due to: {error}", self.device_name(), self.path.display());
@@ -315,6 +495,11 @@ impl InputDevice {
         self.device.input_id().vendor()
     }
 
+    // The LEDs on now; none for a device without any
+    pub fn lit_leds(&self) -> Vec<LedCode> {
+        self.device.get_led_state().map(|leds| leds.iter().collect()).unwrap_or_default()
+    }
+
     pub fn to_info(&self) -> InputDeviceInfo<'_> {
         InputDeviceInfo {
             name: self.device_name(),
@@ -327,14 +512,13 @@ impl InputDevice {
 
 impl InputDevice {
     pub fn is_input_device(&self, device_filter: &[String], ignore_filter: &[String], mouse: bool) -> bool {
-        if self.device_name() == Self::current_name() {
-            return false;
-        }
-        (if device_filter.is_empty() {
-            self.is_keyboard() || (mouse && self.is_mouse())
+        // Only check the kind of device when there's no --device to go by
+        let (keyboard, mouse_device) = if device_filter.is_empty() {
+            (self.is_keyboard(), mouse && self.is_mouse())
         } else {
-            self.matches_any(device_filter)
-        }) && (ignore_filter.is_empty() || !self.matches_any(ignore_filter))
+            (false, false)
+        };
+        is_selected(&self.to_info(), keyboard, mouse_device, device_filter, ignore_filter, mouse)
     }
 
     // We can't know the device path from evdev::enumerate(). So we re-implement it.
@@ -346,7 +530,7 @@ impl InputDevice {
     }
 
     #[allow(static_mut_refs)]
-    fn current_name() -> &'static str {
+    pub fn current_name() -> &'static str {
         if unsafe { DEVICE_NAME.is_none() } {
             let device_name = if Self::has_device_name("xremap") {
                 format!("xremap pid={}", process::id())
@@ -368,14 +552,6 @@ impl InputDevice {
         devices.iter().any(|device| device.device_name().contains(device_name))
     }
 
-    fn matches_any(&self, filter: &[String]) -> bool {
-        // Force unmatch its own device
-        if self.device_name() == Self::current_name() {
-            return false;
-        }
-        filter.iter().any(|f| self.to_info().matches(f))
-    }
-
     fn is_keyboard(&self) -> bool {
         // Credit: https://github.com/mooz/xkeysnail/blob/bf3c93b4fe6efd42893db4e6588e5ef1c4909cfb/xkeysnail/input.py#L17-L32
         match self.device.supported_keys() {
@@ -401,3 +577,96 @@ impl InputDevice {
 }
 
 pub const SEPARATOR: &str = "------------------------------------------------------------------------------";
+
+#[cfg(test)]
+mod tests {
+    use super::{mark_selected, output_device_identity, uinput_error, ListedDevice, OutputDeviceIdentity};
+    use crate::tests::parse_config;
+    use std::io;
+    use std::path::PathBuf;
+
+    fn device(path: &str, name: &str, keyboard: bool, mouse: bool) -> ListedDevice {
+        ListedDevice {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+            vendor: 0x046d,
+            product: 0xc52b,
+            keyboard,
+            mouse,
+            selected: false,
+        }
+    }
+
+    fn selected(device_filter: &[&str], ignore_filter: &[&str], mouse: bool) -> Vec<String> {
+        let devices = vec![
+            device("/dev/input/event5", "Logitech USB Receiver Mouse", false, true),
+            device("/dev/input/event3", "AT Translated Set 2 keyboard", true, false),
+            device("/dev/input/event4", "Power Button", false, false),
+        ];
+        let to_strings = |filter: &[&str]| filter.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        mark_selected(devices, &to_strings(device_filter), &to_strings(ignore_filter), mouse)
+            .iter()
+            .filter(|device| device.selected)
+            .map(|device| device.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_list_marks_selected_devices() {
+        assert_eq!(selected(&[], &[], false), ["AT Translated Set 2 keyboard"]);
+        assert_eq!(selected(&[], &[], true), ["AT Translated Set 2 keyboard", "Logitech USB Receiver Mouse"]);
+        assert_eq!(selected(&["event4"], &[], false), ["Power Button"]);
+        assert_eq!(
+            selected(&["ids:0x046d:0xc52b"], &["keyboard"], false),
+            ["Power Button", "Logitech USB Receiver Mouse"]
+        );
+    }
+
+    #[test]
+    fn test_listed_device_display() {
+        let mut keyboard = device("/dev/input/event3", "AT Translated Set 2 keyboard", true, false);
+        keyboard.selected = true;
+        assert_eq!(keyboard.to_string(), "* /dev/input/event3 : AT Translated Set 2 keyboard (046d:c52b)");
+    }
+
+    #[test]
+    fn test_uinput_error() {
+        let denied = uinput_error(io::Error::from_raw_os_error(13));
+        assert_eq!(io::ErrorKind::PermissionDenied, denied.kind());
+        assert_eq!(
+            "Permission denied (os error 13): this user can't write to /dev/uinput; add a udev rule such as \
+             `KERNEL==\"uinput\", GROUP=\"input\", TAG+=\"uaccess\"` and join the input group \
+             (`sudo gpasswd -a $USER input`), then log in again",
+            denied.to_string()
+        );
+        let missing = uinput_error(io::Error::from_raw_os_error(2));
+        assert_eq!(
+            "No such file or directory (os error 2): /dev/uinput doesn't exist; \
+             load the uinput module with `sudo modprobe uinput`",
+            missing.to_string()
+        );
+        let busy = uinput_error(io::Error::from_raw_os_error(16));
+        assert_eq!("Device or resource busy (os error 16)", busy.to_string());
+    }
+
+    #[test]
+    fn test_output_device_identity() {
+        let config = parse_config("virtual_device_name: laptop\nvirtual_device_vendor: 0x046d\n");
+        let flag = |value: &str| Some(value.to_string());
+
+        // The command line wins over the config, and the defaults fill in the rest
+        assert_eq!(
+            output_device_identity(flag("desk"), flag("0x1d6b"), None, &config),
+            OutputDeviceIdentity { name: flag("desk"), vendor: 0x1d6b, product: 0x5678 }
+        );
+        // An invalid id is ignored
+        assert_eq!(
+            output_device_identity(None, flag("xyz"), flag("c52b"), &config),
+            OutputDeviceIdentity { name: flag("laptop"), vendor: 0x046d, product: 0xc52b }
+        );
+        assert_eq!(
+            output_device_identity(None, None, None, &parse_config("")),
+            OutputDeviceIdentity { name: None, vendor: 0x1234, product: 0x5678 }
+        );
+    }
+}
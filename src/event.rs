@@ -1,4 +1,7 @@
 use evdev::{EventType, InputEvent, KeyCode as Key};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::time::SystemTime;
 
 use crate::device::InputDeviceInfo;
 
@@ -30,23 +33,81 @@ impl<'a> Event<'a> {
     }
     #[cfg(test)]
     pub fn relative(code: u16, value: i32) -> Event<'a> {
-        Event::RelativeEvent(crate::tests::get_input_device_info(), RelativeEvent { code, value })
+        Event::RelativeEvent(crate::tests::get_input_device_info(), RelativeEvent::new_with(code, value))
+    }
+
+    // When the kernel read the event, if it came from a device
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        match self {
+            Event::KeyEvent(_, event) => event.time,
+            Event::RelativeEvent(_, event) => event.time,
+            Event::OtherEvents(event) => Some(event.timestamp()),
+            Event::OverrideTimeout => None,
+        }
     }
 }
 
-#[derive(Debug)]
+// Merges the events read from each device into the order they happened in, so a modifier held
+// on one keyboard applies to a key pressed on another in the same round. Each batch is already in
+// order; an event without a timestamp stays right after the one before it.
+pub fn merge_by_time(batches: Vec<Vec<Event<'_>>>) -> Vec<Event<'_>> {
+    let mut events = vec![];
+    for batch in batches {
+        let mut time = None;
+        for event in batch {
+            time = event.timestamp().or(time);
+            events.push((time, event));
+        }
+    }
+    // Stable, so events of one SYN report keep their order
+    events.sort_by_key(|(time, _)| *time);
+    events.into_iter().map(|(_, event)| event).collect()
+}
+
+#[derive(Serialize)]
 pub struct KeyEvent {
+    #[serde(serialize_with = "serialize_key")]
     pub key: Key,
     value: KeyValue,
+    // Only set on input; sent events go out whenever they're written
+    #[serde(skip)]
+    time: Option<SystemTime>,
 }
 
-#[derive(Debug)]
+#[derive(Serialize)]
 pub struct RelativeEvent {
     pub code: u16,
     pub value: i32,
+    #[serde(skip)]
+    time: Option<SystemTime>,
 }
 
-#[derive(Debug)]
+// By name, e.g. "KEY_A"
+fn serialize_key<S: Serializer>(key: &Key, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{key:?}"))
+}
+
+// Without the timestamps, which only matter for ordering the input
+impl fmt::Debug for KeyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyEvent").field("key", &self.key).field("value", &self.value).finish()
+    }
+}
+
+impl fmt::Debug for RelativeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelativeEvent").field("code", &self.code).field("value", &self.value).finish()
+    }
+}
+
+// Values of EventType::KEY events as evdev reports them
+pub const RELEASE: i32 = 0;
+pub const PRESS: i32 = 1;
+// Autorepeat while the key is held
+pub const REPEAT: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum KeyValue {
     Press,
     Release,
@@ -55,9 +116,12 @@ pub enum KeyValue {
 impl<'a> Event<'a> {
     // Convert evdev's raw InputEvent to xremap's internal Event
     pub fn new(device: InputDeviceInfo, event: InputEvent) -> Event {
+        let time = event.timestamp();
         let event = match event.event_type() {
-            EventType::KEY => Event::KeyEvent(device, KeyEvent::new_with(event.code(), event.value())),
-            EventType::RELATIVE => Event::RelativeEvent(device, RelativeEvent::new_with(event.code(), event.value())),
+            EventType::KEY => Event::KeyEvent(device, KeyEvent::new_with(event.code(), event.value()).at(time)),
+            EventType::RELATIVE => {
+                Event::RelativeEvent(device, RelativeEvent::new_with(event.code(), event.value()).at(time))
+            }
             _ => Event::OtherEvents(event),
         };
         event
@@ -67,48 +131,77 @@ impl<'a> Event<'a> {
 impl KeyEvent {
     // Constructor with newer interface
     pub fn new(key: Key, value: KeyValue) -> KeyEvent {
-        KeyEvent { key, value }
+        KeyEvent { key, value, time: None }
     }
 
-    // Constructor with legacy interface
+    // Constructor with legacy interface. Panics on a value other than RELEASE, PRESS or REPEAT.
     pub fn new_with(code: u16, value: i32) -> KeyEvent {
         let key = Key::new(code);
-        let value = KeyValue::new(value).unwrap();
+        let value = KeyValue::try_from(value).unwrap();
         KeyEvent::new(key, value)
     }
 
+    pub fn at(self, time: SystemTime) -> KeyEvent {
+        KeyEvent { time: Some(time), ..self }
+    }
+
     pub fn code(&self) -> u16 {
         self.key.code()
     }
 
     pub fn value(&self) -> i32 {
-        self.value.value()
+        self.value.into()
     }
 }
 
 // constructor for relative events.
 impl RelativeEvent {
     pub fn new_with(code: u16, value: i32) -> RelativeEvent {
-        RelativeEvent { code, value }
+        RelativeEvent { code, value, time: None }
+    }
+
+    pub fn at(self, time: SystemTime) -> RelativeEvent {
+        RelativeEvent { time: Some(time), ..self }
     }
 }
 
-impl KeyValue {
-    fn new(value: i32) -> Option<KeyValue> {
-        let event_value = match value {
-            0 => KeyValue::Release,
-            1 => KeyValue::Press,
-            2 => KeyValue::Repeat,
-            _ => return None,
-        };
-        Some(event_value)
+// Fails on values key events don't have, returning the value
+impl TryFrom<i32> for KeyValue {
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<KeyValue, i32> {
+        match value {
+            RELEASE => Ok(KeyValue::Release),
+            PRESS => Ok(KeyValue::Press),
+            REPEAT => Ok(KeyValue::Repeat),
+            _ => Err(value),
+        }
     }
+}
 
-    fn value(&self) -> i32 {
-        match self {
-            KeyValue::Release => 0,
-            KeyValue::Press => 1,
-            KeyValue::Repeat => 2,
+impl From<KeyValue> for i32 {
+    fn from(value: KeyValue) -> i32 {
+        match value {
+            KeyValue::Release => RELEASE,
+            KeyValue::Press => PRESS,
+            KeyValue::Repeat => REPEAT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyEvent, KeyValue, PRESS, RELEASE, REPEAT};
+    use evdev::KeyCode as Key;
+
+    #[test]
+    fn test_key_value_conversions() {
+        for (value, key_value) in [(RELEASE, KeyValue::Release), (PRESS, KeyValue::Press), (REPEAT, KeyValue::Repeat)] {
+            assert_eq!(KeyValue::try_from(value), Ok(key_value));
+            assert_eq!(i32::from(key_value), value);
+            assert_eq!(KeyEvent::new_with(Key::KEY_A.code(), value).value(), value);
         }
+        assert_eq!(KeyValue::try_from(3), Err(3));
+        assert_eq!(KeyValue::try_from(-1), Err(-1));
     }
 }
@@ -1,29 +1,53 @@
 use crate::action::Action;
+use crate::ahk::interpreter::{AhkInterpreter, PendingWait};
 use crate::client::WMClient;
 use crate::config::application::OnlyOrNot;
 use crate::config::application::ApplicationMatcher;
 use crate::config::key_press::{KeyPress, Modifier};
-use crate::config::keymap::{build_override_table, OverrideEntry};
-use crate::config::keymap_action::KeymapAction;
+use crate::config::keymap::{build_override_table, KeymapEntry, LockCondition, OverrideEntry};
+use crate::config::keymap_action::{KeymapAction, SequenceStep};
 use crate::config::modmap_action::{Keys, ModmapAction, MultiPurposeKey, PressReleaseKey};
 use crate::config::remap::Remap;
-use crate::device::InputDeviceInfo;
-use crate::event::{Event, KeyEvent, RelativeEvent};
-use crate::hotstring;
+use crate::device::{GrabMode, InputDevice, InputDeviceInfo};
+use crate::event::{Event, KeyEvent, RelativeEvent, PRESS, RELEASE, REPEAT};
+use crate::hotstring::{self, HotstringMode};
+use crate::keys;
 use crate::Config;
+use evdev::EventType;
 use evdev::KeyCode as Key;
+use evdev::LedCode;
+use evdev::RelativeAxisCode;
 use lazy_static::lazy_static;
-use log::debug;
+use log::{debug, warn};
+use serde::Deserialize;
 use nix::sys::time::TimeSpec;
 use nix::sys::timerfd::{Expiration, TimerFd, TimerSetTimeFlags};
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::path::Path;
+use std::process::{Command, Stdio};
 use std::time::{Duration, Instant};
+use wait_timeout::ChildExt;
 
 pub const DISGUISED_EVENT_OFFSETTER: u16 = 59974;
 pub const KEY_MATCH_ANY: Key = Key(DISGUISED_EVENT_OFFSETTER + 26);
 
+// How many leveled Sends may trigger hotkeys that send again, within one keystroke
+const MAX_SEND_LEVEL_DEPTH: usize = 4;
+
+// Where pointer motion goes among the other output of a batch of events. Either way motion in a
+// row stays in one report, and motion before a mouse button goes out before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseMotionOrder {
+    // After everything else, so a batch sends its motion once
+    #[default]
+    BatchAtEnd,
+    // Where it happened among the other events
+    Interleave,
+}
+
 pub struct EventHandler {
     modifiers: HashSet<Key>,
     extra_modifiers: HashSet<Key>,
@@ -31,6 +55,7 @@ pub struct EventHandler {
     application_client: WMClient,
     application_cache: Option<String>,
     title_cache: Option<String>,
+    instance_cache: Option<String>,
     multi_purpose_keys: HashMap<Key, MultiPurposeKeyState>,
     override_remaps: Vec<HashMap<Key, Vec<OverrideEntry>>>,
     override_timeout_key: Option<Vec<Key>>,
@@ -38,15 +63,48 @@ pub struct EventHandler {
     mode: String,
     mark_set: bool,
     escape_next_key: bool,
+    // Pass every key through untouched until toggle_suspend is pressed again
+    suspended: bool,
+    // Set by set_wheel_enabled or toggle_wheel; while false, wheel events are dropped
+    wheel_enabled: Option<bool>,
+    // Keyboard LEDs lit as of the last EV_LED events, for keymaps' capslock and numlock
+    lit_leds: HashSet<LedCode>,
     keypress_delay: Duration,
     actions: Vec<Action>,
     hotstring_state: Option<hotstring::HotstringMatcherState>,
     hotstring_buffer: String,
+    // When the hotstring buffer last got a character, for hotstring_idle_timeout_ms
+    hotstring_last_char_at: Option<Instant>,
+    // Trigger key -> when the actions it dispatched (including delays) are done
+    in_flight_hotkeys: HashMap<Key, Instant>,
+    // Predicate command -> (when it was run, whether it exited 0)
+    predicate_cache: HashMap<Vec<String>, (Instant, bool)>,
+    // Source of the per-Send generators used for type_jitter_ms
+    type_jitter_rng: fastrand::Rng,
+    // WinWait/WinWaitActive/KeyWait scripts parked until their window or key state shows up,
+    // re-checked from on_events
+    pending_waits: Vec<PendingWait>,
+    // SendLevels of the Sends being run through the hotkeys again, innermost last
+    send_levels: Vec<u8>,
+    // The repeat_while_held hotkey being held, re-dispatched from on_events
+    held_hotkey: Option<HeldHotkey>,
+    // Pointer motion not sent yet; see MouseMotionOrder
+    mouse_motion: Vec<RelativeEvent>,
+    clock: fn() -> Instant,
 }
 
+#[derive(Clone)]
 struct TaggedAction {
     action: KeymapAction,
     exact_match: bool,
+    repeat_interval: Option<Duration>,
+}
+
+struct HeldHotkey {
+    key: Key,
+    actions: Vec<TaggedAction>,
+    interval: Duration,
+    next_at: Instant,
 }
 
 impl EventHandler {
@@ -58,6 +116,7 @@ impl EventHandler {
             application_client,
             application_cache: None,
             title_cache: None,
+            instance_cache: None,
             multi_purpose_keys: HashMap::new(),
             override_remaps: vec![],
             override_timeout_key: None,
@@ -65,85 +124,94 @@ impl EventHandler {
             mode: mode.to_string(),
             mark_set: false,
             escape_next_key: false,
+            suspended: false,
+            wheel_enabled: None,
+            lit_leds: HashSet::new(),
             keypress_delay,
             actions: vec![],
             hotstring_state: None,
             hotstring_buffer: String::new(),
+            hotstring_last_char_at: None,
+            in_flight_hotkeys: HashMap::new(),
+            predicate_cache: HashMap::new(),
+            type_jitter_rng: fastrand::Rng::new(),
+            pending_waits: vec![],
+            send_levels: vec![],
+            held_hotkey: None,
+            mouse_motion: vec![],
+            clock: Instant::now,
         }
     }
 
+    // The hotstring idle timeout, held hotkey repeats, pending waits and the in-flight guard go by it
+    pub fn set_clock(&mut self, clock: fn() -> Instant) {
+        self.clock = clock;
+    }
+
+    // Name of the current mode, for callers that want to show it
+    pub fn mode_info(&self) -> &str {
+        &self.mode
+    }
+
+    // How long the caller may block before on_events is due to re-check a pending WinWait/KeyWait or
+    // repeat a held hotkey
+    pub fn next_wait_check(&self) -> Option<Duration> {
+        let next_check = self
+            .pending_waits
+            .iter()
+            .map(PendingWait::next_check)
+            .chain(self.held_hotkey.as_ref().map(|held| held.next_at))
+            .min()?;
+        Some(next_check.saturating_duration_since((self.clock)()))
+    }
+
     pub fn on_events(&mut self, events: &Vec<Event>, config: &Config) -> Result<Vec<Action>, Box<dyn Error>> {
-        let mut mouse_movement_collection: Vec<RelativeEvent> = Vec::new();
+        if !self.pending_waits.is_empty() {
+            self.poll_pending_waits(config);
+        }
+        self.repeat_held_hotkey(config)?;
         for event in events {
+            let is_motion = matches!(event, Event::RelativeEvent(_, event) if event.code <= 2);
+            if config.mouse_motion_order == MouseMotionOrder::Interleave && !is_motion {
+                self.flush_mouse_motion();
+            }
             match event {
+                // Injected events only come back if our output device is read after all (it is never
+                // grabbed); pass them through so they aren't remapped a second time
+                Event::KeyEvent(device, key_event) if device.is_own_output() => {
+                    self.send_action(Action::KeyEvent(KeyEvent::new_with(key_event.code(), key_event.value())))
+                }
+                Event::RelativeEvent(device, relative_event) if device.is_own_output() => {
+                    self.send_action(Action::RelativeEvent(RelativeEvent::new_with(
+                        relative_event.code,
+                        relative_event.value,
+                    )))
+                }
                 Event::KeyEvent(device, key_event) => {
                     self.on_key_event(key_event, config, device)?;
                 }
                 Event::RelativeEvent(device, relative_event) => {
-                    self.on_relative_event(relative_event, &mut mouse_movement_collection, config, device)?
+                    self.on_relative_event(relative_event, config, device)?
+                }
+                Event::OtherEvents(event) => {
+                    if event.event_type() == EventType::LED {
+                        self.update_led(LedCode(event.code()), event.value() != 0);
+                    }
+                    self.send_action(Action::InputEvent(*event))
                 }
-                Event::OtherEvents(event) => self.send_action(Action::InputEvent(*event)),
                 Event::OverrideTimeout => self.timeout_override()?,
             };
         }
-        if !mouse_movement_collection.is_empty() {
-            self.send_action(Action::MouseMovementEventCollection(mouse_movement_collection));
+        self.flush_mouse_motion();
+        if config.grab_mode == GrabMode::Monitor {
+            // Apps already got the events as typed, so anything sent would come on top of them
+            self.actions.retain(|action| matches!(action, Action::Command(_)));
         }
         Ok(self.actions.drain(..).collect())
     }
 
-fn key_to_char(&mut self, key: &Key) -> Option<String> {
+fn key_to_char(&mut self, key: &Key, config: &Config) -> Option<String> {
     match *key {
-        Key::KEY_A => Some("a".to_string()),
-        Key::KEY_B => Some("b".to_string()),
-        Key::KEY_C => Some("c".to_string()),
-        Key::KEY_D => Some("d".to_string()),
-        Key::KEY_E => Some("e".to_string()),
-        Key::KEY_F => Some("f".to_string()),
-        Key::KEY_G => Some("g".to_string()),
-        Key::KEY_H => Some("h".to_string()),
-        Key::KEY_I => Some("i".to_string()),
-        Key::KEY_J => Some("j".to_string()),
-        Key::KEY_K => Some("k".to_string()),
-        Key::KEY_L => Some("l".to_string()),
-        Key::KEY_M => Some("m".to_string()),
-        Key::KEY_N => Some("n".to_string()),
-        Key::KEY_O => Some("o".to_string()),
-        Key::KEY_P => Some("p".to_string()),
-        Key::KEY_Q => Some("q".to_string()),
-        Key::KEY_R => Some("r".to_string()),
-        Key::KEY_S => Some("s".to_string()),
-        Key::KEY_T => Some("t".to_string()),
-        Key::KEY_U => Some("u".to_string()),
-        Key::KEY_V => Some("v".to_string()),
-        Key::KEY_W => Some("w".to_string()),
-        Key::KEY_X => Some("x".to_string()),
-        Key::KEY_Y => Some("y".to_string()),
-        Key::KEY_Z => Some("z".to_string()),
-        Key::KEY_0 => Some("0".to_string()),
-        Key::KEY_1 => Some("1".to_string()),
-        Key::KEY_2 => Some("2".to_string()),
-        Key::KEY_3 => Some("3".to_string()),
-        Key::KEY_4 => Some("4".to_string()),
-        Key::KEY_5 => Some("5".to_string()),
-        Key::KEY_6 => Some("6".to_string()),
-        Key::KEY_7 => Some("7".to_string()),
-        Key::KEY_8 => Some("8".to_string()),
-        Key::KEY_9 => Some("9".to_string()),
-        Key::KEY_DOT => Some(".".to_string()),
-        Key::KEY_COMMA => Some(",".to_string()),
-        Key::KEY_SEMICOLON => Some(";".to_string()),
-        Key::KEY_SLASH => Some("/".to_string()),
-        Key::KEY_APOSTROPHE => Some("'".to_string()),
-        Key::KEY_MINUS => Some("-".to_string()),
-        Key::KEY_EQUAL => Some("=".to_string()),
-        Key::KEY_LEFTBRACE => Some("[".to_string()),
-        Key::KEY_RIGHTBRACE => Some("]".to_string()),
-        Key::KEY_BACKSLASH => Some("\\".to_string()),
-        Key::KEY_GRAVE => Some("`".to_string()),
-        Key::KEY_SPACE => Some(" ".to_string()),
-        Key::KEY_TAB => Some("\t".to_string()),
-        Key::KEY_ENTER => Some("\n".to_string()),
         Key::KEY_BACKSPACE => {
             // Just pop one char from buffer, DON'T reset state
             if !self.hotstring_buffer.is_empty() {
@@ -152,19 +220,15 @@ fn key_to_char(&mut self, key: &Key) -> Option<String> {
             // Return None so hotstring matcher doesn't process this
             None
         }
-        Key::KEY_LEFTSHIFT | Key::KEY_RIGHTSHIFT | Key::KEY_LEFTCTRL | Key::KEY_RIGHTCTRL
-        | Key::KEY_LEFTALT | Key::KEY_RIGHTALT | Key::KEY_LEFTMETA | Key::KEY_RIGHTMETA
-        | Key::KEY_UP | Key::KEY_DOWN | Key::KEY_LEFT | Key::KEY_RIGHT
-        | Key::KEY_HOME | Key::KEY_END | Key::KEY_PAGEUP | Key::KEY_PAGEDOWN
-        | Key::KEY_F1 | Key::KEY_F2 | Key::KEY_F3 | Key::KEY_F4 | Key::KEY_F5 | Key::KEY_F6
-        | Key::KEY_F7 | Key::KEY_F8 | Key::KEY_F9 | Key::KEY_F10 | Key::KEY_F11 | Key::KEY_F12
-        | Key::KEY_ESC | Key::KEY_DELETE | Key::KEY_INSERT | Key::KEY_CAPSLOCK => {
-            // Navigation/modifier/function keys CLEAR the buffer and state
+        key if config.hotstring_reset_keys.contains(&key) => {
+            // Navigation/function keys (hotstring_reset_keys) CLEAR the buffer and state
+            self.fire_pending_hotstring(config);
             self.hotstring_state = None;
             self.hotstring_buffer.clear();
             None
         }
-        _ => None,
+        // Shift isn't tracked here, so hotstrings always see lowercase
+        _ => keys::key_to_char(*key, false).map(String::from),
     }
 }
 
@@ -177,12 +241,17 @@ fn on_key_event(
 ) -> Result<bool, Box<dyn Error>> {
     self.application_cache = None;
     self.title_cache = None;
+    self.instance_cache = None;
     let key = Key::new(event.code());
     
     if key.code() < DISGUISED_EVENT_OFFSETTER {
         debug!("=> {}: {:?}", event.value(), &key);
     }
 
+    if self.suspended {
+        return self.on_suspended_key_event(event, config, device);
+    }
+
     let mut key_values = if let Some(key_action) = self.find_modmap(config, &key, device) {
         self.dispatch_keys(key_action, key, event.value(), config)?
     } else {
@@ -196,6 +265,15 @@ fn on_key_event(
     let mut send_original_relative_event = false;
 
     for (key, value) in key_values.into_iter() {
+        // A repeat_while_held hotkey repeats on its own interval rather than the keyboard's
+        if self.held_hotkey.as_ref().is_some_and(|held| held.key == key) {
+            match value {
+                REPEAT => continue,
+                RELEASE => self.held_hotkey = None,
+                _ => {}
+            }
+        }
+
         if config.virtual_modifiers.contains(&key) {
             self.update_modifier(key, value);
             continue;
@@ -214,63 +292,56 @@ fn on_key_event(
 
             // === HOTSTRING PROCESSING ===
             if let Some(matcher) = &config.hotstring_matcher {
-                match self.key_to_char(&key) {
+                match self.key_to_char(&key, config) {
                     Some(ch) => {
+                        // A trigger typed after a long pause doesn't continue what came before it
+                        let now = (self.clock)();
+                        let idle_timeout = Duration::from_millis(config.hotstring_idle_timeout_ms);
+                        if self.hotstring_last_char_at.is_some_and(|at| {
+                            config.hotstring_idle_timeout_ms > 0 && now.duration_since(at) >= idle_timeout
+                        }) {
+                            self.fire_pending_hotstring(config);
+                            self.hotstring_buffer.clear();
+                            self.hotstring_state = None;
+                        }
+                        self.hotstring_last_char_at = Some(now);
                         self.hotstring_buffer.push_str(&ch);
                         
                         // Cap buffer at 100 chars - remove oldest when full
-                        if self.hotstring_buffer.len() > 100 {
+                        let buffered = self.hotstring_buffer.chars().count();
+                        if buffered > 100 {
                             self.hotstring_buffer = self.hotstring_buffer
                                 .chars()
-                                .skip(self.hotstring_buffer.len() - 100)
+                                .skip(buffered - 100)
                                 .collect();
                         }
                         
-                        let (new_state, matched) = matcher.process(self.hotstring_state.as_ref(), &ch);
+                        let (new_state, fired) = matcher.process(self.hotstring_state.as_ref(), &ch);
                         self.hotstring_state = Some(new_state);
 
-                        if let Some(hotstring_match) = matched {
-                            // Calculate how many chars to delete
-                            // If omit_char is true (O option), we don't delete the ending character
-                            let chars_to_delete = if hotstring_match.omit_char {
-                                hotstring_match.trigger.len()
-                            } else {
-                                // Delete trigger + the ending character (space/enter/etc)
-                                hotstring_match.trigger.len() + 1
-                            };
-
-                            if hotstring_match.execute {
-                                // X option: execute the replacement as code
-                                for _ in 0..chars_to_delete {
-                                    self.send_key(&Key::KEY_BACKSPACE, PRESS);
-                                    self.send_key(&Key::KEY_BACKSPACE, RELEASE);
-                                }
-
-                                // Parse and run the command
-                                if let Some(rest) = hotstring_match.replacement.strip_prefix("Run(") {
-                                    if let Some(cmd) = rest.strip_suffix(')') {
-                                        let cmd = cmd.trim().trim_matches(|c| c == '"' || c == '\'');
-                                        let command: Vec<String> = if cmd.starts_with("http://") || cmd.starts_with("https://") {
-                                            vec!["xdg-open".to_string(), cmd.to_string()]
-                                        } else {
-                                            cmd.split_whitespace().map(String::from).collect()
-                                        };
-                                        self.send_action(Action::Command(command));
-                                    }
-                                }
-                            } else {
-                                // Regular text expansion via clipboard
-                                let final_replacement = hotstring_match.replacement.clone();
-                                self.send_action(Action::TextExpansion {
-                                    trigger_len: chars_to_delete,
-                                    replacement: final_replacement,
-                                    add_space: !hotstring_match.omit_char && !hotstring_match.immediate,
-                                });
-                            }
+                        if let Some(hotstring::Fired { hotstring: hotstring_match, end_char, typed_after }) = fired {
+                            // Typed after the expansion rather than swallowed, so it's the trailing char
+                            let passthrough = config.passthrough_keys.contains(&key);
+                            // One that waited on a longer trigger fires on a later key. What was typed
+                            // since is erased along with it and typed again after the expansion.
+                            let mut already_typed = typed_after.clone();
+                            already_typed.pop();
+                            let retyped = if passthrough { &already_typed } else { &typed_after };
+                            let trailing = !passthrough || !typed_after.is_empty();
+                            self.expand_hotstring(
+                                hotstring_match,
+                                &end_char,
+                                &already_typed,
+                                retyped,
+                                trailing,
+                                config,
+                            );
 
                             self.hotstring_buffer.clear();
                             self.hotstring_state = None;
-                            continue;
+                            if !passthrough {
+                                continue;
+                            }
                         }
                     }
                     None => {
@@ -283,7 +354,19 @@ fn on_key_event(
 
             // === HOTKEY PROCESSING ===
             if let Some(actions) = self.find_keymap(config, &key, device)? {
-                self.dispatch_actions(&actions, &key, config)?;
+                if config.hotkey_reentrancy_guard {
+                    self.dispatch_guarded_actions(&actions, &key, config)?;
+                } else {
+                    self.dispatch_actions(&actions, &key, config)?;
+                }
+                if let Some(interval) = actions.first().and_then(|action| action.repeat_interval) {
+                    self.held_hotkey = Some(HeldHotkey {
+                        key,
+                        actions,
+                        interval,
+                        next_at: (self.clock)() + interval,
+                    });
+                }
                 continue;
             }
             
@@ -291,31 +374,138 @@ fn on_key_event(
                 self.dispatch_actions(&actions, &KEY_MATCH_ANY, config)?;
                 continue;
             }
-
-            self.send_key(&key, value);
-        } else {
-            self.send_key(&key, value);
         }
 
+        // An unmapped disguised relative event goes out as the original relative event
         if key.code() >= DISGUISED_EVENT_OFFSETTER && (key.code(), value) == (event.code(), event.value()) {
             send_original_relative_event = true;
             continue;
         }
+        self.send_key(&key, value);
     }
 
     Ok(send_original_relative_event)
 }
 
+    // Erases a fired hotstring's trigger and `already_typed` after it, then sends its replacement
+    // followed by `retyped`
+    fn expand_hotstring(
+        &mut self,
+        hotstring_match: &hotstring::HotstringMatch,
+        end_char: &str,
+        already_typed: &str,
+        retyped: &str,
+        trailing: bool,
+        config: &Config,
+    ) {
+        let chars_to_delete = hotstring_match.chars_to_delete() + already_typed.chars().count();
+        if hotstring_match.execute {
+            // X option: execute the replacement as code
+            for _ in 0..chars_to_delete {
+                self.send_key(&Key::KEY_BACKSPACE, PRESS);
+                self.send_key(&Key::KEY_BACKSPACE, RELEASE);
+            }
+
+            // Parse and run the command
+            if let Some(rest) = hotstring_match.replacement.strip_prefix("Run(") {
+                if let Some(cmd) = rest.strip_suffix(')') {
+                    let cmd = cmd.trim().trim_matches(|c| c == '"' || c == '\'');
+                    let command: Vec<String> = if cmd.starts_with("http://") || cmd.starts_with("https://") {
+                        vec!["xdg-open".to_string(), cmd.to_string()]
+                    } else {
+                        cmd.split_whitespace().map(String::from).collect()
+                    };
+                    self.send_action(Action::Command(command));
+                }
+            }
+            if !retyped.is_empty() {
+                self.send_action(Action::TextExpansion {
+                    trigger_len: 0,
+                    replacement: retyped.to_string(),
+                });
+            }
+        } else {
+            // Regular text expansion
+            let mut final_replacement = hotstring_match.replacement.clone();
+            let trailing_text = hotstring_match
+                .trailing_text(config.hotstring_trailing_char, end_char)
+                .filter(|_| trailing);
+            if let Some(trailing_text) = trailing_text {
+                final_replacement.push_str(&trailing_text);
+            }
+            final_replacement.push_str(retyped);
+            match config.hotstring_mode {
+                HotstringMode::Paste => self.send_action(Action::TextExpansion {
+                    trigger_len: chars_to_delete,
+                    replacement: final_replacement,
+                }),
+                HotstringMode::Type => {
+                    for _ in 0..chars_to_delete {
+                        self.send_key(&Key::KEY_BACKSPACE, PRESS);
+                        self.send_key(&Key::KEY_BACKSPACE, RELEASE);
+                    }
+                    let typed = self.ahk_interpreter(config).type_text(&final_replacement);
+                    for action in typed {
+                        self.send_action(action);
+                    }
+                }
+            }
+        }
+    }
+
+    // A hotstring held back for a longer trigger fires once typing stops short of that one
+    fn fire_pending_hotstring(&mut self, config: &Config) {
+        let Some(matcher) = &config.hotstring_matcher else {
+            return;
+        };
+        if let Some(fired) = matcher.pending(self.hotstring_state.as_ref()) {
+            let typed_after = &fired.typed_after;
+            self.expand_hotstring(fired.hotstring, &fired.end_char, typed_after, typed_after, true, config);
+        }
+    }
+
+// Only the key bound to toggle_suspend is looked up; everything else is sent as is.
+fn on_suspended_key_event(
+    &mut self,
+    event: &KeyEvent,
+    config: &Config,
+    device: &InputDeviceInfo,
+) -> Result<bool, Box<dyn Error>> {
+    let key = Key::new(event.code());
+    if key.code() >= DISGUISED_EVENT_OFFSETTER {
+        return Ok(true);
+    }
+    if MODIFIER_KEYS.contains(&key) {
+        self.update_modifier(key, event.value());
+    } else if event.value() == PRESS {
+        if let Some(actions) = self.find_keymap(config, &key, device)? {
+            if actions.iter().any(|action| matches!(action.action, KeymapAction::ToggleSuspend)) {
+                self.dispatch_actions(&actions, &key, config)?;
+                return Ok(false);
+            }
+        }
+    }
+    self.send_key(&key, event.value());
+    Ok(false)
+}
+
 
     fn on_relative_event(
         &mut self,
         event: &RelativeEvent,
-        mouse_movement_collection: &mut Vec<RelativeEvent>,
         config: &Config,
         device: &InputDeviceInfo,
     ) -> Result<(), Box<dyn Error>> {
-        const RELEASE: i32 = 0;
-        const PRESS: i32 = 1;
+        let wheels = [
+            RelativeAxisCode::REL_WHEEL,
+            RelativeAxisCode::REL_HWHEEL,
+            RelativeAxisCode::REL_WHEEL_HI_RES,
+            RelativeAxisCode::REL_HWHEEL_HI_RES,
+        ];
+        let is_wheel = wheels.iter().any(|wheel| wheel.0 == event.code);
+        if is_wheel && self.wheel_enabled == Some(false) {
+            return Ok(());
+        }
 
         let key = match event.value {
             1..=i32::MAX => (event.code * 2) + DISGUISED_EVENT_OFFSETTER,
@@ -330,7 +520,7 @@ fn on_key_event(
             true => {
                 let action = RelativeEvent::new_with(event.code, event.value);
                 if event.code <= 2 {
-                    mouse_movement_collection.push(action);
+                    self.mouse_motion.push(action);
                 } else {
                     self.send_action(Action::RelativeEvent(action));
                 }
@@ -372,9 +562,20 @@ fn on_key_event(
     }
 
     fn send_action(&mut self, action: Action) {
+        // A click lands where the pointer was moved before it
+        if matches!(&action, Action::KeyEvent(event) if is_mouse_button(event.key)) {
+            self.flush_mouse_motion();
+        }
         self.actions.push(action);
     }
 
+    fn flush_mouse_motion(&mut self) {
+        if !self.mouse_motion.is_empty() {
+            let motion = std::mem::take(&mut self.mouse_motion);
+            self.actions.push(Action::MouseMovementEventCollection(motion));
+        }
+    }
+
     fn maintain_pressed_keys(&mut self, key: Key, value: i32, events: &mut [(Key, i32)]) {
         if events.len() != 1 || value != events[0].1 {
             return;
@@ -460,6 +661,7 @@ fn on_key_event(
                         .map(|action| TaggedAction {
                             action,
                             exact_match: false,
+                            repeat_interval: None,
                         })
                         .collect(),
                     &key,
@@ -519,6 +721,11 @@ fn on_key_event(
                         continue;
                     }
                 }
+                if let Some(instance_matcher) = &modmap.instance {
+                    if !self.match_instance(instance_matcher) {
+                        continue;
+                    }
+                }
                 if let Some(device_matcher) = &modmap.device {
                     if !self.match_device(device_matcher, device) {
                         continue;
@@ -580,13 +787,22 @@ fn find_keymap(
     }
 
     if let Some(entries) = config.keymap_table.get(key) {
+        // Fast path for the common `a: b` remap: with no modifiers held, a lone plain
+        // entry is what both passes below would pick, without any extra modifiers.
+        if let [entry] = entries.as_slice() {
+            if entry.is_plain() && self.modifiers.is_empty() && self.triggers_at_send_level(entry.input_level) {
+                return Ok(Some(entry_actions(entry, &[])));
+            }
+        }
+
         for exact_match in [true, false] {
             // First pass: look for contextual matches
             for entry in entries {
-                if entry.exact_match && !exact_match {
+                if (entry.exact_match && !exact_match) || !self.triggers_at_send_level(entry.input_level) {
                     continue;
                 }
-                let (extra_modifiers, missing_modifiers) = self.diff_modifiers(&entry.modifiers);
+                let (extra_modifiers, missing_modifiers) =
+                    self.diff_modifier_keys(&entry.modifiers, &entry.modifier_keys);
                 if (exact_match && !extra_modifiers.is_empty()) || !missing_modifiers.is_empty() {
                     continue;
                 }
@@ -602,11 +818,36 @@ fn find_keymap(
                         continue;
                     }
                 }
+                if !self.match_locks(entry) {
+                    continue;
+                }
+
+                // Check predicate command context
+                if let Some(predicate) = &entry.predicate {
+                    if !self.match_predicate(predicate) {
+                        continue;
+                    }
+                    if entry.title.is_none() && entry.application.is_none() && entry.instance.is_none() {
+                        let actions = entry_actions(entry, &extra_modifiers);
+                        return Ok(Some(actions));
+                    }
+                }
+
+                // Check instance context, which narrows any window/application context
+                if let Some(instance_matcher) = &entry.instance {
+                    if !self.match_instance(instance_matcher) {
+                        continue;
+                    }
+                    if entry.title.is_none() && entry.application.is_none() {
+                        let actions = entry_actions(entry, &extra_modifiers);
+                        return Ok(Some(actions));
+                    }
+                }
                 
                 // Check window context
                 if let Some(window_matcher) = &entry.title {
                     if self.match_window(window_matcher) {
-                        let actions = with_extra_modifiers(&entry.actions, &extra_modifiers, entry.exact_match);
+                        let actions = entry_actions(entry, &extra_modifiers);
                         return Ok(Some(actions));
                     }
                     continue; // Has window context but didn't match
@@ -615,7 +856,7 @@ fn find_keymap(
                 // Check application context
                 if let Some(application_matcher) = &entry.application {
                     if self.match_application(application_matcher) {
-                        let actions = with_extra_modifiers(&entry.actions, &extra_modifiers, entry.exact_match);
+                        let actions = entry_actions(entry, &extra_modifiers);
                         return Ok(Some(actions));
                     }
                     continue; // Has app context but didn't match
@@ -625,16 +866,21 @@ fn find_keymap(
             // Second pass: look for global matches (no context)
             let mut remaps = vec![];
             for entry in entries {
-                if entry.exact_match && !exact_match {
+                if (entry.exact_match && !exact_match) || !self.triggers_at_send_level(entry.input_level) {
                     continue;
                 }
-                let (extra_modifiers, missing_modifiers) = self.diff_modifiers(&entry.modifiers);
+                let (extra_modifiers, missing_modifiers) =
+                    self.diff_modifier_keys(&entry.modifiers, &entry.modifier_keys);
                 if (exact_match && !extra_modifiers.is_empty()) || !missing_modifiers.is_empty() {
                     continue;
                 }
                 
                 // Skip entries with context
-                if entry.title.is_some() || entry.application.is_some() {
+                if entry.title.is_some()
+                    || entry.application.is_some()
+                    || entry.instance.is_some()
+                    || entry.predicate.is_some()
+                {
                     continue;
                 }
                 
@@ -648,8 +894,11 @@ fn find_keymap(
                         continue;
                     }
                 }
+                if !self.match_locks(entry) {
+                    continue;
+                }
 
-                let actions = with_extra_modifiers(&entry.actions, &extra_modifiers, entry.exact_match);
+                let actions = entry_actions(entry, &extra_modifiers);
                 let is_remap = is_remap(&entry.actions);
 
                 if remaps.is_empty() && !is_remap {
@@ -673,9 +922,39 @@ fn find_keymap(
         Ok(())
     }
 
-    fn dispatch_action(&mut self, action: &TaggedAction, key: &Key, _config: &Config) -> Result<(), Box<dyn Error>> {
+    // Like dispatch_actions, but drops the press while the previous action group
+    // of the same hotkey is still in flight (AHK's #MaxThreadsPerHotkey 1).
+    // Only delays count: commands run detached, so a slow launch gets no window.
+    fn dispatch_guarded_actions(
+        &mut self,
+        actions: &Vec<TaggedAction>,
+        key: &Key,
+        config: &Config,
+    ) -> Result<(), Box<dyn Error>> {
+        let now = (self.clock)();
+        self.in_flight_hotkeys.retain(|_, done_at| *done_at > now);
+        if self.in_flight_hotkeys.contains_key(key) {
+            debug!("Dropping {:?}: previous actions still in flight", key);
+            return Ok(());
+        }
+
+        let start = self.actions.len();
+        self.dispatch_actions(actions, key, config)?;
+        let in_flight: Duration = self.actions[start..]
+            .iter()
+            .filter_map(|action| match action {
+                Action::Delay(duration) => Some(*duration),
+                _ => None,
+            })
+            .sum();
+        self.in_flight_hotkeys.insert(*key, now + in_flight);
+        Ok(())
+    }
+
+    fn dispatch_action(&mut self, action: &TaggedAction, key: &Key, config: &Config) -> Result<(), Box<dyn Error>> {
         match &action.action {
             KeymapAction::KeyPressAndRelease(key_press) => self.send_key_press_and_release(key_press),
+            KeymapAction::Atomic(key_press) => self.send_atomic_key_press(key_press),
             KeymapAction::KeyPress(key) => self.send_key(key, PRESS),
             KeymapAction::KeyRepeat(key) => self.send_key(key, REPEAT),
             KeymapAction::KeyRelease(key) => self.send_key(key, RELEASE),
@@ -698,14 +977,82 @@ fn find_keymap(
                 }
             }
             KeymapAction::Launch(command) => self.run_command(command.clone()),
+            KeymapAction::PipeSelection(action) => self.send_action(Action::PipeSelection {
+                command: action.command.clone(),
+                timeout: Duration::from_millis(action.timeout_ms.unwrap_or(config.command_timeout_ms)),
+            }),
+            KeymapAction::Paste(method) => self.send_action(Action::Paste(*method)),
+            KeymapAction::Scroll(scroll) => self.send_action(Action::RelativeEvent(scroll.relative_event())),
+            KeymapAction::RawEvent(event) => {
+                if matches!(event.event_type, EventType::LED | EventType::SOUND) {
+                    self.send_action(Action::FeedbackEvent(event.input_event()))
+                } else {
+                    self.send_action(Action::InputEvent(event.input_event()))
+                }
+            }
             KeymapAction::SetMode(mode) => {
                 self.mode = mode.clone();
                 println!("mode: {mode}");
             }
             KeymapAction::SetMark(set) => self.mark_set = *set,
-            KeymapAction::WithMark(key_press) => self.send_key_press_and_release(&self.with_mark(key_press)),
+            KeymapAction::WithMark(key_press) => {
+                self.send_key_press_and_release(&self.with_mark(key_press, &config.mark_modifier))
+            }
+            KeymapAction::RouteTo(route) => {
+                // Held modifiers apply to every keyboard of the seat, so only the missing ones are pressed
+                let modifiers = self.missing_modifiers(&route.key_press.modifiers);
+                let event = |key: &Key, value| Action::KeyEvent(KeyEvent::new_with(key.code(), value));
+                let mut actions: Vec<Action> = modifiers.iter().map(|key| event(key, PRESS)).collect();
+                actions.push(event(&route.key_press.key, PRESS));
+                actions.push(event(&route.key_press.key, RELEASE));
+                actions.extend(modifiers.iter().map(|key| event(key, RELEASE)));
+                self.send_action(Action::Routed {
+                    device_id: route.device_id.clone(),
+                    actions,
+                });
+            }
             KeymapAction::EscapeNextKey(escape_next_key) => self.escape_next_key = *escape_next_key,
             KeymapAction::Sleep(millis) => self.send_action(Action::Delay(Duration::from_millis(*millis))),
+            KeymapAction::Sequence(steps) => {
+                for step in steps {
+                    match step {
+                        SequenceStep::KeyPressAndRelease(key_press) => self.send_key_press_and_release(key_press),
+                        SequenceStep::KeyPress(key) => self.send_key(key, PRESS),
+                        SequenceStep::KeyRelease(key) => self.send_key(key, RELEASE),
+                        SequenceStep::Sleep(millis) => self.send_action(Action::Delay(Duration::from_millis(*millis))),
+                    }
+                }
+            }
+            KeymapAction::Conditional(conditional) => {
+                let matched = conditional
+                    .application
+                    .as_ref()
+                    .is_none_or(|matcher| self.match_application(matcher))
+                    && conditional.window.as_ref().is_none_or(|matcher| self.match_window(matcher));
+                let branch = if matched { &conditional.then } else { &conditional.otherwise };
+                for branch_action in branch {
+                    let branch_action = TaggedAction {
+                        action: branch_action.clone(),
+                        exact_match: action.exact_match,
+                        repeat_interval: None,
+                    };
+                    self.dispatch_action(&branch_action, key, config)?;
+                }
+            }
+            KeymapAction::ToggleSuspend => {
+                self.suspended = !self.suspended;
+                println!("suspended: {}", self.suspended);
+            }
+            // The output device only gets wheel axes at startup, with enable_wheel
+            KeymapAction::SetWheelEnabled(_) | KeymapAction::ToggleWheel if !config.enable_wheel => {
+                warn!("enable_wheel is false, so the output device has no wheel to turn on or off");
+            }
+            KeymapAction::SetWheelEnabled(enabled) => self.wheel_enabled = Some(*enabled),
+            KeymapAction::ToggleWheel => {
+                let enabled = !self.wheel_enabled.unwrap_or(true);
+                self.wheel_enabled = Some(enabled);
+                println!("wheel enabled: {enabled}");
+            }
             KeymapAction::SetExtraModifiers(keys) => {
                 self.extra_modifiers.clear();
                 for key in keys {
@@ -713,22 +1060,112 @@ fn find_keymap(
                 }
             }
             KeymapAction::AhkInterpreted(ahk_action) => {
-                let held_modifiers: Vec<Key> = self.modifiers.iter().copied().collect();
-                
-                let mut interpreter = crate::ahk::interpreter::AhkInterpreter::new(&mut self.application_client);
-                interpreter.set_virtual_modifiers(&held_modifiers);
-                
-                match interpreter.execute(ahk_action) {
-                    Ok(interp_actions) => {
-                        for action in interp_actions {
-                            self.send_action(action);
+                let result = self.ahk_interpreter(config).execute_deferred(ahk_action);
+                self.on_interpreter_result(result, config);
+            }
+        }
+        Ok(())
+    }
+
+    fn ahk_interpreter(&mut self, config: &Config) -> AhkInterpreter<'_> {
+        let held_modifiers: Vec<Key> = self.modifiers.iter().copied().collect();
+        let held_keys: Vec<Key> = self.pressed_keys.keys().copied().collect();
+        let rng = self.type_jitter_rng.fork();
+
+        let mut interpreter = AhkInterpreter::new(&mut self.application_client);
+        interpreter.set_clock(self.clock);
+        interpreter.set_virtual_modifiers(&held_modifiers);
+        interpreter.set_keypress_delay(self.keypress_delay);
+        interpreter.set_type_jitter(Duration::from_millis(config.type_jitter_ms), rng);
+        interpreter.set_compose_key(config.compose.then_some(config.compose_key));
+        interpreter.set_max_wait(Duration::from_millis(config.max_wait_ms));
+        interpreter.set_window_groups(config.window_groups.clone());
+        interpreter.set_variables(config.variables.clone());
+        interpreter.set_title_match_mode(config.title_match_mode);
+        interpreter.set_title_regexes(config.title_regexes.clone());
+        interpreter.set_held_keys(held_keys);
+        interpreter
+    }
+
+    // Waits are parked instead of blocking here, so input keeps flowing while they're pending
+    fn on_interpreter_result(
+        &mut self,
+        result: Result<(Vec<Action>, Option<PendingWait>), Box<dyn Error>>,
+        config: &Config,
+    ) {
+        match result {
+            Ok((interp_actions, pending)) => {
+                for action in interp_actions {
+                    self.on_interpreter_action(action, config);
+                }
+                self.pending_waits.extend(pending);
+            }
+            Err(e) => eprintln!("ERROR: AHK interpreter failed: {}", e),
+        }
+    }
+
+    // Keys of a leveled Send go through on_key_event like typed ones, so they can trigger hotkeys
+    // below their SendLevel; the depth limit stops hotkeys that keep sending each other
+    fn on_interpreter_action(&mut self, action: Action, config: &Config) {
+        match action {
+            Action::SendLevel { level, actions } if self.send_levels.len() < MAX_SEND_LEVEL_DEPTH => {
+                let device = InputDeviceInfo {
+                    name: InputDevice::current_name(),
+                    path: Path::new("/dev/uinput"),
+                    vendor: 0,
+                    product: 0,
+                };
+                self.send_levels.push(level);
+                for action in actions {
+                    match action {
+                        Action::KeyEvent(event) => {
+                            if let Err(e) = self.on_key_event(&event, config, &device) {
+                                eprintln!("ERROR: Failed to re-process {:?} at SendLevel {}: {}", event, level, e);
+                            }
                         }
+                        action => self.on_interpreter_action(action, config),
                     }
-                    Err(e) => eprintln!("ERROR: AHK interpreter failed: {}", e),
                 }
+                self.send_levels.pop();
             }
+            Action::SendLevel { actions, .. } => {
+                warn!("SendLevel: hotkeys nested more than {MAX_SEND_LEVEL_DEPTH} deep, sending the rest as is");
+                for action in actions {
+                    self.send_action(action);
+                }
+            }
+            action => self.send_action(action),
+        }
+    }
+
+    // Keys re-sent at a SendLevel only trigger hotkeys below it; typed keys trigger all of them
+    fn triggers_at_send_level(&self, input_level: u8) -> bool {
+        self.send_levels.last().is_none_or(|level| input_level < *level)
+    }
+
+    fn repeat_held_hotkey(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        let Some(held) = &mut self.held_hotkey else {
+            return Ok(());
+        };
+        let now = (self.clock)();
+        if now < held.next_at {
+            return Ok(());
+        }
+        held.next_at = now + held.interval;
+        let (actions, key) = (held.actions.clone(), held.key);
+        self.dispatch_actions(&actions, &key, config)
+    }
+
+    fn poll_pending_waits(&mut self, config: &Config) {
+        let now = (self.clock)();
+        for pending in std::mem::take(&mut self.pending_waits) {
+            if pending.next_check() > now {
+                self.pending_waits.push(pending);
+                continue;
+            }
+            let result = self.ahk_interpreter(config).resume(pending);
+            self.on_interpreter_result(result, config);
         }
-        Ok(())
     }
 
     fn send_key_press_and_release(&mut self, key_press: &KeyPress) {
@@ -749,10 +1186,34 @@ fn find_keymap(
         self.send_keys(&missing_modifiers, RELEASE);
     }
 
-    fn with_mark(&self, key_press: &KeyPress) -> KeyPress {
-        if self.mark_set && !self.match_modifier(&Modifier::Shift) {
+    // Every press in one report and every release in the next, for shortcuts that only work when
+    // all their keys are down at once, e.g. some window manager ones
+    fn send_atomic_key_press(&mut self, key_press: &KeyPress) {
+        let (mut extra_modifiers, mut missing_modifiers) = self.diff_modifiers(&key_press.modifiers);
+        extra_modifiers.retain(|key| MODIFIER_KEYS.contains(key) && !self.extra_modifiers.contains(key));
+        missing_modifiers.retain(|key| MODIFIER_KEYS.contains(key));
+
+        let event = |key: &Key, value| KeyEvent::new_with(key.code(), value);
+        let presses = extra_modifiers.iter().map(|key| event(key, RELEASE))
+            .chain(missing_modifiers.iter().map(|key| event(key, PRESS)))
+            .chain([event(&key_press.key, PRESS)])
+            .collect();
+        let releases = [event(&key_press.key, RELEASE)]
+            .into_iter()
+            .chain(missing_modifiers.iter().rev().map(|key| event(key, RELEASE)))
+            .chain(extra_modifiers.iter().map(|key| event(key, PRESS)))
+            .collect();
+
+        self.send_action(Action::KeyEventBatch(presses));
+        self.send_action(Action::Delay(self.keypress_delay));
+        self.send_action(Action::KeyEventBatch(releases));
+    }
+
+    // A held mark modifier is part of the key press too, so it stays down rather than being released
+    fn with_mark(&self, key_press: &KeyPress, mark_modifier: &Modifier) -> KeyPress {
+        if self.mark_set && !key_press.modifiers.contains(mark_modifier) {
             let mut modifiers = key_press.modifiers.clone();
-            modifiers.push(Modifier::Shift);
+            modifiers.push(mark_modifier.clone());
             KeyPress {
                 key: key_press.key,
                 modifiers,
@@ -773,7 +1234,22 @@ fn find_keymap(
             .filter(|modifier| !contains_modifier(modifiers, modifier))
             .copied()
             .collect();
-        let missing_modifiers: Vec<Key> = modifiers
+        (extra_modifiers, self.missing_modifiers(modifiers))
+    }
+
+    // Same as diff_modifiers, using the keys precomputed by build_keymap_table
+    fn diff_modifier_keys(&self, modifiers: &[Modifier], modifier_keys: &HashSet<Key>) -> (Vec<Key>, Vec<Key>) {
+        let extra_modifiers: Vec<Key> = self
+            .modifiers
+            .iter()
+            .filter(|modifier| !modifier_keys.contains(modifier))
+            .copied()
+            .collect();
+        (extra_modifiers, self.missing_modifiers(modifiers))
+    }
+
+    fn missing_modifiers(&self, modifiers: &[Modifier]) -> Vec<Key> {
+        modifiers
             .iter()
             .filter_map(|modifier| {
                 if self.match_modifier(modifier) {
@@ -788,8 +1264,7 @@ fn find_keymap(
                     }
                 }
             })
-            .collect();
-        (extra_modifiers, missing_modifiers)
+            .collect()
     }
 
     fn match_modifier(&self, modifier: &Modifier) -> bool {
@@ -855,16 +1330,64 @@ fn match_application(&mut self, application_matcher: &OnlyOrNot) -> bool {
     }
     false
 }
-    fn match_device(&self, device_matcher: &crate::config::device::Device, device: &InputDeviceInfo) -> bool {
-        if let Some(device_only) = &device_matcher.only {
-            return device_only.iter().any(|m| device.matches(m));
+
+    // Exact matching, unlike the application: instances such as Chrome app ids often share a prefix
+    fn match_instance(&mut self, instance_matcher: &OnlyOrNot) -> bool {
+        let instance = self
+            .instance_cache
+            .get_or_insert_with(|| self.application_client.current_instance().unwrap_or_default());
+        if let Some(instance_only) = &instance_matcher.only {
+            return instance_only.iter().any(|m| m.matches(instance));
         }
-        if let Some(device_not) = &device_matcher.not {
-            return device_not.iter().all(|m| !device.matches(m));
+        if let Some(instance_not) = &instance_matcher.not {
+            return instance_not.iter().all(|m| !m.matches(instance));
         }
         false
     }
 
+    // Runs synchronously on the input thread, so the command gets PREDICATE_TIMEOUT
+    // to exit before it's killed and treated as a non-match.
+    fn match_predicate(&mut self, command: &[String]) -> bool {
+        if let Some((checked_at, matched)) = self.predicate_cache.get(command) {
+            if checked_at.elapsed() < PREDICATE_CACHE_TTL {
+                return *matched;
+            }
+        }
+        let matched = run_predicate(command);
+        self.predicate_cache.insert(command.to_vec(), (Instant::now(), matched));
+        matched
+    }
+
+    // Lock states from before the devices were grabbed, which no LED event reports
+    pub fn set_lit_leds(&mut self, leds: impl IntoIterator<Item = LedCode>) {
+        self.lit_leds = leds.into_iter().collect();
+    }
+
+    fn update_led(&mut self, led: LedCode, lit: bool) {
+        if lit {
+            self.lit_leds.insert(led);
+        } else {
+            self.lit_leds.remove(&led);
+        }
+    }
+
+    fn match_locks(&self, entry: &KeymapEntry) -> bool {
+        let matches = |condition: Option<LockCondition>, led| {
+            condition.is_none_or(|condition| condition.matches(self.lit_leds.contains(&led)))
+        };
+        matches(entry.capslock, LedCode::LED_CAPSL) && matches(entry.numlock, LedCode::LED_NUML)
+    }
+
+    fn match_device(&self, device_matcher: &crate::config::device::Device, device: &InputDeviceInfo) -> bool {
+        if device_matcher.only.is_none() && device_matcher.not.is_none() {
+            return false;
+        }
+        let matches_any = |names: &Vec<String>| names.iter().any(|m| device.matches(m));
+        device_matcher.only.as_ref().is_none_or(matches_any)
+            && !device_matcher.not.as_ref().is_some_and(matches_any)
+            && device_matcher.within.iter().all(matches_any)
+    }
+
     fn update_modifier(&mut self, key: Key, value: i32) {
         if value == PRESS {
             self.modifiers.insert(key);
@@ -874,6 +1397,34 @@ fn match_application(&mut self, application_matcher: &OnlyOrNot) -> bool {
     }
 }
 
+fn run_predicate(command: &[String]) -> bool {
+    let Some((program, args)) = command.split_first() else {
+        return false;
+    };
+    let mut child = match Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            debug!("Failed to run predicate {:?}: {}", command, e);
+            return false;
+        }
+    };
+    match child.wait_timeout(PREDICATE_TIMEOUT) {
+        Ok(Some(status)) => status.success(),
+        _ => {
+            debug!("Predicate {:?} timed out", command);
+            let _ = child.kill();
+            let _ = child.wait();
+            false
+        }
+    }
+}
+
 fn is_remap(actions: &[KeymapAction]) -> bool {
     if actions.is_empty() {
         return false;
@@ -888,21 +1439,32 @@ fn with_extra_modifiers(actions: &[KeymapAction], extra_modifiers: &[Key], exact
         result.push(TaggedAction {
             action: KeymapAction::SetExtraModifiers(extra_modifiers.to_vec()),
             exact_match,
+            repeat_interval: None,
         });
     }
     result.extend(actions.iter().map(|action| TaggedAction {
         action: action.clone(),
         exact_match,
+        repeat_interval: None,
     }));
     if !extra_modifiers.is_empty() {
         result.push(TaggedAction {
             action: KeymapAction::SetExtraModifiers(vec![]),
             exact_match,
+            repeat_interval: None,
         });
     }
     result
 }
 
+fn entry_actions(entry: &KeymapEntry, extra_modifiers: &[Key]) -> Vec<TaggedAction> {
+    let mut actions = with_extra_modifiers(&entry.actions, extra_modifiers, entry.exact_match);
+    for action in &mut actions {
+        action.repeat_interval = entry.repeat_interval;
+    }
+    actions
+}
+
 fn contains_modifier(modifiers: &[Modifier], key: &Key) -> bool {
     for modifier in modifiers {
         if match modifier {
@@ -919,7 +1481,7 @@ fn contains_modifier(modifiers: &[Modifier], key: &Key) -> bool {
 }
 
 lazy_static! {
-    static ref MODIFIER_KEYS: [Key; 8] = [
+    pub(crate) static ref MODIFIER_KEYS: [Key; 8] = [
         Key::KEY_LEFTSHIFT,
         Key::KEY_RIGHTSHIFT,
         Key::KEY_LEFTCTRL,
@@ -931,13 +1493,16 @@ lazy_static! {
     ];
 }
 
+fn is_mouse_button(key: Key) -> bool {
+    (Key::BTN_LEFT.code()..=Key::BTN_TASK.code()).contains(&key.code())
+}
+
 fn is_pressed(value: i32) -> bool {
     value == PRESS || value == REPEAT
 }
 
-const RELEASE: i32 = 0;
-const PRESS: i32 = 1;
-const REPEAT: i32 = 2;
+const PREDICATE_TIMEOUT: Duration = Duration::from_millis(200);
+const PREDICATE_CACHE_TTL: Duration = Duration::from_millis(500);
 
 #[derive(Debug)]
 struct MultiPurposeKeyState {
@@ -1039,3 +1604,120 @@ fn modifiers_first(a: &Key, b: &Key) -> Ordering {
 fn modifiers_last(a: &Key, b: &Key) -> Ordering {
     modifiers_first(a, b).reverse()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::action::Action;
+    use crate::config::key_press::Modifier;
+    use crate::config::keymap::modifier_keys;
+    use crate::event::{Event, KeyEvent, KeyValue};
+    use crate::tests::{
+        advance_fake_clock, fake_clock, get_event_handler, get_input_device_info, load_temp_config, parse_config,
+    };
+    use evdev::KeyCode as Key;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_diff_modifier_keys_matches_diff_modifiers() {
+        let config = parse_config("keymap: []");
+        let held_sets = [
+            vec![],
+            vec![Key::KEY_LEFTCTRL],
+            vec![Key::KEY_RIGHTSHIFT, Key::KEY_LEFTALT],
+            vec![Key::KEY_CAPSLOCK, Key::KEY_LEFTMETA],
+        ];
+        let required_sets = [
+            vec![],
+            vec![Modifier::Control],
+            vec![Modifier::Shift, Modifier::Alt],
+            vec![Modifier::Key(Key::KEY_CAPSLOCK), Modifier::Windows],
+        ];
+
+        for held in &held_sets {
+            let mut handler = get_event_handler(&config, None);
+            for key in held {
+                handler.update_modifier(*key, super::PRESS);
+            }
+            for required in &required_sets {
+                let (mut extra, missing) = handler.diff_modifiers(required);
+                let (mut precomputed_extra, precomputed_missing) =
+                    handler.diff_modifier_keys(required, &modifier_keys(required));
+                extra.sort();
+                precomputed_extra.sort();
+                assert_eq!(extra, precomputed_extra);
+                assert_eq!(missing, precomputed_missing);
+            }
+        }
+    }
+
+    #[test]
+    fn test_send_output_is_not_remapped_again() {
+        let config = load_temp_config("hook", &[("config.ahk", "$a::Send(\"b\")\nb::Send(\"a\")\n")]).unwrap();
+        let mut handler = get_event_handler(&config, None);
+        let key = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+
+        // a sends b, which b:: would turn back into a if the output were processed again
+        let actions = handler.on_events(&vec![key(Key::KEY_A, KeyValue::Press)], &config).unwrap();
+        let expected = vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+        ];
+        assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+    }
+
+    #[test]
+    fn test_send_level_triggers_lower_input_level() {
+        let script = "b::Send(\"c\")\nF2::\n{\n    SendLevel(1)\n    Send(\"{F2}\")\n}\n#InputLevel 1\n\
+            a::\n{\n    SendLevel(1)\n    Send(\"b\")\n}\nx::Send(\"b\")\n";
+        let config = load_temp_config("sendlevel", &[("config.ahk", script)]).unwrap();
+        let mut handler = get_event_handler(&config, None);
+        let key = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+        let presses = |actions: &Vec<Action>, key: Key| {
+            let press = format!("{:?}", Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)));
+            actions.iter().filter(|action| format!("{action:?}") == press).count()
+        };
+
+        // a's b is sent at SendLevel 1, so it runs b:: (InputLevel 0) once instead of typing b
+        let actions = handler.on_events(&vec![key(Key::KEY_A, KeyValue::Press)], &config).unwrap();
+        assert_eq!(presses(&actions, Key::KEY_C), 1);
+        assert_eq!(presses(&actions, Key::KEY_B), 0);
+
+        // Without SendLevel, x's b is just typed
+        let actions = handler.on_events(&vec![key(Key::KEY_X, KeyValue::Press)], &config).unwrap();
+        assert_eq!(presses(&actions, Key::KEY_B), 1);
+        assert_eq!(presses(&actions, Key::KEY_C), 0);
+
+        // A hotkey that triggers itself stops at the depth limit and sends its key once
+        let actions = handler.on_events(&vec![key(Key::KEY_F2, KeyValue::Press)], &config).unwrap();
+        assert_eq!(presses(&actions, Key::KEY_F2), 1);
+    }
+
+    #[test]
+    fn test_pending_win_wait_active_does_not_block() {
+        let script = "F1::\n{\n    WinWaitActive(\"ahk_exe never-focused\")\n    Send(\"a\")\n}\n";
+        let config = load_temp_config("wait", &[("config.ahk", script)]).unwrap();
+        let mut handler = get_event_handler(&config, Some("firefox".to_string()));
+        handler.set_clock(fake_clock);
+        let key = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+
+        let start = Instant::now();
+        let actions = handler.on_events(&vec![key(Key::KEY_F1, KeyValue::Press)], &config).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(!format!("{actions:?}").contains("KEY_A"));
+        assert!(handler.next_wait_check().is_some());
+
+        // Other keys keep flowing while the wait is pending
+        let actions = handler.on_events(&vec![key(Key::KEY_B, KeyValue::Press)], &config).unwrap();
+        assert!(format!("{actions:?}").contains("KEY_B"));
+        assert!(handler.next_wait_check().is_some());
+
+        // The wait gives up at max_wait_ms on the handler's clock, then the rest runs
+        advance_fake_clock(config.max_wait_ms - 1);
+        let actions = handler.on_events(&vec![key(Key::KEY_C, KeyValue::Press)], &config).unwrap();
+        assert!(!format!("{actions:?}").contains("KEY_A"));
+        advance_fake_clock(50); // the next poll
+        let actions = handler.on_events(&vec![key(Key::KEY_D, KeyValue::Press)], &config).unwrap();
+        assert!(format!("{actions:?}").contains("KEY_A"));
+        assert!(handler.next_wait_check().is_none());
+    }
+}
@@ -1,5 +1,31 @@
 // Hotstring matching for text expansion
 
+use serde::{Deserialize, Serialize};
+
+// Typed after an expansion in place of the ending char, which is erased along with the trigger
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailingChar {
+    None,
+    Space,
+    // Non-breaking space, so the expansion can't be split from the next word
+    Nbsp,
+    // The space, tab or punctuation that ended the trigger, as AHK does
+    #[default]
+    EndChar,
+}
+
+// How an expansion gets typed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HotstringMode {
+    // Through the clipboard, which keeps newlines as they are
+    #[default]
+    Paste,
+    // Key by key, newlines being Enter, for apps where pasting doesn't work
+    Type,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RollingItem {
     WordSeparator,
@@ -17,6 +43,8 @@ pub struct HotstringMatch {
     pub case_sensitive: bool,
     pub omit_char: bool, // NEW
     pub execute: bool,   // NEW
+    // None uses the config's hotstring_trailing_char
+    pub trailing_char: Option<TrailingChar>,
 }
 
 impl HotstringMatch {
@@ -60,13 +88,51 @@ impl HotstringMatch {
             case_sensitive,
             omit_char,
             execute,
+            trailing_char: None,
+        }
+    }
+
+    // What to type after the replacement. `*` has no ending char and `O` leaves it in place,
+    // so neither gets one whatever the setting.
+    pub fn trailing_text(&self, default: TrailingChar, end_char: &str) -> Option<String> {
+        if self.immediate || self.omit_char {
+            return None;
+        }
+        match self.trailing_char.unwrap_or(default) {
+            TrailingChar::None => None,
+            TrailingChar::Space => Some(" ".to_string()),
+            TrailingChar::Nbsp => Some("\u{00A0}".to_string()),
+            TrailingChar::EndChar => Some(end_char.to_string()),
+        }
+    }
+
+    // Backspaces needed to erase the typed trigger, plus the ending character
+    // unless the O option keeps it
+    pub fn chars_to_delete(&self) -> usize {
+        let trigger_len = self.trigger.chars().count();
+        if self.omit_char {
+            trigger_len
+        } else {
+            trigger_len + 1
         }
     }
 }
 
+// A hotstring that fired while HotstringMatcher::expand typed its input. `start..end` are the
+// char indices of the text it replaces: the trigger and, unless it's immediate, the ending char.
+// One that waited on a longer trigger fires later, so what comes after `end` is typed again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub id: usize,
+    pub start: usize,
+    pub end: usize,
+    pub replacement: String,
+}
+
 #[derive(Clone)]
 pub struct HotstringMatcherState {
     paths: Vec<HotstringPath>,
+    pending: Option<Pending>,
 }
 
 #[derive(Clone)]
@@ -76,6 +142,24 @@ struct HotstringPath {
     chars_matched: String,
 }
 
+// A completed hotstring held back while a longer trigger that contains it could still complete
+#[derive(Clone)]
+struct Pending {
+    match_id: usize,
+    end_char: String,
+    typed_after: String,
+}
+
+// A hotstring to expand. One that waited on a longer trigger fires on a later char, so the chars
+// typed since its ending char, the current one last, are in `typed_after`.
+#[derive(Debug)]
+pub struct Fired<'a> {
+    pub hotstring: &'a HotstringMatch,
+    // The char that completed it, which the trailing text may repeat
+    pub end_char: String,
+    pub typed_after: String,
+}
+
 #[derive(Debug)]
 pub struct HotstringMatcher {
     matches: Vec<HotstringMatch>,
@@ -96,12 +180,17 @@ impl HotstringMatcher {
         }
     }
 
+    // Of the hotstrings a char completes, the longest trigger wins, then the first in the config.
+    // It waits while a longer trigger it's the start of could still complete, e.g. `:*:btw` for
+    // `:*:btw2`, and fires once the next chars rule that one out. A hotstring a later char
+    // completes wins over a waiting one.
     pub fn process(
         &self,
         prev_state: Option<&HotstringMatcherState>,
         char: &str,
-    ) -> (HotstringMatcherState, Option<&HotstringMatch>) {
+    ) -> (HotstringMatcherState, Option<Fired<'_>>) {
         let mut new_paths = Vec::new();
+        let mut completed = Vec::new();
 
         let is_word_sep = self.word_separators.contains(&char.to_string());
 
@@ -122,7 +211,8 @@ impl HotstringMatcher {
                         new_chars.push_str(char);
 
                         if next_index >= m.items.len() {
-                            return (HotstringMatcherState { paths: vec![] }, Some(m));
+                            completed.push(path.match_id);
+                            continue;
                         }
 
                         new_paths.push(HotstringPath {
@@ -141,12 +231,19 @@ impl HotstringMatcher {
 
                 // If first item is WordSeparator (left boundary marker)
                 if matches!(m.items[0], RollingItem::WordSeparator) {
-                    if prev_state.is_none() {
+                    if is_word_sep {
+                        // Current char is separator - boundary satisfied, the trigger starts with
+                        // the next char. Spaces inside a trigger are matched as its chars by the
+                        // paths above, so a multi-word trigger isn't cut off here.
+                        new_paths.push(HotstringPath {
+                            item_index: 1,
+                            match_id: idx,
+                            chars_matched: String::new(),
+                        });
+                        continue;
+                    } else if prev_state.is_none() {
                         // At start of input - boundary satisfied, skip to trigger chars
                         start_index = 1;
-                    } else if is_word_sep {
-                        // Current char is separator - boundary satisfied, skip to trigger chars
-                        start_index = 1;
                     } else {
                         // In middle of word - boundary not satisfied
                         continue;
@@ -166,7 +263,8 @@ impl HotstringMatcher {
 
                 if matches {
                     if start_index + 1 >= m.items.len() {
-                        return (HotstringMatcherState { paths: vec![] }, Some(m));
+                        completed.push(idx);
+                        continue;
                     }
                     new_paths.push(HotstringPath {
                         item_index: start_index + 1,
@@ -177,6 +275,175 @@ impl HotstringMatcher {
             }
         }
 
-        (HotstringMatcherState { paths: new_paths }, None)
+        let longest = completed
+            .into_iter()
+            .min_by_key(|&id| (std::cmp::Reverse(self.matches[id].trigger.chars().count()), id));
+        let pending = match (longest, prev_state.and_then(|state| state.pending.clone())) {
+            (Some(match_id), _) => Pending {
+                match_id,
+                end_char: char.to_string(),
+                typed_after: String::new(),
+            },
+            (None, Some(mut pending)) => {
+                pending.typed_after.push_str(char);
+                pending
+            }
+            (None, None) => return (HotstringMatcherState { paths: new_paths, pending: None }, None),
+        };
+
+        // A path that has matched the waiting trigger and everything typed after it contains it
+        let m = &self.matches[pending.match_id];
+        let typed = m.trigger.chars().count() + usize::from(!m.immediate) + pending.typed_after.chars().count();
+        if new_paths.iter().any(|path| path.chars_matched.chars().count() >= typed) {
+            return (HotstringMatcherState { paths: new_paths, pending: Some(pending) }, None);
+        }
+        let fired = Fired {
+            hotstring: m,
+            end_char: pending.end_char,
+            typed_after: pending.typed_after,
+        };
+        (HotstringMatcherState { paths: vec![], pending: None }, Some(fired))
+    }
+
+    // The hotstring waiting on a longer trigger, to fire when typing stops short of that one.
+    // Unlike `process`'s, all of its `typed_after` is typed already.
+    pub fn pending(&self, state: Option<&HotstringMatcherState>) -> Option<Fired<'_>> {
+        let pending = state?.pending.clone()?;
+        Some(Fired {
+            hotstring: &self.matches[pending.match_id],
+            end_char: pending.end_char,
+            typed_after: pending.typed_after,
+        })
+    }
+
+    // Types `input` from a fresh start, as the event loop would, without its idle timeout
+    pub fn expand(&self, input: &str) -> Vec<Expansion> {
+        let mut state = None;
+        let mut expansions = vec![];
+        for (index, c) in input.chars().enumerate() {
+            let (new_state, fired) = self.process(state.as_ref(), &c.to_string());
+            state = Some(new_state);
+            if let Some(Fired { hotstring, typed_after, .. }) = fired {
+                let end = index + 1 - typed_after.chars().count();
+                let len = hotstring.trigger.chars().count() + usize::from(!hotstring.immediate);
+                expansions.push(Expansion {
+                    id: hotstring.id,
+                    start: end - len,
+                    end,
+                    replacement: hotstring.replacement.clone(),
+                });
+                // The event loop starts over after an expansion too
+                state = None;
+            }
+        }
+        expansions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expansion, HotstringMatch, HotstringMatcher, TrailingChar};
+
+    #[test]
+    fn test_chars_to_delete_multibyte_trigger() {
+        let hotstring =
+            |omit_char| HotstringMatch::from_trigger(0, "café", String::new(), false, false, omit_char, false);
+        assert_eq!(hotstring(false).chars_to_delete(), 5);
+        assert_eq!(hotstring(true).chars_to_delete(), 4);
+    }
+
+    #[test]
+    fn test_trigger_with_space() {
+        let matcher = HotstringMatcher::new(vec![HotstringMatch::from_trigger(
+            0,
+            "my addr",
+            "1 Main St".to_string(),
+            false,
+            false,
+            false,
+            false,
+        )]);
+        let mut state = None;
+        let mut matched = vec![];
+        for c in "i said my addr ".chars() {
+            let (new_state, fired) = matcher.process(state.as_ref(), &c.to_string());
+            matched.push(fired.map(|fired| fired.hotstring.replacement.clone()));
+            state = Some(new_state);
+        }
+        assert_eq!(matched.last().unwrap().as_deref(), Some("1 Main St"));
+        assert_eq!(matched.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn test_expand() {
+        let hotstring = |id, trigger: &str, immediate| {
+            HotstringMatch::from_trigger(id, trigger, trigger.to_uppercase(), immediate, false, false, false)
+        };
+        let expansion = |id, start, end, replacement: &str| Expansion {
+            id,
+            start,
+            end,
+            replacement: replacement.to_string(),
+        };
+
+        // The shorter of two overlapping immediate triggers waits for the longer to be ruled out
+        let matcher = HotstringMatcher::new(vec![hotstring(0, "ab", true), hotstring(1, "abc", true)]);
+        assert_eq!(matcher.expand("xabcabx"), [expansion(1, 1, 4, "ABC"), expansion(0, 4, 6, "AB")]);
+        assert_eq!(matcher.expand("ab"), []);
+
+        let matcher = HotstringMatcher::new(vec![hotstring(0, "btw", false), hotstring(1, "tw", false)]);
+        assert_eq!(matcher.expand("btw tw."), [expansion(0, 0, 4, "BTW"), expansion(1, 4, 7, "TW")]);
+        assert_eq!(matcher.expand("abtw btwx"), []);
+    }
+
+    #[test]
+    fn test_longest_trigger_wins() {
+        let hotstring = |id, trigger: &str, replacement: &str| {
+            HotstringMatch::from_trigger(id, trigger, replacement.to_string(), false, false, false, false)
+        };
+        let matcher = HotstringMatcher::new(vec![
+            hotstring(0, "my", "mine"),
+            hotstring(1, "my addr", "1 Main St"),
+            hotstring(2, "my", "ours"),
+        ]);
+        let replacements = |input| -> Vec<_> {
+            matcher.expand(input).into_iter().map(|expansion| expansion.replacement).collect()
+        };
+        assert_eq!(replacements("my addr "), ["1 Main St"]);
+        // The first of equally long triggers wins
+        assert_eq!(replacements("my dog "), ["mine"]);
+
+        // "my" fires once the `x` rules out "my addr", with what was typed since to type again
+        let mut state = None;
+        for c in "my a".chars() {
+            let (new_state, fired) = matcher.process(state.as_ref(), &c.to_string());
+            assert!(fired.is_none());
+            state = Some(new_state);
+        }
+        let fired = matcher.process(state.as_ref(), "x").1.unwrap();
+        assert_eq!((fired.hotstring.id, fired.end_char.as_str(), fired.typed_after.as_str()), (0, " ", "ax"));
+    }
+
+    #[test]
+    fn test_trailing_text() {
+        let hotstring = |immediate, omit_char| {
+            HotstringMatch::from_trigger(0, "btw", "by the way".to_string(), immediate, false, omit_char, false)
+        };
+        let trailing = |default| hotstring(false, false).trailing_text(default, "\t");
+        assert_eq!(trailing(TrailingChar::None), None);
+        assert_eq!(trailing(TrailingChar::Space).as_deref(), Some(" "));
+        assert_eq!(trailing(TrailingChar::Nbsp).as_deref(), Some("\u{00A0}"));
+        assert_eq!(trailing(TrailingChar::EndChar).as_deref(), Some("\t"));
+
+        // The hotstring's own setting wins over the config's
+        let own = HotstringMatch {
+            trailing_char: Some(TrailingChar::Space),
+            ..hotstring(false, false)
+        };
+        assert_eq!(own.trailing_text(TrailingChar::None, ".").as_deref(), Some(" "));
+
+        // `*` and `O` don't get one
+        assert_eq!(hotstring(true, false).trailing_text(TrailingChar::Space, "e"), None);
+        assert_eq!(hotstring(false, true).trailing_text(TrailingChar::EndChar, "."), None);
     }
 }
@@ -0,0 +1,256 @@
+use crate::device::is_output_key;
+use evdev::KeyCode as Key;
+
+// Every character that can be typed on a US layout, with the key and whether it needs Shift.
+// Characters map one-to-one onto (key, shift) pairs, so the table reads both ways.
+const CHAR_KEYS: &[(char, Key, bool)] = &[
+    ('a', Key::KEY_A, false),
+    ('b', Key::KEY_B, false),
+    ('c', Key::KEY_C, false),
+    ('d', Key::KEY_D, false),
+    ('e', Key::KEY_E, false),
+    ('f', Key::KEY_F, false),
+    ('g', Key::KEY_G, false),
+    ('h', Key::KEY_H, false),
+    ('i', Key::KEY_I, false),
+    ('j', Key::KEY_J, false),
+    ('k', Key::KEY_K, false),
+    ('l', Key::KEY_L, false),
+    ('m', Key::KEY_M, false),
+    ('n', Key::KEY_N, false),
+    ('o', Key::KEY_O, false),
+    ('p', Key::KEY_P, false),
+    ('q', Key::KEY_Q, false),
+    ('r', Key::KEY_R, false),
+    ('s', Key::KEY_S, false),
+    ('t', Key::KEY_T, false),
+    ('u', Key::KEY_U, false),
+    ('v', Key::KEY_V, false),
+    ('w', Key::KEY_W, false),
+    ('x', Key::KEY_X, false),
+    ('y', Key::KEY_Y, false),
+    ('z', Key::KEY_Z, false),
+    ('A', Key::KEY_A, true),
+    ('B', Key::KEY_B, true),
+    ('C', Key::KEY_C, true),
+    ('D', Key::KEY_D, true),
+    ('E', Key::KEY_E, true),
+    ('F', Key::KEY_F, true),
+    ('G', Key::KEY_G, true),
+    ('H', Key::KEY_H, true),
+    ('I', Key::KEY_I, true),
+    ('J', Key::KEY_J, true),
+    ('K', Key::KEY_K, true),
+    ('L', Key::KEY_L, true),
+    ('M', Key::KEY_M, true),
+    ('N', Key::KEY_N, true),
+    ('O', Key::KEY_O, true),
+    ('P', Key::KEY_P, true),
+    ('Q', Key::KEY_Q, true),
+    ('R', Key::KEY_R, true),
+    ('S', Key::KEY_S, true),
+    ('T', Key::KEY_T, true),
+    ('U', Key::KEY_U, true),
+    ('V', Key::KEY_V, true),
+    ('W', Key::KEY_W, true),
+    ('X', Key::KEY_X, true),
+    ('Y', Key::KEY_Y, true),
+    ('Z', Key::KEY_Z, true),
+    ('0', Key::KEY_0, false),
+    ('1', Key::KEY_1, false),
+    ('2', Key::KEY_2, false),
+    ('3', Key::KEY_3, false),
+    ('4', Key::KEY_4, false),
+    ('5', Key::KEY_5, false),
+    ('6', Key::KEY_6, false),
+    ('7', Key::KEY_7, false),
+    ('8', Key::KEY_8, false),
+    ('9', Key::KEY_9, false),
+    (')', Key::KEY_0, true),
+    ('!', Key::KEY_1, true),
+    ('@', Key::KEY_2, true),
+    ('#', Key::KEY_3, true),
+    ('$', Key::KEY_4, true),
+    ('%', Key::KEY_5, true),
+    ('^', Key::KEY_6, true),
+    ('&', Key::KEY_7, true),
+    ('*', Key::KEY_8, true),
+    ('(', Key::KEY_9, true),
+    (' ', Key::KEY_SPACE, false),
+    ('\n', Key::KEY_ENTER, false),
+    ('\t', Key::KEY_TAB, false),
+    ('.', Key::KEY_DOT, false),
+    (',', Key::KEY_COMMA, false),
+    (';', Key::KEY_SEMICOLON, false),
+    ('/', Key::KEY_SLASH, false),
+    ('\'', Key::KEY_APOSTROPHE, false),
+    ('-', Key::KEY_MINUS, false),
+    ('=', Key::KEY_EQUAL, false),
+    ('[', Key::KEY_LEFTBRACE, false),
+    (']', Key::KEY_RIGHTBRACE, false),
+    ('\\', Key::KEY_BACKSLASH, false),
+    ('`', Key::KEY_GRAVE, false),
+    ('>', Key::KEY_DOT, true),
+    ('<', Key::KEY_COMMA, true),
+    (':', Key::KEY_SEMICOLON, true),
+    ('?', Key::KEY_SLASH, true),
+    ('"', Key::KEY_APOSTROPHE, true),
+    ('_', Key::KEY_MINUS, true),
+    ('+', Key::KEY_EQUAL, true),
+    ('{', Key::KEY_LEFTBRACE, true),
+    ('}', Key::KEY_RIGHTBRACE, true),
+    ('|', Key::KEY_BACKSLASH, true),
+    ('~', Key::KEY_GRAVE, true),
+];
+
+// The key that types `c`, and whether Shift has to be held for it
+pub fn char_to_key_shift(c: char) -> Option<(Key, bool)> {
+    CHAR_KEYS.iter().find(|(ch, _, _)| *ch == c).map(|&(_, key, shift)| (key, shift))
+}
+
+// What `key` types, with or without Shift held
+pub fn key_to_char(key: Key, shift: bool) -> Option<char> {
+    CHAR_KEYS
+        .iter()
+        .find(|(_, k, s)| *k == key && *s == shift)
+        .map(|&(ch, _, _)| ch)
+}
+
+// KEY_MAX, the highest evdev key code
+const MAX_KEY_CODE: u16 = 0x2ff;
+
+// `code:16`: a key by its evdev code, for apps that only react to that exact code.
+// None if `name` isn't one, an error if the code isn't a key the output device can send.
+pub fn parse_key_code(name: &str) -> Option<Result<Key, String>> {
+    let code = name.get(..5).filter(|prefix| prefix.eq_ignore_ascii_case("code:")).map(|_| name[5..].trim())?;
+    Some(match code.parse::<u16>() {
+        Ok(number @ 1..=MAX_KEY_CODE) if is_output_key(Key(number)) => Ok(Key(number)),
+        Ok(1..=MAX_KEY_CODE) => Err(format!("key code '{code}' isn't one the output device can send")),
+        _ => Err(format!("key code '{code}' isn't between 1 and {MAX_KEY_CODE}")),
+    })
+}
+
+// AHK's `sc01E`, a scan code of the PC keyboard's set 1. Up to 0x58, F12, evdev numbers the
+// keys the same way; beyond that, and for the 0xE0-prefixed (`sc1xx`) ones, it doesn't.
+fn scan_code_to_key(name: &str) -> Option<Key> {
+    let code = u16::from_str_radix(name.strip_prefix("sc")?, 16).ok()?;
+    (1..=0x58).contains(&code).then_some(Key(code))
+}
+
+// AHK key names as used in hotkeys and `{...}` in Send, case-insensitive. A single
+// character names the key that types it, unless it takes Shift (`!` is a modifier, not 1).
+pub fn name_to_key(name: &str) -> Option<Key> {
+    if let Some(key) = parse_key_code(name) {
+        return key.ok();
+    }
+    if let Some(key) = scan_code_to_key(&name.to_lowercase()) {
+        return Some(key);
+    }
+    let key = match name.to_lowercase().as_str() {
+        "space" => Key::KEY_SPACE,
+        "enter" | "return" => Key::KEY_ENTER,
+        "tab" => Key::KEY_TAB,
+        "backspace" | "bs" => Key::KEY_BACKSPACE,
+        "delete" | "del" => Key::KEY_DELETE,
+        "escape" | "esc" => Key::KEY_ESC,
+        "insert" | "ins" => Key::KEY_INSERT,
+        "capslock" => Key::KEY_CAPSLOCK,
+        "up" => Key::KEY_UP,
+        "down" => Key::KEY_DOWN,
+        "left" => Key::KEY_LEFT,
+        "right" => Key::KEY_RIGHT,
+        "home" => Key::KEY_HOME,
+        "end" => Key::KEY_END,
+        "pageup" | "pgup" => Key::KEY_PAGEUP,
+        "pagedown" | "pgdn" => Key::KEY_PAGEDOWN,
+        "f1" => Key::KEY_F1,
+        "f2" => Key::KEY_F2,
+        "f3" => Key::KEY_F3,
+        "f4" => Key::KEY_F4,
+        "f5" => Key::KEY_F5,
+        "f6" => Key::KEY_F6,
+        "f7" => Key::KEY_F7,
+        "f8" => Key::KEY_F8,
+        "f9" => Key::KEY_F9,
+        "f10" => Key::KEY_F10,
+        "f11" => Key::KEY_F11,
+        "f12" => Key::KEY_F12,
+        "f13" => Key::KEY_F13,
+        "f14" => Key::KEY_F14,
+        "f15" => Key::KEY_F15,
+        "f16" => Key::KEY_F16,
+        "f17" => Key::KEY_F17,
+        "f18" => Key::KEY_F18,
+        "f19" => Key::KEY_F19,
+        "f20" => Key::KEY_F20,
+        "f21" => Key::KEY_F21,
+        "f22" => Key::KEY_F22,
+        "f23" => Key::KEY_F23,
+        "f24" => Key::KEY_F24,
+        "media_play_pause" => Key::KEY_PLAYPAUSE,
+        "media_next" => Key::KEY_NEXTSONG,
+        "media_prev" | "media_previous" => Key::KEY_PREVIOUSSONG,
+        "media_stop" => Key::KEY_STOPCD,
+        "volume_up" => Key::KEY_VOLUMEUP,
+        "volume_down" => Key::KEY_VOLUMEDOWN,
+        "volume_mute" | "mute" => Key::KEY_MUTE,
+        "lwin" => Key::KEY_LEFTMETA,
+        "rwin" => Key::KEY_RIGHTMETA,
+        "ctrl" | "control" | "lctrl" | "lcontrol" => Key::KEY_LEFTCTRL,
+        "rctrl" | "rcontrol" => Key::KEY_RIGHTCTRL,
+        "alt" | "lalt" => Key::KEY_LEFTALT,
+        "ralt" => Key::KEY_RIGHTALT,
+        "shift" | "lshift" => Key::KEY_LEFTSHIFT,
+        "rshift" => Key::KEY_RIGHTSHIFT,
+        "lbutton" => Key::BTN_LEFT,
+        "rbutton" => Key::BTN_RIGHT,
+        "mbutton" => Key::BTN_MIDDLE,
+        "xbutton1" => Key::BTN_SIDE,
+        "xbutton2" => Key::BTN_EXTRA,
+        name => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => match char_to_key_shift(c)? {
+                    (key, false) => key,
+                    (_, true) => return None,
+                },
+                _ => return None,
+            }
+        }
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{char_to_key_shift, key_to_char, name_to_key, CHAR_KEYS};
+    use evdev::KeyCode as Key;
+
+    #[test]
+    fn test_printable_ascii_round_trips() {
+        for c in (' '..='~').chain(['\n', '\t']) {
+            let (key, shift) = char_to_key_shift(c).unwrap_or_else(|| panic!("no key for {c:?}"));
+            assert_eq!(key_to_char(key, shift), Some(c));
+        }
+        assert_eq!(CHAR_KEYS.len(), ('~' as usize - ' ' as usize + 1) + 2);
+        assert_eq!(char_to_key_shift('é'), None);
+    }
+
+    #[test]
+    fn test_name_to_key() {
+        assert_eq!(name_to_key("PgDn"), Some(Key::KEY_PAGEDOWN));
+        assert_eq!(name_to_key("F24"), Some(Key::KEY_F24));
+        assert_eq!(name_to_key("LWin"), Some(Key::KEY_LEFTMETA));
+        assert_eq!(name_to_key("LButton"), Some(Key::BTN_LEFT));
+        assert_eq!(name_to_key("A"), Some(Key::KEY_A));
+        assert_eq!(name_to_key(";"), Some(Key::KEY_SEMICOLON));
+        assert_eq!(name_to_key("sc010"), Some(Key::KEY_Q));
+        assert_eq!(name_to_key("code:16"), Some(Key::KEY_Q));
+        assert_eq!(name_to_key("sc1000"), None);
+        assert_eq!(name_to_key("code:768"), None);
+        // BTN_SOUTH, a gamepad button the output device doesn't have
+        assert_eq!(name_to_key("code:304"), None);
+        assert_eq!(name_to_key("!"), None);
+        assert_eq!(name_to_key("nosuchkey"), None);
+    }
+}
@@ -9,6 +9,34 @@ pub mod client;
 pub mod config;
 pub mod event;
 pub mod event_handler;
+pub mod keys;
+pub mod selftest;
+pub mod single_instance;
 
 pub use config::Config;
 pub mod hotstring;
+
+#[cfg(test)]
+mod tests;
+#[cfg(test)]
+mod tests_disguised_events_in;
+#[cfg(test)]
+mod tests_disguised_events_out;
+#[cfg(test)]
+mod tests_extra_modifiers;
+#[cfg(test)]
+mod tests_keymap_mark;
+#[cfg(test)]
+mod tests_keymap_mode;
+#[cfg(test)]
+mod tests_modmap_keys;
+#[cfg(test)]
+mod tests_modmap_mul_purpose;
+#[cfg(test)]
+mod tests_modmap_press_release_key;
+#[cfg(test)]
+mod tests_nested_remap;
+#[cfg(test)]
+mod tests_replay;
+#[cfg(test)]
+mod tests_virtual_modifier;
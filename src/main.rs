@@ -1,49 +1,31 @@
 use crate::config::Config;
-use crate::device::{device_watcher, get_input_devices, output_device, DEVICE_NAME};
+use crate::device::{device_watcher, get_input_devices, output_device, routed_output_device, GrabMode, DEVICE_NAME};
 use crate::event_handler::EventHandler;
 use action_dispatcher::ActionDispatcher;
 use anyhow::{anyhow, bail, Context};
 use clap::{CommandFactory, Parser, ValueEnum};
 use clap_complete::Shell;
 use client::build_client;
-use config::{config_watcher, load_configs};
+use config::{config_watcher, load_configs_with};
 use device::InputDevice;
+use evdev::InputEvent;
 use event::Event;
 use nix::libc::ENODEV;
 use nix::sys::inotify::{AddWatchFlags, Inotify, InotifyEvent};
 use nix::sys::select::select;
 use nix::sys::select::FdSet;
+use nix::sys::time::{TimeVal, TimeValLike};
 use nix::sys::timerfd::{ClockId, TimerFd, TimerFlags};
 use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
 use std::io::stdout;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::PathBuf;
 use std::time::Duration;
-use ahk_wayland::{action_dispatcher, client, config, device, event, event_handler};
+use ahk_wayland::{action_dispatcher, client, config, device, event, event_handler, single_instance};
+use ahk_wayland::action::write_actions;
 use ahk_wayland::ahk::interpreter::AhkInterpreter;
-
-#[cfg(test)]
-mod tests;
-#[cfg(test)]
-mod tests_disguised_events_in;
-#[cfg(test)]
-mod tests_disguised_events_out;
-#[cfg(test)]
-mod tests_extra_modifiers;
-#[cfg(test)]
-mod tests_keymap_mark;
-#[cfg(test)]
-mod tests_keymap_mode;
-#[cfg(test)]
-mod tests_modmap_keys;
-#[cfg(test)]
-mod tests_modmap_mul_purpose;
-#[cfg(test)]
-mod tests_modmap_press_release_key;
-#[cfg(test)]
-mod tests_nested_remap;
-#[cfg(test)]
-mod tests_virtual_modifier;
+use ahk_wayland::ahk::WaylandTextInjector;
 
 #[derive(Parser, Debug)]
 #[command(version)]
@@ -73,6 +55,32 @@ struct Args {
     /// - in fish: xremap --completions fish | source
     #[arg(long, value_enum, display_order = 100, value_name = "SHELL", verbatim_doc_comment)]
     completions: Option<Shell>,
+    /// Print the loaded hotkeys and hotstrings, then exit
+    #[arg(long)]
+    list: bool,
+    /// Print the loaded config as YAML, e.g. to convert an AHK script, then exit
+    #[arg(long)]
+    export_yaml: bool,
+    /// Run an unknown action in a single-line AHK hotkey as a shell command, with a
+    /// warning, instead of refusing to load the file
+    #[arg(long)]
+    ahk_lenient: bool,
+    /// Print the input devices with their names, paths and vendor:product ids, marking
+    /// the ones --device, --ignore and --mouse would select with `*`, then exit
+    #[arg(long)]
+    list_devices: bool,
+    /// Check that a virtual output device can be created and its key events
+    /// read back, then exit. Needs no config file or compositor.
+    #[arg(long)]
+    selftest: bool,
+    /// Also write each action sent, such as a key event or a command, as a line of JSON
+    /// to this file, e.g. a FIFO or /dev/fd/3, for another program to follow
+    #[arg(long, value_name = "PATH")]
+    print_actions: Option<PathBuf>,
+    /// Wait for each command run, e.g. by an AHK `Run`, and log its output and exit
+    /// status at debug level (RUST_LOG=debug)
+    #[arg(long)]
+    debug_commands: bool,
     /// Choose the name of the created output device.
     /// Default is 'xremap' or 'xremap pid=xx'
     #[arg(long)]
@@ -81,7 +89,7 @@ struct Args {
     ///
     /// When more than one file is given, then will modmap, keymap and virtual_modifiers
     /// from the subsequent files be merged into the first configuration file.
-    #[arg(required_unless_present = "completions", num_args = 1..)]
+    #[arg(required_unless_present_any = ["completions", "selftest", "list_devices"], num_args = 1..)]
     configs: Vec<PathBuf>,
     /// Choose the vendor value of the created output device.
     /// Default is: 0x1234
@@ -117,6 +125,13 @@ fn main() -> anyhow::Result<()> {
         watch,
         configs,
         completions,
+        list,
+        export_yaml,
+        ahk_lenient,
+        list_devices,
+        selftest,
+        print_actions,
+        debug_commands,
         output_device_name,
         product,
         vendor,
@@ -127,19 +142,36 @@ fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
-    if let Some(output_device_name) = output_device_name {
+    if let Some(output_device_name) = &output_device_name {
         unsafe {
-            DEVICE_NAME = Some(output_device_name);
+            DEVICE_NAME = Some(output_device_name.clone());
         }
     }
 
+    if selftest {
+        ahk_wayland::selftest::selftest().context("Self-test failed")?;
+        println!("Self-test passed: uinput events round-trip");
+        return Ok(());
+    }
+
+    if list_devices {
+        let devices = device::list(&device_filter, &ignore_filter, mouse).context("Failed to list input devices")?;
+        if devices.is_empty() {
+            bail!("No input device could be opened; check the permissions on /dev/input");
+        }
+        for device in devices {
+            println!("{device}");
+        }
+        return Ok(());
+    }
+
     // Configuration
     let config_paths = match configs[..] {
-        [] => panic!("config is set, if not completions"),
+        [] => panic!("config is set, if not completions, selftest or list_devices"),
         _ => configs,
     };
 
-    let mut config = match config::load_configs(&config_paths) {
+    let mut config = match config::load_configs_with(&config_paths, ahk_lenient) {
         Ok(config) => config,
         Err(e) => bail!(
             "Failed to load config '{}': {}",
@@ -151,6 +183,33 @@ fn main() -> anyhow::Result<()> {
             e
         ),
     };
+    if list {
+        for hotkey in config.list_hotkeys() {
+            println!("{hotkey}");
+        }
+        for hotstring in config.list_hotstrings() {
+            println!("{hotstring}");
+        }
+        return Ok(());
+    }
+    if export_yaml {
+        print!("{}", config.to_yaml().context("Failed to export config")?);
+        return Ok(());
+    }
+
+    // Before the input devices are opened, so the configured name is the one left out as our own
+    let identity = device::output_device_identity(output_device_name, vendor, product, &config);
+    if let Some(name) = &identity.name {
+        unsafe {
+            DEVICE_NAME = Some(name.clone());
+        }
+    }
+
+    let _instance_lock = match config.single_instance {
+        Some(mode) => single_instance::acquire(&single_instance::lock_path(), mode)?,
+        None => None,
+    };
+
     let watch_devices = watch.contains(&WatchTargets::Device);
     let watch_config = watch.contains(&WatchTargets::Config);
 
@@ -158,7 +217,9 @@ fn main() -> anyhow::Result<()> {
     let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty())?;
     let timer_fd = timer.as_raw_fd();
     let delay = Duration::from_millis(config.keypress_delay_ms);
-    let mut input_devices = match get_input_devices(&device_filter, &ignore_filter, mouse, watch_devices) {
+    // Devices stay grabbed or not until a restart, whatever a reloaded config says
+    let grab_mode = config.grab_mode;
+    let mut input_devices = match get_input_devices(&device_filter, &ignore_filter, mouse, watch_devices, grab_mode) {
         Ok(input_devices) => input_devices,
         Err(e) => bail!("Failed to prepare input devices: {}", e),
     };
@@ -166,8 +227,8 @@ fn main() -> anyhow::Result<()> {
     let config_watcher = config_watcher(watch_config, &config_paths).context("Setting up config watcher")?;
     let watchers: Vec<_> = device_watcher.iter().chain(config_watcher.iter()).collect();
     let mut handler = EventHandler::new(timer, &config.default_mode, delay, build_client());
-    let vendor = u16::from_str_radix(vendor.unwrap_or_default().trim_start_matches("0x"), 16).unwrap_or(0x1234);
-    let product = u16::from_str_radix(product.unwrap_or_default().trim_start_matches("0x"), 16).unwrap_or(0x5678);
+    handler.set_lit_leds(input_devices.values().flat_map(InputDevice::lit_leds));
+    let (vendor, product) = (identity.vendor, identity.product);
    
     let output_device = match output_device(
     input_devices.values().next().map(InputDevice::bus_type),
@@ -179,70 +240,142 @@ fn main() -> anyhow::Result<()> {
         Err(e) => bail!("Failed to prepare an output device: {}", e),
     };
 
+    let mut routed_devices = vec![];
+    for device_id in &config.output_devices {
+        match routed_output_device(
+            device_id,
+            input_devices.values().next().map(InputDevice::bus_type),
+            config.enable_wheel,
+            vendor,
+            product,
+        ) {
+            Ok(device) => routed_devices.push((device_id.clone(), device)),
+            Err(e) => bail!("Failed to prepare the output device {}: {}", device_id, e),
+        }
+    }
+
     // Create WMClient and interpreter for AHK features
     let mut wm_client = build_client();
     let mut interpreter = AhkInterpreter::new(&mut wm_client);
 
     // Create dispatcher with access to interpreter
     let mut dispatcher = ActionDispatcher::new(output_device, &mut interpreter);
+    dispatcher.set_injector(WaylandTextInjector::new(&config.clipboard_copy_cmd, &config.clipboard_paste_cmd));
+    dispatcher.set_debug_commands(debug_commands);
+    for (device_id, device) in routed_devices {
+        dispatcher.add_route(&device_id, device);
+    }
+
+    // Appended to, so a FIFO or an fd opened by the caller works as well as a file
+    let mut action_log = match &print_actions {
+        Some(path) => Some(
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .with_context(|| format!("Failed to open {} for --print-actions", path.display()))?,
+        ),
+        None => None,
+    };
 
     // Main loop
     loop {
         match 'event_loop: loop {
-            let readable_fds = select_readable(input_devices.values(), &watchers, timer_fd)?;
+            let readable_fds =
+                select_readable(input_devices.values(), &watchers, timer_fd, handler.next_wait_check())?;
             if readable_fds.contains(timer_fd) {
-                if let Err(error) =
-                    handle_events(&mut handler, &mut dispatcher, &mut config, vec![Event::OverrideTimeout])
-                {
+                if let Err(error) = handle_events(
+                    &mut handler,
+                    &mut dispatcher,
+                    &mut config,
+                    vec![Event::OverrideTimeout],
+                    &mut action_log,
+                ) {
                     println!("Error on remap timeout: {error}")
                 }
             }
+            if handler.next_wait_check().is_some_and(|wait| wait.is_zero()) {
+                if let Err(error) = handle_events(&mut handler, &mut dispatcher, &mut config, vec![], &mut action_log) {
+                    println!("Error on pending WinWait or held hotkey: {error}")
+                }
+            }
 
-            for input_device in input_devices.values_mut() {
+            let mut device_removed = false;
+            let mut fetched = Vec::new();
+            for (path, input_device) in input_devices.iter_mut() {
                 if !readable_fds.contains(input_device.as_raw_fd()) {
                     continue;
                 }
-
-                if !handle_input_events(input_device, &mut handler, &mut dispatcher, &mut config)? {
-                    println!("Found a removed device. Reselecting devices.");
-                    break 'event_loop ReloadEvent::ReloadDevices;
+                match fetch_input_events(input_device)? {
+                    Some(events) => fetched.push((path.clone(), events)),
+                    None => device_removed = true,
                 }
             }
+            if !fetched.is_empty() {
+                let batches = fetched
+                    .iter()
+                    .map(|(path, events)| {
+                        let device = &input_devices[path];
+                        events.iter().map(|e| Event::new(device.to_info(), *e)).collect()
+                    })
+                    .collect();
+                let events = event::merge_by_time(batches);
+                handle_events(&mut handler, &mut dispatcher, &mut config, events, &mut action_log)?;
+            }
+            if device_removed {
+                println!("Found a removed device. Reselecting devices.");
+                break 'event_loop ReloadEvent::ReloadDevices;
+            }
 
             if let Some(inotify) = device_watcher {
                 if let Ok(events) = inotify.read_events() {
-                    handle_device_changes(events, &mut input_devices, &device_filter, &ignore_filter, mouse)?;
+                    handle_device_changes(
+                        events,
+                        &mut input_devices,
+                        &device_filter,
+                        &ignore_filter,
+                        mouse,
+                        grab_mode,
+                    )?;
                 }
             }
             if let Some(inotify) = config_watcher {
                 if let Ok(events) = inotify.read_events() {
-                    if !handle_config_changes(
+                    if !handle_config_changes(&events, &config_paths, inotify)? {
+                        break 'event_loop ReloadEvent::ReloadConfig;
+                    }
+                    handle_device_changes(
                         events,
                         &mut input_devices,
                         &device_filter,
                         &ignore_filter,
                         mouse,
-                        &config_paths,
-                        inotify,
-                    )? {
-                        break 'event_loop ReloadEvent::ReloadConfig;
-                    }
+                        grab_mode,
+                    )?;
                 }
             }
         } {
             ReloadEvent::ReloadDevices => {
-                for input_device in input_devices.values_mut() {
-                    input_device.ungrab();
+                if grab_mode == GrabMode::Exclusive {
+                    for input_device in input_devices.values_mut() {
+                        input_device.ungrab();
+                    }
                 }
-                input_devices = match get_input_devices(&device_filter, &ignore_filter, mouse, watch_devices) {
+                let selected = get_input_devices(&device_filter, &ignore_filter, mouse, watch_devices, grab_mode);
+                input_devices = match selected {
                     Ok(input_devices) => input_devices,
                     Err(e) => bail!("Failed to prepare input devices: {}", e),
                 };
+                handler.set_lit_leds(input_devices.values().flat_map(InputDevice::lit_leds));
             }
             ReloadEvent::ReloadConfig => {
-                if let Ok(c) = load_configs(&config_paths) {
+                if let Ok(c) = load_configs_with(&config_paths, ahk_lenient) {
                     println!("Reloading Config");
                     config = c;
+                    dispatcher.set_injector(WaylandTextInjector::new(
+                        &config.clipboard_copy_cmd,
+                        &config.clipboard_paste_cmd,
+                    ));
                 }
             }
         }
@@ -253,6 +386,8 @@ fn select_readable<'a>(
     devices: impl Iterator<Item = &'a InputDevice>,
     watchers: &[&Inotify],
     timer_fd: RawFd,
+    // Wake up after this long even without input, e.g. to re-check a pending WinWait
+    timeout: Option<Duration>,
 ) -> anyhow::Result<FdSet> {
     let mut read_fds = FdSet::new();
     read_fds.insert(timer_fd);
@@ -262,29 +397,18 @@ fn select_readable<'a>(
     for inotify in watchers {
         read_fds.insert(inotify.as_raw_fd());
     }
-    select(None, &mut read_fds, None, None, None)?;
+    let mut timeout = timeout.map(|timeout| TimeVal::microseconds(timeout.as_micros() as i64));
+    select(None, &mut read_fds, None, None, timeout.as_mut())?;
     Ok(read_fds)
 }
 
-// Return false when a removed device is found.
-fn handle_input_events(
-    input_device: &mut InputDevice,
-    handler: &mut EventHandler,
-    dispatcher: &mut ActionDispatcher,
-    config: &mut Config,
-) -> anyhow::Result<bool> {
-    let mut device_exists = true;
-    let events = match input_device.fetch_events().map_err(|e| (e.raw_os_error(), e)) {
-        Err((Some(ENODEV), _)) => {
-            device_exists = false;
-            Ok(Vec::new())
-        }
+// Return None when the device was removed.
+fn fetch_input_events(input_device: &mut InputDevice) -> anyhow::Result<Option<Vec<InputEvent>>> {
+    match input_device.fetch_events().map_err(|e| (e.raw_os_error(), e)) {
+        Err((Some(ENODEV), _)) => Ok(None),
         Err((_, error)) => Err(error).context("Error fetching input events"),
-        Ok(events) => Ok(events.collect()),
-    }?;
-    let input_events = events.iter().map(|e| Event::new(input_device.to_info(), *e)).collect();
-    handle_events(handler, dispatcher, config, input_events)?;
-    Ok(device_exists)
+        Ok(events) => Ok(Some(events.collect())),
+    }
 }
 
 // Handle an Event with EventHandler, and dispatch Actions with ActionDispatcher
@@ -293,10 +417,14 @@ fn handle_events(
     dispatcher: &mut ActionDispatcher,
     config: &mut Config,
     events: Vec<Event>,
+    action_log: &mut Option<File>,
 ) -> anyhow::Result<()> {
     let actions = handler
         .on_events(&events, config)
         .map_err(|e| anyhow!("Failed handling {events:?}:\n  {e:?}"))?;
+    if let Some(action_log) = action_log {
+        write_actions(action_log, &actions).context("Failed to write to --print-actions")?;
+    }
     for action in actions {
         dispatcher.on_action(action)?;
     }
@@ -309,12 +437,13 @@ fn handle_device_changes(
     device_filter: &[String],
     ignore_filter: &[String],
     mouse: bool,
+    grab_mode: GrabMode,
 ) -> anyhow::Result<()> {
     input_devices.extend(events.into_iter().filter_map(|event| {
         event.name.and_then(|name| {
             let path = PathBuf::from("/dev/input/").join(name);
             let mut device = InputDevice::try_from(path).ok()?;
-            if device.is_input_device(device_filter, ignore_filter, mouse) && device.grab() {
+            if device.is_input_device(device_filter, ignore_filter, mouse) && device.grab_for(grab_mode) {
                 device.print();
                 Some(device.into())
             } else {
@@ -325,17 +454,14 @@ fn handle_device_changes(
     Ok(())
 }
 
+// False if the config changed; other events may still be devices to add
 fn handle_config_changes(
-    events: Vec<InotifyEvent>,
-    input_devices: &mut HashMap<PathBuf, InputDevice>,
-    device_filter: &[String],
-    ignore_filter: &[String],
-    mouse: bool,
+    events: &[InotifyEvent],
     config_paths: &Vec<PathBuf>,
     inotify: Inotify,
 ) -> anyhow::Result<bool> {
     //Re-add AddWatchFlags if config file has been deleted then recreated or overwritten by renaming another file to its own name
-    for event in &events {
+    for event in events {
         if event
             .mask
             .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO)
@@ -347,7 +473,7 @@ fn handle_config_changes(
             }
         }
     }
-    for event in &events {
+    for event in events {
         match (event.mask, &event.name) {
             // Dir events
             (_, Some(name))
@@ -363,17 +489,5 @@ fn handle_config_changes(
             _ => (),
         }
     }
-    input_devices.extend(events.into_iter().filter_map(|event| {
-        event.name.and_then(|name| {
-            let path = PathBuf::from("/dev/input/").join(name);
-            let mut device = InputDevice::try_from(path).ok()?;
-            if device.is_input_device(device_filter, ignore_filter, mouse) && device.grab() {
-                device.print();
-                Some(device.into())
-            } else {
-                None
-            }
-        })
-    }));
     Ok(true)
 }
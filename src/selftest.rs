@@ -0,0 +1,131 @@
+use crate::device::output_device;
+use crate::event::{PRESS, RELEASE};
+use anyhow::{anyhow, bail, Context};
+use evdev::uinput::VirtualDevice;
+use evdev::{Device, EventType, InputEvent, KeyCode as Key};
+use std::io;
+use std::time::{Duration, Instant};
+
+// Nothing is bound to it by default, and the grab keeps it away from the compositor anyway
+const SELFTEST_KEY: Key = Key::KEY_F24;
+const SELFTEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An output device whose emitted events can be read back, so the self-test can
+/// run against a fake in tests.
+pub trait LoopbackDevice {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()>;
+    // Events that arrived since the last call, without blocking
+    fn read_events(&mut self) -> io::Result<Vec<InputEvent>>;
+}
+
+/// The real uinput device, with its event node opened and grabbed.
+pub struct UinputLoopback {
+    output: VirtualDevice,
+    input: Device,
+}
+
+impl UinputLoopback {
+    pub fn new() -> anyhow::Result<Self> {
+        let mut output = output_device(None, false, 0x1234, 0x5678)
+            .map_err(|e| anyhow!("Failed to create a uinput device: {e}"))?;
+        let path = output
+            .enumerate_dev_nodes_blocking()?
+            .next()
+            .ok_or_else(|| anyhow!("The uinput device has no event node under /dev/input"))??;
+        let mut input = Device::open(&path)
+            .with_context(|| format!("Failed to open {} (is it readable by this user?)", path.display()))?;
+        input.grab().with_context(|| format!("Failed to grab {}", path.display()))?;
+        input.set_nonblocking(true)?;
+        Ok(UinputLoopback { output, input })
+    }
+}
+
+impl LoopbackDevice for UinputLoopback {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        self.output.emit(events)
+    }
+
+    fn read_events(&mut self) -> io::Result<Vec<InputEvent>> {
+        match self.input.fetch_events() {
+            Ok(events) => Ok(events.collect()),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(vec![]),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Emits a press and release of an unused key and waits for both to come back.
+pub fn run_selftest(device: &mut dyn LoopbackDevice, timeout: Duration) -> anyhow::Result<()> {
+    let key_event = |value| InputEvent::new(EventType::KEY.0, SELFTEST_KEY.code(), value);
+    device
+        .emit(&[key_event(PRESS), key_event(RELEASE)])
+        .context("Failed to emit a key event through uinput")?;
+
+    let deadline = Instant::now() + timeout;
+    let (mut pressed, mut released) = (false, false);
+    while !(pressed && released) {
+        for event in device.read_events().context("Failed to read back from the uinput device")? {
+            if event.event_type() == EventType::KEY && event.code() == SELFTEST_KEY.code() {
+                pressed |= event.value() == PRESS;
+                released |= event.value() == RELEASE;
+            }
+        }
+        if Instant::now() >= deadline {
+            bail!("The emitted {SELFTEST_KEY:?} did not come back within {timeout:?}");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+/// `--selftest`: checks that a virtual device can be created and its events read back.
+pub fn selftest() -> anyhow::Result<()> {
+    let mut device = UinputLoopback::new()?;
+    run_selftest(&mut device, SELFTEST_TIMEOUT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_selftest, LoopbackDevice};
+    use evdev::InputEvent;
+    use std::io;
+    use std::time::Duration;
+
+    // Echoes emitted events back, or drops them when `broken`
+    struct FakeLoopback {
+        broken: bool,
+        pending: Vec<InputEvent>,
+    }
+
+    impl LoopbackDevice for FakeLoopback {
+        fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+            if !self.broken {
+                self.pending.extend_from_slice(events);
+            }
+            Ok(())
+        }
+
+        fn read_events(&mut self) -> io::Result<Vec<InputEvent>> {
+            Ok(std::mem::take(&mut self.pending))
+        }
+    }
+
+    #[test]
+    fn test_selftest_round_trip() {
+        let mut device = FakeLoopback {
+            broken: false,
+            pending: vec![],
+        };
+        run_selftest(&mut device, Duration::from_millis(100)).unwrap();
+    }
+
+    #[test]
+    fn test_selftest_reports_lost_events() {
+        let mut device = FakeLoopback {
+            broken: true,
+            pending: vec![],
+        };
+        let error = run_selftest(&mut device, Duration::from_millis(50)).unwrap_err();
+        assert!(error.to_string().contains("did not come back"));
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{bail, Context};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+// How long Force waits for the running instance to exit
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// AHK's #SingleInstance. Two instances would both grab the devices and fight over the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SingleInstance {
+    // Stop the running instance and take its place
+    Force,
+    // Refuse to start while another instance runs. Also AHK's Prompt, as there's no dialog to ask with.
+    Ignore,
+    Off,
+}
+
+// Held until the daemon exits; the kernel drops the lock with the file, even on a crash. The file
+// itself stays, since removing it would let an instance lock a file no later instance can see.
+pub struct InstanceLock {
+    _file: File,
+}
+
+// `$XDG_RUNTIME_DIR/ahk-wayland.lock`, or in the temp directory without a runtime directory
+pub fn lock_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("ahk-wayland.lock")
+}
+
+pub fn acquire(path: &Path, mode: SingleInstance) -> anyhow::Result<Option<InstanceLock>> {
+    if mode == SingleInstance::Off {
+        return Ok(None);
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    if !try_lock(&file)? {
+        let pid = read_pid(&mut file);
+        let running = pid.map_or("another instance".to_string(), |pid| format!("pid {pid}"));
+        if mode == SingleInstance::Ignore {
+            bail!("Already running as {running} (lock {})", path.display());
+        }
+        let Some(pid) = pid.filter(|&pid| pid != process::id()) else {
+            bail!("Can't replace {running}: its pid isn't in {}", path.display());
+        };
+        println!("Replacing the running instance (pid {pid})");
+        kill(Pid::from_raw(pid as i32), Signal::SIGTERM).with_context(|| format!("Failed to stop pid {pid}"))?;
+        let start = Instant::now();
+        while !try_lock(&file)? {
+            if start.elapsed() >= REPLACE_TIMEOUT {
+                bail!("pid {pid} didn't exit within {REPLACE_TIMEOUT:?}");
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    file.set_len(0)?;
+    file.rewind()?;
+    write!(file, "{}", process::id())?;
+    Ok(Some(InstanceLock { _file: file }))
+}
+
+// False when another process holds the lock
+fn try_lock(file: &File) -> anyhow::Result<bool> {
+    match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(()) => Ok(true),
+        Err(Errno::EWOULDBLOCK) => Ok(false),
+        Err(e) => Err(e).context("Failed to lock the instance file"),
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{acquire, SingleInstance};
+    use std::process;
+
+    #[test]
+    fn test_second_instance_detects_lock() {
+        let path = std::env::temp_dir().join(format!("ahk-wayland-test-{}.lock", process::id()));
+
+        let lock = acquire(&path, SingleInstance::Ignore).unwrap();
+        assert!(lock.is_some());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), process::id().to_string());
+
+        let error = acquire(&path, SingleInstance::Ignore).err().unwrap();
+        assert!(error.to_string().contains(&format!("Already running as pid {}", process::id())));
+        assert!(acquire(&path, SingleInstance::Off).unwrap().is_none());
+
+        drop(lock);
+        assert!(acquire(&path, SingleInstance::Ignore).unwrap().is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
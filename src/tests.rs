@@ -1,18 +1,22 @@
 use evdev::EventType;
 use evdev::InputEvent;
 use evdev::KeyCode as Key;
+use evdev::LedCode;
 use indoc::indoc;
+use lazy_static::lazy_static;
 use nix::sys::timerfd::{ClockId, TimerFd, TimerFlags};
-use std::path::Path;
-use std::time::Duration;
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 use crate::client::{Client, WMClient};
 use crate::device::InputDeviceInfo;
 use crate::{
     action::Action,
-    config::{keymap::build_keymap_table, Config},
-    event::{Event, KeyEvent, KeyValue, RelativeEvent},
+    config::{keymap::build_keymap_table, load_configs, Config, ConfigError},
+    event::{merge_by_time, Event, KeyEvent, KeyValue, RelativeEvent, PRESS, RELEASE},
     event_handler::EventHandler,
+    hotstring::HotstringMatcher,
 };
 
 /// There are a lot of features and some interact.
@@ -48,6 +52,27 @@ impl Client for StaticClient {
     fn current_application(&mut self) -> Option<String> {
         self.current_application.clone()
     }
+
+    // "instance.class", as X11 reports it
+    fn current_instance(&mut self) -> Option<String> {
+        let application = self.current_application.as_ref()?;
+        application.split_once('.').map(|(instance, _)| instance.to_string())
+    }
+}
+
+// No window manager to ask, for tests that don't depend on the active window
+pub struct NoWindowClient;
+
+impl Client for NoWindowClient {
+    fn supported(&mut self) -> bool {
+        false
+    }
+    fn current_window(&mut self) -> Option<String> {
+        None
+    }
+    fn current_application(&mut self) -> Option<String> {
+        None
+    }
 }
 
 pub fn get_input_device_info<'a>() -> InputDeviceInfo<'a> {
@@ -82,6 +107,22 @@ fn test_basic_modmap() {
     )
 }
 
+#[test]
+fn test_modmap_key_code() {
+    assert_actions(
+        indoc! {"
+        modmap:
+          - remap:
+              a: code:16
+        "},
+        vec![Event::key_press(Key::KEY_A), Event::key_release(Key::KEY_A)],
+        vec![
+            Action::KeyEvent(KeyEvent::new_with(16, PRESS)),
+            Action::KeyEvent(KeyEvent::new_with(16, RELEASE)),
+        ],
+    )
+}
+
 /* Table to see which scancodes/custom key events correspond to which relative events
     Original RELATIVE event | scancode | Custom keyname if                              | Info
                             |          | positive value (+)     | negative value (-)    |
@@ -167,6 +208,43 @@ fn test_mouse_movement_event_accumulation() {
     )
 }
 
+#[test]
+fn test_mouse_motion_order() {
+    let events = || vec![
+        Event::relative(_REL_X, 5),
+        Event::key_press(Key::KEY_A),
+        Event::key_press(Key::BTN_LEFT),
+        Event::relative(_REL_Y, 3),
+        Event::key_press(Key::KEY_B),
+    ];
+    let key = |key| Action::KeyEvent(KeyEvent::new(key, KeyValue::Press));
+    let motion = |code, value| Action::MouseMovementEventCollection(vec![RelativeEvent::new_with(code, value)]);
+
+    // The motion before the click still goes out before it
+    assert_actions(
+        "",
+        events(),
+        vec![
+            key(Key::KEY_A),
+            motion(_REL_X, 5),
+            key(Key::BTN_LEFT),
+            key(Key::KEY_B),
+            motion(_REL_Y, 3),
+        ],
+    );
+    assert_actions(
+        "mouse_motion_order: interleave",
+        events(),
+        vec![
+            motion(_REL_X, 5),
+            key(Key::KEY_A),
+            key(Key::BTN_LEFT),
+            motion(_REL_Y, 3),
+            key(Key::KEY_B),
+        ],
+    );
+}
+
 #[test]
 #[ignore]
 // The OS interprets a REL_X event¹ combined with a REL_Y event² differently if they are separated by synchronization event.
@@ -208,9 +286,10 @@ fn test_mouse_movement_event_accumulation() {
 //
 fn test_cursor_behavior_1() {
     use crate::device::InputDevice;
-    use crate::device::{get_input_devices, output_device};
+    use crate::device::{get_input_devices, output_device, GrabMode};
     // Setup to be able to send events
-    let mut input_devices = match get_input_devices(&[String::from("/dev/input/event25")], &[], true, false) {
+    let device = [String::from("/dev/input/event25")];
+    let mut input_devices = match get_input_devices(&device, &[], true, false, GrabMode::Exclusive) {
         Ok(input_devices) => input_devices,
         Err(e) => panic!("Failed to prepare input devices: {e}"),
     };
@@ -249,9 +328,10 @@ fn test_cursor_behavior_1() {
 // Please refer to the comment above test_cursor_behavior_1 for information on how to run these tests.
 fn test_cursor_behavior_2() {
     use crate::device::InputDevice;
-    use crate::device::{get_input_devices, output_device};
+    use crate::device::{get_input_devices, output_device, GrabMode};
     // Setup to be able to send events
-    let mut input_devices = match get_input_devices(&[String::from("/dev/input/event25")], &[], true, false) {
+    let device = [String::from("/dev/input/event25")];
+    let mut input_devices = match get_input_devices(&device, &[], true, false, GrabMode::Exclusive) {
         Ok(input_devices) => input_devices,
         Err(e) => panic!("Failed to prepare input devices: {e}"),
     };
@@ -520,6 +600,138 @@ fn test_keymap_emit_is_not_used_in_subsequent_remaps() {
     )
 }
 
+#[test]
+fn test_keymap_to_mouse_button() {
+    assert_actions(
+        indoc! {"
+        keymap:
+            - remap:
+                f13: BTN_LEFT
+        "},
+        vec![Event::key_press(Key::KEY_F13), Event::key_release(Key::KEY_F13)],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::BTN_LEFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::BTN_LEFT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F13, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_keymap_scroll() {
+    assert_actions(
+        indoc! {"
+        keymap:
+            - remap:
+                f13: { scroll: { direction: down, amount: 2 } }
+                f14: { scroll: { direction: right } }
+        "},
+        vec![Event::key_press(Key::KEY_F13), Event::key_press(Key::KEY_F14)],
+        vec![
+            Action::RelativeEvent(RelativeEvent::new_with(_REL_WHEEL, -2)),
+            Action::RelativeEvent(RelativeEvent::new_with(_REL_HWHEEL, 1)),
+        ],
+    )
+}
+
+#[test]
+fn test_injected_events_pass_through() {
+    let device = InputDeviceInfo {
+        name: crate::device::InputDevice::current_name(),
+        ..get_input_device_info()
+    };
+    assert_actions(
+        indoc! {"
+        modmap:
+          - remap:
+              a: b
+        "},
+        vec![
+            Event::KeyEvent(device, KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+        ],
+    )
+}
+
+#[test]
+fn test_keymap_raw_event() {
+    assert_actions(
+        indoc! {"
+        keymap:
+            - remap:
+                f13: { raw_event: { event_type: led, code: LED_CAPSL, value: 1 } }
+                f14: { raw_event: { event_type: key, code: a, value: 1 } }
+        "},
+        vec![Event::key_press(Key::KEY_F13), Event::key_press(Key::KEY_F14)],
+        vec![
+            Action::FeedbackEvent(InputEvent::new(EventType::LED.0, LedCode::LED_CAPSL.0, 1)),
+            Action::InputEvent(InputEvent::new(EventType::KEY.0, Key::KEY_A.code(), 1)),
+        ],
+    )
+}
+
+#[test]
+fn test_keymap_sequence() {
+    assert_actions(
+        indoc! {"
+        keymap:
+            - remap:
+                f13: { sequence: [alt-f, { sleep: 300 }, { press: o }, { release: o }] }
+        "},
+        vec![Event::key_press(Key::KEY_F13)],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+            Action::Delay(Duration::from_millis(300)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_O, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_O, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_keymap_repeat_while_held() {
+    let config = parse_config(indoc! {"
+        keymap:
+            - repeat_while_held: true
+              repeat_interval_ms: 20
+              remap:
+                f13: a
+        "});
+    let mut event_handler = get_event_handler(&config, None);
+    event_handler.set_clock(fake_clock);
+    let a = vec![
+        Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+        Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        Action::Delay(Duration::ZERO),
+        Action::Delay(Duration::ZERO),
+    ];
+    let mut on_events = |events| format!("{:?}", event_handler.on_events(&events, &config).unwrap());
+
+    assert_eq!(on_events(vec![Event::key_press(Key::KEY_F13)]), format!("{a:?}"));
+    // The keyboard's own repeat doesn't dispatch again, the interval does
+    assert_eq!(on_events(vec![Event::key_repeat(Key::KEY_F13)]), "[]");
+    advance_fake_clock(19);
+    assert_eq!(on_events(vec![]), "[]");
+    for _ in 0..2 {
+        advance_fake_clock(20);
+        assert_eq!(on_events(vec![]), format!("{a:?}"));
+    }
+    on_events(vec![Event::key_release(Key::KEY_F13)]);
+    advance_fake_clock(20);
+    assert_eq!(on_events(vec![]), "[]");
+}
+
 #[test]
 fn test_application_override() {
     let config = indoc! {"
@@ -570,6 +782,343 @@ fn test_application_override() {
     );
 }
 
+#[test]
+fn test_instance_override() {
+    let config = indoc! {"
+        keymap:
+          - name: chrome app
+            instance:
+              only: [crx_abc]
+            remap:
+              a: C-c
+
+          - name: chrome
+            application:
+              only: [Google-chrome]
+            remap:
+              a: C-b
+    "};
+    let ctrl = |key| {
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ]
+    };
+
+    // Both windows have the Google-chrome class, only the instance tells them apart
+    assert_actions_with_current_application(
+        config,
+        Some(String::from("crx_abc.Google-chrome")),
+        vec![Event::key_press(Key::KEY_A)],
+        ctrl(Key::KEY_C),
+    );
+    assert_actions_with_current_application(
+        config,
+        Some(String::from("crx_xyz.Google-chrome")),
+        vec![Event::key_press(Key::KEY_A)],
+        ctrl(Key::KEY_B),
+    );
+}
+
+#[test]
+fn test_keymap_atomic() {
+    let key = |key, value| KeyEvent::new(key, value);
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              F13: { atomic: C-M-t }
+        "},
+        vec![Event::key_press(Key::KEY_F13)],
+        vec![
+            Action::KeyEventBatch(vec![
+                key(Key::KEY_LEFTCTRL, KeyValue::Press),
+                key(Key::KEY_LEFTALT, KeyValue::Press),
+                key(Key::KEY_T, KeyValue::Press),
+            ]),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEventBatch(vec![
+                key(Key::KEY_T, KeyValue::Release),
+                key(Key::KEY_LEFTALT, KeyValue::Release),
+                key(Key::KEY_LEFTCTRL, KeyValue::Release),
+            ]),
+        ],
+    )
+}
+
+#[test]
+fn test_keymap_conditional() {
+    let config = indoc! {"
+        keymap:
+          - remap:
+              f13:
+                conditional:
+                  application:
+                    only: firefox
+                  then: a
+                  otherwise: [b, c]
+    "};
+    let tap = |key| {
+        vec![
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
+
+    assert_actions_with_current_application(
+        config,
+        Some(String::from("firefox")),
+        vec![Event::key_press(Key::KEY_F13)],
+        tap(Key::KEY_A),
+    );
+    assert_actions_with_current_application(
+        config,
+        Some(String::from("kitty")),
+        vec![Event::key_press(Key::KEY_F13)],
+        tap(Key::KEY_B).into_iter().chain(tap(Key::KEY_C)).collect(),
+    );
+}
+
+#[test]
+fn test_ahk_shifted_symbol_hotkey() {
+    // `!::` is bound as Shift+1, the keys that type it
+    let config = load_temp_config("symbol", &[("config.ahk", "!::Run(\"true\")\n")]).unwrap();
+    let mut event_handler = get_event_handler(&config, None);
+
+    let actions = event_handler
+        .on_events(&vec![Event::key_press(Key::KEY_LEFTSHIFT), Event::key_press(Key::KEY_1)], &config)
+        .unwrap();
+    let expected = vec![
+        Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+        Action::Command(vec!["/bin/sh".to_string(), "-c".to_string(), "true".to_string()]),
+    ];
+    assert_eq!(format!("{expected:?}"), format!("{actions:?}"));
+}
+
+#[test]
+fn test_hotstring_keeps_end_char() {
+    let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+    assert_actions(
+        indoc! {"
+        hotstrings:
+          - trigger: btw
+            replacement: by the way
+        "},
+        vec![
+            Event::key_press(Key::KEY_B),
+            Event::key_press(Key::KEY_T),
+            Event::key_press(Key::KEY_W),
+            Event::key_press(Key::KEY_DOT),
+        ],
+        vec![
+            key(Key::KEY_B, KeyValue::Press),
+            key(Key::KEY_T, KeyValue::Press),
+            key(Key::KEY_W, KeyValue::Press),
+            Action::TextExpansion {
+                trigger_len: 4,
+                replacement: "by the way.".to_string(),
+            },
+        ],
+    )
+}
+
+#[test]
+fn test_hotstring_reset_keys() {
+    let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+    let events = || {
+        [Key::KEY_B, Key::KEY_T, Key::KEY_LEFT, Key::KEY_RIGHT, Key::KEY_W, Key::KEY_DOT]
+            .into_iter()
+            .map(Event::key_press)
+            .collect()
+    };
+    let typed = || {
+        [Key::KEY_B, Key::KEY_T, Key::KEY_LEFT, Key::KEY_RIGHT, Key::KEY_W]
+            .into_iter()
+            .map(|k| key(k, KeyValue::Press))
+            .collect::<Vec<_>>()
+    };
+    let config = |reset_keys: &str| format!("hotstrings:\n  - trigger: btw\n    replacement: by the way\n{reset_keys}");
+
+    // Arrows reset the trigger by default
+    let mut unmatched = typed();
+    unmatched.push(key(Key::KEY_DOT, KeyValue::Press));
+    assert_actions(&config(""), events(), unmatched);
+
+    let mut matched = typed();
+    matched.push(Action::TextExpansion {
+        trigger_len: 4,
+        replacement: "by the way.".to_string(),
+    });
+    assert_actions(&config("hotstring_reset_keys: [Home, End]\n"), events(), matched);
+}
+
+#[test]
+fn test_hotstring_pending_fires_on_reset() {
+    let config = parse_config(indoc! {"
+        hotstrings:
+          - { trigger: ab, replacement: AB, immediate: true }
+          - { trigger: abcd, replacement: ABCD, immediate: true }
+        hotstring_idle_timeout_ms: 5000
+    "});
+    let mut event_handler = get_event_handler(&config, None);
+    event_handler.set_clock(fake_clock);
+    let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+    let mut on_keys = |keys: &[Key]| {
+        let events: Vec<Event> = keys.iter().map(|&k| Event::key_press(k)).collect();
+        format!("{:?}", event_handler.on_events(&events, &config).unwrap())
+    };
+    let expansion = |trigger_len, replacement: &str| Action::TextExpansion {
+        trigger_len,
+        replacement: replacement.to_string(),
+    };
+
+    // `ab` waits on `abcd`, and a reset key rules that out, along with the `c` typed since
+    assert_eq!(
+        on_keys(&[Key::KEY_A, Key::KEY_B, Key::KEY_C, Key::KEY_LEFT]),
+        format!(
+            "{:?}",
+            vec![
+                key(Key::KEY_A, KeyValue::Press),
+                key(Key::KEY_B, KeyValue::Press),
+                key(Key::KEY_C, KeyValue::Press),
+                expansion(4, "ABc"),
+                key(Key::KEY_LEFT, KeyValue::Press),
+            ]
+        )
+    );
+    // So does a pause
+    on_keys(&[Key::KEY_A, Key::KEY_B]);
+    advance_fake_clock(5000);
+    assert_eq!(
+        on_keys(&[Key::KEY_X]),
+        format!("{:?}", vec![expansion(3, "AB"), key(Key::KEY_X, KeyValue::Press)])
+    );
+}
+
+#[test]
+fn test_hotstring_passthrough_key() {
+    let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+    assert_actions(
+        indoc! {"
+        hotstrings:
+          - trigger: btw
+            replacement: by the way
+        passthrough_keys: [Enter]
+        "},
+        vec![
+            Event::key_press(Key::KEY_B),
+            Event::key_press(Key::KEY_T),
+            Event::key_press(Key::KEY_W),
+            Event::key_press(Key::KEY_ENTER),
+        ],
+        vec![
+            key(Key::KEY_B, KeyValue::Press),
+            key(Key::KEY_T, KeyValue::Press),
+            key(Key::KEY_W, KeyValue::Press),
+            Action::TextExpansion {
+                trigger_len: 4,
+                replacement: "by the way".to_string(),
+            },
+            key(Key::KEY_ENTER, KeyValue::Press),
+        ],
+    )
+}
+
+#[test]
+fn test_hotstring_type_mode_newline() {
+    let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+    let tap = |k| [key(k, KeyValue::Press), key(k, KeyValue::Release)];
+    let mut expected = vec![key(Key::KEY_S, KeyValue::Press), key(Key::KEY_G, KeyValue::Press)];
+    let typed = [Key::KEY_H, Key::KEY_I, Key::KEY_ENTER, Key::KEY_B, Key::KEY_Y, Key::KEY_E];
+    expected.extend([Key::KEY_BACKSPACE; 3].into_iter().chain(typed).flat_map(tap));
+    assert_actions(
+        indoc! {"
+        hotstrings:
+          - trigger: sg
+            replacement: \"hi\\r\\nbye\"
+            trailing_char: none
+        hotstring_mode: type
+        "},
+        vec![Event::key_press(Key::KEY_S), Event::key_press(Key::KEY_G), Event::key_press(Key::KEY_SPACE)],
+        expected,
+    )
+}
+
+#[test]
+fn test_hotstring_resets_after_idle_timeout() {
+    let config = parse_config(indoc! {"
+        hotstrings:
+          - trigger: btw
+            replacement: by the way
+        hotstring_idle_timeout_ms: 5000
+    "});
+    let mut event_handler = get_event_handler(&config, None);
+    event_handler.set_clock(fake_clock);
+    let key = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+
+    let actions = event_handler.on_events(&vec![Event::key_press(Key::KEY_B)], &config).unwrap();
+    assert_eq!(format!("{actions:?}"), format!("{:?}", vec![key(Key::KEY_B, KeyValue::Press)]));
+
+    advance_fake_clock(5000);
+    let events = vec![Event::key_press(Key::KEY_T), Event::key_press(Key::KEY_W), Event::key_press(Key::KEY_DOT)];
+    let actions = event_handler.on_events(&events, &config).unwrap();
+    assert_eq!(
+        format!("{actions:?}"),
+        format!(
+            "{:?}",
+            vec![
+                key(Key::KEY_T, KeyValue::Press),
+                key(Key::KEY_W, KeyValue::Press),
+                key(Key::KEY_DOT, KeyValue::Press),
+            ]
+        )
+    );
+}
+
+#[test]
+fn test_events_from_two_devices_in_time_order() {
+    let keypad = InputDeviceInfo {
+        name: "Some Keypad",
+        path: Path::new("/dev/input/event1"),
+        vendor: 0x1234,
+        product: 0x9abc,
+    };
+    let at = |ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms);
+    let shift = |value| KeyEvent::new(Key::KEY_LEFTSHIFT, value);
+    // The keypad is read first, but its key was pressed while Shift on the keyboard was held
+    let events = merge_by_time(vec![
+        vec![Event::KeyEvent(keypad, KeyEvent::new(Key::KEY_A, KeyValue::Press).at(at(20)))],
+        vec![
+            Event::KeyEvent(get_input_device_info(), shift(KeyValue::Press).at(at(10))),
+            Event::KeyEvent(get_input_device_info(), shift(KeyValue::Release).at(at(30))),
+        ],
+    ]);
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              Shift-a: b
+        "},
+        events,
+        vec![
+            Action::KeyEvent(shift(KeyValue::Press)),
+            Action::KeyEvent(shift(KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(shift(KeyValue::Press)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(shift(KeyValue::Release)),
+        ],
+    )
+}
+
 #[test]
 fn test_device_override() {
     let config = indoc! {"
@@ -880,6 +1429,264 @@ fn test_keymap_with_modifier_alone_is_not_supported() {
     )
 }
 
+#[test]
+fn test_toggle_suspend() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              a: b
+              F12: { toggle_suspend: true }
+        "},
+        vec![
+            Event::key_press(Key::KEY_F12),
+            Event::key_release(Key::KEY_F12),
+            Event::key_press(Key::KEY_A),
+            Event::key_release(Key::KEY_A),
+            Event::key_press(Key::KEY_F12),
+            Event::key_release(Key::KEY_F12),
+            Event::key_press(Key::KEY_A),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ],
+    )
+}
+
+#[test]
+fn test_grab_mode_monitor() {
+    assert_actions(
+        indoc! {"
+        grab_mode: monitor
+        hotstrings:
+          - trigger: btw
+            replacement: by the way
+        keymap:
+          - remap:
+              a: b
+              F1: { launch: [\"true\"] }
+        "},
+        vec![
+            Event::key_press(Key::KEY_A),
+            Event::key_release(Key::KEY_A),
+            Event::key_press(Key::KEY_B),
+            Event::key_press(Key::KEY_T),
+            Event::key_press(Key::KEY_W),
+            Event::key_press(Key::KEY_DOT),
+            Event::relative(_REL_WHEEL, _POSITIVE),
+            Event::key_press(Key::KEY_F1),
+        ],
+        // Hotkeys' commands still run, but no event is sent or held back
+        vec![Action::Command(vec!["true".to_string()])],
+    )
+}
+
+#[test]
+fn test_toggle_wheel() {
+    let wheel = || Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_WHEEL, _POSITIVE));
+    let scrolled = || Action::RelativeEvent(RelativeEvent::new_with(_REL_WHEEL, _POSITIVE));
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              F11: { set_wheel_enabled: false }
+              F12: { toggle_wheel: true }
+        "},
+        vec![
+            wheel(),
+            Event::key_press(Key::KEY_F11),
+            wheel(),
+            Event::key_press(Key::KEY_F12),
+            wheel(),
+            Event::key_press(Key::KEY_F12),
+            wheel(),
+        ],
+        // Only the first and last scroll get through
+        vec![scrolled(), scrolled()],
+    )
+}
+
+#[test]
+fn test_keymap_numlock() {
+    let numlock = |value| InputEvent::new(EventType::LED.0, LedCode::LED_NUML.0, value);
+    let end = || {
+        [
+            Action::KeyEvent(KeyEvent::new(Key::KEY_END, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_END, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
+    let mut expected = vec![];
+    expected.extend(end());
+    expected.push(Action::InputEvent(numlock(1)));
+    expected.push(Action::KeyEvent(KeyEvent::new(Key::KEY_KP1, KeyValue::Press)));
+    expected.push(Action::InputEvent(numlock(0)));
+    expected.extend(end());
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              KP1: End
+            numlock: off
+        "},
+        vec![
+            Event::key_press(Key::KEY_KP1),
+            Event::OtherEvents(numlock(1)),
+            Event::key_press(Key::KEY_KP1),
+            Event::OtherEvents(numlock(0)),
+            Event::key_press(Key::KEY_KP1),
+        ],
+        expected,
+    )
+}
+
+#[test]
+fn test_keymap_numlock_lit_before_start() {
+    let config = parse_config(indoc! {"
+        keymap:
+          - remap:
+              KP1: End
+            numlock: off
+    "});
+    let mut event_handler = get_event_handler(&config, None);
+    event_handler.set_lit_leds([LedCode::LED_NUML]);
+    let actions = event_handler.on_events(&vec![Event::key_press(Key::KEY_KP1)], &config).unwrap();
+    assert_eq!(
+        format!("{actions:?}"),
+        format!("{:?}", vec![Action::KeyEvent(KeyEvent::new(Key::KEY_KP1, KeyValue::Press))])
+    );
+}
+
+#[test]
+fn test_hotkey_reentrancy_guard() {
+    assert_actions(
+        indoc! {"
+        hotkey_reentrancy_guard: true
+        keymap:
+          - remap:
+              a: [b, { sleep: 1000 }]
+        "},
+        vec![
+            Event::key_press(Key::KEY_A),
+            Event::key_release(Key::KEY_A),
+            Event::key_press(Key::KEY_A),
+            Event::key_release(Key::KEY_A),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_millis(1000)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_hotkey_reentrancy_guard_expires() {
+    let config = parse_config(indoc! {"
+        hotkey_reentrancy_guard: true
+        keymap:
+          - remap:
+              a: [b, { sleep: 1000 }]
+    "});
+    let mut handler = get_event_handler(&config, None);
+    handler.set_clock(fake_clock);
+    let mut press_a = || {
+        let events = vec![Event::key_press(Key::KEY_A), Event::key_release(Key::KEY_A)];
+        format!("{:?}", handler.on_events(&events, &config).unwrap()).contains("KEY_B")
+    };
+
+    assert!(press_a());
+    // The guard goes by the handler's clock, like the sleep would
+    advance_fake_clock(999);
+    assert!(!press_a());
+    advance_fake_clock(1);
+    assert!(press_a());
+}
+
+#[test]
+fn test_hotkey_reentrancy_guard_disabled() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              a: [b, { sleep: 1000 }]
+        "},
+        vec![Event::key_press(Key::KEY_A), Event::key_press(Key::KEY_A)],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_millis(1000)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_millis(1000)),
+        ],
+    )
+}
+
+#[test]
+fn test_keymap_predicate() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - predicate: [sh, -c, 'exit 1']
+            remap:
+              a: b
+          - predicate: [sh, -c, 'exit 0']
+            remap:
+              a: c
+          - remap:
+              a: d
+        "},
+        vec![Event::key_press(Key::KEY_A)],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ],
+    )
+}
+
+#[test]
+fn test_plain_keymap_fast_path_matches_general_path() {
+    let events = vec![Event::key_press(Key::KEY_A), Event::key_release(Key::KEY_A)];
+    let fast = parse_config(indoc! {"
+    keymap:
+      - remap:
+          a: [b, { sleep: 10 }]
+    "});
+    // The extra mode-restricted entry forces the general two-pass lookup
+    let general = parse_config(indoc! {"
+    keymap:
+      - mode: other
+        remap:
+          a: c
+      - remap:
+          a: [b, { sleep: 10 }]
+    "});
+
+    let fast_actions = get_event_handler(&fast, None).on_events(&events, &fast).unwrap();
+    let general_actions = get_event_handler(&general, None).on_events(&events, &general).unwrap();
+
+    assert_eq!(format!("{fast_actions:?}"), format!("{general_actions:?}"));
+}
+
 pub fn assert_actions(config_yaml: &str, events: Vec<Event>, actions: Vec<Action>) {
     assert_actions_with_current_application(config_yaml, None, events, actions);
 }
@@ -890,18 +1697,90 @@ pub fn assert_actions_with_current_application(
     events: Vec<Event>,
     actions: Vec<Action>,
 ) {
-    let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty()).unwrap();
+    let config = parse_config(config_yaml);
+    let mut event_handler = get_event_handler(&config, current_application);
+    let mut actual: Vec<Action> = vec![];
+
+    actual.append(&mut event_handler.on_events(&events, &config).unwrap());
+
+    assert_eq!(format!("{actions:?}"), format!("{:?}", actual));
+}
+
+pub fn parse_config(config_yaml: &str) -> Config {
     let mut config: Config = serde_yaml::from_str(config_yaml).unwrap();
     config.keymap_table = build_keymap_table(&config.keymap);
-    let mut event_handler = EventHandler::new(
+    if !config.hotstrings.is_empty() {
+        config.hotstring_matcher = Some(HotstringMatcher::new(config.hotstrings.clone()));
+    }
+    config
+}
+
+lazy_static! {
+    static ref CLOCK_START: Instant = Instant::now();
+}
+thread_local! {
+    // Per test, as each runs on its own thread
+    static CLOCK_ELAPSED_MS: Cell<u64> = const { Cell::new(0) };
+}
+
+// A clock for set_clock that only moves when a test advances it
+pub fn fake_clock() -> Instant {
+    *CLOCK_START + Duration::from_millis(CLOCK_ELAPSED_MS.get())
+}
+
+pub fn advance_fake_clock(ms: u64) {
+    CLOCK_ELAPSED_MS.set(CLOCK_ELAPSED_MS.get() + ms);
+}
+
+// Config files in a directory of their own under the temp dir, removed when this is dropped,
+// so a failed assertion doesn't leave them behind
+pub struct TempConfigDir {
+    pub path: PathBuf,
+}
+
+impl TempConfigDir {
+    pub fn new(name: &str, files: &[(&str, &str)]) -> Self {
+        let dir = TempConfigDir {
+            path: std::env::temp_dir().join(format!("ahk-wayland-{name}-{}", std::process::id())),
+        };
+        for (file, contents) in files {
+            dir.write(file, contents);
+        }
+        dir
+    }
+
+    pub fn join(&self, file: &str) -> PathBuf {
+        self.path.join(file)
+    }
+
+    pub fn write(&self, file: &str, contents: &str) {
+        let path = self.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    pub fn load(&self, file: &str) -> Result<Config, ConfigError> {
+        load_configs(&[self.join(file)])
+    }
+}
+
+impl Drop for TempConfigDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+// Loads the first of `files`, which may include the others
+pub fn load_temp_config(name: &str, files: &[(&str, &str)]) -> Result<Config, ConfigError> {
+    TempConfigDir::new(name, files).load(files[0].0)
+}
+
+pub fn get_event_handler(config: &Config, current_application: Option<String>) -> EventHandler {
+    let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty()).unwrap();
+    EventHandler::new(
         timer,
         &config.default_mode,
         Duration::from_micros(0),
         WMClient::new("static", Box::new(StaticClient { current_application })),
-    );
-    let mut actual: Vec<Action> = vec![];
-
-    actual.append(&mut event_handler.on_events(&events, &config).unwrap());
-
-    assert_eq!(format!("{actions:?}"), format!("{:?}", actual));
+    )
 }
@@ -49,3 +49,59 @@ fn test_emacs_like() {
         ],
     )
 }
+
+#[test]
+fn test_mark_modifier() {
+    assert_actions(
+        indoc! {"
+        mark_modifier: Alt
+        keymap:
+            - remap:
+                F1: { set_mark: true }
+                F2: { with_mark: right }
+        "},
+        vec![
+            Event::key_press(Key::KEY_F2),
+            Event::key_press(Key::KEY_F1),
+            Event::key_press(Key::KEY_F2),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            // The mark adds Alt instead of Shift
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_mark_keeps_held_shift() {
+    assert_actions(
+        indoc! {"
+        keymap:
+            - remap:
+                F1: { set_mark: true }
+                Shift-F2: { with_mark: right }
+        "},
+        vec![
+            Event::key_press(Key::KEY_F1),
+            Event::key_press(Key::KEY_LEFTSHIFT),
+            Event::key_press(Key::KEY_F2),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            // Shift stays down rather than being released around the key
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ],
+    )
+}
@@ -1,6 +1,6 @@
 use crate::action::Action;
 use crate::event::{Event, KeyEvent, KeyValue};
-use crate::tests::assert_actions;
+use crate::tests::{assert_actions, get_event_handler, parse_config};
 use evdev::KeyCode as Key;
 use indoc::indoc;
 use std::time::Duration;
@@ -43,3 +43,27 @@ fn test_custom_start_mode() {
         ],
     )
 }
+
+#[test]
+fn test_mode_info_follows_set_mode() {
+    let config = parse_config(indoc! {"
+    default_mode: insert
+    modes:
+      normal:
+        display_name: NORMAL
+        color: '#ff0000'
+    keymap:
+        - mode: insert
+          remap:
+            Esc: { set_mode: normal }
+    "});
+    let mut event_handler = get_event_handler(&config, None);
+    assert_eq!(event_handler.mode_info(), "insert");
+
+    event_handler.on_events(&vec![Event::key_press(Key::KEY_ESC)], &config).unwrap();
+
+    assert_eq!(event_handler.mode_info(), "normal");
+    let metadata = config.mode_metadata(event_handler.mode_info()).unwrap();
+    assert_eq!(metadata.display_name.as_deref(), Some("NORMAL"));
+    assert_eq!(metadata.color.as_deref(), Some("#ff0000"));
+}
@@ -175,3 +175,29 @@ fn test_modmap_emit_is_not_used_in_subsequent_remaps() {
         ],
     )
 }
+
+#[test]
+fn test_modmap_mode() {
+    assert_actions(
+        indoc! {"
+        modmap:
+          - remap:
+              A: B
+            mode: [vim, visual]
+        keymap:
+          - remap:
+              Esc: { set_mode: vim }
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_ESC, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+        ],
+    );
+}
@@ -1,7 +1,7 @@
 use crate::action::Action;
 use crate::event::Event;
 use crate::event::{KeyEvent, KeyValue};
-use crate::tests::{assert_actions, get_input_device_info};
+use crate::tests::{assert_actions, get_event_handler, get_input_device_info, load_temp_config};
 use evdev::KeyCode as Key;
 use indoc::indoc;
 use std::time::Duration;
@@ -355,3 +355,44 @@ fn test_modifiers_are_sorted_first_and_last_alone() {
         ],
     );
 }
+
+#[test]
+fn test_caps_escape_control() {
+    let config = load_temp_config("caps-tap", &[("config.yml", "caps_escape_control: true\n")]).unwrap();
+    let mut event_handler = get_event_handler(&config, None);
+    let mut on_events = |events: Vec<Event>| format!("{:?}", event_handler.on_events(&events, &config).unwrap());
+
+    // Tapped alone it's Escape
+    assert_eq!(
+        on_events(vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_CAPSLOCK, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_CAPSLOCK, KeyValue::Release)),
+        ]),
+        format!(
+            "{:?}",
+            vec![
+                Action::KeyEvent(KeyEvent::new(Key::KEY_ESC, KeyValue::Press)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_ESC, KeyValue::Release)),
+            ]
+        )
+    );
+
+    // Held with another key it's Control
+    assert_eq!(
+        on_events(vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_CAPSLOCK, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_CAPSLOCK, KeyValue::Release)),
+        ]),
+        format!(
+            "{:?}",
+            vec![
+                Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            ]
+        )
+    );
+}
@@ -0,0 +1,105 @@
+// Replays recorded evdev events through EventHandler and compares the actions with a fixture.
+//
+// Each scenario is a directory under tests/fixtures/replay with:
+//   config.yml   the config to load
+//   events.txt   one event per line: `<seconds> <type> <code> <value>`, e.g. `0.016 EV_KEY KEY_A 1`.
+//                Events with the same timestamp arrive together, as one fetch_events() batch would.
+//   actions.txt  the expected actions, one `{:?}` per line
+// Lines starting with `#` and blank lines are skipped. On a mismatch the actual actions are
+// printed in the same format, so a new scenario can start from what the handler does today.
+use crate::action::Action;
+use crate::event::Event;
+use crate::tests::{get_event_handler, get_input_device_info};
+use crate::Config;
+use evdev::{EventType, InputEvent, KeyCode as Key, RelativeAxisCode};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+struct RecordedEvent {
+    time: String,
+    event: InputEvent,
+}
+
+fn fixture_dir(scenario: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/replay")
+        .join(scenario)
+}
+
+fn fixture_lines(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()))
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+fn parse_event(line: &str) -> RecordedEvent {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [time, event_type, code, value] = fields[..] else {
+        panic!("Expected `<seconds> <type> <code> <value>`: {line}");
+    };
+    let event_type = match event_type {
+        "EV_SYN" => EventType::SYNCHRONIZATION,
+        "EV_KEY" => EventType::KEY,
+        "EV_REL" => EventType::RELATIVE,
+        "EV_MSC" => EventType::MISC,
+        _ => panic!("Unsupported event type {event_type}: {line}"),
+    };
+    let code = code.parse::<u16>().ok().unwrap_or_else(|| match event_type {
+        EventType::KEY => Key::from_str(code)
+            .unwrap_or_else(|_| panic!("Unknown key {code}: {line}"))
+            .code(),
+        EventType::RELATIVE => {
+            RelativeAxisCode::from_str(code)
+                .unwrap_or_else(|_| panic!("Unknown axis {code}: {line}"))
+                .0
+        }
+        _ => panic!("Expected a numeric code: {line}"),
+    });
+    let value = value
+        .parse()
+        .unwrap_or_else(|_| panic!("Expected a numeric value: {line}"));
+    RecordedEvent {
+        time: time.to_string(),
+        event: InputEvent::new(event_type.0, code, value),
+    }
+}
+
+pub fn assert_replay(scenario: &str) {
+    let dir = fixture_dir(scenario);
+    let config_yaml = fs::read_to_string(dir.join("config.yml")).unwrap();
+    let mut config: Config = serde_yaml::from_str(&config_yaml).unwrap();
+    config.keymap_table = crate::config::keymap::build_keymap_table(&config.keymap);
+    let mut event_handler = get_event_handler(&config, None);
+
+    let recorded: Vec<RecordedEvent> = fixture_lines(&dir.join("events.txt"))
+        .iter()
+        .map(|line| parse_event(line))
+        .collect();
+    let mut actual: Vec<Action> = vec![];
+    for batch in recorded.chunk_by(|a, b| a.time == b.time) {
+        let events: Vec<Event> = batch
+            .iter()
+            .map(|recorded| Event::new(get_input_device_info(), recorded.event))
+            .collect();
+        actual.append(&mut event_handler.on_events(&events, &config).unwrap());
+    }
+
+    let actual: Vec<String> = actual.iter().map(|action| format!("{action:?}")).collect();
+    let expected = fixture_lines(&dir.join("actions.txt"));
+    assert!(
+        actual == expected,
+        "{scenario}: actions differ from {}; actual:\n{}",
+        dir.join("actions.txt").display(),
+        actual.join("\n")
+    );
+}
+
+#[test]
+fn test_replay_modmap_keymap() {
+    assert_replay("modmap_keymap");
+}
@@ -1,8 +1,9 @@
 use crate::action::Action;
 use crate::event::Event;
-use crate::event::{KeyEvent, KeyValue};
+use crate::event::{KeyEvent, KeyValue, RelativeEvent};
 use crate::tests::{assert_actions, get_input_device_info};
 use evdev::KeyCode as Key;
+use evdev::RelativeAxisCode;
 use indoc::indoc;
 use std::time::Duration;
 
@@ -228,3 +229,40 @@ fn test_virtual_terminal_modifier_is_not_supported() {
         vec![],
     )
 }
+
+#[test]
+fn test_mouse_button_as_virtual_modifier() {
+    let config = indoc! {"
+        virtual_modifiers:
+            - BTN_RIGHT
+        keymap:
+            - remap:
+                BTN_RIGHT-A: B
+        "};
+
+    // Only while the button is down, and mouse movement in between still goes through
+    assert_actions(
+        config,
+        vec![
+            Event::key_press(Key::KEY_A),
+            Event::key_release(Key::KEY_A),
+            Event::key_press(Key::BTN_RIGHT),
+            Event::relative(RelativeAxisCode::REL_X.0, 5),
+            Event::key_press(Key::KEY_A),
+            Event::key_release(Key::KEY_A),
+            Event::key_release(Key::BTN_RIGHT),
+            Event::key_press(Key::KEY_A),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::MouseMovementEventCollection(vec![RelativeEvent::new_with(RelativeAxisCode::REL_X.0, 5)]),
+        ],
+    )
+}